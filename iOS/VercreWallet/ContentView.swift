@@ -14,6 +14,10 @@ struct ContentView: View {
     init(core: Core) {
         self.core = core
         core.update(.ready)
+        core.update(.setLocale(LocalePreference(
+            locale: Locale.current.identifier(.bcp47),
+            time_zone: TimeZone.current.identifier
+        )))
     }
     
     var body: some View {
@@ -30,9 +34,9 @@ struct ContentView: View {
             case .issuanceScan:
                 IssuanceScan(core: Core()).navBar(context: core.view.active_view)
             case .issuanceOffer:
-                IssuanceOffer(credential: core.view.issuance_view.offered).navBar(context: core.view.active_view)
+                IssuanceOffer(issuance: core.view.issuance_view).navBar(context: core.view.active_view)
             case .error:
-                ErrorDetail(message: core.view.error)
+                ErrorDetail(error: core.view.error)
             default : EmptyView()
             }
         }
@@ -9,21 +9,32 @@ import SharedTypes
 import SwiftUI
 
 struct ClaimList: View {
-    let claims: [ClaimView]
+    let claims: [ClaimNode]
     
     var body: some View {
         VStack(alignment: .leading) {
-            ForEach(claims, id: \.self.name) { claim in
-                DetailItem(title: claim.name, content: claim.value, compact: true)
+            ForEach(claims, id: \.self.path) { claim in
+                switch claim.value {
+                case .object(let nested), .array(let nested):
+                    Text(claim.label).font(.caption).opacity(0.5)
+                        .padding(.leading, 12)
+                    ClaimList(claims: nested).padding(.leading, 12)
+                case .boolean(let value):
+                    DetailItem(title: claim.label, content: value ? "Yes" : "No", compact: true)
+                case .string(let value), .number(let value), .date(let value), .image(let value):
+                    DetailItem(title: claim.label, content: value, compact: true)
+                case .unset:
+                    DetailItem(title: claim.label, content: "", compact: true)
+                }
             }
         }
     }
 }
 
 #Preview {
-    let claims: [ClaimView] = [
-        .init(name: "Given Name", value: "Normal"),
-        .init(name: "Family Name", value: "Person"),
+    let claims: [ClaimNode] = [
+        .init(label: "Given Name", path: ["given_name"], value: .string("Normal"), order: 0),
+        .init(label: "Family Name", path: ["family_name"], value: .string("Person"), order: 1),
     ]
     ClaimList(claims: claims)
 }
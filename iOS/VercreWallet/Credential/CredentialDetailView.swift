@@ -61,12 +61,15 @@ struct CredentialDetailView: View {
 }
 
 #Preview {
-    let claims: [ClaimView] = [
-        .init(name: "Address.Locality", value: "Wellington"),
-        .init(name: "Address.Street address", value: "123 Fake St"),
-        .init(name: "Email", value: "normal.user@example.com"),
-        .init(name: "Family name", value: "Person"),
-        .init(name: "Given name", value: "Normal"),
+    let address: [ClaimNode] = [
+        .init(label: "Locality", path: ["address", "locality"], value: .string("Wellington"), order: 1),
+        .init(label: "Street address", path: ["address", "street_address"], value: .string("123 Fake St"), order: 3),
+    ]
+    let claims: [ClaimNode] = [
+        .init(label: "Address", path: ["address"], value: .object(address), order: 0),
+        .init(label: "Email", path: ["email"], value: .string("normal.user@example.com"), order: 1),
+        .init(label: "Family name", path: ["family_name"], value: .string("Person"), order: 2),
+        .init(label: "Given name", path: ["given_name"], value: .string("Normal"), order: 3),
     ]
     let credential = Credential(
         id: "http://vercre.io/credentials/EmployeeIDCredential",
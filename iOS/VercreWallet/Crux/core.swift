@@ -32,6 +32,16 @@ class Core: ObservableObject {
         switch request.effect {
         case .render:
             view = try! .bincodeDeserialize(input: [UInt8](VercreWallet.view()))
+        case .clock(.now):
+            let now = Date().timeIntervalSince1970
+            let seconds = Int64(now.rounded(.down))
+            let nanos = UInt32((now - Double(seconds)) * 1_000_000_000)
+            let response = ClockResponse.now(seconds: seconds, nanos: nanos)
+            let effects = [UInt8](handleResponse(request.id, Data(try! response.bincodeSerialize())))
+            let requests: [Request] = try! .bincodeDeserialize(input: effects)
+            for request in requests {
+                processEffect(request)
+            }
         case let .http(req):
             Task {
                 let response = try! await requestHttp(req).get()
@@ -55,6 +65,16 @@ class Core: ObservableObject {
                     }
                 }
             }
+        case let .store(.watch(catalog)):
+            Task {
+                for await result in watchStore(catalog) {
+                    let effects = [UInt8](handleResponse(request.id, Data(try! result.bincodeSerialize())))
+                    let requests: [Request] = try! .bincodeDeserialize(input: effects)
+                    for request in requests {
+                        processEffect(request)
+                    }
+                }
+            }
         case let .store(req):
             Task {
                 let response = try! await requestStore(req).get()
@@ -24,15 +24,41 @@ func requestHttp(_ request: HttpRequest) async -> Result<HttpResponse, HttpError
     debugPrint(request.method + ": " + request.url)
     
     do {
-        let (data, response) = try await URLSession.shared.data(for: req)
-        if let httpResponse = response as? HTTPURLResponse {
-            let status = UInt16(httpResponse.statusCode)
-            let body = [UInt8](data)
-            return .success(HttpResponse(status: status, headers: [], body: body))
-        } else {
+        let (bytes, response) = try await URLSession.shared.bytes(for: req)
+        guard let httpResponse = response as? HTTPURLResponse else {
             return .failure(.message("bad response"))
         }
+        // A request for a leading byte range is read no further than the end
+        // of the range, even if the server ignores it and sends more.
+        let limit = rangeLength(request.headers)
+        var body = [UInt8]()
+        for try await byte in bytes {
+            body.append(byte)
+            if let limit, body.count >= limit {
+                break
+            }
+        }
+        let status = UInt16(httpResponse.statusCode)
+        let headers = httpResponse.allHeaderFields.compactMap { name, value -> HttpHeader? in
+            guard let name = name as? String, let value = value as? String else {
+                return nil
+            }
+            return HttpHeader(name: name, value: value)
+        }
+        return .success(HttpResponse(status: status, headers: headers, body: body))
     } catch {
         return .failure(.generic(error))
     }
 }
+
+/// The number of bytes asked for by a `Range: bytes=0-<end>` request header.
+func rangeLength(_ headers: [HttpHeader]) -> Int? {
+    let prefix = "bytes=0-"
+    guard let range = headers.first(where: { $0.name.lowercased() == "range" })?.value,
+          range.hasPrefix(prefix),
+          let end = Int(range.dropFirst(prefix.count))
+    else {
+        return nil
+    }
+    return end + 1
+}
@@ -21,9 +21,24 @@ enum KeyStoreError: Error {
 // management and types that we avoid since the key value is being used in the
 // Crux layer, not directly in Swift itself.
 
+// The algorithm a key was set with is kept in the item's label, and the
+// keychain records when the item was created, so a key's metadata is read from
+// the item's attributes rather than its data.
+
 // See: https://www.andyibanez.com/posts/using-ios-keychain-swift/ for a
 // reasonable resource for this strange API.
 
+// Get the metadata of a key from its keychain item attributes.
+func keyMetadata(_ attributes: [String: Any]) -> KeyMetadata {
+    let created = attributes[kSecAttrCreationDate as String] as? Date ?? Date()
+    return KeyMetadata(
+        id: attributes[kSecAttrAccount as String] as? String ?? "",
+        purpose: attributes[kSecAttrService as String] as? String ?? "",
+        algorithm: attributes[kSecAttrLabel as String] as? String ?? "",
+        created: ISO8601DateFormatter().string(from: created)
+    )
+}
+
 func requestKeyStore(_ request: KeyStoreOperation) async -> Result<KeyStoreResponse, KeyStoreError> {
     switch request {
     case .get(let id, let purpose):
@@ -35,7 +50,7 @@ func requestKeyStore(_ request: KeyStoreOperation) async -> Result<KeyStoreRespo
             kSecReturnData: true,
         ] as CFDictionary
         var ref: AnyObject?
-        let status = SecItemAdd(query, &ref)
+        let status = SecItemCopyMatching(query, &ref)
         if status == errSecItemNotFound {
             return .success(.retrieved(key: KeyStoreEntry.none))
         }
@@ -45,14 +60,24 @@ func requestKeyStore(_ request: KeyStoreOperation) async -> Result<KeyStoreRespo
         let result = ref as! Data
         let entry = KeyStoreEntry.data(Array(result))
         return .success(.retrieved(key: entry))
-    case .set(let id, let purpose, let data):
+    case .set(let id, let purpose, let algorithm, let data):
         let query = [
-            kSecClass: kSecClassKey,
+            kSecClass: kSecClassGenericPassword,
             kSecAttrService: purpose,
             kSecAttrAccount: id,
-            kSecValueData: data
         ] as CFDictionary
-        let status = SecItemAdd(query, nil)
+        // Replace the data of an existing key so its creation date is kept.
+        var status = SecItemUpdate(query, [kSecValueData: Data(data)] as CFDictionary)
+        if status == errSecItemNotFound {
+            let item = [
+                kSecClass: kSecClassGenericPassword,
+                kSecAttrService: purpose,
+                kSecAttrAccount: id,
+                kSecAttrLabel: algorithm,
+                kSecValueData: Data(data),
+            ] as CFDictionary
+            status = SecItemAdd(item, nil)
+        }
         if status != errSecSuccess {
             return .failure(.message("failed to store key: \(status)"))
         }
@@ -64,7 +89,7 @@ func requestKeyStore(_ request: KeyStoreOperation) async -> Result<KeyStoreRespo
             kSecAttrAccount: id
         ] as CFDictionary
         let status = SecItemDelete(query)
-        if status != errSecSuccess {
+        if status != errSecSuccess && status != errSecItemNotFound {
             return .failure(.message("failed to delete key: \(status)"))
         }
         return .success(.deleted)
@@ -79,5 +104,42 @@ func requestKeyStore(_ request: KeyStoreOperation) async -> Result<KeyStoreRespo
             return .failure(.message("failed to generate secret: \(status)"))
         }
         return .success(.generatedSecret(secret: Array(bytes)))
+    case .list:
+        let query = [
+            kSecClass: kSecClassGenericPassword,
+            kSecMatchLimit: kSecMatchLimitAll,
+            kSecReturnAttributes: true,
+        ] as CFDictionary
+        var ref: AnyObject?
+        let status = SecItemCopyMatching(query, &ref)
+        if status == errSecItemNotFound {
+            return .success(.listed(keys: []))
+        }
+        if status != errSecSuccess {
+            return .failure(.message("failed to list keys: \(status)"))
+        }
+        let items = ref as? [[String: Any]] ?? []
+        return .success(.listed(keys: items.map(keyMetadata)))
+    case .metadata(let id, let purpose):
+        let query = [
+            kSecClass: kSecClassGenericPassword,
+            kSecAttrService: purpose,
+            kSecAttrAccount: id,
+            kSecMatchLimit: kSecMatchLimitOne,
+            kSecReturnAttributes: true,
+        ] as CFDictionary
+        var ref: AnyObject?
+        let status = SecItemCopyMatching(query, &ref)
+        if status == errSecItemNotFound {
+            return .success(.described(metadata: KeyMetadataEntry.none))
+        }
+        if status != errSecSuccess {
+            return .failure(.message("failed to describe key: \(status)"))
+        }
+        let attributes = ref as? [String: Any] ?? [:]
+        return .success(.described(metadata: .metadata(keyMetadata(attributes))))
+    case .attest:
+        // The wallet does not use App Attest, so keys cannot be attested.
+        return .success(.attested(attestation: .none))
     }
 }
@@ -13,15 +13,80 @@ enum SseError: Error {
     case message(String)
 }
 
+// Open connections, by the ID the core knows them by, so they can be closed.
+@MainActor
+private var sseConnections: [String: (token: UUID, task: Task<Void, Never>)] = [:]
+
+// Size of the chunks the response body is passed to the core in.
+private let sseChunkSize = 1024
+
+@MainActor
 func requestSse(_ request: SseRequest) async -> AsyncStream<Result<SseResponse, SseError>> {
-    return AsyncStream { continuation in
-        Task {
-            let req = URLRequest(url: URL(string: request.url)!)
-            do {
-                let (_, _) = try await URLSession.shared.bytes(for: req)
-            } catch {
-                
+    switch request {
+    case .connect(let id, let url, let lastEventId, let retry):
+        return AsyncStream { continuation in
+            // A reconnection reuses the ID, so only this connection's entry is removed.
+            let token = UUID()
+            let task = Task {
+                defer {
+                    Task { @MainActor in
+                        if sseConnections[id]?.token == token {
+                            sseConnections[id] = nil
+                        }
+                    }
+                    continuation.finish()
+                }
+                guard let url = URL(string: url) else {
+                    continuation.yield(.success(.error("invalid URL: \(url)")))
+                    return
+                }
+                do {
+                    if retry > 0 {
+                        try await Task.sleep(nanoseconds: retry * 1_000_000)
+                    }
+                    var req = URLRequest(url: url)
+                    req.setValue("text/event-stream", forHTTPHeaderField: "Accept")
+                    req.setValue("no-cache", forHTTPHeaderField: "Cache-Control")
+                    if !lastEventId.isEmpty {
+                        req.setValue(lastEventId, forHTTPHeaderField: "Last-Event-ID")
+                    }
+                    let (bytes, response) = try await URLSession.shared.bytes(for: req)
+                    let status = (response as? HTTPURLResponse)?.statusCode ?? 0
+                    let contentType = (response as? HTTPURLResponse)?.value(forHTTPHeaderField: "Content-Type") ?? ""
+                    if status != 200 || !contentType.hasPrefix("text/event-stream") {
+                        continuation.yield(.success(.rejected(UInt16(clamping: status))))
+                        return
+                    }
+                    var chunk: [UInt8] = []
+                    for try await byte in bytes {
+                        chunk.append(byte)
+                        // Pass on complete lines promptly so events are not held back.
+                        if chunk.count >= sseChunkSize || byte == UInt8(ascii: "\n") {
+                            continuation.yield(.success(.chunk(chunk)))
+                            chunk = []
+                        }
+                    }
+                    if !chunk.isEmpty {
+                        continuation.yield(.success(.chunk(chunk)))
+                    }
+                    continuation.yield(.success(.done))
+                } catch is CancellationError {
+                    continuation.yield(.success(.closed))
+                } catch {
+                    if Task.isCancelled {
+                        continuation.yield(.success(.closed))
+                    } else {
+                        continuation.yield(.success(.error(error.localizedDescription)))
+                    }
+                }
             }
+            sseConnections[id] = (token, task)
+        }
+    case .close(let id):
+        return AsyncStream { continuation in
+            sseConnections.removeValue(forKey: id)?.task.cancel()
+            continuation.yield(.success(.closed))
+            continuation.finish()
         }
     }
 }
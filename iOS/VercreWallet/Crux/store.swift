@@ -19,13 +19,30 @@ final class StoredObject {
     // serialize/deserialize into its model.
     var data: Data
     
-    init(id: String, data: Data) {
+    // Metadata the Crux layer indexes the object by, stored in the clear so objects can be
+    // queried without reading their data.
+    var types: [String] = []
+    var issuer: String = ""
+    var expires: String = ""
+    
+    init(id: String, data: Data, index: StoreIndex) {
         self.id = id
         self.data = data
+        self.types = index.types
+        self.issuer = index.issuer
+        self.expires = index.expires
+    }
+    
+    convenience init(id: String, bytes: [UInt8], index: StoreIndex) {
+        self.init(id: id, data: Data(bytes), index: index)
     }
     
-    convenience init(id: String, bytes: [UInt8]) {
-        self.init(id: id, data: Data(bytes))
+    // Whether the object's indexed metadata matches a query. Empty query fields match any
+    // object. Expiry timestamps are RFC 3339 in UTC so compare as strings.
+    func matches(_ query: StoreQuery) -> Bool {
+        (query.type.isEmpty || types.contains(query.type))
+            && (query.issuer.isEmpty || issuer == query.issuer)
+            && (query.expires_after.isEmpty || expires.isEmpty || expires > query.expires_after)
     }
 }
 
@@ -73,6 +90,13 @@ final class ObjectStore: Store {
         let config = ModelConfiguration(url: storeUrl)
         self.container = try ModelContainer(for: StoredObject.self, configurations: config)
     }
+    
+    // Find the object with the specified ID.
+    func find(_ id: String, in context: ModelContext) throws -> StoredObject? {
+        var descriptor = FetchDescriptor<StoredObject>(predicate: #Predicate { $0.id == id })
+        descriptor.fetchLimit = 1
+        return try context.fetch(descriptor).first
+    }
 }
 
 enum StoreError: Error {
@@ -80,41 +104,126 @@ enum StoreError: Error {
     case message(String)
 }
 
+// Watchers of each catalog. Every change to the store is made through this shell, so changes
+// are pushed to a catalog's watchers as they are saved.
+@MainActor
+private var storeWatchers: [String: [UUID: AsyncStream<StoreResult>.Continuation]] = [:]
+
+@MainActor
+private func notifyWatchers(_ catalog: String, _ delta: StoreDelta) {
+    for continuation in storeWatchers[catalog, default: [:]].values {
+        continuation.yield(.ok(response: .changed(delta: delta)))
+    }
+}
+
+// Watch a catalog for changes. The stream yields a `changed` response for each object inserted,
+// updated or deleted, and ends when the watcher is cancelled.
+@MainActor
+func watchStore(_ catalog: String) -> AsyncStream<StoreResult> {
+    return AsyncStream { continuation in
+        let id = UUID()
+        storeWatchers[catalog, default: [:]][id] = continuation
+        continuation.onTermination = { _ in
+            Task { @MainActor in
+                storeWatchers[catalog]?[id] = nil
+            }
+        }
+    }
+}
+
+// Save an object in the context, returning the change to report to watchers.
+private func save(_ store: ObjectStore, _ context: ModelContext, id: String, data: [UInt8], index: StoreIndex) throws -> StoreDelta {
+    if let object = try store.find(id, in: context) {
+        object.data = Data(data)
+        object.types = index.types
+        object.issuer = index.issuer
+        object.expires = index.expires
+        return .updated(id: id, data: data)
+    }
+    context.insert(StoredObject(id: id, bytes: data, index: index))
+    return .inserted(id: id, data: data)
+}
+
+// Delete an object from the context, returning the change to report to watchers if there was
+// an object to delete.
+private func delete(_ store: ObjectStore, _ context: ModelContext, id: String) throws -> StoreDelta? {
+    guard let object = try store.find(id, in: context) else {
+        return nil
+    }
+    context.delete(object)
+    return .deleted(id: id)
+}
+
+@MainActor
 func requestStore(_ request: StoreOperation) async -> Result<StoreResponse, StoreError> {
     do {
         switch request {
-        case .save(let catalog, let id, let data):
+        case .save(let catalog, let id, let data, let index):
             let store = try ObjectStore(catalog: catalog)
-            do {
-                try store.save(StoredObject(id: id, bytes: data))
-                return .success(.saved)
-            } catch {
-                return .failure(.message(error.localizedDescription))
-            }
+            let context = ModelContext(store.container)
+            let delta = try save(store, context, id: id, data: data, index: index)
+            try context.save()
+            notifyWatchers(catalog, delta)
+            return .success(.saved)
+        case .get(let catalog, let id):
+            let store = try ObjectStore(catalog: catalog)
+            let object = try store.find(id, in: ModelContext(store.container))
+            let entry: StoreEntry = object.map { .data(Array($0.data)) } ?? StoreEntry.none
+            return .success(.retrieved(entry: entry))
         case .list(let catalog):
             let store = try ObjectStore(catalog: catalog)
-            do {
-                let objects : [StoredObject] = try store.list()
-                var entries: [StoreEntry] = []
-                for object in objects {
-                    entries.append(StoreEntry.data(Array(object.data)))
-                }
-                return .success(.list(entries: entries))
-            } catch {
-                return .failure(.message(error.localizedDescription))
-            }
-        case .delete(catalog: let catalog, id: let id):
+            let objects: [StoredObject] = try store.list()
+            return .success(.list(entries: objects.map { .data(Array($0.data)) }))
+        case .query(let catalog, let query):
+            let store = try ObjectStore(catalog: catalog)
+            let objects: [StoredObject] = try store.list()
+            let matches = objects.filter { $0.matches(query) }
+            return .success(.list(entries: matches.map { .data(Array($0.data)) }))
+        case .delete(let catalog, let id):
             let store = try ObjectStore(catalog: catalog)
-            do {
-                // Find the object with the specified ID
-                let object: StoredObject? = try store.list().first(where: { $0.id == id })
-                if object != nil {
-                    try store.delete(object!)
+            let context = ModelContext(store.container)
+            let delta = try delete(store, context, id: id)
+            try context.save()
+            if let delta {
+                notifyWatchers(catalog, delta)
+            }
+            return .success(.deleted)
+        case .batch(let changes):
+            // Each catalog is a separate store, so the changes to each are made in one
+            // context and nothing is saved until every change has been applied.
+            var stores: [String: (ObjectStore, ModelContext)] = [:]
+            var deltas: [(String, StoreDelta)] = []
+            for change in changes {
+                let catalog: String
+                switch change {
+                case .save(let c, _, _, _), .delete(let c, _):
+                    catalog = c
+                }
+                if stores[catalog] == nil {
+                    let store = try ObjectStore(catalog: catalog)
+                    let context = ModelContext(store.container)
+                    context.autosaveEnabled = false
+                    stores[catalog] = (store, context)
                 }
-                return .success(.deleted)
-            } catch {
-                return .failure(.message(error.localizedDescription))
+                let (store, context) = stores[catalog]!
+                switch change {
+                case .save(_, let id, let data, let index):
+                    deltas.append((catalog, try save(store, context, id: id, data: data, index: index)))
+                case .delete(_, let id):
+                    if let delta = try delete(store, context, id: id) {
+                        deltas.append((catalog, delta))
+                    }
+                }
+            }
+            for (_, context) in stores.values {
+                try context.save()
+            }
+            for (catalog, delta) in deltas {
+                notifyWatchers(catalog, delta)
             }
+            return .success(.committed)
+        case .watch:
+            return .failure(.message("watch requests are answered by watchStore"))
         }
     } catch {
         return .failure(.message(error.localizedDescription))
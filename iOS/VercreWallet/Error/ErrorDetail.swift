@@ -5,24 +5,41 @@
 //  Created by Andrew Goldie on 21/11/2024.
 //
 
+import SharedTypes
 import SwiftUI
 
 struct ErrorDetail: View {
     @Environment(\.update) var update
-    var message: String?
+    var error: WalletError
     
     var body: some View {
         VStack {
-            Text(message ?? "No current error")
+            Text(error.message.isEmpty ? "No current error" : error.message)
+            if !error.detail.isEmpty {
+                Text(error.detail).font(.caption).opacity(0.5)
+            }
+            if error.retryable {
+                Button("Try again") {
+                    update(.retryLastAction)
+                }
+                .padding()
+                .buttonStyle(.borderedProminent)
+            }
             Button("Dismiss") {
-                update(.ready)
+                update(.dismissError)
             }
             .padding()
-            .buttonStyle(.borderedProminent)
+            .buttonStyle(error.retryable ? .bordered : .borderedProminent)
         }
     }
 }
 
 #Preview {
-    ErrorDetail(message: "An error has occurred.")
+    ErrorDetail(error: WalletError(
+        category: .network,
+        message: "The wallet could not connect. Check your connection and try again.",
+        detail: "connection refused",
+        retryable: true,
+        recovery: .retry
+    ))
 }
@@ -10,9 +10,20 @@ import SwiftUI
 
 struct IssuanceOffer: View {
     @Environment(\.update) var update
+    var issuance: IssuanceView
+    @State private var pin = ""
+
     // This is an "empty" credential - a credential object representing a credential configuration
-    var credential: Credential
-    
+    var credential: Credential {
+        issuance.offered
+    }
+
+    // The offer can be accepted once the issuer's metadata shows the wallet can
+    // handle at least one of the offered credentials.
+    var canAccept: Bool {
+        issuance.support.contains(where: { $0.supported }) && !(issuance.pin_required && pin.isEmpty)
+    }
+
     var body: some View {
         VStack {
             Text("Accept Credential?").font(.title).padding(.bottom, 8)
@@ -20,7 +31,18 @@ struct IssuanceOffer: View {
             CredentialCard(credential: credential)
             VStack(alignment: .leading) {
                 DetailItem(title: "Description", content: credential.description)
-                DetailItem(title: "Issued by", content: credential.issuer_name + " (" + credential.issuer + ")")
+                DetailItem(title: "Issued by", content: issuance.issuer_name + " (" + issuance.issuer + ")")
+                if !issuance.support.isEmpty && issuance.unsigned_metadata {
+                    Label("The issuer's details are not signed", systemImage: "exclamationmark.triangle")
+                        .font(.footnote)
+                        .padding(.horizontal, 12)
+                }
+                ForEach(issuance.support.filter({ !$0.supported }), id: \.config_id) { support in
+                    DetailItem(
+                        title: (support.name.isEmpty ? support.config_id : support.name) + " cannot be issued",
+                        content: support.reasons.joined(separator: "\n")
+                    )
+                }
                 Text("Details").font(.headline).fontWeight(.bold).padding([.vertical, .horizontal], 12)
                 // TODO: Collect which claims the user will accept. For now it's all or nothing.
                 ForEach(credential.claims.keys.sorted(), id: \.self) {key in
@@ -29,15 +51,25 @@ struct IssuanceOffer: View {
                     }
                 }
                 Spacer()
+                if issuance.pin_required {
+                    SecureField(issuance.tx_code.description.isEmpty ? "PIN" : issuance.tx_code.description, text: $pin)
+                        .keyboardType(issuance.tx_code.input_mode == "numeric" ? .numberPad : .default)
+                        .textFieldStyle(.roundedBorder)
+                        .padding(.horizontal, 12)
+                }
                 HStack {
                     Button("Cancel") {
                         update(.cancelIssuance)
                     }
                     Spacer()
                     Button("Accept") {
-                        update(.ready)
+                        update(.issuanceAccepted)
+                        if issuance.pin_required {
+                            update(.issuancePin(pin))
+                        }
                     }
                     .buttonStyle(.borderedProminent)
+                    .disabled(!canAccept)
                 }
                 .padding(.horizontal, 64)
             }
@@ -78,5 +110,16 @@ struct IssuanceOffer: View {
             mediaType: "image/png"
         )
     )
-    IssuanceOffer(credential: credential)
+    let issuance = IssuanceView(
+        issuer: credential.issuer,
+        issuer_name: credential.issuer_name,
+        offered: credential,
+        tx_code: TxCode(input_mode: "numeric", length: 6, description: "Enter the PIN sent to you"),
+        pin_required: true,
+        support: [
+            .init(config_id: "EmployeeID_JWT", name: "Employee ID", supported: true, reasons: []),
+        ],
+        unsigned_metadata: true
+    )
+    IssuanceOffer(issuance: issuance)
 }
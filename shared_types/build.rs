@@ -2,6 +2,10 @@ use std::path::PathBuf;
 
 use crux_core::typegen::TypeGen;
 use crux_http::HttpError;
+use vercre_wallet::view::claim::ClaimValue;
+use vercre_wallet::view::credential::{CredentialSort, CredentialStatus, Validity};
+use vercre_wallet::view::key::KeyStatus;
+use vercre_wallet::view::{ErrorCategory, RecoveryAction};
 use vercre_wallet::{App, Aspect};
 
 fn main() -> anyhow::Result<()> {
@@ -18,6 +22,13 @@ fn main() -> anyhow::Result<()> {
 
     // Register other types the code generator is having trouble inferring
     gen.register_type::<Aspect>()?;
+    gen.register_type::<KeyStatus>()?;
+    gen.register_type::<CredentialStatus>()?;
+    gen.register_type::<Validity>()?;
+    gen.register_type::<CredentialSort>()?;
+    gen.register_type::<ClaimValue>()?;
+    gen.register_type::<ErrorCategory>()?;
+    gen.register_type::<RecoveryAction>()?;
 
     gen.swift("SharedTypes", out_dir.join("swift"))?;
     gen.java("io.vercre.wallet.shared_types", out_dir.join("java"))?;
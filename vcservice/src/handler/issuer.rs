@@ -1,25 +1,36 @@
 //! # Request handlers for issuer endpoints.
 
 use std::collections::HashMap;
+use std::time::Duration;
 use std::vec;
 
 use anyhow::anyhow;
-use axum::extract::State;
+use axum::extract::{Query, State};
 use axum::http::{HeaderMap, StatusCode};
+use axum::response::sse::{Event, KeepAlive, Sse};
 use axum::response::Result;
 use axum::{Form, Json};
 use axum_extra::headers::authorization::Bearer;
 use axum_extra::headers::Authorization;
 use axum_extra::TypedHeader;
+use futures::{stream, Stream, StreamExt};
 use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::error::RecvError;
 use typeshare::typeshare;
 use vercre_issuer::{
     CredentialRequest, CredentialResponse, MetadataRequest, MetadataResponse, OfferType, SendType, TokenRequest, TokenResponse
 };
 
 use super::{AppError, AppJson};
+use crate::status::{self, StatusUpdate};
 use crate::AppState;
 
+/// Kinds of status update that can be published.
+const STATUSES: [&str; 4] = ["deferred_ready", "revoked", "suspended", "valid"];
+
+/// Delay before a wallet should reconnect to the status event stream.
+const RETRY: Duration = Duration::from_secs(3);
+
 /// Create offer request.
 #[derive(Clone, Debug, Deserialize, Serialize)]
 #[typeshare]
@@ -153,3 +164,70 @@ pub async fn credential(
     let response = vercre_issuer::credential(state.issuer_provider.clone(), req).await?;
     Ok(AppJson(response))
 }
+
+/// Status event stream request.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct EventsRequest {
+    /// JWT signed by the holder's key, addressed to the issuer.
+    pub token: String,
+}
+
+// Status event stream of the holder whose key signed the request's token.
+// Wallets reconnecting with a `Last-Event-ID` header are sent the updates they
+// missed.
+#[axum::debug_handler]
+pub async fn events(
+    headers: HeaderMap, State(state): State<AppState>, Query(req): Query<EventsRequest>,
+) -> Result<Sse<impl Stream<Item = Result<Event, axum::Error>>>, AppError> {
+    let holder = status::holder(&req.token, &state.external_address)
+        .map_err(|e| AppError::Status(StatusCode::UNAUTHORIZED, e.to_string()))?;
+    let after = headers
+        .get("last-event-id")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse().ok())
+        .unwrap_or_default();
+    let (missed, receiver) = state.status.subscribe(&holder, after);
+
+    let live = stream::unfold((receiver, holder), |(mut receiver, holder)| async move {
+        loop {
+            match receiver.recv().await {
+                Ok((_, update)) if update.holder != holder => continue,
+                Ok(update) => return Some((update, (receiver, holder))),
+                // A slow subscriber skips updates rather than ending the stream.
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    });
+    let events = stream::iter(missed).chain(live).map(|(id, update)| {
+        Event::default().event("status").id(id.to_string()).retry(RETRY).json_data(update)
+    });
+
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
+// Publish a status update to the holder's subscribed wallets. Publishers
+// authenticate with the service's status token.
+#[axum::debug_handler]
+pub async fn publish_status(
+    State(state): State<AppState>, auth: Option<TypedHeader<Authorization<Bearer>>>,
+    Json(req): Json<StatusUpdate>,
+) -> Result<StatusCode, AppError> {
+    let authorized = auth.is_some_and(|TypedHeader(auth)| {
+        !state.status_token.is_empty() && auth.token() == state.status_token
+    });
+    if !authorized {
+        return Err(AppError::Status(
+            StatusCode::UNAUTHORIZED,
+            "publishing status updates requires the status token".into(),
+        ));
+    }
+    if !STATUSES.contains(&req.status.as_str()) {
+        return Err(AppError::Status(
+            StatusCode::BAD_REQUEST,
+            format!("unknown status: {}", req.status),
+        ));
+    }
+    state.status.publish(req);
+    Ok(StatusCode::ACCEPTED)
+}
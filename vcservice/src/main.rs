@@ -5,6 +5,9 @@
 
 mod handler;
 mod provider;
+mod status;
+#[cfg(test)]
+mod tests;
 
 use std::borrow::Cow;
 use std::env;
@@ -32,8 +35,10 @@ pub struct AppState {
     external_address: Cow<'static, str>,
     issuer: Cow<'static, str>,
     verifier: Cow<'static, str>,
+    status_token: Cow<'static, str>,
     issuer_provider: provider::issuer::Provider,
     verifier_provider: provider::verifier::Provider,
+    status: status::StatusChannel,
 }
 
 #[tokio::main]
@@ -47,22 +52,39 @@ async fn main() {
         env::var("VERCRE_HTTP_ADDR").unwrap_or_else(|_| "http://0.0.0.0:8080".into());
     let issuer = env::var("VERCRE_ISSUER").unwrap_or_else(|_| "http://vercre.io".into());
     let verifier = env::var("VERCRE_VERIFIER").unwrap_or_else(|_| "http://vercre.io".into());
+    // Publishing status updates is disabled unless a token is set.
+    let status_token = env::var("VERCRE_STATUS_TOKEN").unwrap_or_default();
 
     let app_state = AppState {
         external_address: external_address.into(),
         issuer: issuer.into(),
         verifier: verifier.into(),
+        status_token: status_token.into(),
         issuer_provider: provider::issuer::Provider::new(),
         verifier_provider: provider::verifier::Provider::new(),
+        status: status::StatusChannel::new(),
     };
 
+    let router = router(app_state);
+
+    let http_addr = Url::parse("http://0.0.0.0:8080").expect("http_addr should be a valid URL");
+    let addr = format!("{}:{}", http_addr.host_str().unwrap(), http_addr.port().unwrap_or(8080));
+    let listener = TcpListener::bind(addr).await.expect("should bind to address");
+    tracing::info!("listening on {}", listener.local_addr().expect("listener should have address"));
+    axum::serve(listener, router).await.expect("server should run");
+}
+
+/// Routes for the issuer and verifier endpoints.
+fn router(app_state: AppState) -> Router {
     let cors = CorsLayer::new().allow_methods(Any).allow_origin(Any).allow_headers(Any);
-    let router = Router::new()
+    Router::new()
         .route("/", get(handler::index))
         .route("/create_offer", post(issuer::create_offer))
         .route("/.well-known/openid-credential-issuer", get(issuer::metadata))
         .route("/token", post(issuer::token))
         .route("/credential", post(issuer::credential))
+        .route("/events", get(issuer::events))
+        .route("/status", post(issuer::publish_status))
         .route("/create_request", post(verifier::create_request))
         .route("/request/:object_id", get(verifier::request_object))
         .route("/post", post(verifier::response))
@@ -72,13 +94,7 @@ async fn main() {
             header::CACHE_CONTROL,
             HeaderValue::from_static("no-cache, no-store"),
         ))
-        .with_state(app_state);
-
-    let http_addr = Url::parse("http://0.0.0.0:8080").expect("http_addr should be a valid URL");
-    let addr = format!("{}:{}", http_addr.host_str().unwrap(), http_addr.port().unwrap_or(8080));
-    let listener = TcpListener::bind(addr).await.expect("should bind to address");
-    tracing::info!("listening on {}", listener.local_addr().expect("listener should have address"));
-    axum::serve(listener, router).await.expect("server should run");
+        .with_state(app_state)
 }
 
 // Custom JSON extractor to enable overriding the rejection and create our own
@@ -0,0 +1,142 @@
+//! # Status Updates
+//!
+//! Channel for pushing issuer-side status updates, such as a deferred
+//! credential becoming ready or a credential being revoked, to wallets
+//! subscribed to the status event stream.
+//!
+//! Each update is for one holder. A wallet subscribes with a JWT signed by
+//! its `did:key` holder key and is only sent the updates for that holder.
+
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, bail};
+use base64ct::{Base64UrlUnpadded, Encoding};
+use ed25519_dalek::{Signature, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::sync::broadcast;
+use typeshare::typeshare;
+
+/// Number of updates kept so reconnecting wallets can catch up.
+const HISTORY: usize = 100;
+
+/// A status update.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[typeshare]
+pub struct StatusUpdate {
+    /// DID of the holder the update is for: the subject of the credential.
+    pub holder: String,
+
+    /// The kind of update: `deferred_ready`, `revoked`, `suspended` or
+    /// `valid`.
+    pub status: String,
+
+    /// ID of the credential the update is about. Empty for `deferred_ready`.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub credential_id: String,
+
+    /// Transaction ID of the deferred issuance the update is about. Empty
+    /// unless `deferred_ready`.
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub transaction_id: String,
+}
+
+/// Broadcast channel for status updates, with a short history of sequenced
+/// updates.
+#[derive(Clone)]
+pub struct StatusChannel {
+    sender: broadcast::Sender<(u64, StatusUpdate)>,
+    history: Arc<Mutex<Vec<(u64, StatusUpdate)>>>,
+}
+
+impl StatusChannel {
+    /// Create a new status channel.
+    #[must_use]
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(HISTORY);
+        Self {
+            sender,
+            history: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Publish an update to all subscribers.
+    pub fn publish(&self, update: StatusUpdate) {
+        let mut history = self.history.lock().expect("lock should not be poisoned");
+        let id = history.last().map_or(1, |(id, _)| id + 1);
+        history.push((id, update.clone()));
+        if history.len() > HISTORY {
+            history.remove(0);
+        }
+        // No subscribers is not an error: the update is kept in the history.
+        let _ = self.sender.send((id, update));
+    }
+
+    /// Subscribe to updates. Returns the holder's updates published after the
+    /// given sequence number that are still in the history, and a receiver for
+    /// new updates, which the caller filters by holder.
+    pub fn subscribe(
+        &self, holder: &str, after: u64,
+    ) -> (Vec<(u64, StatusUpdate)>, broadcast::Receiver<(u64, StatusUpdate)>) {
+        let history = self.history.lock().expect("lock should not be poisoned");
+        let missed = history
+            .iter()
+            .filter(|(id, update)| *id > after && update.holder == holder)
+            .cloned()
+            .collect();
+        (missed, self.sender.subscribe())
+    }
+}
+
+impl Default for StatusChannel {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Verify a subscription token and get the DID of the holder it was signed
+/// by.
+///
+/// The token is a JWT addressed to the issuer, signed with an Ed25519
+/// `did:key` named by its `kid` header, whose `iss` claim is the same DID.
+///
+/// # Errors
+///
+/// Returns an error if the token is malformed, not addressed to the issuer,
+/// or not signed by the key its issuer names.
+pub fn holder(token: &str, audience: &str) -> anyhow::Result<String> {
+    let decode = |part: &str| -> anyhow::Result<Value> {
+        let bytes =
+            Base64UrlUnpadded::decode_vec(part).map_err(|e| anyhow!("invalid token: {e}"))?;
+        Ok(serde_json::from_slice(&bytes)?)
+    };
+    let [header, payload, signature] = token.split('.').collect::<Vec<_>>()[..] else {
+        bail!("token is not a compact JWS");
+    };
+    let (claims, signing_input) = (decode(payload)?, format!("{header}.{payload}"));
+    let header = decode(header)?;
+    let signature =
+        Base64UrlUnpadded::decode_vec(signature).map_err(|e| anyhow!("invalid signature: {e}"))?;
+
+    if header.get("alg").and_then(Value::as_str) != Some("EdDSA") {
+        bail!("token is not signed with EdDSA");
+    }
+    let kid =
+        header.get("kid").and_then(Value::as_str).ok_or_else(|| anyhow!("token has no kid"))?;
+    let (did, _) = kid.split_once('#').unwrap_or((kid, ""));
+    if claims.get("iss").and_then(Value::as_str) != Some(did) {
+        bail!("token is not issued by the holder of its key");
+    }
+    if claims.get("aud").and_then(Value::as_str) != Some(audience.trim_end_matches('/')) {
+        bail!("token is not addressed to this issuer");
+    }
+
+    let multikey = did.strip_prefix("did:key:").ok_or_else(|| anyhow!("key is not a did:key"))?;
+    let (_, bytes) = multibase::decode(multikey)?;
+    let [0xed, 0x01, key @ ..] = bytes.as_slice() else {
+        bail!("key is not an Ed25519 key");
+    };
+    let key = VerifyingKey::from_bytes(key.try_into()?)?;
+    key.verify_strict(signing_input.as_bytes(), &Signature::from_slice(&signature)?)?;
+    Ok(did.to_string())
+}
@@ -0,0 +1,281 @@
+//! Wire-format tests for the issuer and verifier endpoints.
+//!
+//! Requests are encoded the way the wallet's provider encodes them, to check
+//! the wallet and this service interoperate.
+
+use std::collections::HashMap;
+
+use axum::body::Body;
+use axum::http::{header, Request, StatusCode};
+use axum::Router;
+use base64ct::{Base64UrlUnpadded, Encoding};
+use ed25519_dalek::{Signer, SigningKey};
+use http_body_util::BodyExt;
+use tower::ServiceExt;
+use vercre_issuer::{
+    CreateOfferRequest, CredentialRequest, GrantType, MetadataResponse, OfferType, SendType,
+    TokenRequest, TokenResponse,
+};
+
+use crate::{provider, router, status, AppState};
+
+const ISSUER: &str = "http://vercre.io";
+const FORM: &str = "application/x-www-form-urlencoded";
+const JSON: &str = "application/json";
+const STATUS_TOKEN: &str = "status-secret";
+
+fn app() -> (AppState, Router) {
+    let state = AppState {
+        external_address: "http://localhost:8080".into(),
+        issuer: ISSUER.into(),
+        verifier: ISSUER.into(),
+        status_token: STATUS_TOKEN.into(),
+        issuer_provider: provider::issuer::Provider::new(),
+        verifier_provider: provider::verifier::Provider::new(),
+        status: status::StatusChannel::new(),
+    };
+    (state.clone(), router(state))
+}
+
+// Create a pre-authorized offer and get its pre-authorized code.
+async fn pre_authorized_code(state: &AppState) -> String {
+    let request = CreateOfferRequest {
+        credential_issuer: ISSUER.into(),
+        subject_id: Some("normal_user".into()),
+        credential_configuration_ids: vec!["EmployeeID_JWT".into()],
+        grant_types: Some(vec![GrantType::PreAuthorizedCode]),
+        tx_code_required: false,
+        send_type: SendType::ByVal,
+    };
+    let response = vercre_issuer::create_offer(state.issuer_provider.clone(), request)
+        .await
+        .expect("should create offer");
+    let OfferType::Object(offer) = response.offer_type else {
+        panic!("expected offer object");
+    };
+    offer.pre_authorized_code().expect("should have pre-authorized grant").pre_authorized_code
+}
+
+// Encode a token request as the wallet does.
+fn token_form(pre_authorized_code: String) -> String {
+    let fields = HashMap::from([
+        ("grant_type".to_string(), "urn:ietf:params:oauth:grant-type:pre-authorized_code".into()),
+        ("pre-authorized_code".to_string(), pre_authorized_code),
+    ]);
+    let request = TokenRequest::form_decode(&fields).expect("should decode token request");
+    let form = request.form_encode().expect("should encode token request");
+    serde_urlencoded::to_string(form).expect("should serialize form")
+}
+
+async fn send(router: Router, request: Request<Body>) -> (StatusCode, Vec<u8>) {
+    let response = router.oneshot(request).await.expect("should respond");
+    let status = response.status();
+    let body = response.into_body().collect().await.expect("should read body").to_bytes();
+    (status, body.to_vec())
+}
+
+fn post(uri: &str, content_type: &str, body: impl Into<Body>) -> Request<Body> {
+    Request::post(uri)
+        .header(header::CONTENT_TYPE, content_type)
+        .body(body.into())
+        .expect("should build request")
+}
+
+// Publish a status update with the given bearer token.
+async fn publish(router: Router, token: &str, update: &serde_json::Value) -> StatusCode {
+    let mut request = post("/status", JSON, update.to_string());
+    let bearer = format!("Bearer {token}");
+    request.headers_mut().insert(header::AUTHORIZATION, bearer.parse().expect("valid header"));
+    send(router, request).await.0
+}
+
+// Create a holder's signing key and `did:key`, and the key ID for the DID's
+// verification method.
+fn holder_key(seed: u8) -> (SigningKey, String, String) {
+    let key = SigningKey::from_bytes(&[seed; 32]);
+    let mut multikey = vec![0xed, 0x01];
+    multikey.extend(key.verifying_key().as_bytes());
+    let did = format!("did:key:{}", multibase::encode(multibase::Base::Base58Btc, multikey));
+    let kid = format!("{did}#{}", did.trim_start_matches("did:key:"));
+    (key, did, kid)
+}
+
+// Sign a compact JWS with the holder's key.
+fn sign_jws(key: &SigningKey, header: &serde_json::Value, payload: &serde_json::Value) -> String {
+    let encode = |value: &serde_json::Value| {
+        Base64UrlUnpadded::encode_string(&value.to_string().into_bytes())
+    };
+    let signing_input = format!("{}.{}", encode(header), encode(payload));
+    let signature =
+        Base64UrlUnpadded::encode_string(&key.sign(signing_input.as_bytes()).to_bytes());
+    format!("{signing_input}.{signature}")
+}
+
+// Create a holder's `did:key` and a subscription token signed by its key in
+// the same form as the wallet's, for holders other than the wallet.
+fn holder_token(seed: u8, audience: &str) -> (String, String) {
+    let (key, did, kid) = holder_key(seed);
+    let header = serde_json::json!({ "typ": "JWT", "alg": "EdDSA", "kid": kid });
+    let payload = serde_json::json!({ "iss": did, "aud": audience, "iat": 1_700_000_000 });
+    let token = sign_jws(&key, &header, &payload);
+    (did, token)
+}
+
+// Encode a credential request with a proof of possession, signed with a test
+// holder key, over the token response's nonce.
+fn credential_body(token: &serde_json::Value) -> String {
+    let (key, _, kid) = holder_key(1);
+    let header = serde_json::json!({ "typ": "openid4vci-proof+jwt", "alg": "EdDSA", "kid": kid });
+    let payload = serde_json::json!({
+        "aud": ISSUER,
+        "iat": chrono::Utc::now().timestamp(),
+        "nonce": token["c_nonce"],
+    });
+    let request: CredentialRequest = serde_json::from_value(serde_json::json!({
+        "credential_identifier": token["authorization_details"][0]["credential_identifiers"][0],
+        "proof": { "proof_type": "jwt", "jwt": sign_jws(&key, &header, &payload) },
+    }))
+    .expect("should be a credential request");
+    serde_json::to_string(&request).expect("should serialize credential request")
+}
+
+// Issuer metadata is fetched with a GET, localized by `Accept-Language`.
+#[tokio::test]
+async fn metadata_get() {
+    let (_, router) = app();
+    let request = Request::get("/.well-known/openid-credential-issuer")
+        .header(header::ACCEPT, JSON)
+        .header(header::ACCEPT_LANGUAGE, "en-NZ")
+        .body(Body::empty())
+        .expect("should build request");
+    let (status, body) = send(router, request).await;
+    assert_eq!(status, StatusCode::OK);
+    let metadata: MetadataResponse = serde_json::from_slice(&body).expect("should be metadata");
+    assert_eq!(metadata.credential_issuer.credential_endpoint, "http://localhost:8080/credential");
+}
+
+// The token endpoint accepts a form-encoded request and rejects JSON.
+#[tokio::test]
+async fn token_form_encoded() {
+    let (state, router) = app();
+    let code = pre_authorized_code(&state).await;
+
+    let json = serde_json::json!({ "pre-authorized_code": code }).to_string();
+    let (status, _) = send(router.clone(), post("/token", JSON, json)).await;
+    assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+    let (status, body) = send(router, post("/token", FORM, token_form(code))).await;
+    assert_eq!(status, StatusCode::OK);
+    let token: TokenResponse = serde_json::from_slice(&body).expect("should be token response");
+    assert!(!token.access_token.is_empty());
+}
+
+// The credential endpoint requires the access token as a bearer token, and
+// issues the credential for a request encoded by the wallet.
+#[tokio::test]
+async fn credential_bearer_token() {
+    let (state, router) = app();
+    let code = pre_authorized_code(&state).await;
+    let (_, body) = send(router.clone(), post("/token", FORM, token_form(code))).await;
+    let token: serde_json::Value = serde_json::from_slice(&body).expect("should be token response");
+    let credential_request = credential_body(&token);
+
+    let (status, _) =
+        send(router.clone(), post("/credential", JSON, credential_request.clone())).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let mut request = post("/credential", JSON, credential_request);
+    let access_token = token["access_token"].as_str().expect("should have an access token");
+    let bearer = format!("Bearer {access_token}");
+    request.headers_mut().insert(header::AUTHORIZATION, bearer.parse().expect("valid header"));
+    let (status, body) = send(router, request).await;
+    assert_eq!(status, StatusCode::OK);
+    let response: serde_json::Value =
+        serde_json::from_slice(&body).expect("should be credential response");
+    assert!(response["credential"].is_string(), "should issue a credential: {response}");
+}
+
+// The verifier's response endpoint accepts form fields and rejects JSON.
+#[tokio::test]
+async fn presentation_form_encoded() {
+    let (_, router) = app();
+    let json = serde_json::json!({ "state": "unknown" }).to_string();
+    let (status, _) = send(router.clone(), post("/post", JSON, json)).await;
+    assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+    let (status, _) = send(router, post("/post", FORM, "state=unknown")).await;
+    assert_ne!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+}
+
+// Status updates can only be published with the service's status token.
+#[tokio::test]
+async fn status_publish_token() {
+    let (_, router) = app();
+    let update = serde_json::json!({
+        "holder": "did:key:z6Mk",
+        "status": "revoked",
+        "credential_id": "urn:uuid:1",
+    });
+
+    let (status, _) = send(router.clone(), post("/status", JSON, update.to_string())).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(publish(router.clone(), "wrong", &update).await, StatusCode::UNAUTHORIZED);
+    assert_eq!(publish(router.clone(), STATUS_TOKEN, &update).await, StatusCode::ACCEPTED);
+
+    let unknown = serde_json::json!({ "holder": "did:key:z6Mk", "status": "lost" });
+    assert_eq!(publish(router, STATUS_TOKEN, &unknown).await, StatusCode::BAD_REQUEST);
+}
+
+// The status event stream requires a token signed by the holder for this
+// issuer, and only sends the holder's updates. The holder's token is the one
+// the wallet makes from this service's metadata.
+#[tokio::test]
+async fn status_events_holder() {
+    let (state, router) = app();
+    let get = |uri: String| Request::get(uri).body(Body::empty()).expect("should build request");
+    let (_, metadata) =
+        send(router.clone(), get("/.well-known/openid-credential-issuer".into())).await;
+    let metadata = serde_json::from_slice(&metadata).expect("should be metadata");
+    let now = chrono::Utc::now().timestamp();
+    let url = vercre_wallet::testing::status_events(&state.external_address, &metadata, now)
+        .expect("should make stream URL");
+    let token = url
+        .strip_prefix(&format!("{}/events?token=", state.external_address))
+        .expect("should be this service's stream")
+        .to_string();
+    let payload = token.split('.').nth(1).expect("should be a JWS");
+    let payload = Base64UrlUnpadded::decode_vec(payload).expect("should be base64url");
+    let claims: serde_json::Value = serde_json::from_slice(&payload).expect("should be JSON");
+    let holder = claims["iss"].as_str().expect("should name the holder").to_string();
+
+    let (other, _) = holder_token(2, "http://localhost:8080");
+    for (holder, credential_id) in [(&other, "urn:uuid:other"), (&holder, "urn:uuid:mine")] {
+        let update = serde_json::json!({
+            "holder": holder,
+            "status": "suspended",
+            "credential_id": credential_id,
+        });
+        assert_eq!(publish(router.clone(), STATUS_TOKEN, &update).await, StatusCode::ACCEPTED);
+    }
+
+    let (_, misaddressed) = holder_token(1, "https://other.example");
+    let (status, _) = send(router.clone(), get(format!("/events?token={misaddressed}"))).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (_, other_token) = holder_token(2, "http://localhost:8080");
+    let (signing_input, _) = token.rsplit_once('.').expect("should be a JWS");
+    let (_, signature) = other_token.rsplit_once('.').expect("should be a JWS");
+    let forged = format!("{signing_input}.{signature}");
+    let (status, _) = send(router.clone(), get(format!("/events?token={forged}"))).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let response =
+        router.oneshot(get(format!("/events?token={token}"))).await.expect("should respond");
+    assert_eq!(response.status(), StatusCode::OK);
+    let mut body = response.into_body();
+    let frame = body.frame().await.expect("should send an event").expect("should read event");
+    let event = String::from_utf8(frame.into_data().expect("should be data").to_vec())
+        .expect("should be text");
+    assert!(event.starts_with("event: status\n"));
+    assert!(event.contains("\"credential_id\":\"urn:uuid:mine\""));
+    assert!(!event.contains("urn:uuid:other"));
+}
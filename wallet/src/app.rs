@@ -1,18 +1,36 @@
 //! This module contains the core application fabric for the wallet, including
 //! the model, events, and effects that drive the application.
 
+use std::collections::{HashMap, HashSet};
+
+use chrono::{DateTime, Utc};
+use crux_core::compose::Compose;
 use crux_core::render::Render;
-use crux_http::{Http, HttpError};
+use crux_http::Http;
 use crux_kv::KeyValue;
 use serde::{Deserialize, Serialize};
 use vercre_holder::credential::{Credential, ImageData};
-use vercre_holder::{Issuer, TokenResponse};
+use vercre_holder::provider::Issuer as _;
+use vercre_holder::TokenResponse;
 
+use crate::capabilities::clock::{Clock, ClockError};
 use crate::capabilities::key::KeyStore;
-use crate::capabilities::sse::ServerSentEvents;
-use crate::capabilities::store::{Catalog, Store, StoreEntry, StoreError};
-use crate::model::Model;
+use crate::capabilities::sse::{ServerSentEvents, SseError};
+use crate::capabilities::store::{Catalog, Store, StoreError};
+use crate::model::credential::{CredentialChange, StoredCredentials};
+use crate::model::error::WalletError;
+use crate::model::locale::LocalePreference;
+use crate::model::query::{CredentialFilter, CredentialSort};
+use crate::model::status::{CredentialStatus, StatusUpdate, STATUS_EVENT};
+use crate::model::{IssuanceState, IssuanceStep, Model};
+use crate::provider::issuance::{IssuedCredential, IssuerMetadata};
+use crate::provider::keys::HolderKeys;
+use crate::provider::nonce::NonceResponse;
+use crate::provider::proof::CredentialProof;
+use crate::provider::response::ServiceError;
 use crate::provider::Provider;
+use crate::view::credential::CredentialView;
+use crate::view::issuance::IssuanceView;
 use crate::view::ViewModel;
 
 /// Aspect of the application.
@@ -28,6 +46,9 @@ pub enum Aspect {
     /// Display of a single credential.
     CredentialDetail,
 
+    /// Review and clean up the wallet's holder keys.
+    KeyManagement,
+
     /// Trigger a credential issuance using an offer QR code.
     IssuanceScan,
 
@@ -49,10 +70,22 @@ pub enum Event {
     #[serde(skip)]
     Error(String),
 
+    /// Event emitted by the shell when the user dismisses an error. Returns to
+    /// the aspect that was active when the error occurred.
+    DismissError,
+
+    /// Event emitted by the shell when the user wants to retry the action that
+    /// failed. Returns to the aspect that was active when the error occurred.
+    RetryLastAction,
+
     //--- Credential events ----------------------------------------------------
     /// Event emitted by the shell when the app first loads.
     Ready,
 
+    /// Event emitted by the shell when the app first loads and whenever the
+    /// user's locale or time zone changes.
+    SetLocale(LocalePreference),
+
     /// Event emitted by the shell to select a credential from the list of
     /// stored credentials for detailed display.
     SelectCredential(String),
@@ -60,10 +93,74 @@ pub enum Event {
     /// Event emitted by the shell to delete a credential from the wallet.
     DeleteCredential(String),
 
-    /// Event emitted by the core when the store capability has loaded
-    /// credentials.
+    /// Event emitted by the shell when the user changes the text to search
+    /// the credential list for.
+    SearchCredentials(String),
+
+    /// Event emitted by the shell when the user changes the criteria the
+    /// credential list is filtered by.
+    FilterCredentials(CredentialFilter),
+
+    /// Event emitted by the shell when the user changes the order of the
+    /// credential list.
+    SortCredentials { sort: CredentialSort, descending: bool },
+
+    /// Event emitted by the shell to clear the search, filter and sort of the
+    /// credential list.
+    ClearCredentialQuery,
+
+    /// Event emitted by the core when credentials have been loaded and
+    /// decrypted from the store.
+    #[serde(skip)]
+    CredentialsLoaded(Result<StoredCredentials, String>),
+
+    /// Event emitted by the core when the store reports a change to the
+    /// stored credentials.
+    #[serde(skip)]
+    CredentialChanged(Result<CredentialChange, String>),
+
+    /// Event emitted by the core when an issuer pushes a status update for a
+    /// credential.
+    #[serde(skip)]
+    StatusUpdated(Result<StatusUpdate, SseError>),
+
+    /// Event emitted by the core when the URL of an issuer's status event
+    /// stream has been found.
+    #[serde(skip)]
+    StatusEventsFound { issuer: String, url: Result<String, String> },
+
+    /// Event emitted by the core when the status of stored credentials has
+    /// been checked against their issuers' status lists.
     #[serde(skip)]
-    CredentialsLoaded(Result<Vec<StoreEntry>, StoreError>),
+    StatusChecked(HashMap<String, CredentialStatus>),
+
+    /// Event emitted by the core when the shell has reported the current time,
+    /// used to determine whether credentials are within their validity period.
+    #[serde(skip)]
+    TimeUpdated(Result<DateTime<Utc>, ClockError>),
+
+    /// Event emitted by the shell when the user chooses whether credentials
+    /// that are expired or not yet valid may be presented.
+    AllowExpiredPresentation(bool),
+
+    /// Event emitted by the shell to discard a stored entry that could not be
+    /// read and was quarantined.
+    DiscardQuarantined(String),
+
+    /// Event emitted by the shell to refresh the display metadata of stored
+    /// credentials from their issuers.
+    RefreshDisplayMetadata,
+
+    /// Event emitted by the core when the display metadata of stored
+    /// credentials has been refreshed. Refreshed credentials are reported by
+    /// the store as they are saved.
+    #[serde(skip)]
+    DisplayMetadataRefreshed(Result<(), String>),
+
+    /// Event emitted by the core when the store capability has deleted a
+    /// quarantined entry.
+    #[serde(skip)]
+    QuarantineDiscarded(Result<(), StoreError>),
 
     /// Event emitted by the core when the store capability has stored a
     /// credential.
@@ -75,6 +172,32 @@ pub enum Event {
     #[serde(skip)]
     CredentialDeleted(Result<(), StoreError>),
 
+    //--- Key events -----------------------------------------------------------
+    /// Event emitted by the shell to rotate the wallet's holder keys.
+    /// Credentials bound to the superseded keys will need to be re-issued.
+    RotateKeys,
+
+    /// Event emitted by the shell to re-seal the stored credentials with a
+    /// fresh data key and delete the data keys it supersedes.
+    RotateDataKey,
+
+    /// Event emitted by the core when the data key has been rotated.
+    #[serde(skip)]
+    DataKeyRotated(Result<(), String>),
+
+    /// Event emitted by the shell when the user wants to review the wallet's
+    /// holder keys.
+    ManageKeys,
+
+    /// Event emitted by the shell to remove holder keys that no stored
+    /// credential is bound to.
+    RemoveOrphanedKeys,
+
+    /// Event emitted by the core when the holder keys have been loaded or
+    /// updated.
+    #[serde(skip)]
+    KeysUpdated(Result<HolderKeys, String>),
+
     //--- Issuance events ------------------------------------------------------
     /// Event emitted by the shell when the user wants to scan an issuance offer
     /// QR code.
@@ -85,17 +208,17 @@ pub enum Event {
 
     /// Event emitted by the core when issuer metadata has been received.
     #[serde(skip)]
-    IssuanceIssuer(Result<Issuer, HttpError>),
+    IssuanceIssuer(Result<IssuerMetadata, String>),
 
     /// Event emitted by the core when an offered credential's logo has been
     /// fetched.
     #[serde(skip)]
-    IssuanceLogo(Result<(String, ImageData), HttpError>),
+    IssuanceLogo(Result<(String, ImageData), String>),
 
     /// Event emitted by the core when an offered credential's background image
     /// has been fetched.
     #[serde(skip)]
-    IssuanceBackground(Result<(String, ImageData), HttpError>),
+    IssuanceBackground(Result<(String, ImageData), String>),
 
     /// Event emitted by the shell when the user has accepted an issuance offer.
     IssuanceAccepted,
@@ -104,30 +227,83 @@ pub enum Event {
     IssuancePin(String),
 
     /// Event emitted by the core when an access token has been received.
+    /// The issuer's rejection of the token request is reported as
+    /// `IssuanceRejected` instead.
+    #[serde(skip)]
+    IssuanceToken(Result<TokenResponse, String>),
+
+    /// Event emitted by the core when a proof has been created for the
+    /// credential of the given configuration. There is no proof if the issuer
+    /// does not require one.
+    #[serde(skip)]
+    IssuanceProof(Result<(String, Option<CredentialProof>), String>),
+
+    /// Event emitted by the core when the issuer's service has rejected an
+    /// issuance request.
     #[serde(skip)]
-    IssuanceToken(Result<TokenResponse, HttpError>),
+    IssuanceRejected(ServiceError),
 
-    /// Event emitted by the core when a proof has been created.
+    /// Event emitted by the core when a nonce has been fetched from the
+    /// issuer's nonce endpoint.
     #[serde(skip)]
-    IssuanceProof(Result<String, String>),
+    IssuanceNonce(Result<NonceResponse, String>),
 
-    /// Event emitted by the core when a credential has been received.
+    /// Event emitted by the core when a credential has been received. The
+    /// issuer's rejection of the credential request is reported as
+    /// `IssuanceRejected` instead.
     #[serde(skip)]
-    IssuanceCredential(Result<(String, Credential), HttpError>),
+    IssuanceCredential(Result<IssuedCredential, String>),
 
-    /// Event emitted by the core when a credential has been stored.
+    /// Event emitted by the core when a credential has been verified and
+    /// stored.
     #[serde(skip)]
-    IssuanceStored(Result<String, StoreError>),
+    IssuanceStored(Result<String, String>),
 
     /// Event emitted by the shell to cancel an issuance.
     CancelIssuance,
 }
 
+impl Event {
+    /// Whether the event is a shell request that starts work which can fail
+    /// and can safely be repeated, so the user can retry it after an error.
+    ///
+    /// Key rotation and the steps of an issuance are not repeated: rotating
+    /// again would retire the new keys, and an issuance recovers from its own
+    /// failures.
+    fn is_retryable_action(&self) -> bool {
+        matches!(
+            self,
+            Event::Ready
+                | Event::DeleteCredential(_)
+                | Event::DiscardQuarantined(_)
+                | Event::RefreshDisplayMetadata
+                | Event::RemoveOrphanedKeys
+                | Event::IssuanceOffer(_)
+        )
+    }
+
+    /// Whether the event reports the outcome of the given retryable action.
+    fn completes(&self, action: &Event) -> bool {
+        matches!(
+            (self, action),
+            (Event::CredentialsLoaded(_), Event::Ready)
+                | (Event::CredentialDeleted(_), Event::DeleteCredential(_))
+                | (Event::QuarantineDiscarded(_), Event::DiscardQuarantined(_))
+                | (Event::DisplayMetadataRefreshed(_), Event::RefreshDisplayMetadata)
+                | (Event::KeysUpdated(_), Event::RemoveOrphanedKeys)
+                | (Event::IssuanceIssuer(_), Event::IssuanceOffer(_))
+        )
+    }
+}
+
 /// Set of capabilities available to the application.
 #[cfg_attr(feature = "typegen", derive(crux_core::macros::Export))]
 #[derive(crux_core::macros::Effect)]
 pub struct Capabilities {
+    #[effect(skip)]
+    pub compose: Compose<Event>,
     pub render: Render<Event>,
+    pub clock: Clock<Event>,
     pub http: Http<Event>,
     pub key_store: KeyStore<Event>,
     pub kv: KeyValue<Event>,
@@ -146,19 +322,61 @@ impl crux_core::App for App {
 
     fn update(&self, msg: Self::Event, model: &mut Self::Model, caps: &Self::Capabilities) {
         let provider = Provider::new(
+            caps.clock.clone(),
             caps.http.clone(),
             caps.key_store.clone(),
             caps.kv.clone(),
             caps.store.clone(),
-        );
+        )
+        .including_expired(model.allow_expired)
+        .with_locale(&model.locale);
+        // Once the outcome of the last action is known it is only kept, with
+        // its error, if it failed.
+        let completed = model.last_action.take_if(|action| msg.completes(action));
+        if msg.is_retryable_action() {
+            model.last_action = Some(msg.clone());
+        }
+        let held = issuance_token(model).map(|token| (token, provider.clone()));
         match msg {
             Event::Error(e) => {
-                model.error(e);
+                model.error(WalletError::protocol(e));
                 caps.render.render();
             }
+            Event::DismissError => {
+                model.dismiss_error();
+                caps.render.render();
+            }
+            Event::RetryLastAction => {
+                let action = model.retry_last_action();
+                caps.render.render();
+                if let Some(action) = action {
+                    self.update(action, model, caps);
+                }
+            }
             Event::Ready => {
                 model.ready();
-                caps.store.list("credential", Event::CredentialsLoaded);
+                caps.clock.now(Event::TimeUpdated);
+                load_credentials(&caps.compose, provider.clone());
+                if !model.watching {
+                    model.watching = true;
+                    let watcher = provider.clone();
+                    caps.compose.spawn(|ctx| async move {
+                        watcher
+                            .watch_credentials(|change| {
+                                let result = change.map_err(|e| e.to_string());
+                                ctx.update_app(Event::CredentialChanged(result));
+                            })
+                            .await;
+                    });
+                }
+                caps.compose.spawn(|ctx| async move {
+                    let result = provider.holder_keys().await.map_err(|e| e.to_string());
+                    ctx.update_app(Event::KeysUpdated(result));
+                });
+                caps.render.render();
+            }
+            Event::SetLocale(locale) => {
+                model.set_locale(locale);
                 caps.render.render();
             }
             Event::SelectCredential(id) => {
@@ -168,8 +386,78 @@ impl crux_core::App for App {
             Event::DeleteCredential(id) => {
                 caps.store.delete("credential", id, Event::CredentialDeleted);
             }
-            Event::CredentialsLoaded(Ok(entries)) => {
-                model.credentials_loaded(entries);
+            Event::SearchCredentials(search) => {
+                model.search_credentials(search);
+                caps.render.render();
+            }
+            Event::FilterCredentials(filter) => {
+                model.filter_credentials(filter);
+                caps.render.render();
+            }
+            Event::SortCredentials { sort, descending } => {
+                model.sort_credentials(sort, descending);
+                caps.render.render();
+            }
+            Event::ClearCredentialQuery => {
+                model.clear_credential_query();
+                caps.render.render();
+            }
+            Event::DiscardQuarantined(id) => {
+                model.discard_quarantined(&id);
+                caps.store.delete(Catalog::Quarantine.to_string(), id, Event::QuarantineDiscarded);
+                caps.render.render();
+            }
+            Event::CredentialChanged(Ok(change)) => {
+                if let CredentialChange::Saved(record) = &change {
+                    check_status(&caps.compose, provider, vec![record.credential.clone()]);
+                }
+                model.credential_changed(change);
+                subscribe_status(model, &caps.compose, &caps.sse, &provider);
+                caps.render.render();
+            }
+            Event::StatusEventsFound { issuer, url } => {
+                // The issuer's credentials may have been removed while the
+                // URL was found. Without a URL the issuer's credentials are
+                // still checked against its status lists.
+                let stream = model.status_streams.get_mut(&issuer);
+                if let (Some(stream @ None), Ok(url)) = (stream, url) {
+                    *stream = Some(caps.sse.get_json_events(url, &[STATUS_EVENT], |result| {
+                        Event::StatusUpdated(result.map(|message| message.data))
+                    }));
+                }
+            }
+            Event::StatusUpdated(Ok(update)) => {
+                model.status_updated(update);
+                caps.render.render();
+            }
+            Event::TimeUpdated(Ok(now)) => {
+                model.time_updated(now);
+                caps.render.render();
+            }
+            Event::AllowExpiredPresentation(allow) => {
+                model.allow_expired_presentation(allow);
+            }
+            Event::StatusChecked(statuses) => {
+                model.status_checked(statuses);
+                caps.render.render();
+            }
+            // Status updates are advisory. The stream reconnects after a
+            // connection error, a malformed update is skipped and an issuer
+            // that refuses the stream is not asked again.
+            Event::StatusUpdated(Err(_)) => {}
+            Event::RefreshDisplayMetadata => {
+                caps.compose.spawn(|ctx| async move {
+                    let result = provider.refresh_display().await.map_err(|e| e.to_string());
+                    ctx.update_app(Event::DisplayMetadataRefreshed(result));
+                });
+            }
+            Event::DisplayMetadataRefreshed(Ok(())) => {}
+            Event::CredentialsLoaded(Ok(stored)) => {
+                let credentials = stored.records.iter().map(|r| r.credential.clone()).collect();
+                check_status(&caps.compose, provider, credentials);
+                caps.clock.now(Event::TimeUpdated);
+                model.credentials_loaded(stored);
+                subscribe_status(model, &caps.compose, &caps.sse, &provider);
 
                 // TODO: Remove this try-out code
                 // if entries.len() < 1 {
@@ -184,12 +472,43 @@ impl crux_core::App for App {
 
                 caps.render.render();
             }
-            Event::CredentialStored(Ok(())) => {
-                caps.store.list(Catalog::Credential.to_string(), Event::CredentialsLoaded);
-            }
+            // The credential list is updated as the store reports changes.
+            Event::CredentialStored(Ok(())) | Event::QuarantineDiscarded(Ok(())) => {}
             Event::CredentialDeleted(Ok(())) => {
                 model.delete_credential();
-                caps.store.list(Catalog::Credential.to_string(), Event::CredentialsLoaded);
+                caps.compose.spawn(|ctx| async move {
+                    let result = provider.prune_keys().await.map_err(|e| e.to_string());
+                    ctx.update_app(Event::KeysUpdated(result));
+                });
+                caps.render.render();
+            }
+            Event::RotateKeys => {
+                caps.compose.spawn(|ctx| async move {
+                    let result = provider.rotate_keys().await.map_err(|e| e.to_string());
+                    ctx.update_app(Event::KeysUpdated(result));
+                });
+            }
+            Event::RotateDataKey => {
+                caps.compose.spawn(|ctx| async move {
+                    let result = provider.rotate_data_key().await.map_err(|e| e.to_string());
+                    ctx.update_app(Event::DataKeyRotated(result));
+                });
+            }
+            // Re-sealed credentials are reported by the store as they are
+            // saved.
+            Event::DataKeyRotated(Ok(())) => {}
+            Event::ManageKeys => {
+                model.manage_keys();
+                caps.render.render();
+            }
+            Event::RemoveOrphanedKeys => {
+                caps.compose.spawn(|ctx| async move {
+                    let result = provider.remove_orphaned_keys().await.map_err(|e| e.to_string());
+                    ctx.update_app(Event::KeysUpdated(result));
+                });
+            }
+            Event::KeysUpdated(Ok(keys)) => {
+                model.keys_updated(keys);
                 caps.render.render();
             }
             Event::ScanIssuanceOffer => {
@@ -198,69 +517,345 @@ impl crux_core::App for App {
             }
             Event::IssuanceOffer(encoded_offer) => {
                 model.issuance_offer(&encoded_offer);
+                if let Some(IssuanceState::Offered { offer, .. }) = &model.issuance {
+                    let credential_issuer = offer.credential_issuer.clone();
+                    caps.compose.spawn(|ctx| async move {
+                        let result = provider
+                            .offer_metadata(&credential_issuer)
+                            .await
+                            .map_err(|e| e.to_string());
+                        ctx.update_app(Event::IssuanceIssuer(result));
+                    });
+                }
+                caps.render.render();
             }
-            Event::IssuanceIssuer(Ok(_issuer)) => {
-                todo!();
+            Event::IssuanceIssuer(Ok(issuer)) => {
+                model.issuance_issuer(issuer);
+                fetch_offer_images(&caps.compose, &provider, model);
+                caps.render.render();
             }
-            Event::IssuanceLogo(Ok((_config_id, _image))) => {
-                todo!();
+            Event::IssuanceLogo(Ok((config_id, image))) => {
+                model.issuance_logo(&config_id, image);
+                caps.render.render();
             }
-            Event::IssuanceBackground(Ok((_config_id, _image))) => {
-                todo!();
+            Event::IssuanceBackground(Ok((config_id, image))) => {
+                model.issuance_background(&config_id, image);
+                caps.render.render();
             }
+            // An offered credential is shown without an image that cannot be
+            // fetched.
+            Event::IssuanceLogo(Err(_)) | Event::IssuanceBackground(Err(_)) => {}
             Event::IssuanceAccepted => {
-                todo!();
+                let next = model.issuance_accepted();
+                self.issuance_next(next, &provider, model, caps);
+            }
+            Event::IssuancePin(pin) => {
+                let next = model.issuance_pin(pin);
+                self.issuance_next(next, &provider, model, caps);
             }
-            Event::IssuancePin(_pin) => {
-                todo!();
+            Event::IssuanceToken(Ok(token)) => {
+                let next = model.issuance_token(token);
+                self.issuance_next(next, &provider, model, caps);
             }
-            Event::IssuanceToken(Ok(_token)) => {
-                todo!();
+            Event::IssuanceProof(Ok((config_id, proof))) => {
+                let next = model.issuance_proof(config_id, proof);
+                self.issuance_next(next, &provider, model, caps);
             }
-            Event::IssuanceProof(Ok(_jwt)) => {
-                todo!();
+            Event::IssuanceCredential(Ok(issued)) => {
+                let next = model.issuance_credential(issued);
+                self.issuance_next(next, &provider, model, caps);
             }
-            Event::IssuanceCredential(Ok((_config_id, _credential))) => {
-                todo!();
+            Event::IssuanceStored(Ok(config_id)) => {
+                let next = model.issuance_stored(&config_id);
+                self.issuance_next(next, &provider, model, caps);
             }
-            Event::IssuanceStored(Ok(_config_id)) => {
-                todo!();
+            Event::IssuanceRejected(error) => {
+                let next = model.issuance_rejected(&error);
+                self.issuance_next(next, &provider, model, caps);
+            }
+            Event::IssuanceNonce(Ok(response)) => {
+                let next = model.issuance_nonce(&response);
+                self.issuance_next(next, &provider, model, caps);
             }
             Event::CancelIssuance => {
                 model.cancel_issuance(&provider);
                 caps.render.render();
             }
             // Store errors
-            Event::CredentialsLoaded(Err(error))
-            | Event::CredentialStored(Err(error))
+            Event::CredentialStored(Err(error))
             | Event::CredentialDeleted(Err(error))
-            | Event::IssuanceStored(Err(error)) => {
-                model.error(error.to_string());
+            | Event::QuarantineDiscarded(Err(error)) => {
+                model.failed(completed, error.into());
+                caps.render.render();
+            }
+            // Clock errors
+            Event::TimeUpdated(Err(error)) => {
+                model.error(error.into());
+                caps.render.render();
+            }
+            // Error as string
+            Event::CredentialsLoaded(Err(error)) | Event::CredentialChanged(Err(error)) => {
+                model.failed(completed, WalletError::storage(error));
                 caps.render.render();
             }
-            // HTTP errors
             Event::IssuanceIssuer(Err(error))
-            | Event::IssuanceLogo(Err(error))
-            | Event::IssuanceBackground(Err(error))
             | Event::IssuanceToken(Err(error))
-            | Event::IssuanceCredential(Err(error)) => {
-                model.error(error.to_string());
+            | Event::IssuanceNonce(Err(error))
+            | Event::DisplayMetadataRefreshed(Err(error)) => {
+                model.failed(completed, WalletError::network(error));
                 caps.render.render();
             }
-            // Error as string
-            Event::IssuanceProof(Err(error)) => {
-                model.error(error);
+            // A credential that cannot be read is reported as a protocol
+            // error, and one that fails verification as a crypto error.
+            Event::IssuanceCredential(Err(error)) => {
+                model.error(WalletError::protocol(error));
+                caps.render.render();
+            }
+            Event::KeysUpdated(Err(error))
+            | Event::DataKeyRotated(Err(error))
+            | Event::IssuanceProof(Err(error))
+            | Event::IssuanceStored(Err(error)) => {
+                model.failed(completed, WalletError::crypto(error));
                 caps.render.render();
             }
         }
+
+        // An access token the issuance no longer holds, because the issuance
+        // ended or the token was replaced, is released with its DPoP binding.
+        let current = issuance_token(model);
+        if let Some((released, provider)) =
+            held.filter(|(token, _)| current.as_ref() != Some(token))
+        {
+            caps.compose.spawn(|_| async move {
+                // A binding that cannot be released is deleted when the token
+                // expires.
+                provider.release_token(&released).await.ok();
+            });
+        }
     }
 
     fn view(&self, model: &Self::Model) -> Self::ViewModel {
         Self::ViewModel {
             active_view: model.active_view.clone(),
-            credential_view: model.credential.clone().into(),
-            issuance_view: model.issuance.clone().into(),
+            credential_view: CredentialView::new(model.credential.clone(), &model.locale),
+            issuance_view: IssuanceView::new(model.issuance.as_ref(), &model.locale),
+            key_view: (&model.credential).into(),
             error: model.error.clone().unwrap_or_default(),
         }
     }
 }
+
+impl App {
+    /// Render the issuance and carry out its next step, if it has one.
+    fn issuance_next(
+        &self, next: Option<IssuanceStep>, provider: &Provider<Event>, model: &mut Model,
+        caps: &Capabilities,
+    ) {
+        caps.render.render();
+        if let Some(next) = next {
+            self.issuance_step(next, provider, model, caps);
+        }
+    }
+
+    /// Carry out the next step of an issuance.
+    fn issuance_step(
+        &self, step: IssuanceStep, provider: &Provider<Event>, model: &mut Model,
+        caps: &Capabilities,
+    ) {
+        let Some(state) = &model.issuance else {
+            return;
+        };
+        let Some(issuance) = state.issuance().cloned() else {
+            return;
+        };
+        let access_token = state.token().map(|token| token.access_token.clone());
+        // Proofs are signed with, and credentials bound to, the issuer's
+        // pairwise holder key.
+        let provider = provider.for_issuer(issuance.credential_issuer());
+        match step {
+            IssuanceStep::RequestToken => {
+                caps.compose.spawn(|ctx| async move {
+                    let result = provider
+                        .pre_authorized_token(
+                            issuance.credential_issuer(),
+                            &issuance.metadata,
+                            &issuance.grant.pre_authorized_code,
+                            issuance.pin.as_deref(),
+                        )
+                        .await;
+                    ctx.update_app(issuance_event(result, Event::IssuanceToken));
+                });
+            }
+            IssuanceStep::FetchNonce(endpoint) => {
+                caps.compose.spawn(|ctx| async move {
+                    let result = provider.nonce(&endpoint).await;
+                    ctx.update_app(issuance_event(result, Event::IssuanceNonce));
+                });
+            }
+            IssuanceStep::Prove { config_id, nonce } => {
+                let Some(offered) = issuance.offerred.get(&config_id).cloned() else {
+                    return;
+                };
+                caps.compose.spawn(|ctx| async move {
+                    let result = provider
+                        .credential_proof(issuance.credential_issuer(), &offered.config, nonce)
+                        .await
+                        .map(|proof| (config_id, proof))
+                        .map_err(|e| e.to_string());
+                    ctx.update_app(Event::IssuanceProof(result));
+                });
+            }
+            IssuanceStep::RequestCredential { config_id, proof } => {
+                let Some(access_token) = access_token else {
+                    return;
+                };
+                caps.compose.spawn(|ctx| async move {
+                    let result = provider
+                        .request_credential(
+                            issuance.credential_issuer(),
+                            &issuance.metadata,
+                            &access_token,
+                            &config_id,
+                            proof.as_ref(),
+                        )
+                        .await;
+                    ctx.update_app(issuance_event(result, Event::IssuanceCredential));
+                });
+            }
+            IssuanceStep::Save {
+                config_id,
+                credential,
+            } => {
+                caps.compose.spawn(|ctx| async move {
+                    let result = provider
+                        .save_issued(&credential, &issuance.metadata, &config_id)
+                        .await
+                        .map(|()| config_id)
+                        .map_err(|e| e.to_string());
+                    ctx.update_app(Event::IssuanceStored(result));
+                });
+            }
+            IssuanceStep::Complete => {
+                model.issuance_complete();
+                caps.render.render();
+            }
+        }
+    }
+}
+
+/// The access token of the current issuance, if it has one.
+fn issuance_token(model: &Model) -> Option<String> {
+    let token = model.issuance.as_ref().and_then(IssuanceState::token);
+    token.map(|token| token.access_token.clone())
+}
+
+/// Turn the result of a request to an issuer into an event. A rejection by
+/// the issuer's service is reported as `IssuanceRejected`, so the issuance can
+/// recover from it, and any other failure with the given event.
+fn issuance_event<T>(
+    result: anyhow::Result<T>, event: impl FnOnce(Result<T, String>) -> Event,
+) -> Event {
+    match result {
+        Ok(value) => event(Ok(value)),
+        Err(e) => match e.downcast::<ServiceError>() {
+            Ok(error) => Event::IssuanceRejected(error),
+            Err(e) => event(Err(e.to_string())),
+        },
+    }
+}
+
+/// Fetch the logo and background images of the credentials offered.
+fn fetch_offer_images(compose: &Compose<Event>, provider: &Provider<Event>, model: &Model) {
+    let Some(issuance) = model.issuance.as_ref().and_then(IssuanceState::issuance) else {
+        return;
+    };
+    for (config_id, offered) in &issuance.offerred {
+        if let Some(uri) = offered.logo_uri(&model.locale) {
+            let (provider, config_id) = (provider.clone(), config_id.clone());
+            compose.spawn(|ctx| async move {
+                let result = provider.image(&uri).await.map_err(|e| e.to_string());
+                ctx.update_app(Event::IssuanceLogo(result.map(|image| (config_id, image))));
+            });
+        }
+        if let Some(uri) = offered.background_uri(&model.locale) {
+            let (provider, config_id) = (provider.clone(), config_id.clone());
+            compose.spawn(|ctx| async move {
+                let result = provider.image(&uri).await.map_err(|e| e.to_string());
+                ctx.update_app(Event::IssuanceBackground(result.map(|image| (config_id, image))));
+            });
+        }
+    }
+}
+
+/// Load and decrypt the credentials stored in the wallet.
+fn load_credentials(compose: &Compose<Event>, provider: Provider<Event>) {
+    compose.spawn(|ctx| async move {
+        let result = provider.load_credentials().await.map_err(|e| e.to_string());
+        ctx.update_app(Event::CredentialsLoaded(result));
+    });
+}
+
+/// Check the status of credentials against their issuers' status lists.
+fn check_status(compose: &Compose<Event>, provider: Provider<Event>, credentials: Vec<Credential>) {
+    compose.spawn(|ctx| async move {
+        let statuses = provider.check_status(credentials).await;
+        ctx.update_app(Event::StatusChecked(statuses));
+    });
+}
+
+/// Subscribe to the status event streams of the issuers of stored credentials,
+/// closing the streams of issuers the wallet no longer holds credentials from.
+/// A stream is opened once its URL has been found from the issuer's metadata.
+fn subscribe_status(
+    model: &mut Model, compose: &Compose<Event>, sse: &ServerSentEvents<Event>,
+    provider: &Provider<Event>,
+) {
+    let issuers: HashSet<String> =
+        model.credential.credentials.iter().map(|r| r.credential.issuer.clone()).collect();
+    model.status_streams.retain(|issuer, handle| {
+        let held = issuers.contains(issuer);
+        if let (false, Some(handle)) = (held, handle) {
+            sse.close(handle);
+        }
+        held
+    });
+    for issuer in issuers {
+        if model.status_streams.contains_key(&issuer) {
+            continue;
+        }
+        model.status_streams.insert(issuer.clone(), None);
+        let provider = provider.clone();
+        compose.spawn(|ctx| async move {
+            let url = provider.status_events(&issuer).await.map_err(|e| e.to_string());
+            ctx.update_app(Event::StatusEventsFound { issuer, url });
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crux_core::testing::AppTester;
+
+    use super::*;
+
+    // Only an action that failed can be retried: not one that has completed
+    // or one that cannot safely be repeated.
+    #[test]
+    fn retry_failed_action() {
+        let app = AppTester::<App, Effect>::default();
+        let mut model = Model::default();
+
+        let _ = app.update(Event::RefreshDisplayMetadata, &mut model);
+        let _ = app.update(Event::DisplayMetadataRefreshed(Ok(())), &mut model);
+        model.error(WalletError::network("connection refused"));
+        assert_eq!(model.retry_last_action(), None);
+
+        let _ = app.update(Event::RefreshDisplayMetadata, &mut model);
+        let _ = app.update(Event::DisplayMetadataRefreshed(Err("timed out".into())), &mut model);
+        assert_eq!(model.retry_last_action(), Some(Event::RefreshDisplayMetadata));
+
+        let _ = app.update(Event::RotateKeys, &mut model);
+        let _ = app.update(Event::KeysUpdated(Err("no signing key".into())), &mut model);
+        assert_eq!(model.retry_last_action(), None);
+    }
+}
@@ -1,3 +1,4 @@
+pub mod clock;
 pub mod key;
 pub mod sse;
 pub mod store;
@@ -0,0 +1,118 @@
+//! # Clock Capability
+//!
+//! The core has no reliable access to the system clock on every platform, and
+//! tests need to control the time, so the current time is provided by the
+//! shell.
+use chrono::{DateTime, Utc};
+use crux_core::capability::{CapabilityContext, Operation};
+use crux_core::Capability;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// Errors that can be returned by the clock capability.
+#[derive(Clone, Debug, Deserialize, Serialize, Error, PartialEq, Eq)]
+pub enum ClockError {
+    /// The response from the shell capability was invalid.
+    #[error("invalid clock response {message}")]
+    InvalidResponse { message: String },
+}
+
+/// Supported operations for the clock capability.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub enum ClockOperation {
+    /// Get the current time.
+    Now,
+}
+
+/// The possible responses from the clock capability.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub enum ClockResponse {
+    /// The current time as seconds and nanoseconds since the Unix epoch.
+    Now { seconds: i64, nanos: u32 },
+}
+
+impl ClockResponse {
+    fn unwrap_now(self) -> Result<DateTime<Utc>, ClockError> {
+        match self {
+            ClockResponse::Now { seconds, nanos } => DateTime::from_timestamp(seconds, nanos)
+                .ok_or_else(|| ClockError::InvalidResponse {
+                    message: format!("time out of range: {seconds}s {nanos}ns"),
+                }),
+        }
+    }
+}
+
+impl Operation for ClockOperation {
+    type Output = ClockResponse;
+}
+
+/// Capability type for the clock.
+pub struct Clock<Ev> {
+    context: CapabilityContext<ClockOperation, Ev>,
+}
+
+impl<Ev> Capability<Ev> for Clock<Ev> {
+    type MappedSelf<MappedEv> = Clock<MappedEv>;
+    type Operation = ClockOperation;
+
+    fn map_event<F, NewEv>(&self, f: F) -> Self::MappedSelf<NewEv>
+    where
+        F: Fn(NewEv) -> Ev + Send + Sync + 'static,
+        Ev: 'static,
+        NewEv: 'static + Send,
+    {
+        Clock::new(self.context.map_event(f))
+    }
+
+    #[cfg(feature = "typegen")]
+    fn register_types(generator: &mut crux_core::typegen::TypeGen) -> crux_core::typegen::Result {
+        generator.register_type::<ClockResponse>()?;
+        generator.register_type::<ClockError>()?;
+        generator.register_type::<Self::Operation>()?;
+        generator.register_type::<<Self::Operation as Operation>::Output>()?;
+        Ok(())
+    }
+}
+
+impl<Ev> Clone for Clock<Ev> {
+    fn clone(&self) -> Self {
+        Self {
+            context: self.context.clone(),
+        }
+    }
+}
+
+impl<Ev> Clock<Ev>
+where
+    Ev: 'static,
+{
+    /// Create a new clock capability.
+    pub fn new(context: CapabilityContext<ClockOperation, Ev>) -> Self {
+        Self { context }
+    }
+
+    /// Get the current time and send an update event to the application.
+    pub fn now<F>(&self, make_event: F)
+    where
+        F: FnOnce(Result<DateTime<Utc>, ClockError>) -> Ev + Send + Sync + 'static,
+    {
+        self.context.spawn({
+            let context = self.context.clone();
+            async move {
+                let response = now(&context).await;
+                context.update_app(make_event(response))
+            }
+        });
+    }
+
+    /// Get the current time.
+    pub async fn now_async(&self) -> Result<DateTime<Utc>, ClockError> {
+        now(&self.context).await
+    }
+}
+
+async fn now<Ev: 'static>(
+    context: &CapabilityContext<ClockOperation, Ev>,
+) -> Result<DateTime<Utc>, ClockError> {
+    context.request_from_shell(ClockOperation::Now).await.unwrap_now()
+}
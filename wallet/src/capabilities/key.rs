@@ -52,16 +52,82 @@ impl From<Option<Vec<u8>>> for KeyStoreEntry {
     }
 }
 
+/// Metadata describing a key held in the key store. Key data is never
+/// included.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct KeyMetadata {
+    /// ID of the key.
+    pub id: String,
+
+    /// Purpose the key is held for.
+    pub purpose: String,
+
+    /// Algorithm the key is used with, as given when the key was set.
+    pub algorithm: String,
+
+    /// When the key was first set, as an RFC 3339 date-time.
+    pub created: String,
+}
+
+/// Metadata for a key in the key store, if the key exists.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub enum KeyMetadataEntry {
+    /// No key for the given ID and purpose.
+    None,
+
+    /// Metadata for the key.
+    Metadata(KeyMetadata),
+}
+
+impl From<KeyMetadataEntry> for Option<KeyMetadata> {
+    fn from(entry: KeyMetadataEntry) -> Option<KeyMetadata> {
+        match entry {
+            KeyMetadataEntry::None => None,
+            KeyMetadataEntry::Metadata(metadata) => Some(metadata),
+        }
+    }
+}
+
+/// An attestation of a key in the key store, if the platform can attest keys.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub enum KeyAttestation {
+    /// The platform cannot attest the key.
+    None,
+
+    /// A key attestation JWT.
+    Jwt(String),
+}
+
+impl From<KeyAttestation> for Option<String> {
+    fn from(attestation: KeyAttestation) -> Option<String> {
+        match attestation {
+            KeyAttestation::None => None,
+            KeyAttestation::Jwt(jwt) => Some(jwt),
+        }
+    }
+}
+
+impl From<Option<String>> for KeyAttestation {
+    fn from(jwt: Option<String>) -> KeyAttestation {
+        match jwt {
+            None => KeyAttestation::None,
+            Some(jwt) => KeyAttestation::Jwt(jwt),
+        }
+    }
+}
+
 /// Supported operations for the key store capability.
 #[derive(Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub enum KeyStoreOperation {
     /// Get a serialized private key from the key store.
     Get { id: String, purpose: String },
 
-    /// Set a serialized private key in the key store.
+    /// Set a serialized private key in the key store. The shell records the
+    /// algorithm and the time the key was first set as key metadata.
     Set {
         id: String,
         purpose: String,
+        algorithm: String,
         #[serde(with = "serde_bytes")]
         data: Vec<u8>,
     },
@@ -71,6 +137,16 @@ pub enum KeyStoreOperation {
 
     /// Generate a random secret suitable for key derivation.
     GenerateSecret { length: usize },
+
+    /// List the metadata of all keys in the key store.
+    List,
+
+    /// Get the metadata of a key in the key store.
+    Metadata { id: String, purpose: String },
+
+    /// Get the platform's attestation of a key in the key store, over the
+    /// issuer's nonce if it has given one.
+    Attest { id: String, purpose: String, nonce: Option<String> },
 }
 
 impl Debug for KeyStoreOperation {
@@ -79,11 +155,17 @@ impl Debug for KeyStoreOperation {
             KeyStoreOperation::Get { id, purpose } => {
                 f.debug_struct("Get").field("id", id).field("purpose", purpose).finish()
             }
-            KeyStoreOperation::Set { id, purpose, data } => {
+            KeyStoreOperation::Set {
+                id,
+                purpose,
+                algorithm,
+                data,
+            } => {
                 let body = format!("<binary data - {} bytes>", data.len());
                 f.debug_struct("Set")
                     .field("id", id)
                     .field("purpose", purpose)
+                    .field("algorithm", algorithm)
                     .field("data", &body)
                     .finish()
             }
@@ -93,6 +175,16 @@ impl Debug for KeyStoreOperation {
             KeyStoreOperation::GenerateSecret { length } => {
                 f.debug_struct("GenerateSecret").field("length", length).finish()
             }
+            KeyStoreOperation::List => f.debug_struct("List").finish(),
+            KeyStoreOperation::Metadata { id, purpose } => {
+                f.debug_struct("Metadata").field("id", id).field("purpose", purpose).finish()
+            }
+            KeyStoreOperation::Attest { id, purpose, nonce } => f
+                .debug_struct("Attest")
+                .field("id", id)
+                .field("purpose", purpose)
+                .field("nonce", nonce)
+                .finish(),
         }
     }
 }
@@ -111,6 +203,15 @@ pub enum KeyStoreResponse {
 
     /// A random secret suitable for key derivation.
     GeneratedSecret { secret: Vec<u8> },
+
+    /// The result of a list operation.
+    Listed { keys: Vec<KeyMetadata> },
+
+    /// The result of a metadata operation.
+    Described { metadata: KeyMetadataEntry },
+
+    /// The result of an attest operation.
+    Attested { attestation: KeyAttestation },
 }
 
 /// The result of an operation on the key store.
@@ -171,6 +272,42 @@ impl KeyStoreResult {
             }),
         }
     }
+
+    fn unwrap_list(self) -> Result<Vec<KeyMetadata>, KeyStoreError> {
+        match self {
+            KeyStoreResult::Ok {
+                response: KeyStoreResponse::Listed { keys },
+            } => Ok(keys),
+            KeyStoreResult::Err { error } => Err(error),
+            _ => Err(KeyStoreError::InvalidResponse {
+                message: "unexpected response for List operation".to_string(),
+            }),
+        }
+    }
+
+    fn unwrap_metadata(self) -> Result<Option<KeyMetadata>, KeyStoreError> {
+        match self {
+            KeyStoreResult::Ok {
+                response: KeyStoreResponse::Described { metadata },
+            } => Ok(metadata.into()),
+            KeyStoreResult::Err { error } => Err(error),
+            _ => Err(KeyStoreError::InvalidResponse {
+                message: "unexpected response for Metadata operation".to_string(),
+            }),
+        }
+    }
+
+    fn unwrap_attest(self) -> Result<Option<String>, KeyStoreError> {
+        match self {
+            KeyStoreResult::Ok {
+                response: KeyStoreResponse::Attested { attestation },
+            } => Ok(attestation.into()),
+            KeyStoreResult::Err { error } => Err(error),
+            _ => Err(KeyStoreError::InvalidResponse {
+                message: "unexpected response for Attest operation".to_string(),
+            }),
+        }
+    }
 }
 
 impl Operation for KeyStoreOperation {
@@ -200,6 +337,9 @@ impl<Ev> Capability<Ev> for KeyStore<Ev> {
         generator.register_type::<KeyStoreResponse>()?;
         generator.register_type::<KeyStoreError>()?;
         generator.register_type::<KeyStoreEntry>()?;
+        generator.register_type::<KeyMetadata>()?;
+        generator.register_type::<KeyMetadataEntry>()?;
+        generator.register_type::<KeyAttestation>()?;
         generator.register_type::<Self::Operation>()?;
         generator.register_type::<<Self::Operation as Operation>::Output>()?;
         Ok(())
@@ -251,14 +391,15 @@ where
     /// to the application.
     pub fn set<F>(
         &self, id: impl Into<String> + Send + 'static, purpose: impl Into<String> + Send + 'static,
-        data: impl Serialize + Send + 'static, make_event: F,
+        algorithm: impl Into<String> + Send + 'static, data: impl Serialize + Send + 'static,
+        make_event: F,
     ) where
         F: FnOnce(Result<(), KeyStoreError>) -> Ev + Send + Sync + 'static,
     {
         self.context.spawn({
             let context = self.context.clone();
             async move {
-                let response = set(&context, id, purpose, data).await;
+                let response = set(&context, id, purpose, algorithm, data).await;
                 context.update_app(make_event(response))
             }
         });
@@ -266,9 +407,10 @@ where
 
     /// Store a serialized private key in the key store.
     pub async fn set_async(
-        &self, id: impl Into<String>, purpose: impl Into<String>, data: impl Serialize,
+        &self, id: impl Into<String>, purpose: impl Into<String>, algorithm: impl Into<String>,
+        data: impl Serialize,
     ) -> Result<(), KeyStoreError> {
-        set(&self.context, id, purpose, data).await
+        set(&self.context, id, purpose, algorithm, data).await
     }
 
     /// Remove a serialized private key from the key store and send an update
@@ -314,6 +456,75 @@ where
     pub async fn generate_secret_async(&self, length: usize) -> Result<Vec<u8>, KeyStoreError> {
         generate_secret(&self.context, length).await
     }
+
+    /// List the metadata of all keys in the key store and send an update event
+    /// to the application.
+    pub fn list<F>(&self, make_event: F)
+    where
+        F: FnOnce(Result<Vec<KeyMetadata>, KeyStoreError>) -> Ev + Send + Sync + 'static,
+    {
+        self.context.spawn({
+            let context = self.context.clone();
+            async move {
+                let response = list(&context).await;
+                context.update_app(make_event(response))
+            }
+        });
+    }
+
+    /// List the metadata of all keys in the key store.
+    pub async fn list_async(&self) -> Result<Vec<KeyMetadata>, KeyStoreError> {
+        list(&self.context).await
+    }
+
+    /// Get the metadata of a key in the key store and send an update event to
+    /// the application.
+    pub fn metadata<F>(
+        &self, id: impl Into<String> + Send + 'static, purpose: impl Into<String> + Send + 'static,
+        make_event: F,
+    ) where
+        F: FnOnce(Result<Option<KeyMetadata>, KeyStoreError>) -> Ev + Send + Sync + 'static,
+    {
+        self.context.spawn({
+            let context = self.context.clone();
+            async move {
+                let response = metadata(&context, id, purpose).await;
+                context.update_app(make_event(response))
+            }
+        });
+    }
+
+    /// Get the metadata of a key in the key store.
+    pub async fn metadata_async(
+        &self, id: impl Into<String>, purpose: impl Into<String>,
+    ) -> Result<Option<KeyMetadata>, KeyStoreError> {
+        metadata(&self.context, id, purpose).await
+    }
+
+    /// Get the platform's attestation of a key in the key store and send an
+    /// update event to the application.
+    pub fn attest<F>(
+        &self, id: impl Into<String> + Send + 'static, purpose: impl Into<String> + Send + 'static,
+        nonce: Option<String>, make_event: F,
+    ) where
+        F: FnOnce(Result<Option<String>, KeyStoreError>) -> Ev + Send + Sync + 'static,
+    {
+        self.context.spawn({
+            let context = self.context.clone();
+            async move {
+                let response = attest(&context, id, purpose, nonce).await;
+                context.update_app(make_event(response))
+            }
+        });
+    }
+
+    /// Get the platform's attestation of a key in the key store, or `None` if
+    /// the platform cannot attest keys.
+    pub async fn attest_async(
+        &self, id: impl Into<String>, purpose: impl Into<String>, nonce: Option<String>,
+    ) -> Result<Option<String>, KeyStoreError> {
+        attest(&self.context, id, purpose, nonce).await
+    }
 }
 
 async fn get<Ev: 'static>(
@@ -331,7 +542,7 @@ async fn get<Ev: 'static>(
 
 async fn set<Ev: 'static>(
     context: &CapabilityContext<KeyStoreOperation, Ev>, id: impl Into<String>,
-    purpose: impl Into<String>, data: impl Serialize,
+    purpose: impl Into<String>, algorithm: impl Into<String>, data: impl Serialize,
 ) -> Result<(), KeyStoreError> {
     let bytes = serde_json::to_vec(&data).map_err(|e| KeyStoreError::InvalidRequest {
         message: format!("failed to serialize key: {}", e),
@@ -340,6 +551,7 @@ async fn set<Ev: 'static>(
         .request_from_shell(KeyStoreOperation::Set {
             id: id.into(),
             purpose: purpose.into(),
+            algorithm: algorithm.into(),
             data: bytes,
         })
         .await
@@ -366,4 +578,37 @@ async fn generate_secret<Ev: 'static>(
         .request_from_shell(KeyStoreOperation::GenerateSecret { length })
         .await
         .unwrap_generate_secret()
-}
\ No newline at end of file
+}
+
+async fn list<Ev: 'static>(
+    context: &CapabilityContext<KeyStoreOperation, Ev>,
+) -> Result<Vec<KeyMetadata>, KeyStoreError> {
+    context.request_from_shell(KeyStoreOperation::List).await.unwrap_list()
+}
+
+async fn metadata<Ev: 'static>(
+    context: &CapabilityContext<KeyStoreOperation, Ev>, id: impl Into<String>,
+    purpose: impl Into<String>,
+) -> Result<Option<KeyMetadata>, KeyStoreError> {
+    context
+        .request_from_shell(KeyStoreOperation::Metadata {
+            id: id.into(),
+            purpose: purpose.into(),
+        })
+        .await
+        .unwrap_metadata()
+}
+
+async fn attest<Ev: 'static>(
+    context: &CapabilityContext<KeyStoreOperation, Ev>, id: impl Into<String>,
+    purpose: impl Into<String>, nonce: Option<String>,
+) -> Result<Option<String>, KeyStoreError> {
+    context
+        .request_from_shell(KeyStoreOperation::Attest {
+            id: id.into(),
+            purpose: purpose.into(),
+            nonce,
+        })
+        .await
+        .unwrap_attest()
+}
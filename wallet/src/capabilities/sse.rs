@@ -1,26 +1,144 @@
+//! # Server-Sent Events Capability
+//!
+//! Streams events from a server using the shell's HTTP client. A dropped
+//! connection is re-established, resuming from the last event received, until
+//! the subscription is closed or the server refuses the stream. Repeated
+//! connection failures back off exponentially.
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
 
-use async_sse::{decode, Event};
+use async_sse::{decode, Event, Message};
 use async_std::io::Cursor;
+use crux_core::capability::{CapabilityContext, Operation};
 use futures::StreamExt;
-use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
 
-use crux_core::capability::{CapabilityContext, Operation};
+/// Delay before reconnecting, in milliseconds, until the server sets one with
+/// a `retry` field.
+const DEFAULT_RETRY: u64 = 3000;
+
+/// Longest delay before reconnecting after repeated failures, in
+/// milliseconds.
+const MAX_RETRY: u64 = 300_000;
+
+/// HTTP status a server responds with to tell the client to stop
+/// reconnecting.
+const NO_CONTENT: u16 = 204;
 
+/// Name of events sent without an `event` field.
+const MESSAGE: &str = "message";
+
+/// Source of subscription IDs.
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Requests to the shell.
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
-pub struct SseRequest {
-    pub url: String,
+pub enum SseRequest {
+    /// Open a connection to the URL and stream the response body back in
+    /// chunks. When reconnecting, the shell should wait `retry` milliseconds
+    /// before connecting and send a non-empty `last_event_id` in the
+    /// `Last-Event-ID` header.
+    Connect { id: String, url: String, last_event_id: String, retry: u64 },
+
+    /// Close the connection with the given ID.
+    Close { id: String },
 }
 
+/// Responses from the shell.
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
 pub enum SseResponse {
+    /// Part of the response body.
     Chunk(Vec<u8>),
+
+    /// The server ended the response.
     Done,
+
+    /// The connection could not be made or was dropped.
+    Error(String),
+
+    /// The server responded with a status other than 200 OK, or with a
+    /// content type other than `text/event-stream`. The connection is not
+    /// re-established.
+    Rejected(u16),
+
+    /// The connection was closed at the core's request.
+    Closed,
 }
 
 impl Operation for SseRequest {
     type Output = SseResponse;
 }
 
+/// Errors delivered to the application in place of an event.
+#[derive(Clone, Debug, Deserialize, Serialize, Error, PartialEq, Eq)]
+pub enum SseError {
+    /// The event data could not be decoded.
+    #[error("failed to decode event: {message}")]
+    Decode { message: String },
+
+    /// The event stream is malformed.
+    #[error("invalid event stream: {message}")]
+    Stream { message: String },
+
+    /// The connection failed. The capability will reconnect.
+    #[error("event stream connection failed: {message}")]
+    Connection { message: String },
+
+    /// The server refused the stream. The capability will not reconnect.
+    #[error("event stream refused with status {status}")]
+    Rejected { status: u16 },
+}
+
+/// An event received from the server.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct SseMessage<T> {
+    /// Event name. `message` if the server did not name the event.
+    pub name: String,
+
+    /// Event ID. Empty if the server did not give one.
+    pub id: String,
+
+    /// Decoded event data.
+    pub data: T,
+}
+
+/// Handle to an event stream subscription, used to close it.
+#[derive(Clone, Debug)]
+pub struct SseHandle {
+    id: String,
+    closed: Arc<AtomicBool>,
+}
+
+impl SseHandle {
+    fn new() -> Self {
+        Self {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed).to_string(),
+            closed: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    /// ID the shell knows the subscription's connection by.
+    #[must_use]
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Stop delivering events and reconnecting without asking the shell to
+    /// close the connection. Use `ServerSentEvents::close` unless the shell
+    /// has already dropped the connection.
+    pub fn cancel(&self) {
+        self.closed.store(true, Ordering::Release);
+    }
+
+    /// Determine if the subscription has been closed or cancelled.
+    #[must_use]
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Acquire)
+    }
+}
+
 #[derive(crux_core::macros::Capability)]
 pub struct ServerSentEvents<Ev> {
     context: CapabilityContext<SseRequest, Ev>,
@@ -34,36 +152,187 @@ where
         Self { context }
     }
 
-    pub fn get_json<F, T>(&self, url: impl AsRef<str>, make_event: F)
+    /// Subscribe to unnamed events from the URL, decoding the data of each as
+    /// JSON and sending an update event to the application.
+    pub fn get_json<F, T>(&self, url: impl AsRef<str>, make_event: F) -> SseHandle
     where
-        F: Fn(T) -> Ev + Clone + Send + 'static,
+        F: Fn(Result<T, SseError>) -> Ev + Send + Sync + 'static,
         T: DeserializeOwned,
     {
+        self.get_json_events(url, &[MESSAGE], move |result: Result<SseMessage<T>, _>| {
+            make_event(result.map(|message| message.data))
+        })
+    }
+
+    /// Subscribe to events with the given names from the URL, decoding the
+    /// data of each as JSON and sending an update event to the application.
+    /// Events with other names are skipped.
+    pub fn get_json_events<F, T>(
+        &self, url: impl AsRef<str>, names: &[&str], make_event: F,
+    ) -> SseHandle
+    where
+        F: Fn(Result<SseMessage<T>, SseError>) -> Ev + Send + Sync + 'static,
+        T: DeserializeOwned,
+    {
+        let handle = SseHandle::new();
         self.context.spawn({
             let context = self.context.clone();
             let url = url.as_ref().to_string();
+            let names: Vec<String> = names.iter().map(ToString::to_string).collect();
+            let handle = handle.clone();
 
             async move {
-                let mut stream = context.stream_from_shell(SseRequest { url });
+                let mut last_event_id = String::new();
+                let mut retry = 0;
+                let mut reconnect_delay = DEFAULT_RETRY;
+                let mut failures = 0;
 
-                while let Some(response) = stream.next().await {
-                    let make_event = make_event.clone();
+                while !handle.is_closed() {
+                    let mut stream = context.stream_from_shell(SseRequest::Connect {
+                        id: handle.id.clone(),
+                        url: url.clone(),
+                        last_event_id: last_event_id.clone(),
+                        retry,
+                    });
+                    let mut buffer = Vec::new();
 
-                    match response {
-                        SseResponse::Chunk(data) => {
-                            let mut reader = decode(Cursor::new(data));
+                    while let Some(response) = stream.next().await {
+                        if handle.is_closed() {
+                            return;
+                        }
+                        match response {
+                            SseResponse::Chunk(data) => {
+                                failures = 0;
+                                buffer.extend(data);
+                                let complete = take_complete_events(&mut buffer);
+                                let mut reader = decode(Cursor::new(complete));
 
-                            while let Some(sse_event) = reader.next().await {
-                                if let Ok(Event::Message(msg)) = sse_event {
-                                    let t: T = serde_json::from_slice(msg.data()).unwrap();
-                                    context.update_app(make_event(t));
+                                while let Some(sse_event) = reader.next().await {
+                                    match sse_event {
+                                        Ok(Event::Message(msg)) => {
+                                            if let Some(id) = msg.id() {
+                                                last_event_id.clone_from(id);
+                                            }
+                                            if !names.iter().any(|name| name == msg.name()) {
+                                                continue;
+                                            }
+                                            context.update_app(make_event(decode_json(&msg)));
+                                        }
+                                        Ok(Event::Retry(delay)) => {
+                                            let millis = delay.as_millis();
+                                            reconnect_delay =
+                                                u64::try_from(millis).unwrap_or(u64::MAX);
+                                        }
+                                        Err(e) => {
+                                            let error = SseError::Stream {
+                                                message: e.to_string(),
+                                            };
+                                            context.update_app(make_event(Err(error)));
+                                        }
+                                    }
                                 }
                             }
+                            SseResponse::Done => break,
+                            SseResponse::Error(message) => {
+                                let error = SseError::Connection { message };
+                                context.update_app(make_event(Err(error)));
+                                failures += 1;
+                                break;
+                            }
+                            // A 204 response is the server's way of ending the
+                            // subscription, so it is not an error.
+                            SseResponse::Rejected(status) => {
+                                handle.cancel();
+                                if status != NO_CONTENT {
+                                    let error = SseError::Rejected { status };
+                                    context.update_app(make_event(Err(error)));
+                                }
+                                return;
+                            }
+                            SseResponse::Closed => return,
                         }
-                        SseResponse::Done => break,
                     }
+                    retry = backoff(reconnect_delay, failures);
                 }
             }
         });
+        handle
+    }
+
+    /// Close a subscription. No further events are delivered and the shell is
+    /// asked to close the connection.
+    pub fn close(&self, handle: &SseHandle) {
+        handle.cancel();
+        self.context.spawn({
+            let context = self.context.clone();
+            let id = handle.id.clone();
+            async move {
+                context.request_from_shell(SseRequest::Close { id }).await;
+            }
+        });
+    }
+}
+
+/// Decode the data of an event as JSON.
+fn decode_json<T: DeserializeOwned>(msg: &Message) -> Result<SseMessage<T>, SseError> {
+    let data = serde_json::from_slice(msg.data()).map_err(|e| SseError::Decode {
+        message: e.to_string(),
+    })?;
+    Ok(SseMessage {
+        name: msg.name().clone(),
+        id: msg.id().clone().unwrap_or_default(),
+        data,
+    })
+}
+
+/// Delay before reconnecting after the given number of consecutive failed
+/// connections: the server's delay, doubled for each failure up to a limit.
+fn backoff(delay: u64, failures: u32) -> u64 {
+    let factor = 1_u64.checked_shl(failures).unwrap_or(u64::MAX);
+    delay.saturating_mul(factor).min(MAX_RETRY.max(delay))
+}
+
+/// Remove the complete events from the front of the buffer, leaving any
+/// partial event to be completed by the next chunk.
+fn take_complete_events(buffer: &mut Vec<u8>) -> Vec<u8> {
+    let end = (1..=buffer.len()).rev().find(|&end| {
+        let events = &buffer[..end];
+        events.ends_with(b"\n\n") || events.ends_with(b"\r\r") || events.ends_with(b"\r\n\r\n")
+    });
+    match end {
+        Some(end) => {
+            let partial = buffer.split_off(end);
+            std::mem::replace(buffer, partial)
+        }
+        None => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Events split across chunks should be held back until complete.
+    #[test]
+    fn partial_events() {
+        let mut buffer = b"data: 1\n\ndata: 2".to_vec();
+        assert_eq!(take_complete_events(&mut buffer), b"data: 1\n\n");
+        assert_eq!(buffer, b"data: 2");
+
+        assert!(take_complete_events(&mut buffer).is_empty());
+        buffer.extend(b"\r\n\r\n");
+        assert_eq!(take_complete_events(&mut buffer), b"data: 2\r\n\r\n");
+        assert!(buffer.is_empty());
+    }
+
+    // Reconnection delays should double with each failure, up to a limit,
+    // but never be shorter than the server asked for.
+    #[test]
+    fn reconnect_backoff() {
+        assert_eq!(backoff(DEFAULT_RETRY, 0), DEFAULT_RETRY);
+        assert_eq!(backoff(DEFAULT_RETRY, 2), 4 * DEFAULT_RETRY);
+        assert_eq!(backoff(DEFAULT_RETRY, 20), MAX_RETRY);
+        assert_eq!(backoff(DEFAULT_RETRY, 80), MAX_RETRY);
+        assert_eq!(backoff(2 * MAX_RETRY, 1), 2 * MAX_RETRY);
     }
 }
@@ -3,6 +3,7 @@ use std::fmt::{self, Debug, Display};
 
 use crux_core::capability::{CapabilityContext, Operation};
 use crux_core::Capability;
+use futures::{Stream, StreamExt};
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
@@ -10,12 +11,20 @@ use thiserror::Error;
 pub enum Catalog {
     /// Cedentials collection.
     Credential,
+
+    /// Stored entries that could not be read, set aside for inspection.
+    Quarantine,
+
+    /// Images fetched for display metadata, keyed by URL.
+    Image,
 }
 
 impl Display for Catalog {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Catalog::Credential => write!(f, "credential"),
+            Catalog::Quarantine => write!(f, "quarantine"),
+            Catalog::Image => write!(f, "image"),
         }
     }
 }
@@ -70,6 +79,140 @@ impl From<Option<Vec<u8>>> for StoreEntry {
     }
 }
 
+/// Metadata indexed by the store alongside an entry so entries can be queried
+/// without reading their data, which is typically encrypted.
+///
+/// Index fields are stored by the shell in the clear, so types and issuer are
+/// keyed digests under the data key the entry is sealed with: the shell can
+/// match them but not read them. Expiry is kept as a timestamp so the shell can
+/// compare it, which reveals only when an entry expires.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct StoreIndex {
+    /// Digests of the types of the entry, such as the credential types.
+    pub types: Vec<String>,
+
+    /// Digest of the issuer identifier. Empty string if not applicable.
+    pub issuer: String,
+
+    /// Expiry as an RFC 3339 timestamp. Empty string if the entry does not
+    /// expire.
+    pub expires: String,
+}
+
+/// Filter for a query on indexed metadata. Empty fields match any entry.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct StoreQuery {
+    /// Match entries with this type digest.
+    #[serde(rename = "type")]
+    pub type_: String,
+
+    /// Match entries with this issuer digest.
+    pub issuer: String,
+
+    /// Match entries that do not expire, or expire after this RFC 3339
+    /// timestamp.
+    pub expires_after: String,
+}
+
+/// A change to apply to the store as part of a batch.
+#[derive(Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub enum StoreChange {
+    /// Save a serialized object to the store.
+    Save {
+        catalog: String,
+        id: String,
+        #[serde(with = "serde_bytes")]
+        data: Vec<u8>,
+        index: StoreIndex,
+    },
+
+    /// Remove an entry from the store.
+    Delete { catalog: String, id: String },
+}
+
+impl StoreChange {
+    /// Create a change that saves an object to the store.
+    pub fn save(
+        catalog: impl Into<String>, id: impl Into<String>, data: impl Serialize, index: StoreIndex,
+    ) -> Result<Self, StoreError> {
+        Ok(Self::Save {
+            catalog: catalog.into(),
+            id: id.into(),
+            data: to_bytes(&data)?,
+            index,
+        })
+    }
+
+    /// Create a change that removes an entry from the store.
+    pub fn delete(catalog: impl Into<String>, id: impl Into<String>) -> Self {
+        Self::Delete {
+            catalog: catalog.into(),
+            id: id.into(),
+        }
+    }
+}
+
+impl Debug for StoreChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StoreChange::Save {
+                catalog,
+                id,
+                data,
+                index,
+            } => {
+                let body = format!("<binary data - {} bytes>", data.len());
+                f.debug_struct("Save")
+                    .field("catalog", catalog)
+                    .field("id", id)
+                    .field("data", &body)
+                    .field("index", index)
+                    .finish()
+            }
+            StoreChange::Delete { catalog, id } => {
+                f.debug_struct("Delete").field("catalog", catalog).field("id", id).finish()
+            }
+        }
+    }
+}
+
+/// A change to an entry in a watched catalog, pushed by the shell.
+#[derive(Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub enum StoreDelta {
+    /// A new entry was saved.
+    Inserted {
+        id: String,
+        #[serde(with = "serde_bytes")]
+        data: Vec<u8>,
+    },
+
+    /// An existing entry was overwritten.
+    Updated {
+        id: String,
+        #[serde(with = "serde_bytes")]
+        data: Vec<u8>,
+    },
+
+    /// An entry was removed.
+    Deleted { id: String },
+}
+
+impl Debug for StoreDelta {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StoreDelta::Inserted { id, data } => {
+                let body = format!("<binary data - {} bytes>", data.len());
+                f.debug_struct("Inserted").field("id", id).field("data", &body).finish()
+            }
+            StoreDelta::Updated { id, data } => {
+                let body = format!("<binary data - {} bytes>", data.len());
+                f.debug_struct("Updated").field("id", id).field("data", &body).finish()
+            }
+            StoreDelta::Deleted { id } => f.debug_struct("Deleted").field("id", id).finish(),
+        }
+    }
+}
+
 /// Supported operations for the Store capability.
 #[derive(Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub enum StoreOperation {
@@ -80,32 +223,66 @@ pub enum StoreOperation {
         id: String,
         #[serde(with = "serde_bytes")]
         data: Vec<u8>,
+        index: StoreIndex,
     },
 
+    /// Get the serialized object with the given ID from the store.
+    Get { catalog: String, id: String },
+
     /// Get all serialized objects from the store.
     List { catalog: String },
 
+    /// Get the serialized objects whose indexed metadata matches the query.
+    Query { catalog: String, query: StoreQuery },
+
     /// Remove the credential with the given ID from the store.
     Delete { catalog: String, id: String },
+
+    /// Apply a set of saves and deletes atomically: either all changes are
+    /// applied or none are.
+    Batch { changes: Vec<StoreChange> },
+
+    /// Subscribe to changes to a catalog. The shell responds with a
+    /// `StoreResponse::Changed` each time an entry is inserted, updated or
+    /// deleted, whatever the source of the change.
+    Watch { catalog: String },
 }
 
 impl Debug for StoreOperation {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            StoreOperation::Save { catalog, id, data } => {
+            StoreOperation::Save {
+                catalog,
+                id,
+                data,
+                index,
+            } => {
                 let body = format!("<binary data - {} bytes>", data.len());
                 f.debug_struct("Save")
                     .field("catalog", catalog)
                     .field("id", id)
                     .field("data", &body)
+                    .field("index", index)
                     .finish()
             }
+            StoreOperation::Get { catalog, id } => {
+                f.debug_struct("Get").field("catalog", catalog).field("id", id).finish()
+            }
             StoreOperation::List { catalog } => {
                 f.debug_struct("List").field("catalog", catalog).finish()
             }
+            StoreOperation::Query { catalog, query } => {
+                f.debug_struct("Query").field("catalog", catalog).field("query", query).finish()
+            }
             StoreOperation::Delete { catalog, id } => {
                 f.debug_struct("Delete").field("catalog", catalog).field("id", id).finish()
             }
+            StoreOperation::Batch { changes } => {
+                f.debug_struct("Batch").field("changes", changes).finish()
+            }
+            StoreOperation::Watch { catalog } => {
+                f.debug_struct("Watch").field("catalog", catalog).finish()
+            }
         }
     }
 }
@@ -136,6 +313,18 @@ impl StoreResult {
         }
     }
 
+    fn unwrap_get(self) -> Result<StoreEntry, StoreError> {
+        match self {
+            StoreResult::Ok { response } => match response {
+                StoreResponse::Retrieved { entry } => Ok(entry),
+                _ => {
+                    panic!("unexpected response for Get operation: {:?}", response);
+                }
+            },
+            StoreResult::Err { error } => Err(error.clone()),
+        }
+    }
+
     fn unwrap_list(self) -> Result<Vec<StoreEntry>, StoreError> {
         match self {
             StoreResult::Ok { response } => match response {
@@ -159,6 +348,30 @@ impl StoreResult {
             StoreResult::Err { error } => Err(error.clone()),
         }
     }
+
+    fn unwrap_batch(self) -> Result<(), StoreError> {
+        match self {
+            StoreResult::Ok { response } => match response {
+                StoreResponse::Committed => Ok(()),
+                _ => {
+                    panic!("unexpected response for Batch operation: {:?}", response);
+                }
+            },
+            StoreResult::Err { error } => Err(error.clone()),
+        }
+    }
+
+    fn unwrap_watch(self) -> Result<StoreDelta, StoreError> {
+        match self {
+            StoreResult::Ok { response } => match response {
+                StoreResponse::Changed { delta } => Ok(delta),
+                _ => {
+                    panic!("unexpected response for Watch operation: {:?}", response);
+                }
+            },
+            StoreResult::Err { error } => Err(error.clone()),
+        }
+    }
 }
 
 /// The possible responses from the Store capability.
@@ -167,11 +380,20 @@ pub enum StoreResponse {
     /// The result of a save operation.
     Saved,
 
-    /// The result of a list operation.
+    /// The result of a get operation.
+    Retrieved { entry: StoreEntry },
+
+    /// The result of a list or query operation.
     List { entries: Vec<StoreEntry> },
 
     /// The result of a delete operation.
     Deleted,
+
+    /// The result of a batch operation. All changes have been applied.
+    Committed,
+
+    /// A change to a watched catalog.
+    Changed { delta: StoreDelta },
 }
 
 impl Operation for StoreOperation {
@@ -201,6 +423,10 @@ impl<Ev> Capability<Ev> for Store<Ev> {
         generator.register_type::<StoreResponse>()?;
         generator.register_type::<StoreError>()?;
         generator.register_type::<StoreEntry>()?;
+        generator.register_type::<StoreIndex>()?;
+        generator.register_type::<StoreQuery>()?;
+        generator.register_type::<StoreChange>()?;
+        generator.register_type::<StoreDelta>()?;
         generator.register_type::<Self::Operation>()?;
         generator.register_type::<<Self::Operation as Operation>::Output>()?;
         Ok(())
@@ -234,7 +460,7 @@ where
         let context = self.context.clone();
         self.context.spawn({
             async move {
-                let response = save(&context, catalog, id, data).await;
+                let response = save(&context, catalog, id, data, StoreIndex::default()).await;
                 context.update_app(make_event(response))
             }
         });
@@ -244,7 +470,40 @@ where
     pub async fn save_async(
         &self, catalog: impl Into<String>, id: impl Into<String>, data: impl Serialize,
     ) -> Result<(), StoreError> {
-        save(&self.context, catalog, id, data).await
+        save(&self.context, catalog, id, data, StoreIndex::default()).await
+    }
+
+    /// Save a serialized credential to the store, with metadata to index it
+    /// by, while in an async context.
+    pub async fn save_indexed_async(
+        &self, catalog: impl Into<String>, id: impl Into<String>, data: impl Serialize,
+        index: StoreIndex,
+    ) -> Result<(), StoreError> {
+        save(&self.context, catalog, id, data, index).await
+    }
+
+    /// Get the serialized credential with the given ID from the store.
+    pub fn get<F>(
+        &self, catalog: impl Into<String> + Send + 'static, id: impl Into<String> + Send + 'static,
+        make_event: F,
+    ) where
+        F: FnOnce(Result<StoreEntry, StoreError>) -> Ev + Send + Sync + 'static,
+    {
+        self.context.spawn({
+            let context = self.context.clone();
+            async move {
+                let response = get(&context, catalog, id).await;
+                context.update_app(make_event(response))
+            }
+        });
+    }
+
+    /// Get the serialized credential with the given ID from the store while in
+    /// an async context.
+    pub async fn get_async(
+        &self, catalog: impl Into<String>, id: impl Into<String>,
+    ) -> Result<StoreEntry, StoreError> {
+        get(&self.context, catalog, id).await
     }
 
     /// Get all serialized credentials from the store.
@@ -268,6 +527,30 @@ where
         list(&self.context, catalog).await
     }
 
+    /// Get the serialized credentials matching a query on their indexed
+    /// metadata.
+    pub fn query<F>(
+        &self, catalog: impl Into<String> + Send + 'static, query: StoreQuery, make_event: F,
+    ) where
+        F: FnOnce(Result<Vec<StoreEntry>, StoreError>) -> Ev + Send + Sync + 'static,
+    {
+        self.context.spawn({
+            let context = self.context.clone();
+            async move {
+                let response = query_entries(&context, catalog, query).await;
+                context.update_app(make_event(response))
+            }
+        });
+    }
+
+    /// Get the serialized credentials matching a query on their indexed
+    /// metadata while in an async context.
+    pub async fn query_async(
+        &self, catalog: impl Into<String>, query: StoreQuery,
+    ) -> Result<Vec<StoreEntry>, StoreError> {
+        query_entries(&self.context, catalog, query).await
+    }
+
     /// Remove the credential with the given ID from the store.
     pub fn delete<F>(
         &self, catalog: impl Into<String> + Send + 'static, id: impl Into<String> + Send + 'static,
@@ -291,25 +574,88 @@ where
     ) -> Result<(), StoreError> {
         delete(&self.context, catalog, id).await
     }
+
+    /// Apply a set of saves and deletes to the store atomically.
+    pub fn batch<F>(&self, changes: Vec<StoreChange>, make_event: F)
+    where
+        F: FnOnce(Result<(), StoreError>) -> Ev + Send + Sync + 'static,
+    {
+        self.context.spawn({
+            let context = self.context.clone();
+            async move {
+                let response = batch(&context, changes).await;
+                context.update_app(make_event(response))
+            }
+        });
+    }
+
+    /// Apply a set of saves and deletes to the store atomically while in an
+    /// async context.
+    pub async fn batch_async(&self, changes: Vec<StoreChange>) -> Result<(), StoreError> {
+        batch(&self.context, changes).await
+    }
+
+    /// Watch a catalog and send an update event to the application for each
+    /// change to it.
+    pub fn watch<F>(&self, catalog: impl Into<String> + Send + 'static, make_event: F)
+    where
+        F: Fn(Result<StoreDelta, StoreError>) -> Ev + Send + Sync + 'static,
+    {
+        self.context.spawn({
+            let context = self.context.clone();
+            async move {
+                let mut deltas = watch(&context, catalog);
+                while let Some(delta) = deltas.next().await {
+                    context.update_app(make_event(delta));
+                }
+            }
+        });
+    }
+
+    /// Watch a catalog for changes while in an async context. The stream ends
+    /// when the shell ends the subscription.
+    pub fn watch_async(
+        &self, catalog: impl Into<String>,
+    ) -> impl Stream<Item = Result<StoreDelta, StoreError>> {
+        watch(&self.context, catalog)
+    }
+}
+
+fn to_bytes(data: &impl Serialize) -> Result<Vec<u8>, StoreError> {
+    serde_json::to_vec(data).map_err(|e| StoreError::InvalidRequest {
+        message: format!("failed to serialize data: {}", e),
+    })
 }
 
 async fn save<Ev: 'static>(
     context: &CapabilityContext<StoreOperation, Ev>, catalog: impl Into<String>,
-    id: impl Into<String>, data: impl Serialize,
+    id: impl Into<String>, data: impl Serialize, index: StoreIndex,
 ) -> Result<(), StoreError> {
-    let bytes = serde_json::to_vec(&data).map_err(|e| StoreError::InvalidRequest {
-        message: format!("failed to serialize data: {}", e),
-    })?;
+    let bytes = to_bytes(&data)?;
     context
         .request_from_shell(StoreOperation::Save {
             catalog: catalog.into(),
             id: id.into(),
             data: bytes,
+            index,
         })
         .await
         .unwrap_save()
 }
 
+async fn get<Ev: 'static>(
+    context: &CapabilityContext<StoreOperation, Ev>, catalog: impl Into<String>,
+    id: impl Into<String>,
+) -> Result<StoreEntry, StoreError> {
+    context
+        .request_from_shell(StoreOperation::Get {
+            catalog: catalog.into(),
+            id: id.into(),
+        })
+        .await
+        .unwrap_get()
+}
+
 async fn list<Ev: 'static>(
     context: &CapabilityContext<StoreOperation, Ev>, catalog: impl Into<String>,
 ) -> Result<Vec<StoreEntry>, StoreError> {
@@ -321,6 +667,18 @@ async fn list<Ev: 'static>(
         .unwrap_list()
 }
 
+async fn query_entries<Ev: 'static>(
+    context: &CapabilityContext<StoreOperation, Ev>, catalog: impl Into<String>, query: StoreQuery,
+) -> Result<Vec<StoreEntry>, StoreError> {
+    context
+        .request_from_shell(StoreOperation::Query {
+            catalog: catalog.into(),
+            query,
+        })
+        .await
+        .unwrap_list()
+}
+
 async fn delete<Ev: 'static>(
     context: &CapabilityContext<StoreOperation, Ev>, catalog: impl Into<String>,
     id: impl Into<String>,
@@ -333,3 +691,19 @@ async fn delete<Ev: 'static>(
         .await
         .unwrap_delete()
 }
+
+async fn batch<Ev: 'static>(
+    context: &CapabilityContext<StoreOperation, Ev>, changes: Vec<StoreChange>,
+) -> Result<(), StoreError> {
+    context.request_from_shell(StoreOperation::Batch { changes }).await.unwrap_batch()
+}
+
+fn watch<Ev: 'static>(
+    context: &CapabilityContext<StoreOperation, Ev>, catalog: impl Into<String>,
+) -> impl Stream<Item = Result<StoreDelta, StoreError>> {
+    context
+        .stream_from_shell(StoreOperation::Watch {
+            catalog: catalog.into(),
+        })
+        .map(StoreResult::unwrap_watch)
+}
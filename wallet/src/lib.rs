@@ -8,6 +8,12 @@ mod provider;
 mod model;
 pub mod view;
 
+/// Test support for the services the wallet talks to.
+#[cfg(feature = "testing")]
+pub mod testing {
+    pub use crate::provider::testing::status_events;
+}
+
 use lazy_static::lazy_static;
 use wasm_bindgen::prelude::wasm_bindgen;
 
@@ -1,14 +1,36 @@
 //! Model for the wallet application state.
 
+pub mod compatibility;
 pub mod credential;
+pub mod error;
 mod issuance;
+pub mod locale;
+pub mod query;
+pub mod status;
 
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
 pub use credential::CredentialState;
-pub use issuance::{IssuanceState, OfferedCredential};
+pub use issuance::{Issuance, IssuanceState, IssuanceStep, OfferedCredential};
+use serde::Serialize;
+use vercre_holder::credential::ImageData;
+use vercre_holder::TokenResponse;
 
 use super::Aspect;
-use crate::capabilities::store::StoreEntry;
+use crate::capabilities::sse::SseHandle;
+use crate::model::credential::{CredentialChange, StoredCredentials};
+use crate::model::error::WalletError;
+use crate::model::locale::LocalePreference;
+use crate::model::query::{CredentialFilter, CredentialQuery, CredentialSort};
+use crate::model::status::{CredentialStatus, StatusUpdate};
+use crate::provider::issuance::{IssuedCredential, IssuerMetadata};
+use crate::provider::keys::HolderKeys;
+use crate::provider::nonce::NonceResponse;
+use crate::provider::proof::CredentialProof;
+use crate::provider::response::ServiceError;
 use crate::provider::Provider;
+use crate::Event;
 
 /// State for the wallet application.
 #[derive(Default)]
@@ -23,15 +45,70 @@ pub struct Model {
     pub issuance: Option<IssuanceState>,
 
     /// Error state.
-    pub error: Option<String>,
+    pub error: Option<WalletError>,
+
+    /// The aspect that was active when the error occurred, restored when the
+    /// error is dismissed or the failed action is retried.
+    pub previous_view: Aspect,
+
+    /// The last retryable action requested by the shell, until its outcome is
+    /// known.
+    pub last_action: Option<Event>,
+
+    /// The action that caused the current error, repeated if the user retries.
+    pub failed_action: Option<Event>,
+
+    /// The shell has been asked to report changes to the stored credentials.
+    pub watching: bool,
+
+    /// Subscriptions to issuer status event streams, keyed by issuer. `None`
+    /// until the stream's URL has been found.
+    pub status_streams: HashMap<String, Option<SseHandle>>,
+
+    /// The user has chosen to present credentials that are expired or not yet
+    /// valid.
+    pub allow_expired: bool,
+
+    /// The user's locale and time zone.
+    pub locale: LocalePreference,
 }
 
 /// Methods to set the application state based on events.
 impl Model {
-    /// An error has occurred. Set the error state.
-    pub fn error(&mut self, error: String) {
+    /// An error has occurred. Set the error state, remembering the aspect to
+    /// return to.
+    pub fn error(&mut self, error: WalletError) {
+        self.failed(None, error);
+    }
+
+    /// An action, if known, has failed with the error. Set the error state,
+    /// remembering the aspect to return to and the action to retry.
+    pub fn failed(&mut self, action: Option<Event>, error: WalletError) {
+        if self.active_view != Aspect::Error {
+            self.previous_view = self.active_view.clone();
+        }
         self.active_view = Aspect::Error;
         self.error = Some(error);
+        self.failed_action = action;
+    }
+
+    /// The user has dismissed the error. Return to the previous aspect.
+    pub fn dismiss_error(&mut self) {
+        if self.active_view == Aspect::Error {
+            self.active_view = self.previous_view.clone();
+        }
+        self.error = None;
+        self.failed_action = None;
+    }
+
+    /// The user wants to retry the action that failed. Return to the previous
+    /// aspect and get the action to repeat, if the error was caused by an
+    /// action and can be retried.
+    pub fn retry_last_action(&mut self) -> Option<Event> {
+        let retryable = self.error.as_ref().is_some_and(|error| error.retryable);
+        let action = self.failed_action.take().filter(|_| retryable);
+        self.dismiss_error();
+        action
     }
 
     /// Set up the model with an initial state.
@@ -47,9 +124,77 @@ impl Model {
     }
 
     /// The credentials have been retrieved from the wallet's store.
-    pub fn credentials_loaded(&mut self, entries: Vec<StoreEntry>) {
+    pub fn credentials_loaded(&mut self, stored: StoredCredentials) {
         self.active_view = Aspect::CredentialList;
-        self.credential.set_credentials(entries);
+        self.credential.set_credentials(stored);
+    }
+
+    /// A stored credential has changed.
+    pub fn credential_changed(&mut self, change: CredentialChange) {
+        self.credential.apply_change(change);
+    }
+
+    /// An issuer has pushed a status update.
+    pub fn status_updated(&mut self, update: StatusUpdate) {
+        self.credential.status_updated(update);
+    }
+
+    /// The status of stored credentials has been checked.
+    pub fn status_checked(&mut self, statuses: HashMap<String, CredentialStatus>) {
+        self.credential.status_checked(statuses);
+    }
+
+    /// The user has discarded a quarantined store entry.
+    pub fn discard_quarantined(&mut self, id: &str) {
+        self.credential.discard_quarantined(id);
+    }
+
+    /// The shell has reported the current time.
+    pub fn time_updated(&mut self, now: DateTime<Utc>) {
+        self.credential.now = Some(now);
+    }
+
+    /// The user has chosen whether to present credentials that are expired or
+    /// not yet valid.
+    pub fn allow_expired_presentation(&mut self, allow: bool) {
+        self.allow_expired = allow;
+    }
+
+    /// The user has changed the text to search the credential list for.
+    pub fn search_credentials(&mut self, search: String) {
+        self.credential.query.search = search;
+    }
+
+    /// The user has changed the criteria the credential list is filtered by.
+    pub fn filter_credentials(&mut self, filter: CredentialFilter) {
+        self.credential.query.filter = filter;
+    }
+
+    /// The user has changed the order of the credential list.
+    pub fn sort_credentials(&mut self, sort: CredentialSort, descending: bool) {
+        self.credential.query.sort = sort;
+        self.credential.query.descending = descending;
+    }
+
+    /// The user has cleared the search, filter and sort of the credential
+    /// list.
+    pub fn clear_credential_query(&mut self) {
+        self.credential.query = CredentialQuery::default();
+    }
+
+    /// The shell has set the user's locale and time zone.
+    pub fn set_locale(&mut self, locale: LocalePreference) {
+        self.locale = locale;
+    }
+
+    /// The holder keys have been loaded or updated.
+    pub fn keys_updated(&mut self, keys: HolderKeys) {
+        self.credential.keys = keys;
+    }
+
+    /// The user wants to review the wallet's holder keys.
+    pub fn manage_keys(&mut self) {
+        self.active_view = Aspect::KeyManagement;
     }
 
     /// The user has deleted a credential from their wallet.
@@ -64,12 +209,132 @@ impl Model {
     }
 
     /// The user has scanned an issuance offer QR code so we can initiate a
-    /// pre-authorized issuance flow.
+    /// pre-authorized issuance flow. The offer is shown while its issuer's
+    /// metadata is fetched.
     pub fn issuance_offer(&mut self, encoded_offer: &str) {
         match IssuanceState::from_offer(encoded_offer) {
-            Ok(issuance_state) => self.issuance = Some(issuance_state),
-            Err(e) => self.error(e.to_string()),
+            Ok(issuance_state) => {
+                self.active_view = Aspect::IssuanceOffer;
+                self.issuance = Some(issuance_state);
+            }
+            Err(e) => self.error(
+                WalletError::user_input(e.to_string())
+                    .with_message("The offer could not be read. Scan it again."),
+            ),
+        }
+    }
+
+    /// Issuer metadata has been received for the offer being considered.
+    /// Evaluate whether the wallet can handle each offered credential so the
+    /// user can see before accepting.
+    pub fn issuance_issuer(&mut self, issuer: IssuerMetadata) {
+        if let Some(issuance) = &mut self.issuance {
+            issuance.issuer_metadata(issuer);
+        }
+    }
+
+    /// An offered credential's logo has been fetched.
+    pub fn issuance_logo(&mut self, config_id: &str, logo: ImageData) {
+        if let Some(issuance) = &mut self.issuance {
+            issuance.logo_received(config_id, logo);
+        }
+    }
+
+    /// An offered credential's background image has been fetched.
+    pub fn issuance_background(&mut self, config_id: &str, background: ImageData) {
+        if let Some(issuance) = &mut self.issuance {
+            issuance.background_received(config_id, background);
+        }
+    }
+
+    /// The user has accepted the offer. Get the step that requests an access
+    /// token, unless the user must enter a PIN first.
+    pub fn issuance_accepted(&mut self) -> Option<IssuanceStep> {
+        let Some(issuance) = &mut self.issuance else {
+            self.error(WalletError::protocol("no offer is awaiting acceptance"));
+            return None;
+        };
+        match issuance.accept() {
+            Ok(next) => next,
+            Err(e) => {
+                self.error(WalletError::protocol(e.to_string()));
+                None
+            }
+        }
+    }
+
+    /// The user has entered the offer's PIN. Get the step that requests an
+    /// access token with it.
+    pub fn issuance_pin(&mut self, pin: String) -> Option<IssuanceStep> {
+        self.issuance_next("a PIN", |issuance, _| issuance.pin(pin))
+    }
+
+    /// An access token has been received. Get the step that creates the
+    /// first proof.
+    pub fn issuance_token(&mut self, token: TokenResponse) -> Option<IssuanceStep> {
+        self.issuance_next("an access token", |issuance, now| issuance.token_received(token, now))
+    }
+
+    /// A proof has been created. Get the step that requests the credential
+    /// with it.
+    pub fn issuance_proof(
+        &mut self, config_id: String, proof: Option<CredentialProof>,
+    ) -> Option<IssuanceStep> {
+        self.issuance_next("a proof", |issuance, _| issuance.proof_created(config_id, proof))
+    }
+
+    /// A credential has been received. Get the step that stores it.
+    pub fn issuance_credential(&mut self, issued: IssuedCredential) -> Option<IssuanceStep> {
+        self.issuance_next("a credential", |issuance, now| {
+            issuance.credential_received(issued, now)
+        })
+    }
+
+    /// A received credential has been stored. Get the step that requests the
+    /// next credential or completes the issuance.
+    pub fn issuance_stored(&mut self, config_id: &str) -> Option<IssuanceStep> {
+        self.issuance_next("a stored credential", |issuance, now| {
+            issuance.credential_stored(config_id, now)
+        })
+    }
+
+    /// The issuer has rejected an issuance request. Get the step that
+    /// recovers from the rejection, if it can be recovered from, otherwise
+    /// show the error.
+    pub fn issuance_rejected(&mut self, error: &ServiceError) -> Option<IssuanceStep> {
+        let now = self.credential.now;
+        let next = self.issuance.as_mut().and_then(|issuance| issuance.recover(error, now));
+        if next.is_none() {
+            self.error(error.into());
+        }
+        next
+    }
+
+    /// A nonce has been fetched from the issuer's nonce endpoint. Get the step
+    /// that creates a proof with it.
+    pub fn issuance_nonce(&mut self, response: &NonceResponse) -> Option<IssuanceStep> {
+        self.issuance_next("a nonce", |issuance, now| issuance.nonce_received(response, now))
+    }
+
+    /// Every supported credential offered has been stored. The new credentials
+    /// are reported by the store, so return to the credential list.
+    pub fn issuance_complete(&mut self) {
+        self.active_view = Aspect::CredentialList;
+        self.issuance = None;
+    }
+
+    /// Move the issuance on to its next step. If the issuance is not expecting
+    /// what was received, show the error.
+    fn issuance_next(
+        &mut self, expected: &str,
+        next: impl FnOnce(&mut IssuanceState, Option<DateTime<Utc>>) -> Option<IssuanceStep>,
+    ) -> Option<IssuanceStep> {
+        let now = self.credential.now;
+        let next = self.issuance.as_mut().and_then(|issuance| next(issuance, now));
+        if next.is_none() {
+            self.error(WalletError::protocol(format!("no issuance is awaiting {expected}")));
         }
+        next
     }
 
     /// The user has decided to no longer go ahead with the issuance process.
@@ -81,7 +346,7 @@ impl Model {
         if let Some(issuance) = &mut self.issuance {
             match issuance.cancel(provider) {
                 Ok(_) => self.issuance = None,
-                Err(e) => self.error(e.to_string()),
+                Err(e) => self.error(WalletError::protocol(e.to_string())),
             }
         }
     }
@@ -0,0 +1,139 @@
+//! Compatibility of offered credentials with the wallet.
+//!
+//! Before the user accepts an offer, each offered credential configuration is
+//! compared with what the wallet can handle: the credential format, how the
+//! credential is bound to the holder, the algorithms the issuer signs with and
+//! the proofs of possession the issuer accepts. A credential the wallet cannot
+//! handle is reported with the reasons, rather than failing when it is
+//! requested.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::provider::proof::choose_proof_type;
+
+/// Credential formats the wallet can verify and store.
+const FORMATS: [&str; 5] = ["jwt_vc_json", "jwt_vc_json-ld", "ldp_vc", "vc+sd-jwt", "dc+sd-jwt"];
+
+/// Methods the wallet can bind a credential to its holder key with.
+const BINDING_METHODS: [&str; 2] = ["jwk", "did:key"];
+
+/// Signature algorithms the wallet can verify JWT-secured credentials with.
+const JOSE_ALGS: [&str; 2] = ["EdDSA", "ES256K"];
+
+/// Data Integrity cryptosuites the wallet can verify credentials with.
+const CRYPTOSUITES: [&str; 1] = ["eddsa-jcs-2022"];
+
+/// Whether the wallet can handle an offered credential.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Compatibility {
+    /// The wallet can request, verify and store the credential. Offered
+    /// credentials are assumed to be supported until evaluated.
+    #[default]
+    Supported,
+
+    /// The wallet cannot handle the credential, for the given reasons.
+    Unsupported(Vec<String>),
+}
+
+impl Compatibility {
+    /// Evaluate a credential configuration from issuer metadata against the
+    /// wallet's capabilities.
+    pub fn evaluate(config: &impl Serialize) -> Self {
+        let Ok(config) = serde_json::to_value(config) else {
+            return Self::Unsupported(vec!["the credential configuration is invalid".into()]);
+        };
+        let values = |name: &str| -> Option<Vec<String>> {
+            let values = config.get(name)?.as_array()?;
+            Some(
+                values
+                    .iter()
+                    .map(|v| v.as_str().map_or_else(|| v.to_string(), String::from))
+                    .collect(),
+            )
+        };
+        let mut reasons: Vec<String> = Vec::new();
+
+        let format = config.get("format").and_then(Value::as_str).unwrap_or_default();
+        if format.is_empty() {
+            reasons.push("the credential has no format".into());
+        } else if !FORMATS.contains(&format) {
+            reasons.push(format!("the wallet does not support the {format} format"));
+        }
+
+        if let Some(methods) = values("cryptographic_binding_methods_supported") {
+            if !methods.is_empty() && !methods.iter().any(|m| BINDING_METHODS.contains(&m.as_str()))
+            {
+                reasons.push(format!(
+                    "the wallet cannot bind the credential to its key by {}",
+                    methods.join(", ")
+                ));
+            }
+        }
+
+        if let Some(algorithms) = values("credential_signing_alg_values_supported") {
+            let verifiable: &[&str] = if format == "ldp_vc" { &CRYPTOSUITES } else { &JOSE_ALGS };
+            if !algorithms.is_empty()
+                && !algorithms.iter().any(|alg| verifiable.contains(&alg.as_str()))
+            {
+                reasons.push(format!(
+                    "the wallet cannot verify credentials signed with {}",
+                    algorithms.join(", ")
+                ));
+            }
+        }
+
+        if let Err(e) = choose_proof_type(&config, false) {
+            reasons.push(e.to_string());
+        }
+
+        if reasons.is_empty() {
+            Self::Supported
+        } else {
+            Self::Unsupported(reasons)
+        }
+    }
+
+    /// Determine if the wallet can handle the credential.
+    pub const fn is_supported(&self) -> bool {
+        matches!(self, Self::Supported)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    // A configuration is supported if the wallet handles its format, binding
+    // method, signing algorithm and proof type, and otherwise every mismatch
+    // is reported.
+    #[test]
+    fn evaluate_configuration() {
+        let config = json!({
+            "format": "jwt_vc_json",
+            "cryptographic_binding_methods_supported": ["did:key", "did:web"],
+            "credential_signing_alg_values_supported": ["ES256K", "ES256"],
+            "proof_types_supported": {
+                "jwt": { "proof_signing_alg_values_supported": ["EdDSA"] }
+            }
+        });
+        assert_eq!(Compatibility::evaluate(&config), Compatibility::Supported);
+
+        let config = json!({
+            "format": "mso_mdoc",
+            "cryptographic_binding_methods_supported": ["cose_key"],
+            "credential_signing_alg_values_supported": ["ES256"],
+            "proof_types_supported": {
+                "jwt": { "proof_signing_alg_values_supported": ["ES256"] }
+            }
+        });
+        let Compatibility::Unsupported(reasons) = Compatibility::evaluate(&config) else {
+            panic!("should be unsupported");
+        };
+        assert_eq!(reasons.len(), 4);
+        assert_eq!(reasons[0], "the wallet does not support the mso_mdoc format");
+        assert_eq!(reasons[1], "the wallet cannot bind the credential to its key by cose_key");
+    }
+}
@@ -1,9 +1,98 @@
 //! Credential sub-app state.
 
+use std::collections::HashMap;
+
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use vercre_holder::credential::Credential;
 
-use crate::capabilities::store::StoreEntry;
+use crate::model::query::CredentialQuery;
+use crate::model::status::{CredentialStatus, StatusUpdate};
+use crate::provider::keys::HolderKeys;
+
+/// A credential as persisted in the wallet's store, together with the ID of
+/// the holder key it is bound to.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[allow(clippy::module_name_repetitions)]
+pub struct CredentialRecord {
+    /// The stored credential.
+    #[serde(flatten)]
+    pub credential: Credential,
+
+    /// ID of the holder key the credential is bound to.
+    pub key_id: String,
+
+    /// The claim definitions of the issuer's metadata for the credential, as
+    /// the metadata lists them, so claims can be shown in the issuer's order.
+    /// None if the credential was stored without them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub claim_metadata: Option<Value>,
+}
+
+/// A stored credential that could not be read and has been set aside rather
+/// than loaded.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+pub struct QuarantinedRecord {
+    /// ID of the stored entry, or a digest of its data if the entry is
+    /// unreadable to the extent that its ID is unknown.
+    pub id: String,
+
+    /// Why the entry could not be read.
+    pub reason: String,
+}
+
+/// Credentials loaded from the store.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct StoredCredentials {
+    /// Credentials that were read successfully.
+    pub records: Vec<CredentialRecord>,
+
+    /// Entries that could not be read.
+    pub quarantined: Vec<QuarantinedRecord>,
+}
+
+/// A change to the stored credentials, reported as the store is watched.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[allow(clippy::module_name_repetitions)]
+pub enum CredentialChange {
+    /// A credential was inserted or updated.
+    Saved(CredentialRecord),
+
+    /// The credential with the given ID was deleted.
+    Deleted(String),
+
+    /// A changed entry could not be read and was quarantined.
+    Quarantined(QuarantinedRecord),
+}
+
+/// Whether a credential is within its validity period.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Validity {
+    /// The credential is valid, or the current time is not yet known.
+    #[default]
+    Valid,
+
+    /// The credential's `valid_from` date is in the future.
+    NotYetValid,
+
+    /// The credential's `valid_until` date has passed.
+    Expired,
+}
+
+impl Validity {
+    /// Determine the validity of a credential at the given time.
+    #[must_use]
+    pub fn at(credential: &Credential, now: DateTime<Utc>) -> Self {
+        if credential.valid_until.is_some_and(|until| until <= now) {
+            Self::Expired
+        } else if credential.valid_from.is_some_and(|from| from > now) {
+            Self::NotYetValid
+        } else {
+            Self::Valid
+        }
+    }
+}
 
 /// Application state for the credential sub-app.
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
@@ -13,7 +102,28 @@ pub struct CredentialState {
     pub id: Option<String>,
 
     /// Credentials stored in the wallet.
-    pub credentials: Vec<Credential>,
+    pub credentials: Vec<CredentialRecord>,
+
+    /// Holder keys the credentials are bound to.
+    pub keys: HolderKeys,
+
+    /// Stored entries that could not be read.
+    pub quarantined: Vec<QuarantinedRecord>,
+
+    /// Status of stored credentials, keyed by credential ID, as last reported
+    /// by their issuers.
+    pub status: HashMap<String, CredentialStatus>,
+
+    /// Transaction IDs of deferred issuances whose credentials are ready to be
+    /// retrieved.
+    pub deferred_ready: Vec<String>,
+
+    /// Current time as last reported by the shell. Credential validity is
+    /// not checked until it is known.
+    pub now: Option<DateTime<Utc>>,
+
+    /// The active search, filter and sort of the credential list.
+    pub query: CredentialQuery,
 }
 
 impl CredentialState {
@@ -23,19 +133,158 @@ impl CredentialState {
         Self {
             id: None,
             credentials: vec![],
+            keys: HolderKeys::default(),
+            quarantined: vec![],
+            status: HashMap::new(),
+            deferred_ready: vec![],
+            now: None,
+            query: CredentialQuery::default(),
         }
     }
 
-    /// Set the credential list from a set of StoreEntries.
-    pub fn set_credentials(&mut self, entries: Vec<StoreEntry>) {
-        let mut credentials = vec![];
-        for entry in entries {
-            if let StoreEntry::Data(bytes) = entry {
-                let credential: Credential =
-                    serde_json::from_slice(&bytes).expect("should deserialize");
-                credentials.push(credential);
+    /// Set the credential list from credentials loaded from the store.
+    pub fn set_credentials(&mut self, stored: StoredCredentials) {
+        self.credentials = stored.records;
+        self.quarantined = stored.quarantined;
+    }
+
+    /// Apply a change to the stored credentials without reloading the list.
+    pub fn apply_change(&mut self, change: CredentialChange) {
+        match change {
+            CredentialChange::Saved(record) => {
+                let id = &record.credential.id;
+                match self.credentials.iter_mut().find(|r| &r.credential.id == id) {
+                    Some(existing) => *existing = record,
+                    None => self.credentials.push(record),
+                }
+            }
+            CredentialChange::Deleted(id) => {
+                self.credentials.retain(|r| r.credential.id != id);
+                self.status.remove(&id);
+                if self.id.as_ref() == Some(&id) {
+                    self.id = None;
+                }
+            }
+            CredentialChange::Quarantined(quarantined) => {
+                self.credentials.retain(|r| r.credential.id != quarantined.id);
+                if !self.quarantined.iter().any(|q| q.id == quarantined.id) {
+                    self.quarantined.push(quarantined);
+                }
+            }
+        }
+    }
+
+    /// Apply a status update pushed by an issuer. Updates for credentials not
+    /// held in the wallet are ignored.
+    pub fn status_updated(&mut self, update: StatusUpdate) {
+        let (credential_id, status) = match update {
+            StatusUpdate::DeferredReady { transaction_id } => {
+                if !self.deferred_ready.contains(&transaction_id) {
+                    self.deferred_ready.push(transaction_id);
+                }
+                return;
+            }
+            StatusUpdate::Revoked { credential_id } => (credential_id, CredentialStatus::Revoked),
+            StatusUpdate::Suspended { credential_id } => {
+                (credential_id, CredentialStatus::Suspended)
             }
+            StatusUpdate::Valid { credential_id } => (credential_id, CredentialStatus::Valid),
+        };
+        if self.credentials.iter().any(|r| r.credential.id == credential_id) {
+            self.status.insert(credential_id, status);
+        }
+    }
+
+    /// Record the status of credentials as checked against their issuers'
+    /// status lists.
+    pub fn status_checked(&mut self, statuses: HashMap<String, CredentialStatus>) {
+        for (credential_id, status) in statuses {
+            if self.credentials.iter().any(|r| r.credential.id == credential_id) {
+                self.status.insert(credential_id, status);
+            }
+        }
+    }
+
+    /// Get the validity of a credential at the last reported time.
+    #[must_use]
+    pub fn validity(&self, credential: &Credential) -> Validity {
+        self.now.map_or_else(Validity::default, |now| Validity::at(credential, now))
+    }
+
+    /// Remove a discarded entry from the quarantined list.
+    pub fn discard_quarantined(&mut self, id: &str) {
+        self.quarantined.retain(|q| q.id != id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+
+    use super::*;
+
+    // A credential is only valid between its `valid_from` and `valid_until`
+    // dates.
+    #[test]
+    fn credential_validity() {
+        let json = include_bytes!("credentials.json");
+        let credentials: Vec<Credential> =
+            serde_json::from_slice(json).expect("should deserialize");
+        let now = DateTime::from_timestamp(1_700_000_000, 0).expect("should be in range");
+
+        let mut credential = credentials[0].clone();
+        credential.valid_from = None;
+        credential.valid_until = None;
+        assert_eq!(Validity::at(&credential, now), Validity::Valid);
+
+        credential.valid_from = Some(now + Duration::days(1));
+        assert_eq!(Validity::at(&credential, now), Validity::NotYetValid);
+
+        credential.valid_from = Some(now - Duration::days(1));
+        credential.valid_until = Some(now);
+        assert_eq!(Validity::at(&credential, now), Validity::Expired);
+
+        credential.valid_until = Some(now + Duration::days(1));
+        assert_eq!(Validity::at(&credential, now), Validity::Valid);
+    }
+
+    // Status updates are applied to held credentials only, and a deferred
+    // credential is recorded as ready once.
+    #[test]
+    fn status_updates() {
+        let json = include_bytes!("credentials.json");
+        let credentials: Vec<Credential> =
+            serde_json::from_slice(json).expect("should deserialize");
+        let id = credentials[0].id.clone();
+        let mut state = CredentialState::init();
+        state.set_credentials(StoredCredentials {
+            records: vec![CredentialRecord {
+                credential: credentials[0].clone(),
+                key_id: String::new(),
+                claim_metadata: None,
+            }],
+            quarantined: vec![],
+        });
+
+        state.status_updated(StatusUpdate::Suspended {
+            credential_id: id.clone(),
+        });
+        assert_eq!(state.status.get(&id), Some(&CredentialStatus::Suspended));
+        state.status_updated(StatusUpdate::Valid {
+            credential_id: id.clone(),
+        });
+        assert_eq!(state.status.get(&id), Some(&CredentialStatus::Valid));
+
+        state.status_updated(StatusUpdate::Revoked {
+            credential_id: "urn:uuid:not-held".into(),
+        });
+        assert!(!state.status.contains_key("urn:uuid:not-held"));
+
+        for _ in 0..2 {
+            state.status_updated(StatusUpdate::DeferredReady {
+                transaction_id: "txn".into(),
+            });
         }
-        self.credentials = credentials;
+        assert_eq!(state.deferred_ready, vec!["txn".to_string()]);
     }
 }
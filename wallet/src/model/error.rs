@@ -0,0 +1,219 @@
+//! Structured wallet errors.
+
+use crux_http::HttpError;
+use serde::{Deserialize, Serialize};
+
+use crate::capabilities::clock::ClockError;
+use crate::capabilities::store::StoreError;
+use crate::provider::response::{ErrorCode, ServiceError};
+
+/// Where an error came from.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[allow(clippy::module_name_repetitions)]
+pub enum ErrorCategory {
+    /// A request to an issuer or verifier could not be completed.
+    #[default]
+    Network,
+
+    /// An issuer or verifier, or the shell, responded in a way the wallet does
+    /// not understand or cannot accept.
+    Protocol,
+
+    /// Data could not be saved to or read from the wallet's store.
+    Storage,
+
+    /// A key, signature or encryption operation failed.
+    Crypto,
+
+    /// Something the user provided, such as a scanned offer or a PIN, is not
+    /// valid.
+    UserInput,
+}
+
+/// What the user can do to recover from an error.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub enum RecoveryAction {
+    /// Dismiss the error and carry on.
+    #[default]
+    Dismiss,
+
+    /// Try the failed action again.
+    Retry,
+
+    /// Scan the offer or presentation request again.
+    ScanAgain,
+
+    /// Contact the issuer or verifier, as the wallet cannot resolve the
+    /// problem.
+    ContactIssuer,
+}
+
+/// An error, described for both the user and for diagnosis.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[allow(clippy::module_name_repetitions)]
+pub struct WalletError {
+    /// Where the error came from.
+    pub category: ErrorCategory,
+
+    /// A message to show the user.
+    pub message: String,
+
+    /// Technical detail for diagnosis.
+    pub detail: String,
+
+    /// Whether trying the failed action again may succeed.
+    pub retryable: bool,
+
+    /// The action suggested to the user.
+    pub recovery: RecoveryAction,
+}
+
+impl WalletError {
+    /// A request to an issuer or verifier could not be completed.
+    pub fn network(detail: impl Into<String>) -> Self {
+        Self {
+            category: ErrorCategory::Network,
+            message: "The wallet could not connect. Check your connection and try again.".into(),
+            detail: detail.into(),
+            retryable: true,
+            recovery: RecoveryAction::Retry,
+        }
+    }
+
+    /// An issuer, verifier or the shell responded unexpectedly.
+    pub fn protocol(detail: impl Into<String>) -> Self {
+        Self {
+            category: ErrorCategory::Protocol,
+            message: "Something went wrong talking to the service.".into(),
+            detail: detail.into(),
+            retryable: false,
+            recovery: RecoveryAction::ContactIssuer,
+        }
+    }
+
+    /// Data could not be saved to or read from the store.
+    pub fn storage(detail: impl Into<String>) -> Self {
+        Self {
+            category: ErrorCategory::Storage,
+            message: "Your wallet data could not be saved or read.".into(),
+            detail: detail.into(),
+            retryable: true,
+            recovery: RecoveryAction::Retry,
+        }
+    }
+
+    /// A key, signature or encryption operation failed.
+    pub fn crypto(detail: impl Into<String>) -> Self {
+        Self {
+            category: ErrorCategory::Crypto,
+            message: "A security check failed.".into(),
+            detail: detail.into(),
+            retryable: false,
+            recovery: RecoveryAction::Dismiss,
+        }
+    }
+
+    /// Something the user provided is not valid.
+    pub fn user_input(detail: impl Into<String>) -> Self {
+        Self {
+            category: ErrorCategory::UserInput,
+            message: "That didn't work. Please check and try again.".into(),
+            detail: detail.into(),
+            retryable: false,
+            recovery: RecoveryAction::ScanAgain,
+        }
+    }
+
+    /// Replace the message shown to the user.
+    #[must_use]
+    pub fn with_message(mut self, message: impl Into<String>) -> Self {
+        self.message = message.into();
+        self
+    }
+}
+
+impl From<&ServiceError> for WalletError {
+    fn from(error: &ServiceError) -> Self {
+        let detail = error.to_string();
+        match error.error {
+            ErrorCode::InvalidGrant => Self::user_input(detail)
+                .with_message("The offer or PIN is no longer valid. Scan the offer again."),
+            ErrorCode::InvalidToken => Self {
+                retryable: true,
+                recovery: RecoveryAction::Retry,
+                ..Self::protocol(detail).with_message("Your session with the issuer has expired.")
+            },
+            ErrorCode::AccessDenied | ErrorCode::CredentialRequestDenied => {
+                Self::protocol(detail).with_message("The request was declined.")
+            }
+            ErrorCode::IssuancePending => Self {
+                retryable: true,
+                recovery: RecoveryAction::Retry,
+                ..Self::protocol(detail).with_message("The credential is not ready yet.")
+            },
+            _ if error.is_transient() => Self {
+                retryable: true,
+                recovery: RecoveryAction::Retry,
+                ..Self::protocol(detail)
+                    .with_message("The service is unavailable. Try again later.")
+            },
+            _ => Self::protocol(detail),
+        }
+    }
+}
+
+impl From<HttpError> for WalletError {
+    fn from(error: HttpError) -> Self {
+        Self::network(error.to_string())
+    }
+}
+
+impl From<StoreError> for WalletError {
+    fn from(error: StoreError) -> Self {
+        Self::storage(error.to_string())
+    }
+}
+
+impl From<ClockError> for WalletError {
+    fn from(error: ClockError) -> Self {
+        Self::protocol(error.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::Model;
+    use crate::{Aspect, Event};
+
+    // Dismissing an error returns to the previous aspect, and only retryable
+    // errors repeat the action that caused them.
+    #[test]
+    fn dismiss_and_retry() {
+        let action = Event::RefreshDisplayMetadata;
+        let mut model = Model {
+            active_view: Aspect::CredentialList,
+            ..Model::default()
+        };
+        model.failed(Some(action.clone()), WalletError::network("connection refused"));
+        model.failed(Some(action.clone()), WalletError::network("connection refused again"));
+        assert_eq!(model.active_view, Aspect::Error);
+
+        assert_eq!(model.retry_last_action(), Some(action.clone()));
+        assert_eq!(model.active_view, Aspect::CredentialList);
+        assert!(model.error.is_none());
+
+        // Nothing is repeated without an error, for an error no action caused
+        // or for an error retrying cannot fix.
+        assert_eq!(model.retry_last_action(), None);
+        model.error(WalletError::network("stream closed"));
+        assert_eq!(model.retry_last_action(), None);
+        model.failed(Some(action), WalletError::crypto("bad signature"));
+        assert_eq!(model.retry_last_action(), None);
+        assert_eq!(model.active_view, Aspect::CredentialList);
+
+        model.error(WalletError::user_input("bad offer"));
+        model.dismiss_error();
+        assert_eq!(model.active_view, Aspect::CredentialList);
+    }
+}
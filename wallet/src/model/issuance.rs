@@ -2,12 +2,20 @@
 use std::collections::HashMap;
 
 use anyhow::bail;
-use vercre_holder::credential::ImageData;
-use vercre_holder::issuance::{
-    Accepted, IssuanceFlow, NotAccepted, PreAuthorized, WithOffer, WithToken, WithoutToken,
+use chrono::{DateTime, TimeDelta, Utc};
+use serde::Serialize;
+use serde_json::Value;
+use vercre_holder::credential::{Credential, ImageData};
+use vercre_holder::{
+    CredentialConfiguration, CredentialOffer, PreAuthorizedCodeGrant, TokenResponse,
 };
-use vercre_holder::{CredentialConfiguration, CredentialOffer, PreAuthorizedCodeGrant};
 
+use crate::model::compatibility::Compatibility;
+use crate::model::locale::LocalePreference;
+use crate::provider::issuance::{IssuedCredential, IssuerMetadata};
+use crate::provider::nonce::NonceResponse;
+use crate::provider::proof::CredentialProof;
+use crate::provider::response::{ServiceError, ServiceRecovery};
 use crate::provider::Provider;
 
 /// Configuration and image information for an offered credential.
@@ -27,38 +35,185 @@ pub struct OfferedCredential {
 
     /// Credential has been stored.
     pub stored: bool,
+
+    /// Whether the wallet can handle the credential.
+    pub compatibility: Compatibility,
 }
 
 impl OfferedCredential {
-    /// Determine if the credential logo needs to be fetched.
-    pub fn needs_logo(&self) -> bool {
-        if self.logo.is_some() {
-            return false;
+    /// Create an offered credential from its configuration in the issuer's
+    /// metadata, evaluating whether the wallet can handle it.
+    pub fn new(config: CredentialConfiguration) -> Self {
+        Self {
+            compatibility: Compatibility::evaluate(&config),
+            config,
+            ..Self::default()
         }
-        if let Some(display) = &self.config.display {
-            if let Some(logo) = &display[0].logo {
-                if logo.uri.is_some() {
-                    return true;
-                }
-            }
+    }
+
+    /// URI of the logo of the credential's display for the locale, if it
+    /// needs to be fetched.
+    pub fn logo_uri(&self, locale: &LocalePreference) -> Option<String> {
+        if self.logo.is_some() {
+            return None;
         }
-        false
+        let display_list = self.config.display.as_deref()?;
+        let display = locale.lookup(display_list, |display| display.locale.as_deref())?;
+        display.logo.as_ref()?.uri.clone()
     }
 
-    /// Determine if the credential background needs to be fetched.
-    pub fn needs_background(&self) -> bool {
+    /// URI of the background image of the credential's display for the
+    /// locale, if it needs to be fetched.
+    pub fn background_uri(&self, locale: &LocalePreference) -> Option<String> {
         if self.background.is_some() {
-            return false;
+            return None;
         }
-        if let Some(display) = &self.config.display {
-            if let Some(background) = &display[0].background_image {
-                if background.uri.is_some() {
-                    return true;
-                }
-            }
+        let display_list = self.config.display.as_deref()?;
+        let display = locale.lookup(display_list, |display| display.locale.as_deref())?;
+        display.background_image.as_ref()?.uri.clone()
+    }
+}
+
+/// A nonce from the issuer for proofs of possession.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProofNonce {
+    /// The nonce.
+    pub value: String,
+
+    /// When the nonce expires, if the issuer said.
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+impl ProofNonce {
+    /// Create a nonce received at the given time with the given lifetime in
+    /// seconds.
+    pub fn new(
+        value: impl Into<String>, expires_in: Option<i64>, received: Option<DateTime<Utc>>,
+    ) -> Self {
+        Self {
+            value: value.into(),
+            expires_at: received.zip(expires_in).map(|(at, secs)| at + TimeDelta::seconds(secs)),
         }
-        false
     }
+
+    /// Determine if the nonce can still be used. A nonce without a known
+    /// expiry is assumed to be usable until the issuer rejects it.
+    pub fn is_fresh(&self, now: Option<DateTime<Utc>>) -> bool {
+        match (self.expires_at, now) {
+            (Some(expires_at), Some(now)) => now < expires_at,
+            _ => true,
+        }
+    }
+}
+
+/// Nonce state for the proofs of an issuance.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct NonceState {
+    /// The latest nonce from the issuer, from the token response, an error
+    /// response or the nonce endpoint.
+    pub c_nonce: Option<ProofNonce>,
+
+    /// The issuer's nonce endpoint, if it advertises one.
+    pub endpoint: Option<String>,
+
+    /// A proof has already been rebuilt after the issuer rejected its nonce.
+    /// A second rejection is not retried.
+    pub retried: bool,
+}
+
+impl NonceState {
+    /// Create the nonce state on receiving an access token. The token
+    /// response's nonce is used unless the issuer advertises a nonce endpoint.
+    pub fn new(
+        metadata: &impl Serialize, token: &TokenResponse, now: Option<DateTime<Utc>>,
+    ) -> Self {
+        let endpoint = serde_json::to_value(metadata)
+            .ok()
+            .and_then(|metadata| metadata.get("nonce_endpoint")?.as_str().map(String::from));
+        let c_nonce = match (&endpoint, &token.c_nonce) {
+            (None, Some(c_nonce)) => Some(ProofNonce::new(c_nonce, token.c_nonce_expires_in, now)),
+            _ => None,
+        };
+        Self {
+            c_nonce,
+            endpoint,
+            retried: false,
+        }
+    }
+}
+
+/// An offer whose issuer's metadata has been received, with the user's
+/// progress through it.
+#[derive(Clone, Debug)]
+pub struct Issuance {
+    /// The credential offer.
+    pub offer: CredentialOffer,
+
+    /// The offer's pre-authorized code grant.
+    pub grant: PreAuthorizedCodeGrant,
+
+    /// The issuer's metadata.
+    pub metadata: Value,
+
+    /// Whether the issuer's metadata was signed.
+    pub signed_metadata: bool,
+
+    /// The offered credentials, keyed by credential configuration ID.
+    pub offerred: HashMap<String, OfferedCredential>,
+
+    /// Transaction code entered by the user, if the offer requires one.
+    pub pin: Option<String>,
+
+    /// A new access token has been requested after the issuer rejected one,
+    /// and no credential has been received with it yet. A second rejection is
+    /// not retried.
+    pub reauthorized: bool,
+}
+
+impl Issuance {
+    /// The credential issuer's identifier.
+    pub fn credential_issuer(&self) -> &str {
+        &self.offer.credential_issuer
+    }
+
+    /// Configuration ID of the next credential to request: the first, by ID,
+    /// of the supported credentials that have not been received.
+    pub fn next_credential(&self) -> Option<String> {
+        self.offerred
+            .iter()
+            .filter(|(_, offered)| offered.compatibility.is_supported() && !offered.received)
+            .map(|(config_id, _)| config_id)
+            .min()
+            .cloned()
+    }
+
+    /// The offer requires a transaction code the user has not entered yet.
+    pub fn needs_pin(&self) -> bool {
+        self.grant.tx_code.is_some() && self.pin.is_none()
+    }
+}
+
+/// The next step of an issuance.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum IssuanceStep {
+    /// Request an access token with the offer's pre-authorized code.
+    RequestToken,
+
+    /// Fetch a fresh nonce from the issuer's nonce endpoint.
+    FetchNonce(String),
+
+    /// Create a proof of possession for the credential of the given
+    /// configuration, over the issuer's nonce if it has given one.
+    Prove { config_id: String, nonce: Option<String> },
+
+    /// Request the credential of the given configuration with its proof.
+    RequestCredential { config_id: String, proof: Option<CredentialProof> },
+
+    /// Verify and store a received credential.
+    Save { config_id: String, credential: Credential },
+
+    /// Every supported credential has been stored.
+    Complete,
 }
 
 /// Application state for the issuance sub-app.
@@ -70,37 +225,26 @@ pub enum IssuanceState {
     #[default]
     Inactive,
 
-    /// An offer has been received
+    /// An offer has been received. The issuer's metadata is needed before the
+    /// offered credentials are known.
     Offered { offer: CredentialOffer, grant: PreAuthorizedCodeGrant },
 
-    /// Issuer metadata has been received. Can use this state to keep updating
+    /// Issuer metadata has been received, so the offered credentials can be
+    /// checked before the user accepts. Can use this state to keep updating
     /// the offered credentials' logo and background images.
-    IssuerMetadata {
-        flow: IssuanceFlow<WithOffer, PreAuthorized, NotAccepted, WithoutToken>,
-        offerred: HashMap<String, OfferedCredential>,
-    },
+    IssuerMetadata { issuance: Issuance },
 
     /// The offer has been accepted by the user. Can use this state to update
     /// the PIN number if needed.
-    Accepted {
-        flow: IssuanceFlow<WithOffer, PreAuthorized, Accepted, WithoutToken>,
-        offerred: HashMap<String, OfferedCredential>,
-    },
-
-    /// An access token has been received.
-    Token {
-        flow: IssuanceFlow<WithOffer, PreAuthorized, Accepted, WithToken>,
-        offerred: HashMap<String, OfferedCredential>,
-    },
-
-    /// A proof has been created. Can use this state to receive credentials and
-    /// update the offered list to keep track of outstanding credentials. Can
-    /// also use it to keep track of the credentials stored.
-    Proof {
-        flow: IssuanceFlow<WithOffer, PreAuthorized, Accepted, WithToken>,
-        offerred: HashMap<String, OfferedCredential>,
-        proof: String,
-    },
+    Accepted { issuance: Issuance },
+
+    /// An access token has been received. Proofs are created and credentials
+    /// received in this state, one credential at a time.
+    Token { issuance: Issuance, token: TokenResponse, nonce: NonceState },
+
+    /// A proof has been created and the credential of the given configuration
+    /// requested with it.
+    Proof { issuance: Issuance, token: TokenResponse, nonce: NonceState, config_id: String },
 }
 
 /// State change implementation.
@@ -122,12 +266,538 @@ impl IssuanceState {
         })
     }
 
+    /// The offer and what is known of its issuer, once the issuer's metadata
+    /// has been received.
+    pub fn issuance(&self) -> Option<&Issuance> {
+        match self {
+            Self::Inactive | Self::Offered { .. } => None,
+            Self::IssuerMetadata { issuance }
+            | Self::Accepted { issuance }
+            | Self::Token { issuance, .. }
+            | Self::Proof { issuance, .. } => Some(issuance),
+        }
+    }
+
+    fn issuance_mut(&mut self) -> Option<&mut Issuance> {
+        match self {
+            Self::Inactive | Self::Offered { .. } => None,
+            Self::IssuerMetadata { issuance }
+            | Self::Accepted { issuance }
+            | Self::Token { issuance, .. }
+            | Self::Proof { issuance, .. } => Some(issuance),
+        }
+    }
+
+    /// The access token, once one has been received.
+    pub fn token(&self) -> Option<&TokenResponse> {
+        match self {
+            Self::Token { token, .. } | Self::Proof { token, .. } => Some(token),
+            _ => None,
+        }
+    }
+
+    /// The issuer's metadata has been received. Look up the configuration of
+    /// each offered credential and evaluate whether the wallet can handle it.
+    /// An offered credential the issuer has no configuration for is reported
+    /// as unsupported.
+    pub fn issuer_metadata(&mut self, issuer: IssuerMetadata) {
+        let Self::Offered { offer, grant } = self else {
+            return;
+        };
+        let IssuerMetadata { metadata, signed } = issuer;
+        let offer_value = serde_json::to_value(&*offer).unwrap_or_default();
+        let config_ids = offer_value
+            .get("credential_configuration_ids")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten();
+        let mut offerred = HashMap::new();
+        for config_id in config_ids.filter_map(Value::as_str) {
+            let config = metadata
+                .get("credential_configurations_supported")
+                .and_then(|configs| configs.get(config_id))
+                .cloned()
+                .and_then(|config| serde_json::from_value(config).ok());
+            let offered = config.map_or_else(
+                || OfferedCredential {
+                    compatibility: Compatibility::Unsupported(vec![format!(
+                        "the issuer does not describe the {config_id} credential"
+                    )]),
+                    ..OfferedCredential::default()
+                },
+                OfferedCredential::new,
+            );
+            offerred.insert(config_id.into(), offered);
+        }
+        *self = Self::IssuerMetadata {
+            issuance: Issuance {
+                offer: offer.clone(),
+                grant: grant.clone(),
+                signed_metadata: signed,
+                metadata,
+                offerred,
+                pin: None,
+                reauthorized: false,
+            },
+        };
+    }
+
+    /// An offered credential's logo has been fetched.
+    pub fn logo_received(&mut self, config_id: &str, logo: ImageData) {
+        if let Some(offered) =
+            self.issuance_mut().and_then(|issuance| issuance.offerred.get_mut(config_id))
+        {
+            offered.logo = Some(logo);
+        }
+    }
+
+    /// An offered credential's background image has been fetched.
+    pub fn background_received(&mut self, config_id: &str, background: ImageData) {
+        if let Some(offered) =
+            self.issuance_mut().and_then(|issuance| issuance.offerred.get_mut(config_id))
+        {
+            offered.background = Some(background);
+        }
+    }
+
+    /// The user has accepted the offer. Get the step that requests an access
+    /// token, or `None` if the user must enter a PIN first.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no offer is awaiting acceptance or the wallet can
+    /// handle none of the offered credentials.
+    pub fn accept(&mut self) -> anyhow::Result<Option<IssuanceStep>> {
+        let Self::IssuerMetadata { issuance } = self else {
+            bail!("no offer is awaiting acceptance");
+        };
+        if issuance.next_credential().is_none() {
+            bail!("none of the offered credentials can be issued to this wallet");
+        }
+        let needs_pin = issuance.needs_pin();
+        *self = Self::Accepted {
+            issuance: issuance.clone(),
+        };
+        Ok((!needs_pin).then_some(IssuanceStep::RequestToken))
+    }
+
+    /// The user has entered the offer's PIN. Get the step that requests an
+    /// access token with it.
+    pub fn pin(&mut self, pin: String) -> Option<IssuanceStep> {
+        let Self::Accepted { issuance } = self else {
+            return None;
+        };
+        issuance.pin = Some(pin);
+        Some(IssuanceStep::RequestToken)
+    }
+
+    /// An access token has been received. Get the step that creates the proof
+    /// for the first credential.
+    pub fn token_received(
+        &mut self, token: TokenResponse, now: Option<DateTime<Utc>>,
+    ) -> Option<IssuanceStep> {
+        let Self::Accepted { issuance } = self else {
+            return None;
+        };
+        *self = Self::Token {
+            nonce: NonceState::new(&issuance.metadata, &token, now),
+            issuance: issuance.clone(),
+            token,
+        };
+        self.prove(now)
+    }
+
+    /// A proof has been created for the credential of the given
+    /// configuration. Get the step that requests the credential with it.
+    pub fn proof_created(
+        &mut self, config_id: String, proof: Option<CredentialProof>,
+    ) -> Option<IssuanceStep> {
+        let Self::Token {
+            issuance,
+            token,
+            nonce,
+        } = self
+        else {
+            return None;
+        };
+        *self = Self::Proof {
+            issuance: issuance.clone(),
+            token: token.clone(),
+            nonce: nonce.clone(),
+            config_id: config_id.clone(),
+        };
+        Some(IssuanceStep::RequestCredential { config_id, proof })
+    }
+
+    /// The requested credential has been received. Keep any nonce the issuer
+    /// sent with it for the next proof and get the step that stores it.
+    pub fn credential_received(
+        &mut self, issued: IssuedCredential, now: Option<DateTime<Utc>>,
+    ) -> Option<IssuanceStep> {
+        let Self::Proof {
+            issuance,
+            token,
+            nonce,
+            config_id,
+        } = self
+        else {
+            return None;
+        };
+        if *config_id != issued.config_id {
+            return None;
+        }
+        let mut issuance = issuance.clone();
+        if let Some(offered) = issuance.offerred.get_mut(config_id) {
+            offered.received = true;
+        }
+        issuance.reauthorized = false;
+        let c_nonce = match (&nonce.endpoint, issued.c_nonce) {
+            (None, Some(c_nonce)) => Some(ProofNonce::new(c_nonce, issued.c_nonce_expires_in, now)),
+            _ => nonce.c_nonce.clone(),
+        };
+        *self = Self::Token {
+            issuance,
+            token: token.clone(),
+            nonce: NonceState {
+                c_nonce,
+                endpoint: nonce.endpoint.clone(),
+                retried: false,
+            },
+        };
+        Some(IssuanceStep::Save {
+            config_id: issued.config_id,
+            credential: issued.credential,
+        })
+    }
+
+    /// A received credential has been stored. Get the step that creates the
+    /// proof for the next credential, or completes the issuance.
+    pub fn credential_stored(
+        &mut self, config_id: &str, now: Option<DateTime<Utc>>,
+    ) -> Option<IssuanceStep> {
+        let Self::Token { issuance, .. } = self else {
+            return None;
+        };
+        issuance.offerred.get_mut(config_id)?.stored = true;
+        self.prove(now)
+    }
+
+    /// The issuer has rejected a request. Step back to where the error can be
+    /// recovered from and get the next step, or `None` if the error cannot be
+    /// recovered from.
+    ///
+    /// A proof rejected for its nonce is rebuilt, once, over a fresh nonce:
+    /// the one the issuer supplied with the error or one from its nonce
+    /// endpoint. A rejected access token is replaced, once, by requesting a
+    /// new one.
+    pub fn recover(
+        &mut self, error: &ServiceError, now: Option<DateTime<Utc>>,
+    ) -> Option<IssuanceStep> {
+        match (error.recovery(), std::mem::take(self)) {
+            (
+                ServiceRecovery::RefreshNonce(c_nonce),
+                Self::Proof {
+                    issuance,
+                    token,
+                    nonce,
+                    ..
+                },
+            ) if !nonce.retried => {
+                *self = Self::Token {
+                    issuance,
+                    token,
+                    nonce: NonceState {
+                        c_nonce: c_nonce
+                            .map(|c_nonce| ProofNonce::new(c_nonce, error.c_nonce_expires_in, now)),
+                        retried: true,
+                        ..nonce
+                    },
+                };
+                self.prove(now)
+            }
+            (
+                ServiceRecovery::Reauthorize,
+                Self::Token { issuance, .. } | Self::Proof { issuance, .. },
+            ) if !issuance.reauthorized => {
+                *self = Self::Accepted {
+                    issuance: Issuance {
+                        reauthorized: true,
+                        ..issuance
+                    },
+                };
+                Some(IssuanceStep::RequestToken)
+            }
+            (_, state) => {
+                *self = state;
+                None
+            }
+        }
+    }
+
+    /// A nonce has been fetched from the issuer's nonce endpoint. Get the next
+    /// step.
+    pub fn nonce_received(
+        &mut self, response: &NonceResponse, now: Option<DateTime<Utc>>,
+    ) -> Option<IssuanceStep> {
+        let Self::Token { nonce, .. } = self else {
+            return None;
+        };
+        nonce.c_nonce = Some(ProofNonce::new(&response.c_nonce, response.c_nonce_expires_in, now));
+        self.prove(now)
+    }
+
+    /// Get the step that creates a proof for the next credential: over a
+    /// fresh nonce, after fetching one from the nonce endpoint if there is no
+    /// fresh nonce, or over the latest nonce if there is no way to get a fresh
+    /// one. Complete the issuance if every supported credential has been
+    /// received.
+    pub fn prove(&self, now: Option<DateTime<Utc>>) -> Option<IssuanceStep> {
+        let Self::Token { issuance, nonce, .. } = self else {
+            return None;
+        };
+        let Some(config_id) = issuance.next_credential() else {
+            return Some(IssuanceStep::Complete);
+        };
+        let nonce = match (&nonce.c_nonce, &nonce.endpoint) {
+            (Some(c_nonce), _) if c_nonce.is_fresh(now) => Some(c_nonce.value.clone()),
+            (_, Some(endpoint)) => return Some(IssuanceStep::FetchNonce(endpoint.clone())),
+            (c_nonce, None) => c_nonce.as_ref().map(|c_nonce| c_nonce.value.clone()),
+        };
+        Some(IssuanceStep::Prove { config_id, nonce })
+    }
+
     /// Cancel the issuance process.
     pub fn cancel<Ev>(&mut self, _provider: &Provider<Ev>) -> anyhow::Result<()>
     where
         Ev: 'static,
     {
-        // TODO: Reset state
+        *self = Self::Inactive;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::model::Model;
+    use crate::provider::response::ErrorCode;
+    use crate::Aspect;
+
+    const CONFIG_ID: &str = "EmployeeID_JWT";
+
+    // A URL-encoded offer of one credential.
+    fn encoded_offer() -> String {
+        let offer = json!({
+            "credential_issuer": "https://issuer.example",
+            "credential_configuration_ids": [CONFIG_ID],
+            "grants": {
+                "urn:ietf:params:oauth:grant-type:pre-authorized_code": {
+                    "pre-authorized_code": "code"
+                }
+            }
+        });
+        urlencoding::encode(&offer.to_string()).into_owned()
+    }
+
+    // An issuance with an access token whose nonce is `n1`, that has
+    // requested its credential with a proof.
+    fn credential_requested(now: Option<DateTime<Utc>>) -> IssuanceState {
+        let mut state = IssuanceState::from_offer(&encoded_offer()).expect("should read offer");
+        let metadata = json!({
+            "credential_issuer": "https://issuer.example",
+            "credential_configurations_supported": {
+                CONFIG_ID: {
+                    "format": "jwt_vc_json",
+                    "cryptographic_binding_methods_supported": ["did:key"],
+                    "credential_signing_alg_values_supported": ["EdDSA"],
+                    "proof_types_supported": {
+                        "jwt": { "proof_signing_alg_values_supported": ["EdDSA"] }
+                    },
+                    "credential_definition": {
+                        "type": ["VerifiableCredential", "EmployeeIDCredential"]
+                    }
+                }
+            }
+        });
+        state.issuer_metadata(IssuerMetadata {
+            metadata,
+            signed: false,
+        });
+        assert_eq!(state.accept().expect("should accept"), Some(IssuanceStep::RequestToken));
+
+        let token = serde_json::from_value(json!({
+            "access_token": "token",
+            "token_type": "Bearer",
+            "expires_in": 900,
+            "c_nonce": "n1",
+            "c_nonce_expires_in": 300
+        }))
+        .expect("should read token");
+        let prove = IssuanceStep::Prove {
+            config_id: CONFIG_ID.into(),
+            nonce: Some("n1".into()),
+        };
+        assert_eq!(state.token_received(token, now), Some(prove));
+        let request = state.proof_created(CONFIG_ID.into(), None);
+        assert!(matches!(request, Some(IssuanceStep::RequestCredential { .. })));
+        state
+    }
+
+    fn rejected(error: ErrorCode, c_nonce: Option<&str>) -> ServiceError {
+        ServiceError {
+            status: 400,
+            error,
+            c_nonce: c_nonce.map(String::from),
+            ..ServiceError::default()
+        }
+    }
+
+    // A nonce expires after its lifetime, and one without a known expiry is
+    // used until the issuer rejects it.
+    #[test]
+    fn nonce_expiry() {
+        let received = DateTime::from_timestamp(1_700_000_000, 0);
+        let later = received.map(|at| at + TimeDelta::seconds(300));
+        let nonce = ProofNonce::new("n1", Some(300), received);
+        assert!(nonce.is_fresh(received));
+        assert!(!nonce.is_fresh(later));
+        assert!(nonce.is_fresh(None));
+        assert!(ProofNonce::new("n2", None, received).is_fresh(later));
+    }
+
+    // A proof rejected for its nonce steps back to the token and is rebuilt
+    // over the issuer's fresh nonce, once. A second rejection is shown to the
+    // user.
+    #[test]
+    fn recover_rejected_nonce() {
+        let now = DateTime::from_timestamp(1_700_000_000, 0);
+        let mut state = credential_requested(now);
+
+        let next = state.recover(&rejected(ErrorCode::InvalidNonce, Some("n2")), now);
+        let prove = IssuanceStep::Prove {
+            config_id: CONFIG_ID.into(),
+            nonce: Some("n2".into()),
+        };
+        assert_eq!(next, Some(prove));
+        let IssuanceState::Token { nonce, .. } = &state else {
+            panic!("should step back to the token");
+        };
+        assert!(nonce.retried);
+
+        state.proof_created(CONFIG_ID.into(), None);
+        let mut model = Model {
+            active_view: Aspect::IssuanceOffer,
+            issuance: Some(state),
+            ..Model::default()
+        };
+        let next = model.issuance_rejected(&rejected(ErrorCode::InvalidProof, Some("n3")));
+        assert_eq!(next, None);
+        assert_eq!(model.active_view, Aspect::Error);
+    }
+
+    // A rejected access token steps back to the accepted offer and a new
+    // token is requested. If the new token is rejected too, the error is
+    // reported.
+    #[test]
+    fn recover_rejected_token() {
+        let now = DateTime::from_timestamp(1_700_000_000, 0);
+        let mut state = credential_requested(now);
+
+        let next = state.recover(&rejected(ErrorCode::InvalidToken, None), now);
+        assert_eq!(next, Some(IssuanceStep::RequestToken));
+        assert!(matches!(state, IssuanceState::Accepted { .. }));
+
+        let token = serde_json::from_value(json!({
+            "access_token": "token2",
+            "token_type": "Bearer",
+            "expires_in": 900
+        }))
+        .expect("should read token");
+        assert!(state.token_received(token, now).is_some());
+        let request = state.proof_created(CONFIG_ID.into(), None);
+        assert!(matches!(request, Some(IssuanceStep::RequestCredential { .. })));
+
+        let next = state.recover(&rejected(ErrorCode::InvalidToken, None), now);
+        assert_eq!(next, None);
+        assert!(matches!(state, IssuanceState::Proof { .. }));
+    }
+
+    // Images are fetched for the display matching the locale, and none for an
+    // empty display list.
+    #[test]
+    fn offer_images_for_locale() {
+        let config = |display: Value| {
+            let config = json!({
+                "format": "jwt_vc_json",
+                "credential_definition": {
+                    "type": ["VerifiableCredential", "EmployeeIDCredential"]
+                },
+                "display": display,
+            });
+            OfferedCredential::new(serde_json::from_value(config).expect("should deserialize"))
+        };
+        let offered = config(json!([
+            { "name": "Employee ID", "locale": "en-NZ",
+              "logo": { "uri": "https://issuer.example/en.png" } },
+            { "name": "Carte d'employé", "locale": "fr-FR",
+              "logo": { "uri": "https://issuer.example/fr.png" } },
+        ]));
+        let locale = LocalePreference {
+            locale: "fr-FR".into(),
+            ..LocalePreference::default()
+        };
+        assert_eq!(offered.logo_uri(&locale).as_deref(), Some("https://issuer.example/fr.png"));
+
+        let offered = config(json!([]));
+        assert_eq!(offered.logo_uri(&locale), None);
+        assert_eq!(offered.background_uri(&locale), None);
+    }
+
+    // An offer is shown while its issuer's metadata is fetched, and then with
+    // the images of the offered credentials as they are fetched.
+    #[test]
+    fn offer_images() {
+        let mut model = Model::default();
+        model.issuance_offer(&encoded_offer());
+        assert_eq!(model.active_view, Aspect::IssuanceOffer);
+
+        let metadata = json!({
+            "credential_issuer": "https://issuer.example",
+            "credential_configurations_supported": {
+                CONFIG_ID: {
+                    "format": "jwt_vc_json",
+                    "credential_definition": {
+                        "type": ["VerifiableCredential", "EmployeeIDCredential"]
+                    },
+                    "display": [{
+                        "name": "Employee ID",
+                        "logo": { "uri": "https://issuer.example/logo.png" }
+                    }]
+                }
+            }
+        });
+        model.issuance_issuer(IssuerMetadata {
+            metadata,
+            signed: false,
+        });
+        let offered = |model: &Model| {
+            let issuance = model.issuance.as_ref().and_then(IssuanceState::issuance);
+            issuance.and_then(|issuance| issuance.offerred.get(CONFIG_ID)).cloned()
+        };
+        let before = offered(&model).expect("should offer credential");
+        let locale = LocalePreference::default();
+        assert_eq!(before.logo_uri(&locale).as_deref(), Some("https://issuer.example/logo.png"));
+        assert_eq!(before.background_uri(&locale), None);
+
+        let logo = ImageData {
+            data: "bG9nbw".into(),
+            media_type: "image/png".into(),
+        };
+        model.issuance_logo(CONFIG_ID, logo.clone());
+        let after = offered(&model).expect("should offer credential");
+        assert_eq!(after.logo, Some(logo));
+        assert_eq!(after.logo_uri(&locale), None);
+    }
+}
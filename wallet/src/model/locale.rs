@@ -0,0 +1,164 @@
+//! Locale preference for display metadata and formatting.
+
+use chrono::{DateTime, Locale, Utc};
+use chrono_tz::Tz;
+use pure_rust_locales::locale_match;
+use serde::{Deserialize, Serialize};
+
+/// Conversions of a date and time format that format the time rather than the
+/// date.
+const TIME_CONVERSIONS: [&str; 14] =
+    ["%T", "%X", "%r", "%R", "%H", "%I", "%k", "%l", "%M", "%S", "%p", "%P", "%Z", "%z"];
+
+/// The user's locale and time zone, as set by the shell.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[allow(clippy::module_name_repetitions)]
+pub struct LocalePreference {
+    /// BCP 47 language tag, such as `en-NZ`. Empty if the user has no
+    /// preference.
+    pub locale: String,
+
+    /// IANA name of the user's time zone, such as `Pacific/Auckland`, so
+    /// dates are shown with the offset in effect on the day, daylight saving
+    /// included. Dates are shown in UTC if the time zone is empty or unknown.
+    pub time_zone: String,
+}
+
+impl LocalePreference {
+    /// Choose the display entry best matching the preferred locale.
+    ///
+    /// Entries are matched by BCP 47 lookup: the preferred tag is truncated
+    /// one subtag at a time until an entry's locale matches. Failing that, an
+    /// entry in the same language is chosen, then an entry with no locale,
+    /// then the first entry.
+    pub fn lookup<'a, T>(
+        &self, entries: &'a [T], locale_of: impl Fn(&T) -> Option<&str>,
+    ) -> Option<&'a T> {
+        let matches = |entry: &&T, range: &str| {
+            locale_of(entry).is_some_and(|locale| locale.eq_ignore_ascii_case(range))
+        };
+
+        let mut range = self.locale.as_str();
+        while !range.is_empty() {
+            if let Some(entry) = entries.iter().find(|entry| matches(entry, range)) {
+                return Some(entry);
+            }
+            range = truncate(range);
+        }
+
+        let language = self.locale.split('-').next().unwrap_or_default();
+        entries
+            .iter()
+            .find(|entry| {
+                !language.is_empty()
+                    && locale_of(entry)
+                        .and_then(|l| l.split('-').next())
+                        .is_some_and(|l| l.eq_ignore_ascii_case(language))
+            })
+            .or_else(|| entries.iter().find(|entry| locale_of(entry).is_none()))
+            .or_else(|| entries.first())
+    }
+
+    /// Format a date for the preferred locale and time zone.
+    #[must_use]
+    pub fn format_date(&self, date: &DateTime<Utc>) -> String {
+        let locale = self.date_locale();
+        let format = long_date_format(locale);
+        let time_zone = self.time_zone.parse().unwrap_or(Tz::UTC);
+        date.with_timezone(&time_zone).format_localized(&format, locale).to_string()
+    }
+
+    /// The value to send in an `Accept-Language` header, if the user has a
+    /// preference.
+    #[must_use]
+    pub fn accept_language(&self) -> Option<String> {
+        (!self.locale.is_empty()).then(|| self.locale.clone())
+    }
+
+    /// Get the locale dates are formatted with: the preferred language and
+    /// region, or the language's own region, or POSIX.
+    fn date_locale(&self) -> Locale {
+        let mut subtags = self.locale.split('-');
+        let language = subtags.next().unwrap_or_default().to_lowercase();
+        let region = subtags.find(|s| s.len() == 2).map(str::to_uppercase);
+        let candidates = [
+            region.map(|region| format!("{language}_{region}")),
+            Some(format!("{language}_{}", language.to_uppercase())),
+        ];
+        candidates
+            .into_iter()
+            .flatten()
+            .find_map(|name| Locale::try_from(name.as_str()).ok())
+            .unwrap_or(Locale::POSIX)
+    }
+}
+
+/// Get the long date format of a locale: the date part of its date and time
+/// format, with the year in full.
+fn long_date_format(locale: Locale) -> String {
+    let date_time = locale_match!(locale => LC_TIME::D_T_FMT);
+    let format = date_time.replace("%x", locale_match!(locale => LC_TIME::D_FMT));
+    let end = TIME_CONVERSIONS.iter().filter_map(|c| format.find(c)).min().unwrap_or(format.len());
+    let mut format = format[..end]
+        .trim_end_matches(|c: char| c.is_whitespace() || c == ',')
+        .replace("%y", "%Y")
+        .replace("%e", "%-e");
+    if !format.contains("%Y") {
+        format.push_str(" %Y");
+    }
+    format
+}
+
+/// Remove the last subtag of a language tag, along with any single-character
+/// subtag left at its end.
+fn truncate(tag: &str) -> &str {
+    let mut tag = tag.rfind('-').map_or("", |i| &tag[..i]);
+    if let Some(i) = tag.rfind('-') {
+        if tag.len() - i == 2 {
+            tag = &tag[..i];
+        }
+    }
+    tag
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Display entries are chosen by BCP 47 lookup with fallback to the same
+    // language, no locale and then the first entry.
+    #[test]
+    fn lookup_fallback() {
+        let entries = [Some("fr"), Some("en-NZ"), None, Some("en")];
+        let lookup = |locale: &str| {
+            let preference = LocalePreference {
+                locale: locale.into(),
+                time_zone: String::new(),
+            };
+            *preference.lookup(&entries, |e| *e).expect("should find an entry")
+        };
+        assert_eq!(lookup("en-NZ"), Some("en-NZ"));
+        assert_eq!(lookup("en-nz-x-private"), Some("en-NZ"));
+        assert_eq!(lookup("en-US"), Some("en"));
+        assert_eq!(lookup("fr-CA"), Some("fr"));
+        assert_eq!(lookup("de"), None);
+        assert_eq!(lookup(""), None);
+    }
+
+    // Dates are formatted with the preferred locale's long date format, in
+    // the preferred time zone with the offset in effect on the day.
+    #[test]
+    fn format_dates() {
+        let summer = DateTime::from_timestamp(1_730_160_000, 0).expect("should be in range");
+        let winter = DateTime::from_timestamp(1_719_747_000, 0).expect("should be in range");
+        let preference = |locale: &str| LocalePreference {
+            locale: locale.into(),
+            time_zone: "Pacific/Auckland".into(),
+        };
+        assert_eq!(preference("en-NZ").format_date(&summer), "Tue 29 Oct 2024");
+        assert_eq!(preference("en-NZ").format_date(&winter), "Sun 30 Jun 2024");
+        assert_eq!(preference("de-DE").format_date(&summer), "Di 29 Okt 2024");
+        assert_eq!(preference("ja-JP").format_date(&summer), "2024年10月29日");
+        assert_eq!(LocalePreference::default().format_date(&summer), "Tue Oct 29 2024");
+    }
+}
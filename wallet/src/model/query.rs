@@ -0,0 +1,71 @@
+//! Search, filter and sort of the credential list.
+
+use serde::{Deserialize, Serialize};
+
+use crate::model::credential::Validity;
+
+/// Field the credential list is sorted by.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub enum CredentialSort {
+    /// The order credentials are held in the store.
+    #[default]
+    Stored,
+
+    /// The date the credential was issued.
+    IssuanceDate,
+
+    /// The date the credential expires. Credentials that do not expire are
+    /// sorted last.
+    Expiry,
+
+    /// The credential's display name.
+    Name,
+}
+
+/// Criteria a credential must meet to be listed. An empty list places no
+/// restriction on the corresponding field.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct CredentialFilter {
+    /// Credential types, any of which the credential must have.
+    pub types: Vec<String>,
+
+    /// Issuer IDs, one of which must have issued the credential.
+    pub issuers: Vec<String>,
+
+    /// Credential formats, one of which the credential must be in.
+    pub formats: Vec<String>,
+
+    /// Validity states, one of which the credential must be in.
+    pub validity: Vec<Validity>,
+}
+
+/// The active search, filter and sort applied to the credential list.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[allow(clippy::module_name_repetitions)]
+pub struct CredentialQuery {
+    /// Text to search for in credential names, issuers and claims. Matching is
+    /// case-insensitive. Empty to list all credentials.
+    pub search: String,
+
+    /// Criteria credentials must meet to be listed.
+    pub filter: CredentialFilter,
+
+    /// Field the list is sorted by.
+    pub sort: CredentialSort,
+
+    /// Sort in descending rather than ascending order.
+    pub descending: bool,
+}
+
+impl CredentialFilter {
+    /// Whether a credential with the given attributes meets the criteria.
+    #[must_use]
+    pub fn accepts(
+        &self, types: &[String], issuer: &str, format: &str, validity: &Validity,
+    ) -> bool {
+        (self.types.is_empty() || types.iter().any(|t| self.types.contains(t)))
+            && (self.issuers.is_empty() || self.issuers.iter().any(|i| i == issuer))
+            && (self.formats.is_empty() || self.formats.iter().any(|f| f == format))
+            && (self.validity.is_empty() || self.validity.contains(validity))
+    }
+}
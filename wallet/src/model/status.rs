@@ -0,0 +1,44 @@
+//! Credential status and issuer status updates.
+
+use serde::{Deserialize, Serialize};
+
+/// Path, relative to the credential issuer, of the issuer's status event
+/// stream.
+pub const STATUS_EVENTS_PATH: &str = "/events";
+
+/// Name of status update events on the issuer's event stream.
+pub const STATUS_EVENT: &str = "status";
+
+/// Status of a stored credential.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub enum CredentialStatus {
+    /// The status of the credential has not been determined.
+    #[default]
+    Unknown,
+
+    /// The issuer considers the credential valid.
+    Valid,
+
+    /// The issuer has revoked the credential.
+    Revoked,
+
+    /// The issuer has suspended the credential.
+    Suspended,
+}
+
+/// A status update pushed by an issuer.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum StatusUpdate {
+    /// A deferred credential is ready to be retrieved.
+    DeferredReady { transaction_id: String },
+
+    /// A credential has been revoked.
+    Revoked { credential_id: String },
+
+    /// A credential has been suspended.
+    Suspended { credential_id: String },
+
+    /// A credential is valid again after being suspended.
+    Valid { credential_id: String },
+}
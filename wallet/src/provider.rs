@@ -3,12 +3,32 @@
 //! Implementation of the `vercre-holder` `Provider` traits. Uses capabilities
 //! where necessary to provide the underlying connectivity and storage.
 
+mod cache;
+pub mod dpop;
+pub mod image;
+pub mod issuance;
+pub mod jose;
+pub mod keys;
+mod metadata;
+pub mod nonce;
+pub mod proof;
+pub mod response;
+pub mod schema;
+pub mod status;
+#[cfg(any(test, feature = "testing"))]
+mod testing;
+pub mod vault;
+mod verify;
+
 use anyhow::anyhow;
 use base64ct::{Base64UrlUnpadded, Encoding};
 use chrono::{DateTime, Utc};
-use ed25519_dalek::{Signature, Signer as _, SigningKey};
+use ed25519_dalek::{Signature, Signer as _};
+use futures::StreamExt;
 use serde::de::DeserializeOwned;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
 use vercre_holder::credential::{Credential, ImageData};
 use vercre_holder::provider::{
     Algorithm, CredentialStorer, DidResolver, Document, HolderProvider, Issuer, Signer, StateStore,
@@ -22,61 +42,130 @@ use vercre_holder::{
     TokenResponse,
 };
 
-use crate::capabilities::key::{KeyStore, KeyStoreEntry};
-use crate::capabilities::store::{Catalog, Store, StoreEntry};
+use crate::capabilities::clock::Clock;
+use crate::capabilities::key::KeyStore;
+use crate::capabilities::store::{Catalog, Store, StoreChange, StoreDelta, StoreEntry, StoreIndex};
+use crate::model::credential::{
+    CredentialChange, CredentialRecord, QuarantinedRecord, StoredCredentials, Validity,
+};
+use crate::model::locale::LocalePreference;
+use crate::model::status::CredentialStatus;
+use crate::provider::keys::KeyBinding;
+use crate::provider::response::json_body;
+use crate::provider::vault::{SealedEntry, UnreadableEntry};
 
 pub struct Provider<Ev> {
+    clock: Clock<Ev>,
     http: crux_http::Http<Ev>,
     key_store: KeyStore<Ev>,
     kv: crux_kv::KeyValue<Ev>,
     store: Store<Ev>,
+
+    /// Holder key used for signing and bound to stored credentials.
+    key: KeyBinding,
+
+    /// Include credentials outside their validity period when finding
+    /// credentials to present.
+    include_expired: bool,
+
+    /// The user's locale, to request display metadata in and to choose
+    /// display entries for.
+    locale: LocalePreference,
 }
 
 impl<Ev> Clone for Provider<Ev> {
     fn clone(&self) -> Self {
         Self {
+            clock: self.clock.clone(),
             http: self.http.clone(),
             key_store: self.key_store.clone(),
             kv: self.kv.clone(),
             store: self.store.clone(),
+            key: self.key.clone(),
+            include_expired: self.include_expired,
+            locale: self.locale.clone(),
         }
     }
 }
 
 impl<Ev> Provider<Ev> {
     pub fn new(
-        http: crux_http::Http<Ev>, key_store: KeyStore<Ev>, kv: crux_kv::KeyValue<Ev>,
-        store: Store<Ev>,
+        clock: Clock<Ev>, http: crux_http::Http<Ev>, key_store: KeyStore<Ev>,
+        kv: crux_kv::KeyValue<Ev>, store: Store<Ev>,
     ) -> Self {
         Self {
+            clock,
             http,
             key_store,
             kv,
             store,
+            key: KeyBinding::Scope(keys::LEGACY_KEY_ID.into()),
+            include_expired: false,
+            locale: LocalePreference::default(),
         }
     }
+
+    /// Create a provider that includes, or not, credentials that are expired
+    /// or not yet valid when finding credentials to present.
+    #[must_use]
+    pub fn including_expired(&self, include_expired: bool) -> Self {
+        Self {
+            include_expired,
+            ..self.clone()
+        }
+    }
+
+    /// Create a provider that requests, and chooses, display metadata for
+    /// the user's locale.
+    #[must_use]
+    pub fn with_locale(&self, locale: &LocalePreference) -> Self {
+        Self {
+            locale: locale.clone(),
+            ..self.clone()
+        }
+    }
+
+    /// Create a provider bound to the current pairwise holder key for the
+    /// given issuer. Proofs are signed with, and received credentials are
+    /// bound to, a key that is not shared with any other issuer.
+    #[must_use]
+    pub fn for_issuer(&self, issuer: &str) -> Self {
+        self.with_key(KeyBinding::Scope(keys::issuer_key_id(issuer)))
+    }
+
+    /// Create a provider bound to the given holder key.
+    #[must_use]
+    pub fn with_key(&self, key: KeyBinding) -> Self {
+        Self { key, ..self.clone() }
+    }
 }
 
+/// Media type of JSON request and response bodies.
+const JSON: &str = "application/json";
+
 impl<Ev> HolderProvider for Provider<Ev> where Ev: 'static {}
 
 impl<Ev> Issuer for Provider<Ev>
 where
     Ev: 'static,
 {
-    /// Get issuer metadata from the issuer service endpoint.
+    /// Get issuer metadata from the issuer's well-known endpoint, with display
+    /// metadata for the user's locale where the issuer supports it. The
+    /// metadata must be for the issuer requested, and signed metadata must
+    /// verify.
     async fn metadata(&self, req: MetadataRequest) -> anyhow::Result<MetadataResponse> {
-        let url = format!("{}/.well-known/openid-credential-issuer", req.credential_issuer);
-        let req_bytes = serde_json::to_vec(&req)?;
-        let mut response = self.http.post(url).body_bytes(req_bytes).send_async().await?;
-        let res_bytes = response.body_bytes().await?;
-        let metadata: MetadataResponse = serde_json::from_slice(&res_bytes)?;
-        Ok(metadata)
+        let metadata = self.issuer_metadata(&req.credential_issuer, false).await?;
+        Ok(serde_json::from_value(metadata.metadata)?)
     }
 
-    /// Get OAuth authorization configuration from the issuer's service
-    /// endpoint.
-    async fn oauth_server(&self, _req: OAuthServerRequest) -> anyhow::Result<OAuthServerResponse> {
-        unimplemented!()
+    /// Get OAuth authorization server metadata from the server's well-known
+    /// endpoint, or the cache. The credential issuer is its own authorization
+    /// server unless the request names another.
+    async fn oauth_server(&self, req: OAuthServerRequest) -> anyhow::Result<OAuthServerResponse> {
+        let issuer = req.issuer.as_deref().unwrap_or(&req.credential_issuer);
+        let url = format!("{issuer}/.well-known/oauth-authorization-server");
+        let metadata = self.get_cached(&url, false).await?;
+        Ok(serde_json::from_slice(&metadata)?)
     }
 
     /// Get an authorization code.
@@ -90,58 +179,57 @@ where
             AuthorizationRequest::Object(request) => {
                 let url = format!("{}/authorize", request.credential_issuer);
                 let mut response = self.http.get(url).query(&request)?.send_async().await?;
-                let res_bytes = response.body_bytes().await?;
-                let authorization: AuthorizationResponse = serde_json::from_slice(&res_bytes)?;
+                let authorization: AuthorizationResponse = json_body(&mut response).await?;
                 Ok(authorization)
             }
         }
     }
 
-    /// Get an access token.
+    /// Get an access token from the token endpoint of the issuer's
+    /// authorization server. Token requests are form-encoded (RFC 6749) and,
+    /// if the server supports DPoP, carry a DPoP proof so the server can bind
+    /// the token to the wallet.
     async fn token(&self, req: TokenRequest) -> anyhow::Result<TokenResponse> {
-        let url = format!("{}/token", req.credential_issuer);
-        let req_bytes = serde_json::to_vec(&req)?;
-        let mut response = self.http.post(url).body_bytes(req_bytes).send_async().await?;
-        let res_bytes = response.body_bytes().await?;
-        let token: TokenResponse = serde_json::from_slice(&res_bytes)?;
-        Ok(token)
+        let metadata = self.issuer_metadata(&req.credential_issuer, false).await?.metadata;
+        let (url, server) = self.token_endpoint(&req.credential_issuer, &metadata).await;
+        let form = req.form_encode()?;
+        let request = || Ok(self.http.post(&url).header("Accept", JSON).body_form(&form)?);
+        let token = self.dpop_token(&req.credential_issuer, &url, &server, request).await?;
+        Ok(serde_json::from_value(token)?)
     }
 
-    /// Get a credential.
+    /// Get a credential, authorized by the access token.
     async fn credential(&self, req: CredentialRequest) -> anyhow::Result<CredentialResponse> {
         let url = format!("{}/credential", req.credential_issuer);
-        let req_bytes = serde_json::to_vec(&req)?;
-        let mut response = self.http.post(url).body_bytes(req_bytes).send_async().await?;
-        let res_bytes = response.body_bytes().await?;
-        let credential: CredentialResponse = serde_json::from_slice(&res_bytes)?;
-        Ok(credential)
+        let request = || Ok(self.http.post(&url).header("Accept", JSON).body_json(&req)?);
+        let body = self.send_authorized(&url, &req.access_token, request).await?;
+        Ok(serde_json::from_slice(&body)?)
     }
 
-    /// Get a deferred credential.
+    /// Get a deferred credential, authorized by the access token.
     async fn deferred(
         &self, req: DeferredCredentialRequest,
     ) -> anyhow::Result<DeferredCredentialResponse> {
         let url = format!("{}/deferred_credential", req.credential_issuer);
-        let req_bytes = serde_json::to_vec(&req)?;
-        let mut response = self.http.post(url).body_bytes(req_bytes).send_async().await?;
-        let res_bytes = response.body_bytes().await?;
-        let deferred: DeferredCredentialResponse = serde_json::from_slice(&res_bytes)?;
-        Ok(deferred)
+        let request = || Ok(self.http.post(&url).header("Accept", JSON).body_json(&req)?);
+        let body = self.send_authorized(&url, &req.access_token, request).await?;
+        Ok(serde_json::from_slice(&body)?)
     }
 
     /// Get a base64 encoded form of the credential logo.
     async fn image(self, image_url: &str) -> anyhow::Result<ImageData> {
-        let mut response = self.http.get(image_url).send_async().await?;
-        let data = response.body_string().await?;
-        let media_type = response.content_type().map_or("".into(), |ct| ct.to_string());
-        Ok(ImageData { data, media_type })
+        self.fetch_image(image_url, false).await
     }
 
-    /// Notify the issuer of issuance progress.
-    async fn notification(
-        &self, _req: NotificationRequest,
-    ) -> anyhow::Result<NotificationResponse> {
-        unimplemented!()
+    /// Notify the issuer of issuance progress, authorized by the access
+    /// token.
+    async fn notification(&self, req: NotificationRequest) -> anyhow::Result<NotificationResponse> {
+        let url = format!("{}/notification", req.credential_issuer);
+        let request = || Ok(self.http.post(&url).body_json(&req)?);
+        let body = self.send_authorized(&url, &req.access_token, request).await?;
+        // Issuers acknowledge notifications with an empty body.
+        let body = if body.is_empty() { b"{}".to_vec() } else { body };
+        Ok(serde_json::from_slice(&body)?)
     }
 }
 
@@ -152,23 +240,23 @@ where
     /// Get a request object. If an error is returned, the wallet will cancel
     /// the presentation flow.
     async fn request_object(&self, req: &str) -> anyhow::Result<RequestObjectResponse> {
-        let mut response = self.http.get(req).send_async().await?;
-        let res_bytes = response.body_bytes().await?;
-        let request: RequestObjectResponse = serde_json::from_slice(&res_bytes)?;
+        let mut response = self.http.get(req).header("Accept", JSON).send_async().await?;
+        let request: RequestObjectResponse = json_body(&mut response).await?;
         Ok(request)
     }
 
-    /// Send the presentation to the verifier.
+    /// Send the presentation to the verifier. Authorization responses are
+    /// form-encoded (OpenID4VP `direct_post`).
     async fn present(
         &self, uri: Option<&str>, presentation: &ResponseRequest,
     ) -> anyhow::Result<ResponseResponse> {
         let Some(url) = uri else {
             return Err(anyhow!("no presentation URI"));
         };
-        let req_bytes = serde_json::to_vec(presentation)?;
-        let mut response = self.http.post(url).body_bytes(req_bytes).send_async().await?;
-        let res_bytes = response.body_bytes().await?;
-        let response: ResponseResponse = serde_json::from_slice(&res_bytes)?;
+        let form = presentation.form_encode()?;
+        let request = self.http.post(url).header("Accept", JSON).body_form(&form)?;
+        let mut response = request.send_async().await?;
+        let response: ResponseResponse = json_body(&mut response).await?;
         Ok(response)
     }
 }
@@ -180,43 +268,46 @@ where
     /// Save a `Credential` to the store. Overwrite any existing credential with
     /// the same ID. Create a new credential if one with the same ID does
     /// not exist.
+    ///
+    /// The credential's proof and holder binding are verified first: a
+    /// credential that fails verification is not stored.
+    ///
+    /// The credential is stored, encrypted, together with the ID of the holder
+    /// key the provider is bound to.
     async fn save(&self, credential: &Credential) -> anyhow::Result<()> {
-        let data = serde_json::to_vec(credential)?;
-        let id = credential.id.clone();
-        self.store.save_async(Catalog::Credential.to_string(), id, data).await.map_err(Into::into)
+        self.store_credential(credential, None).await
     }
 
     /// Retrieve a `Credential` from the store with the given ID. Return None if
     /// no credential with the ID exists.
     async fn load(&self, id: &str) -> anyhow::Result<Option<Credential>> {
-        let all_data = self.store.list_async(Catalog::Credential.to_string()).await?;
-        for entry in all_data {
-            if let StoreEntry::Data(data) = entry {
-                let credential: Credential = serde_json::from_slice(&data)?;
-                if credential.id == id {
-                    return Ok(Some(credential));
-                }
-            };
-        }
-        Ok(None)
+        Ok(self.load_record(id).await?.map(|record| record.credential))
     }
 
     /// Find the credentials that match the the provided filter. If `filter` is
     /// None, return all credentials in the store.
+    ///
+    /// Credentials that are expired or not yet valid are left out unless the
+    /// provider has been told to include them. Revoked and suspended
+    /// credentials are always left out, since they cannot be presented. A
+    /// status that cannot be determined, for example while offline, does not
+    /// leave a credential out.
     async fn find(&self, filter: Option<Constraints>) -> anyhow::Result<Vec<Credential>> {
-        let all_data = self.store.list_async(Catalog::Credential.to_string()).await?;
+        let now = self.clock.now_async().await?;
         let mut credentials = Vec::new();
-        for entry in all_data {
-            if let StoreEntry::Data(data) = entry {
-                let credential: Credential = serde_json::from_slice(&data)?;
-                if let Some(filter) = &filter {
-                    match filter.satisfied(&credential) {
-                        Ok(true) => credentials.push(credential),
-                        Ok(false) => {}
-                        Err(e) => return Err(e),
-                    }
-                } else {
-                    credentials.push(credential);
+        for record in self.load_records().await? {
+            if !self.include_expired && Validity::at(&record.credential, now) != Validity::Valid {
+                continue;
+            }
+            if let Some(filter) = &filter {
+                if !filter.satisfied(&record.credential)? {
+                    continue;
+                }
+            }
+            match self.credential_status(&record.credential).await.unwrap_or_default() {
+                CredentialStatus::Revoked | CredentialStatus::Suspended => {}
+                CredentialStatus::Valid | CredentialStatus::Unknown => {
+                    credentials.push(record.credential);
                 }
             }
         }
@@ -302,10 +393,12 @@ where
     /// number of steps in generating or retrieving a secret and generating the
     /// key pair from it.
     async fn verification_method(&self) -> anyhow::Result<String> {
-        let vk = self.public_key().await?;
-        let encoded = Base64UrlUnpadded::encode_string(&vk);
-        let did_key = format!("did:key:{}#{}", encoded, encoded);
-        Ok(did_key)
+        // An Ed25519 multikey: the multicodec prefix and the public key,
+        // base58btc multibase encoded.
+        let mut multikey = vec![0xed, 0x01];
+        multikey.extend(self.public_key().await?);
+        let multikey = multibase::encode(multibase::Base::Base58Btc, multikey);
+        Ok(format!("did:key:{multikey}#{multikey}"))
     }
 }
 
@@ -319,8 +412,7 @@ where Ev: 'static,
     /// Returns an error if the DID URL cannot be resolved.
     async fn resolve(&self, url:  &str) -> anyhow::Result<Document> {
         let mut response = self.http.get(url).send_async().await?;
-        let res_bytes = response.body_bytes().await?;
-        let doc: Document = serde_json::from_slice(&res_bytes)?;
+        let doc: Document = json_body(&mut response).await?;
         Ok(doc)
     }
 }
@@ -329,28 +421,395 @@ impl<Ev> Provider<Ev>
 where
     Ev: 'static,
 {
-    async fn get_or_create_signing_key(&self) -> anyhow::Result<SigningKey> {
-        let secret = self.key_store.get_async("credential", "signing").await?;
-        let _secret_bytes: [u8; 32] = match secret {
-            KeyStoreEntry::Data(bytes) => match bytes.try_into() {
-                Ok(secret) => secret,
-                Err(_) => {
-                    return Err(anyhow!("Unable to convert shell secret to fixed size byte array"));
-                }
-            },
-            KeyStoreEntry::None => {
-                let new_secret = self.key_store.generate_secret_async(32).await?;
-                self.key_store.set_async("credential", "signing", new_secret.clone()).await?;
-                match new_secret.try_into() {
-                    Ok(secret) => secret,
-                    Err(_) => {
-                        return Err(anyhow!(
-                            "Unable to convert shell secret to fixed size byte array"
-                        ));
+    /// Create a provider bound to the holder key of a stored credential, for
+    /// use when presenting that credential.
+    ///
+    /// Only credentials found for presentation, which leaves out revoked and
+    /// suspended credentials, should be presented.
+    pub async fn for_credential(&self, id: &str) -> anyhow::Result<Self> {
+        let Some(record) = self.load_record(id).await? else {
+            return Err(anyhow!("no credential found with ID {id}"));
+        };
+        // Signing would otherwise create a new key the credential is not
+        // bound to.
+        if self.key_store.metadata_async(&record.key_id, keys::SIGNING).await?.is_none() {
+            return Err(anyhow!("holder key for credential {id} is missing"));
+        }
+        Ok(self.with_key(KeyBinding::Key(record.key_id)))
+    }
+
+    /// Retrieve all credentials from the store.
+    ///
+    /// Records that are sealed with an old data key or written with an old
+    /// schema version are upgraded and saved again. Records stored in
+    /// plaintext are sealed the first time credentials are loaded, and are
+    /// unreadable after that. Entries that cannot be read are moved to the
+    /// quarantine catalog and reported alongside the credentials.
+    pub async fn load_credentials(&self) -> anyhow::Result<StoredCredentials> {
+        let catalog = Catalog::Credential.to_string();
+        let all_data = self.store.list_async(&catalog).await?;
+        let mut keys = self.data_keys().await?;
+        let mut stored = StoredCredentials::default();
+        let mut changes = Vec::new();
+        for entry in all_data {
+            let StoreEntry::Data(data) = entry else {
+                continue;
+            };
+            match self.read_record(&catalog, &data, &mut keys).await {
+                Ok((record, upgraded)) => {
+                    if upgraded {
+                        changes.push(self.record_change(&record).await?);
                     }
+                    stored.records.push(record);
                 }
+                Err(e) if e.is::<UnreadableEntry>() => {
+                    changes.extend(self.quarantine(&catalog, None, data, e.to_string()).await?);
+                }
+                Err(e) => return Err(e),
             }
+        }
+
+        // Upgrades and quarantine moves are applied together so a failure
+        // cannot leave an entry in both catalogs.
+        if !changes.is_empty() {
+            self.store.batch_async(changes).await?;
+        }
+        self.plaintext_migrated(&keys).await?;
+        stored.quarantined.extend(self.load_quarantined(&mut keys).await?);
+        Ok(stored)
+    }
+
+    /// Watch the credential catalog and report each change to the stored
+    /// credentials as it happens, until the shell ends the subscription.
+    ///
+    /// Changed entries are upgraded or quarantined in the same way as when
+    /// credentials are loaded.
+    pub async fn watch_credentials(&self, on_change: impl Fn(anyhow::Result<CredentialChange>)) {
+        let catalog = Catalog::Credential.to_string();
+        let mut deltas = self.store.watch_async(&catalog);
+        while let Some(delta) = deltas.next().await {
+            let change = match delta {
+                Ok(delta) => self.credential_change(&catalog, delta).await,
+                Err(e) => Err(e.into()),
+            };
+            on_change(change);
+        }
+    }
+
+    /// Decode a change to the credential catalog.
+    async fn credential_change(
+        &self, catalog: &str, delta: StoreDelta,
+    ) -> anyhow::Result<CredentialChange> {
+        let (id, data) = match delta {
+            StoreDelta::Inserted { id, data } | StoreDelta::Updated { id, data } => (id, data),
+            StoreDelta::Deleted { id } => return Ok(CredentialChange::Deleted(id)),
         };
-        Ok(SigningKey::from_bytes(&_secret_bytes))
+        let mut keys = self.data_keys().await?;
+        match self.read_record(catalog, &data, &mut keys).await {
+            Ok((record, upgraded)) => {
+                // Saving the upgraded record is reported as a further update.
+                if upgraded {
+                    self.save_record(&record).await?;
+                }
+                Ok(CredentialChange::Saved(record))
+            }
+            Err(e) if e.is::<UnreadableEntry>() => {
+                let reason = e.to_string();
+                let changes =
+                    self.quarantine(catalog, Some(id.clone()), data, reason.clone()).await?;
+                self.store.batch_async(changes).await?;
+                Ok(CredentialChange::Quarantined(QuarantinedRecord { id, reason }))
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Retrieve all readable credential records from the store.
+    pub async fn load_records(&self) -> anyhow::Result<Vec<CredentialRecord>> {
+        Ok(self.load_credentials().await?.records)
+    }
+
+    /// Retrieve the credential record with the given ID from the store.
+    async fn load_record(&self, id: &str) -> anyhow::Result<Option<CredentialRecord>> {
+        let catalog = Catalog::Credential.to_string();
+        let StoreEntry::Data(data) = self.store.get_async(&catalog, id).await? else {
+            return Ok(None);
+        };
+        let mut keys = self.data_keys().await?;
+        let (record, upgraded) = self.read_record(&catalog, &data, &mut keys).await?;
+        if upgraded {
+            self.save_record(&record).await?;
+        }
+        Ok(Some(record))
+    }
+
+    /// Open and decode a stored credential record. Also returns whether the
+    /// record was upgraded and should be saved again.
+    async fn read_record(
+        &self, catalog: &str, data: &[u8], keys: &mut vault::DataKeys,
+    ) -> anyhow::Result<(CredentialRecord, bool)> {
+        let opened = self.open(catalog, data, keys).await?;
+        let (record, migrated) = schema::decode::<CredentialRecord>(&opened.plaintext)
+            .map_err(|e| UnreadableEntry(e.to_string()))?;
+        Ok((record, migrated || !opened.current))
+    }
+
+    /// Verify a credential and save it to the store with the ID of the holder
+    /// key the provider is bound to and the claim definitions, if known, of
+    /// the issuer's metadata.
+    async fn store_credential(
+        &self, credential: &Credential, claim_metadata: Option<Value>,
+    ) -> anyhow::Result<()> {
+        self.verify_credential(credential)
+            .await
+            .map_err(|e| anyhow!("credential {} failed verification: {e}", credential.id))?;
+        let record = CredentialRecord {
+            credential: credential.clone(),
+            key_id: self.signing_key_id().await?,
+            claim_metadata,
+        };
+        self.save_record(&record).await
+    }
+
+    /// Seal a credential record and save it to the store.
+    async fn save_record(&self, record: &CredentialRecord) -> anyhow::Result<()> {
+        let catalog = Catalog::Credential.to_string();
+        let (sealed, index) = self.seal_record(&catalog, record).await?;
+        let id = &record.credential.id;
+        self.store.save_indexed_async(catalog, id, sealed, index).await.map_err(Into::into)
+    }
+
+    /// Seal a credential record into a change that saves it to the store.
+    async fn record_change(&self, record: &CredentialRecord) -> anyhow::Result<StoreChange> {
+        let catalog = Catalog::Credential.to_string();
+        let (sealed, index) = self.seal_record(&catalog, record).await?;
+        Ok(StoreChange::save(catalog, &record.credential.id, sealed, index)?)
+    }
+
+    /// Seal a credential record, returning it with the metadata the store
+    /// indexes it by: digests of its types and issuer, and its expiry.
+    async fn seal_record(
+        &self, catalog: &str, record: &CredentialRecord,
+    ) -> anyhow::Result<(SealedEntry, StoreIndex)> {
+        let credential = &record.credential;
+        let sealed = self.seal(catalog, &credential.id, &schema::encode(record)?).await?;
+        let index_key = self.index_key().await?;
+        let index = StoreIndex {
+            types: credential.type_.iter().map(|type_| index_key.digest(type_)).collect(),
+            issuer: index_key.digest(&credential.issuer),
+            expires: credential.valid_until.map_or_else(String::new, |date| date.to_rfc3339()),
+        };
+        Ok((sealed, index))
+    }
+
+    /// Seal an unreadable entry into the changes that move it to the
+    /// quarantine catalog.
+    ///
+    /// An entry whose ID cannot be read is quarantined under a digest of its
+    /// data. It cannot be removed from its catalog without its ID, so it is
+    /// only recorded the first time it is loaded: while an entry with the
+    /// same digest is in quarantine, no changes are made.
+    async fn quarantine(
+        &self, catalog: &str, id: Option<String>, data: Vec<u8>, reason: String,
+    ) -> anyhow::Result<Vec<StoreChange>> {
+        let id = id.or_else(|| vault::entry_id(&data));
+        let quarantine_id = id.clone().unwrap_or_else(|| digest_id(&data));
+        let quarantine = Catalog::Quarantine.to_string();
+        if id.is_none() {
+            let existing = self.store.get_async(&quarantine, &quarantine_id).await?;
+            if matches!(existing, StoreEntry::Data(_)) {
+                return Ok(Vec::new());
+            }
+        }
+        let entry = QuarantinedEntry {
+            catalog: catalog.into(),
+            id: quarantine_id.clone(),
+            reason,
+            data,
+        };
+        let sealed = self.seal(&quarantine, &quarantine_id, &serde_json::to_vec(&entry)?).await?;
+        let mut changes =
+            vec![StoreChange::save(quarantine, quarantine_id, sealed, StoreIndex::default())?];
+        if let Some(id) = id {
+            changes.push(StoreChange::delete(catalog, id));
+        }
+        Ok(changes)
+    }
+
+    /// Retrieve the entries in the quarantine catalog. Entries that are not
+    /// sealed with the current data key are sealed again.
+    async fn load_quarantined(
+        &self, keys: &mut vault::DataKeys,
+    ) -> anyhow::Result<Vec<QuarantinedRecord>> {
+        let catalog = Catalog::Quarantine.to_string();
+        let all_data = self.store.list_async(&catalog).await?;
+        let mut quarantined = Vec::new();
+        for entry in all_data {
+            let StoreEntry::Data(data) = entry else {
+                continue;
+            };
+            let opened = match self.open(&catalog, &data, keys).await {
+                Ok(opened) => opened,
+                Err(e) if e.is::<UnreadableEntry>() => {
+                    quarantined.push(QuarantinedRecord {
+                        id: vault::entry_id(&data).unwrap_or_else(|| digest_id(&data)),
+                        reason: e.to_string(),
+                    });
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            let entry: QuarantinedEntry = serde_json::from_slice(&opened.plaintext)?;
+            if !opened.current {
+                let sealed = self.seal(&catalog, &entry.id, &opened.plaintext).await?;
+                self.store.save_async(&catalog, &entry.id, sealed).await?;
+            }
+            quarantined.push(QuarantinedRecord {
+                id: entry.id,
+                reason: entry.reason,
+            });
+        }
+        Ok(quarantined)
+    }
+}
+
+/// ID of a store entry made from a digest of its data, for entries whose own
+/// ID cannot be read.
+fn digest_id(data: &[u8]) -> String {
+    format!("urn:sha-256:{}", Base64UrlUnpadded::encode_string(&Sha256::digest(data)))
+}
+
+/// An unreadable entry moved to the quarantine catalog. The original data is
+/// kept, sealed, so it can be recovered by a later version of the wallet.
+#[derive(Debug, Deserialize, Serialize)]
+struct QuarantinedEntry {
+    /// Catalog the entry was moved from.
+    catalog: String,
+
+    /// ID of the entry in its original catalog, or a digest of its data if
+    /// that cannot be read.
+    id: String,
+
+    /// Why the entry could not be read.
+    reason: String,
+
+    /// The entry as it was stored.
+    #[serde(with = "serde_bytes")]
+    data: Vec<u8>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provider::testing::Shell;
+
+    // An entry whose ID cannot be read is quarantined, sealed, under a digest
+    // of its data, and is not recorded again when it is next loaded.
+    #[test]
+    fn quarantined_without_id() {
+        let mut shell = Shell::new(1_700_000_000);
+        shell.store.insert(("credential".into(), "x".into()), b"garbage".to_vec());
+        let load = |shell: &mut Shell| {
+            let stored = shell.run(|provider| async move { provider.load_credentials().await });
+            stored.expect("should load").quarantined
+        };
+
+        let first = load(&mut shell);
+        assert_eq!(first.len(), 1);
+        assert_eq!(first[0].id, digest_id(b"garbage"));
+        let quarantine_id = ("quarantine".to_string(), first[0].id.clone());
+        let sealed = shell.store.get(&quarantine_id).expect("should be quarantined").clone();
+        serde_json::from_slice::<SealedEntry>(&sealed).expect("should be sealed");
+
+        // Sealing again would use a fresh nonce, so unchanged bytes show the
+        // entry was not written a second time.
+        let second = load(&mut shell);
+        assert_eq!(second.iter().map(|q| &q.id).collect::<Vec<_>>(), vec![&first[0].id]);
+        assert_eq!(shell.store.get(&quarantine_id), Some(&sealed));
+    }
+
+    // A credential's types and issuer are indexed by digests keyed with the
+    // data key, not in the clear.
+    #[test]
+    fn keyed_index() {
+        let json = include_bytes!("model/credentials.json");
+        let credentials: Vec<Credential> =
+            serde_json::from_slice(json).expect("should deserialize");
+        let record = CredentialRecord {
+            key_id: keys::LEGACY_KEY_ID.into(),
+            credential: credentials[0].clone(),
+            claim_metadata: None,
+        };
+
+        let mut shell = Shell::new(1_700_000_000);
+        let issuer = record.credential.issuer.clone();
+        let (index, digest, rotated) = shell.run(|provider| async move {
+            let (_, index) =
+                provider.seal_record("credential", &record).await.expect("should seal");
+            let digest = provider.index_key().await.expect("should get key").digest(&issuer);
+            provider.rotate_data_key().await.expect("should rotate");
+            let (_, rotated) =
+                provider.seal_record("credential", &record).await.expect("should seal");
+            (index, digest, rotated)
+        });
+        assert_eq!(index.issuer, digest);
+        assert_ne!(index.issuer, issuer);
+        assert!(!index.types.iter().any(|type_| type_.contains("Credential")));
+        assert_ne!(rotated.issuer, index.issuer);
+    }
+
+    // Expired credentials are only found for presentation once the user has
+    // allowed it.
+    #[test]
+    fn find_expired() {
+        let json = include_bytes!("model/credentials.json");
+        let credentials: Vec<Credential> =
+            serde_json::from_slice(json).expect("should deserialize");
+        let credential = credentials[1].clone();
+
+        // The credential expires at the start of 2027.
+        let mut shell = Shell::new(1_900_000_000);
+        let (found, allowed) = shell.run(|provider| async move {
+            let record = CredentialRecord {
+                key_id: provider.signing_key_id().await.expect("should get key ID"),
+                credential,
+                claim_metadata: None,
+            };
+            provider.save_record(&record).await.expect("should save credential");
+            let found = provider.find(None).await.expect("should find credentials");
+            let allowed = provider.including_expired(true).find(None).await;
+            (found, allowed.expect("should find credentials"))
+        });
+        assert!(found.is_empty());
+        assert_eq!(allowed.len(), 1);
+    }
+
+    // A stored credential is presented with the key of the issuer it was
+    // issued by, not the wallet's shared key.
+    #[test]
+    fn presentation_key() {
+        let json = include_bytes!("model/credentials.json");
+        let credentials: Vec<Credential> =
+            serde_json::from_slice(json).expect("should deserialize");
+        let credential = credentials[0].clone();
+
+        let mut shell = Shell::new(1_700_000_000);
+        let (bound_kid, presenting_kid, shared_kid) = shell.run(|provider| async move {
+            let issuer = provider.for_issuer(&credential.issuer);
+            let bound_kid = issuer.verification_method().await.expect("should create issuer key");
+            let record = CredentialRecord {
+                key_id: issuer.signing_key_id().await.expect("should get key ID"),
+                credential: credential.clone(),
+                claim_metadata: None,
+            };
+            provider.save_record(&record).await.expect("should save credential");
+
+            let presenting = provider.for_credential(&credential.id).await.expect("should bind");
+            let presenting_kid = presenting.verification_method().await.expect("should get key");
+            let shared_kid = provider.verification_method().await.expect("should get key");
+            (bound_kid, presenting_kid, shared_kid)
+        });
+        assert_eq!(presenting_kid, bound_kid);
+        assert_ne!(presenting_kid, shared_kid);
     }
 }
@@ -0,0 +1,205 @@
+//! HTTP cache for metadata documents.
+//!
+//! Issuer and authorization server metadata are cached in the key-value store
+//! so each offer does not fetch them again. Responses are cached as the
+//! server's `Cache-Control` header allows, and a cached response with an
+//! `ETag` is revalidated with a conditional request once it is stale.
+//! Responses vary by language, so they are cached per `Accept-Language`.
+
+use serde::{Deserialize, Serialize};
+
+use super::response::success_body;
+use super::{Provider, JSON};
+
+/// Key-value store prefix for cached metadata, keyed by URL and language.
+const CACHE_PREFIX: &str = "metadata_cache:";
+
+/// A cached response.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+struct CachedResponse {
+    /// Response body.
+    body: String,
+
+    /// Entity tag to revalidate the response with.
+    etag: Option<String>,
+
+    /// Time until which the response can be used without revalidation, in
+    /// seconds since the Unix epoch.
+    fresh_until: i64,
+}
+
+/// How long, in seconds, a response can be used without revalidation, from
+/// its `Cache-Control` and `Age` headers. `None` if it must not be stored.
+///
+/// A response without a lifetime is stored for revalidation but is stale
+/// straight away.
+fn lifetime(cache_control: Option<&str>, age: Option<&str>) -> Option<i64> {
+    let mut max_age = 0;
+    for directive in cache_control.unwrap_or_default().split(',') {
+        let directive = directive.trim().to_ascii_lowercase();
+        match directive.split_once('=') {
+            _ if directive == "no-store" => return None,
+            _ if directive == "no-cache" => return Some(0),
+            Some(("max-age", seconds)) => {
+                max_age = seconds.trim_matches('"').parse().unwrap_or_default();
+            }
+            _ => {}
+        }
+    }
+    let age: i64 = age.and_then(|age| age.trim().parse().ok()).unwrap_or_default();
+    Some((max_age - age).max(0))
+}
+
+impl<Ev> Provider<Ev>
+where
+    Ev: 'static,
+{
+    /// Get a JSON metadata document, from the cache while it is fresh.
+    ///
+    /// A stale cached document, or any cached document when `revalidate` is
+    /// set, is requested again, conditionally if it has an entity tag. If the
+    /// server reports it has not been modified the cached document is used.
+    pub(super) async fn get_cached(&self, url: &str, revalidate: bool) -> anyhow::Result<Vec<u8>> {
+        let language = self.locale.accept_language().unwrap_or_default();
+        let key = format!("{CACHE_PREFIX}{url}|{language}");
+        let now = self.clock.now_async().await?.timestamp();
+        let cached = self
+            .kv
+            .get_async(key.clone())
+            .await
+            .ok()
+            .flatten()
+            .and_then(|data| serde_json::from_slice::<CachedResponse>(&data).ok());
+        if let Some(cached) = &cached {
+            if !revalidate && now < cached.fresh_until {
+                return Ok(cached.body.clone().into_bytes());
+            }
+        }
+
+        let mut request = self.http.get(url).header("Accept", JSON);
+        if !language.is_empty() {
+            request = request.header("Accept-Language", language);
+        }
+        if let Some(etag) = cached.as_ref().and_then(|cached| cached.etag.as_deref()) {
+            request = request.header("If-None-Match", etag);
+        }
+        let mut response = request.send_async().await?;
+        let header =
+            |name: &str| response.header(name).map(|values| values.last().as_str().to_string());
+        let fresh_for = lifetime(header("Cache-Control").as_deref(), header("Age").as_deref());
+        let etag = header("ETag");
+
+        let status: u16 = response.status().into();
+        if let (304, Some(mut cached)) = (status, cached) {
+            cached.fresh_until = now + fresh_for.unwrap_or_default();
+            if etag.is_some() {
+                cached.etag = etag;
+            }
+            self.save_cached(key, &cached).await;
+            return Ok(cached.body.into_bytes());
+        }
+
+        let body = success_body(&mut response).await?;
+        match (fresh_for, String::from_utf8(body.clone())) {
+            (Some(fresh_for), Ok(text)) if fresh_for > 0 || etag.is_some() => {
+                let cached = CachedResponse {
+                    body: text,
+                    etag,
+                    fresh_until: now + fresh_for,
+                };
+                self.save_cached(key, &cached).await;
+            }
+            _ => {
+                // A response that must not be stored replaces any earlier one.
+                self.kv.delete_async(key).await.ok();
+            }
+        }
+        Ok(body)
+    }
+
+    /// Save a response to the cache. A response that cannot be cached is
+    /// fetched again next time.
+    async fn save_cached(&self, key: String, cached: &CachedResponse) {
+        if let Ok(data) = serde_json::to_vec(cached) {
+            self.kv.set_async(key, data).await.ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    use crux_http::protocol::{HttpRequest, HttpResponse};
+
+    use super::*;
+    use crate::provider::testing::Shell;
+
+    const METADATA_URL: &str = "https://issuer.io/.well-known/openid-credential-issuer";
+
+    // The value of a request header, if the request has it.
+    fn header<'a>(request: &'a HttpRequest, name: &str) -> Option<&'a str> {
+        let header = request.headers.iter().find(|h| h.name.eq_ignore_ascii_case(name))?;
+        Some(header.value.as_str())
+    }
+
+    // A response is fresh for its max age less the time it has already spent
+    // in caches, and `no-cache` and `no-store` are honoured.
+    #[test]
+    fn cache_lifetime() {
+        assert_eq!(lifetime(Some("public, max-age=3600"), None), Some(3600));
+        assert_eq!(lifetime(Some("max-age=3600"), Some("600")), Some(3000));
+        assert_eq!(lifetime(Some("max-age=60"), Some("120")), Some(0));
+        assert_eq!(lifetime(Some("no-cache, max-age=3600"), None), Some(0));
+        assert_eq!(lifetime(Some("private, no-store"), None), None);
+        assert_eq!(lifetime(None, None), Some(0));
+    }
+
+    // A cached response is used while fresh. Once stale, or when revalidated,
+    // it is requested again with its entity tag, and kept if the server
+    // reports it has not been modified.
+    #[test]
+    fn conditional_requests() {
+        // The server's document is versioned by its entity tag.
+        let version = Rc::new(Cell::new(1));
+        let server_version = Rc::clone(&version);
+        let mut shell = Shell::new(1_700_000_000).with_http(move |request| {
+            let etag = format!("\"v{}\"", server_version.get());
+            if header(request, "If-None-Match") == Some(etag.as_str()) {
+                return HttpResponse::status(304).header("Cache-Control", "max-age=60").build();
+            }
+            HttpResponse::ok()
+                .header("Cache-Control", "max-age=60")
+                .header("ETag", etag)
+                .body(format!(r#"{{"version":{}}}"#, server_version.get()))
+                .build()
+        });
+        let get = |shell: &mut Shell, revalidate: bool| {
+            let body = shell.run(move |provider| async move {
+                provider.get_cached(METADATA_URL, revalidate).await.map_err(|e| e.to_string())
+            });
+            String::from_utf8(body.expect("should get document")).expect("should be text")
+        };
+
+        assert_eq!(get(&mut shell, false), r#"{"version":1}"#);
+        shell.now += 59;
+        assert_eq!(get(&mut shell, false), r#"{"version":1}"#);
+        assert_eq!(shell.requests.len(), 1);
+
+        // A stale response that has not been modified is kept, and is fresh
+        // again for the lifetime the server gives.
+        shell.now += 1;
+        assert_eq!(get(&mut shell, false), r#"{"version":1}"#);
+        assert_eq!(header(&shell.requests[1], "If-None-Match"), Some("\"v1\""));
+        assert_eq!(get(&mut shell, false), r#"{"version":1}"#);
+        assert_eq!(shell.requests.len(), 2);
+
+        // A modified document replaces the cached one when revalidated.
+        version.set(2);
+        assert_eq!(get(&mut shell, true), r#"{"version":2}"#);
+        assert_eq!(header(&shell.requests[2], "If-None-Match"), Some("\"v1\""));
+        assert_eq!(get(&mut shell, false), r#"{"version":2}"#);
+        assert_eq!(shell.requests.len(), 3);
+    }
+}
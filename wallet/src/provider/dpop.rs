@@ -0,0 +1,406 @@
+//! DPoP sender-constrained access tokens (RFC 9449).
+//!
+//! Token, credential, deferred credential and notification requests carry a
+//! DPoP proof: a JWT, signed with a dedicated key, that covers the request's
+//! method and URL. An issuer that supports DPoP binds the access token it
+//! issues to that key, so a leaked token is useless without the key.
+//!
+//! Proofs are only sent to authorization servers that advertise DPoP with
+//! `dpop_signing_alg_values_supported`. The wallet signs proofs with EdDSA,
+//! so a server that advertises DPoP without EdDSA is reported as unsupported
+//! rather than sent proofs it would reject. Other servers issue bearer tokens.
+//!
+//! DPoP keys are held in the key store apart from holder keys, one per issuer,
+//! so DPoP proofs cannot be used to correlate the holder across issuers.
+//! The binding of an access token to its DPoP key is kept until the token
+//! expires or the wallet releases it.
+
+use std::collections::HashMap;
+
+use anyhow::{anyhow, bail};
+use base64ct::{Base64UrlUnpadded, Encoding};
+use crux_http::RequestBuilder;
+use ed25519_dalek::{Signer as _, SigningKey};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use url::Url;
+
+use super::keys::issuer_key_id;
+use super::response::{success_body, ErrorCode, ServiceError};
+use super::{jose, Provider};
+
+/// Key store purpose for DPoP keys.
+pub const DPOP: &str = "dpop";
+
+/// Key-value store prefix for the latest nonce issued by a server, keyed by
+/// origin.
+const NONCE_PREFIX: &str = "dpop_nonce:";
+
+/// Key-value store key for the DPoP keys access tokens are bound to.
+const BINDINGS_KEY: &str = "dpop_tokens";
+
+/// Signing algorithm of DPoP proofs.
+const DPOP_ALG: &str = "EdDSA";
+
+/// Lifetime, in seconds, assumed for a DPoP-bound access token whose token
+/// response has no `expires_in`.
+const DEFAULT_TOKEN_LIFETIME: i64 = 24 * 60 * 60;
+
+/// The DPoP key an access token is bound to, by the token's hash.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct Binding {
+    /// ID of the DPoP key.
+    key_id: String,
+
+    /// When the access token expires, in seconds since the Unix epoch.
+    expires_at: i64,
+}
+
+/// Hash of an access token, as carried in a DPoP proof's `ath` claim and used
+/// to look up the key the token is bound to.
+fn token_hash(access_token: &str) -> String {
+    Base64UrlUnpadded::encode_string(&Sha256::digest(access_token.as_bytes()))
+}
+
+/// Create a DPoP proof for a request.
+fn proof(
+    key: &SigningKey, method: &str, url: &str, issued_at: i64, nonce: Option<&str>,
+    access_token: Option<&str>,
+) -> anyhow::Result<String> {
+    // The target URI excludes any query and fragment.
+    let mut htu = Url::parse(url)?;
+    htu.set_query(None);
+    htu.set_fragment(None);
+
+    let x = Base64UrlUnpadded::encode_string(key.verifying_key().as_bytes());
+    let header = json!({
+        "typ": "dpop+jwt",
+        "alg": DPOP_ALG,
+        "jwk": { "kty": "OKP", "crv": "Ed25519", "x": x },
+    });
+    let mut claims = json!({
+        "jti": Base64UrlUnpadded::encode_string(&rand::random::<[u8; 16]>()),
+        "htm": method,
+        "htu": htu.as_str(),
+        "iat": issued_at,
+    });
+    if let Some(nonce) = nonce {
+        claims["nonce"] = nonce.into();
+    }
+    if let Some(access_token) = access_token {
+        claims["ath"] = token_hash(access_token).into();
+    }
+    jose::encode(&header, &claims, |msg| key.sign(msg).to_vec())
+}
+
+/// Determine whether an authorization server accepts DPoP proofs from the
+/// wallet, from its metadata.
+///
+/// # Errors
+///
+/// Returns an error if the server supports DPoP, but not with EdDSA.
+fn accepts_dpop(server_metadata: &Value) -> anyhow::Result<bool> {
+    let Some(algs) = server_metadata.get("dpop_signing_alg_values_supported") else {
+        return Ok(false);
+    };
+    let algs: Vec<&str> = algs.as_array().into_iter().flatten().filter_map(Value::as_str).collect();
+    if !algs.contains(&DPOP_ALG) {
+        bail!("DPoP with {DPOP_ALG} is not supported: the server accepts {}", algs.join(", "));
+    }
+    Ok(true)
+}
+
+/// Origin of a URL. Servers issue DPoP nonces per origin.
+fn origin(url: &str) -> String {
+    Url::parse(url).map_or_else(|_| url.into(), |url| url.origin().ascii_serialization())
+}
+
+impl<Ev> Provider<Ev>
+where
+    Ev: 'static,
+{
+    /// Send a request with a DPoP proof signed by the given DPoP key and get
+    /// the body of the successful response.
+    ///
+    /// The proof includes the latest nonce the server has issued. If the
+    /// server rejects the proof because it needs a fresh nonce, the request is
+    /// repeated once with the nonce from its `DPoP-Nonce` header.
+    pub(super) async fn send_with_dpop(
+        &self, key_id: &str, method: &str, url: &str, access_token: Option<&str>,
+        request: impl Fn() -> anyhow::Result<RequestBuilder<Ev>>,
+    ) -> anyhow::Result<Vec<u8>> {
+        let key = self.get_or_create_key(key_id, DPOP).await?;
+        let mut nonce = self.dpop_nonce(url).await;
+        let mut retried = false;
+        loop {
+            let issued_at = self.clock.now_async().await?.timestamp();
+            let proof = proof(&key, method, url, issued_at, nonce.as_deref(), access_token)?;
+            let mut response = request()?.header("DPoP", proof.as_str()).send_async().await?;
+
+            let fresh =
+                response.header("DPoP-Nonce").map(|values| values.last().as_str().to_string());
+            if let Some(fresh) = &fresh {
+                self.save_dpop_nonce(url, fresh).await;
+            }
+            match success_body(&mut response).await {
+                Err(e) if !retried && fresh.is_some() && is_nonce_challenge(&e) => {
+                    nonce = fresh;
+                    retried = true;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Send a token request and get the token response. If the authorization
+    /// server supports DPoP, the request carries a proof signed by the
+    /// issuer's DPoP key and, if the server binds the access token to the
+    /// key, the binding is recorded so requests made with the token carry
+    /// proofs from the same key.
+    ///
+    /// Returns the token response, with a DPoP-bound token described as a
+    /// bearer token: `vercre-holder` only models bearer tokens, and the wallet
+    /// tracks the binding itself.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the server supports DPoP, but not with EdDSA, or
+    /// the token request fails.
+    pub(super) async fn dpop_token(
+        &self, issuer: &str, url: &str, server_metadata: &Value,
+        request: impl Fn() -> anyhow::Result<RequestBuilder<Ev>>,
+    ) -> anyhow::Result<Value> {
+        if !accepts_dpop(server_metadata)? {
+            let mut response = request()?.send_async().await?;
+            return Ok(serde_json::from_slice(&success_body(&mut response).await?)?);
+        }
+
+        let key_id = issuer_key_id(issuer);
+        let body = self.send_with_dpop(&key_id, "POST", url, None, request).await?;
+        let mut token: Value = serde_json::from_slice(&body)?;
+        let bound = token
+            .get("token_type")
+            .and_then(Value::as_str)
+            .is_some_and(|token_type| token_type.eq_ignore_ascii_case("DPoP"));
+        if bound {
+            let access_token = token
+                .get("access_token")
+                .and_then(Value::as_str)
+                .ok_or_else(|| anyhow!("token response has no access token"))?;
+            let lifetime = token.get("expires_in").and_then(Value::as_i64);
+            let binding = Binding {
+                key_id,
+                expires_at: self.clock.now_async().await?.timestamp()
+                    + lifetime.unwrap_or(DEFAULT_TOKEN_LIFETIME),
+            };
+            let mut bindings = self.dpop_bindings().await?;
+            bindings.insert(token_hash(access_token), binding);
+            self.save_dpop_bindings(&bindings).await?;
+            token["token_type"] = "Bearer".into();
+        }
+        Ok(token)
+    }
+
+    /// Send a request authorized by an access token and get the body of the
+    /// successful response. A DPoP-bound token is sent with a proof from the
+    /// key it is bound to, and any other token as a bearer token.
+    pub(super) async fn send_authorized(
+        &self, url: &str, access_token: &str,
+        request: impl Fn() -> anyhow::Result<RequestBuilder<Ev>>,
+    ) -> anyhow::Result<Vec<u8>> {
+        match self.dpop_bindings().await?.remove(&token_hash(access_token)) {
+            Some(binding) => {
+                let authorization = format!("DPoP {access_token}");
+                let request = || Ok(request()?.header("Authorization", authorization.as_str()));
+                self.send_with_dpop(&binding.key_id, "POST", url, Some(access_token), request).await
+            }
+            None => {
+                let authorization = format!("Bearer {access_token}");
+                let mut response =
+                    request()?.header("Authorization", authorization).send_async().await?;
+                success_body(&mut response).await
+            }
+        }
+    }
+
+    /// Forget the DPoP binding of an access token the wallet no longer uses.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the bindings cannot be read or saved.
+    pub async fn release_token(&self, access_token: &str) -> anyhow::Result<()> {
+        let mut bindings = self.dpop_bindings().await?;
+        if bindings.remove(&token_hash(access_token)).is_some() {
+            self.save_dpop_bindings(&bindings).await?;
+        }
+        Ok(())
+    }
+
+    /// Get the DPoP bindings of unexpired access tokens. Bindings of expired
+    /// tokens are deleted.
+    async fn dpop_bindings(&self) -> anyhow::Result<HashMap<String, Binding>> {
+        let Some(data) = self.kv.get_async(BINDINGS_KEY.into()).await? else {
+            return Ok(HashMap::new());
+        };
+        let mut bindings: HashMap<String, Binding> = serde_json::from_slice(&data)?;
+        let now = self.clock.now_async().await?.timestamp();
+        let count = bindings.len();
+        bindings.retain(|_, binding| binding.expires_at > now);
+        if bindings.len() < count {
+            self.save_dpop_bindings(&bindings).await?;
+        }
+        Ok(bindings)
+    }
+
+    /// Save the DPoP bindings of access tokens.
+    async fn save_dpop_bindings(&self, bindings: &HashMap<String, Binding>) -> anyhow::Result<()> {
+        if bindings.is_empty() {
+            self.kv.delete_async(BINDINGS_KEY.into()).await?;
+        } else {
+            self.kv.set_async(BINDINGS_KEY.into(), serde_json::to_vec(bindings)?).await?;
+        }
+        Ok(())
+    }
+
+    /// Get the latest nonce the server at a URL has issued, if any.
+    async fn dpop_nonce(&self, url: &str) -> Option<String> {
+        let stored = self.kv.get_async(format!("{NONCE_PREFIX}{}", origin(url))).await.ok()?;
+        stored.and_then(|nonce| String::from_utf8(nonce).ok())
+    }
+
+    /// Remember the latest nonce the server at a URL has issued. A nonce that
+    /// cannot be saved is requested again by the server's next challenge.
+    async fn save_dpop_nonce(&self, url: &str, nonce: &str) {
+        let key = format!("{NONCE_PREFIX}{}", origin(url));
+        self.kv.set_async(key, nonce.as_bytes().to_vec()).await.ok();
+    }
+}
+
+/// Determine if an error is a server's demand for a fresh DPoP nonce.
+fn is_nonce_challenge(error: &anyhow::Error) -> bool {
+    error.downcast_ref::<ServiceError>().is_some_and(|e| e.error == ErrorCode::UseDpopNonce)
+}
+
+#[cfg(test)]
+mod tests {
+    use crux_http::protocol::{HttpRequest, HttpResponse};
+
+    use super::*;
+    use crate::provider::testing::Shell;
+
+    const TOKEN_URL: &str = "https://issuer.io/token";
+
+    // The claims of the DPoP proof a request carries.
+    fn proof_claims(request: &HttpRequest) -> Option<Value> {
+        let header = request.headers.iter().find(|h| h.name.eq_ignore_ascii_case("DPoP"))?;
+        Some(jose::decode(&header.value).ok()?.payload)
+    }
+
+    // A DPoP proof covers the request method and target URI, carries the
+    // server's nonce and the access token hash, and is signed by the key in
+    // its header.
+    #[test]
+    fn dpop_proof() {
+        let key = SigningKey::from_bytes(&[3; 32]);
+        let token = proof(
+            &key,
+            "POST",
+            "https://issuer.io/credential?x=1#f",
+            1_700_000_000,
+            Some("n1"),
+            Some("at"),
+        )
+        .expect("should create proof");
+        let jws = jose::decode(&token).expect("should decode");
+        assert_eq!(jws.header.typ.as_deref(), Some("dpop+jwt"));
+        assert_eq!(jws.payload["htm"], "POST");
+        assert_eq!(jws.payload["htu"], "https://issuer.io/credential");
+        assert_eq!(jws.payload["nonce"], "n1");
+        assert_eq!(jws.payload["ath"], token_hash("at"));
+
+        let (signing_input, signature) = token.rsplit_once('.').expect("should have signature");
+        let public_key = jose::PublicKey::Ed25519(key.verifying_key());
+        let signature = jose::base64url(signature).expect("should decode signature");
+        public_key.verify("EdDSA", signing_input.as_bytes(), &signature).expect("should verify");
+
+        assert_eq!(origin("https://issuer.io:8443/token"), "https://issuer.io:8443");
+    }
+
+    // Proofs are only sent to servers that advertise DPoP, and with EdDSA.
+    #[test]
+    fn dpop_support() {
+        assert!(!accepts_dpop(&json!({ "token_endpoint": TOKEN_URL })).expect("should check"));
+        let metadata = json!({ "dpop_signing_alg_values_supported": ["ES256", "EdDSA"] });
+        assert!(accepts_dpop(&metadata).expect("should check"));
+        let metadata = json!({ "dpop_signing_alg_values_supported": ["ES256"] });
+        let error = accepts_dpop(&metadata).expect_err("should not support EdDSA");
+        assert!(error.to_string().contains("accepts ES256"), "{error}");
+    }
+
+    // A token request rejected for want of a nonce is repeated once with the
+    // server's nonce. The token's binding to the DPoP key is kept until the
+    // token is released or expires.
+    #[test]
+    fn dpop_nonce_retry() {
+        let mut shell = Shell::new(1_700_000_000).with_http(|request| {
+            if proof_claims(request).is_some_and(|claims| claims["nonce"] == "n1") {
+                let token = json!({ "access_token": "at", "token_type": "DPoP", "expires_in": 60 });
+                return HttpResponse::ok().body(token.to_string()).build();
+            }
+            HttpResponse::status(400)
+                .header("DPoP-Nonce", "n1")
+                .body(json!({ "error": "use_dpop_nonce" }).to_string())
+                .build()
+        });
+        let metadata = json!({ "dpop_signing_alg_values_supported": ["EdDSA"] });
+        let token = shell.run(|provider| async move {
+            let request = || Ok(provider.http.post(TOKEN_URL));
+            provider.dpop_token("https://issuer.io", TOKEN_URL, &metadata, request).await
+        });
+        let token = token.expect("should get a token after the nonce challenge");
+        assert_eq!(token["token_type"], "Bearer");
+        assert_eq!(shell.requests.len(), 2);
+        let first = proof_claims(&shell.requests[0]).expect("should send a proof");
+        assert!(first.get("nonce").is_none());
+        assert!(shell.kv.contains_key(BINDINGS_KEY));
+
+        // The bound token is sent with a proof until it is released.
+        shell.run(|provider| async move {
+            let request = || Ok(provider.http.post("https://issuer.io/credential"));
+            provider.send_authorized("https://issuer.io/credential", "at", request).await.ok();
+            provider.release_token("at").await.expect("should release token");
+        });
+        let credential_request = shell.requests.last().expect("should request a credential");
+        let claims = proof_claims(credential_request).expect("should send a proof");
+        assert_eq!(claims["ath"], token_hash("at"));
+        assert!(!shell.kv.contains_key(BINDINGS_KEY));
+    }
+
+    // The binding of an expired token is deleted, and the token is sent as a
+    // bearer token.
+    #[test]
+    fn dpop_binding_expiry() {
+        let mut shell = Shell::new(1_700_000_000).with_http(|_| {
+            let token = json!({ "access_token": "at", "token_type": "DPoP", "expires_in": 60 });
+            HttpResponse::ok().body(token.to_string()).build()
+        });
+        let metadata = json!({ "dpop_signing_alg_values_supported": ["EdDSA"] });
+        shell
+            .run(|provider| async move {
+                let request = || Ok(provider.http.post(TOKEN_URL));
+                provider.dpop_token("https://issuer.io", TOKEN_URL, &metadata, request).await
+            })
+            .expect("should get a token");
+        assert!(shell.kv.contains_key(BINDINGS_KEY));
+
+        shell.now += 60;
+        shell.run(|provider| async move {
+            let request = || Ok(provider.http.post("https://issuer.io/credential"));
+            provider.send_authorized("https://issuer.io/credential", "at", request).await.ok();
+        });
+        let credential_request = shell.requests.last().expect("should request a credential");
+        assert!(proof_claims(credential_request).is_none());
+        assert!(!shell.kv.contains_key(BINDINGS_KEY));
+    }
+}
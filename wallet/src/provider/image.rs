@@ -0,0 +1,243 @@
+//! Credential and issuer images.
+//!
+//! Images referenced by display metadata are fetched as bytes and
+//! base64-encoded for the shell. Images embedded as `data:` URIs are decoded
+//! without a request. Fetched images are cached in the store, keyed by URL, so
+//! the same logo is not downloaded for every offer. A cached image is fetched
+//! again once it is a day old, or when display metadata is refreshed, so an
+//! image replaced at the same URL is picked up. The request is conditional if
+//! the server gave the image an entity tag.
+//!
+//! Only raster images are accepted. SVG images can carry scripts and links,
+//! so they are rejected rather than passed to the shell.
+
+use anyhow::{anyhow, bail};
+use base64ct::{Base64, Encoding};
+use serde::{Deserialize, Serialize};
+use vercre_holder::credential::ImageData;
+
+use super::Provider;
+use crate::capabilities::store::{Catalog, StoreEntry};
+
+/// Largest image, in bytes, the wallet will accept.
+pub const MAX_IMAGE_SIZE: usize = 1024 * 1024;
+
+/// How long, in seconds, a cached image is used before it is fetched again.
+const IMAGE_LIFETIME: i64 = 24 * 60 * 60;
+
+/// An image in the image cache.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+struct CachedImage {
+    /// The image.
+    image: ImageData,
+
+    /// Entity tag to revalidate the image with.
+    etag: Option<String>,
+
+    /// When the image was fetched or last revalidated, in seconds since the
+    /// Unix epoch.
+    fetched_at: i64,
+}
+
+/// Check an image is of a supported media type and within the size limit, and
+/// encode it for the shell.
+fn image_data(bytes: &[u8], media_type: Option<&str>) -> anyhow::Result<ImageData> {
+    if bytes.len() > MAX_IMAGE_SIZE {
+        bail!("image of {} bytes exceeds the limit of {MAX_IMAGE_SIZE} bytes", bytes.len());
+    }
+    // The type is taken from the image's content rather than the declared
+    // type, so an SVG or other document cannot pass as an image.
+    let Some(media_type) = sniff(bytes) else {
+        bail!("unsupported image type {}", media_type.unwrap_or("(none)"));
+    };
+    Ok(ImageData {
+        data: Base64::encode_string(bytes),
+        media_type: media_type.into(),
+    })
+}
+
+/// Determine the media type of an image from its content, if it is one the
+/// wallet can display: PNG, JPEG, GIF or WebP.
+fn sniff(bytes: &[u8]) -> Option<&'static str> {
+    match bytes {
+        [0x89, b'P', b'N', b'G', ..] => Some("image/png"),
+        [0xff, 0xd8, 0xff, ..] => Some("image/jpeg"),
+        [b'G', b'I', b'F', b'8', ..] => Some("image/gif"),
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => Some("image/webp"),
+        _ => None,
+    }
+}
+
+/// Decode an image embedded as a `data:` URI.
+fn decode_data_uri(uri: &str) -> anyhow::Result<ImageData> {
+    let Some((header, data)) = uri.strip_prefix("data:").and_then(|rest| rest.split_once(','))
+    else {
+        bail!("malformed data URI");
+    };
+    let (media_type, base64) = match header.strip_suffix(";base64") {
+        Some(media_type) => (media_type, true),
+        None => (header, false),
+    };
+    let bytes = if base64 {
+        Base64::decode_vec(data).map_err(|e| anyhow!("invalid base64 in data URI: {e}"))?
+    } else {
+        urlencoding::decode_binary(data.as_bytes()).into_owned()
+    };
+    image_data(&bytes, Some(media_type).filter(|media_type| !media_type.is_empty()))
+}
+
+impl<Ev> Provider<Ev>
+where
+    Ev: 'static,
+{
+    /// Get an image referenced by display metadata, from the image cache if
+    /// it has been fetched in the last day. A cached image is fetched again
+    /// if `revalidate` is set.
+    pub(super) async fn fetch_image(
+        &self, image_url: &str, revalidate: bool,
+    ) -> anyhow::Result<ImageData> {
+        if image_url.starts_with("data:") {
+            return decode_data_uri(image_url);
+        }
+
+        let catalog = Catalog::Image.to_string();
+        let now = self.clock.now_async().await?.timestamp();
+        // Images cached without a fetch time, from before cached images
+        // expired and SVG was rejected, are fetched again.
+        let cached = match self.store.get_async(&catalog, image_url).await? {
+            StoreEntry::Data(data) => serde_json::from_slice::<CachedImage>(&data).ok(),
+            _ => None,
+        };
+        if let Some(cached) = &cached {
+            if !revalidate && now < cached.fetched_at + IMAGE_LIFETIME {
+                return Ok(cached.image.clone());
+            }
+        }
+
+        // Ask for no more than one byte over the limit, so a larger image is
+        // detected without downloading the rest of it. The shell stops reading
+        // at the end of the range if the server ignores it.
+        let range = format!("bytes=0-{MAX_IMAGE_SIZE}");
+        let mut request = self.http.get(image_url).header("Range", range);
+        if let Some(etag) = cached.as_ref().and_then(|cached| cached.etag.as_deref()) {
+            request = request.header("If-None-Match", etag);
+        }
+        let mut response = request.send_async().await?;
+        let etag = response.header("ETag").map(|values| values.last().as_str().to_string());
+        let status: u16 = response.status().into();
+        if let (304, Some(mut cached)) = (status, cached) {
+            cached.fetched_at = now;
+            if etag.is_some() {
+                cached.etag = etag;
+            }
+            self.store.save_async(catalog, image_url, &cached).await.ok();
+            return Ok(cached.image);
+        }
+        if !response.status().is_success() {
+            bail!("request for image {image_url} failed with status {}", response.status());
+        }
+        let too_large = response
+            .header("Content-Length")
+            .and_then(|length| length.last().as_str().parse::<usize>().ok())
+            .is_some_and(|length| length > MAX_IMAGE_SIZE);
+        if too_large {
+            bail!("image {image_url} exceeds the limit of {MAX_IMAGE_SIZE} bytes");
+        }
+        let bytes = response.body_bytes().await?;
+        let media_type = response.content_type().map(|content_type| content_type.to_string());
+        let cached = CachedImage {
+            image: image_data(&bytes, media_type.as_deref())?,
+            etag,
+            fetched_at: now,
+        };
+
+        // The image is still usable if it cannot be cached.
+        self.store.save_async(catalog, image_url, &cached).await.ok();
+        Ok(cached.image)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    use crux_http::protocol::{HttpRequest, HttpResponse};
+
+    use super::*;
+    use crate::provider::testing::Shell;
+
+    const LOGO_URL: &str = "https://issuer.io/logo.png";
+
+    // Images embedded as data URIs are decoded whether base64 or
+    // percent-encoded, and unsupported types, including SVG, are rejected.
+    #[test]
+    fn data_uris() {
+        let png = decode_data_uri("data:image/png;base64,iVBORw0KGgo=").expect("should decode");
+        assert_eq!(png.media_type, "image/png");
+        assert_eq!(png.data, "iVBORw0KGgo=");
+
+        let gif = decode_data_uri("data:image/gif,GIF89a%01%00").expect("should decode");
+        assert_eq!(gif.media_type, "image/gif");
+        let bytes = Base64::decode_vec(&gif.data).expect("should be base64");
+        assert_eq!(bytes, b"GIF89a\x01\x00");
+
+        assert!(decode_data_uri("data:image/svg+xml,%3Csvg%20xmlns%3D%22%22%2F%3E").is_err());
+        assert!(image_data(br#"<svg xmlns=""/>"#, Some("image/png")).is_err());
+
+        let sniffed = decode_data_uri("data:;base64,/9j/4AAQ").expect("should decode");
+        assert_eq!(sniffed.media_type, "image/jpeg");
+
+        assert!(decode_data_uri("data:text/html,%3Cp%3E").is_err());
+        assert!(image_data(&vec![0x89; MAX_IMAGE_SIZE + 1], Some("image/png")).is_err());
+    }
+
+    // The value of a request header, if the request has it.
+    fn header<'a>(request: &'a HttpRequest, name: &str) -> Option<&'a str> {
+        let header = request.headers.iter().find(|h| h.name.eq_ignore_ascii_case(name))?;
+        Some(header.value.as_str())
+    }
+
+    // A cached image is used for a day, and then, or when revalidated, is
+    // fetched again with its entity tag so a replaced image is picked up.
+    #[test]
+    fn image_cache() {
+        // The server's image is versioned by its entity tag.
+        let version = Rc::new(Cell::new(1));
+        let server_version = Rc::clone(&version);
+        let mut shell = Shell::new(1_700_000_000).with_http(move |request| {
+            let etag = format!("\"v{}\"", server_version.get());
+            if header(request, "If-None-Match") == Some(etag.as_str()) {
+                return HttpResponse::status(304).build();
+            }
+            let body = [b"GIF89a".as_slice(), &[server_version.get()]].concat();
+            HttpResponse::ok().header("ETag", etag).body(body).build()
+        });
+        let fetch = |shell: &mut Shell, revalidate: bool| {
+            let image = shell.run(move |provider| async move {
+                provider.fetch_image(LOGO_URL, revalidate).await.map_err(|e| e.to_string())
+            });
+            let image = image.expect("should fetch image");
+            Base64::decode_vec(&image.data).expect("should be base64")
+        };
+
+        assert_eq!(fetch(&mut shell, false), b"GIF89a\x01");
+        assert_eq!(fetch(&mut shell, false), b"GIF89a\x01");
+        assert_eq!(shell.requests.len(), 1);
+
+        // An unchanged image is revalidated rather than downloaded, and is
+        // then fresh for another day.
+        shell.now += 60;
+        assert_eq!(fetch(&mut shell, true), b"GIF89a\x01");
+        assert_eq!(header(&shell.requests[1], "If-None-Match"), Some("\"v1\""));
+        shell.now += IMAGE_LIFETIME - 1;
+        assert_eq!(fetch(&mut shell, false), b"GIF89a\x01");
+        assert_eq!(shell.requests.len(), 2);
+
+        // Once the day is up, an image replaced at the same URL is fetched.
+        version.set(2);
+        shell.now += 1;
+        assert_eq!(fetch(&mut shell, false), b"GIF89a\x02");
+        assert_eq!(shell.requests.len(), 3);
+    }
+}
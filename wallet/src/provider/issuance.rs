@@ -0,0 +1,360 @@
+//! Pre-authorized code issuance.
+//!
+//! The wallet requests an access token with the offer's pre-authorized code,
+//! then each accepted credential with a proof of possession, using the
+//! endpoints in the issuer's metadata. A rejection by the issuer is returned
+//! as a `ServiceError` so the issuance can recover from it.
+
+use anyhow::{anyhow, bail};
+use base64ct::{Base64UrlUnpadded, Encoding};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use sha2::{Digest, Sha256};
+use vercre_holder::credential::Credential;
+use vercre_holder::TokenResponse;
+
+use super::metadata::claim_definitions;
+use super::proof::{CredentialProof, ProofClaims};
+use super::status::issued_claims;
+use super::{jose, Provider, JSON};
+use crate::config;
+
+/// Grant type of a token request made with a pre-authorized code.
+const PRE_AUTHORIZED_GRANT: &str = "urn:ietf:params:oauth:grant-type:pre-authorized_code";
+
+/// Claims of an SD-JWT that describe the credential rather than its subject.
+const SD_JWT_CLAIMS: [&str; 10] =
+    ["iss", "sub", "iat", "nbf", "exp", "cnf", "vct", "status", "_sd", "_sd_alg"];
+
+/// An issuer's verified metadata.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IssuerMetadata {
+    /// The metadata, in the order the issuer lists it, with the claims of
+    /// signed metadata applied.
+    pub metadata: Value,
+
+    /// Whether the metadata was signed by the issuer.
+    pub signed: bool,
+}
+
+/// A credential received from an issuer, ready to be verified and stored.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IssuedCredential {
+    /// Configuration ID of the credential.
+    pub config_id: String,
+
+    /// The credential.
+    pub credential: Credential,
+
+    /// Nonce for the next proof, if the issuer supplied one.
+    pub c_nonce: Option<String>,
+
+    /// Lifetime of the nonce in seconds, if the issuer said.
+    pub c_nonce_expires_in: Option<i64>,
+}
+
+/// Get an endpoint from metadata, or the issuer's conventional endpoint if
+/// the metadata does not name one.
+fn endpoint(metadata: &Value, name: &str, issuer: &str, path: &str) -> String {
+    metadata
+        .get(name)
+        .and_then(Value::as_str)
+        .map_or_else(|| format!("{}{path}", issuer.trim_end_matches('/')), String::from)
+}
+
+/// Get the claims disclosed by an SD-JWT's disclosures.
+fn disclosed_claims(issued: &str) -> Map<String, Value> {
+    issued
+        .split('~')
+        .skip(1)
+        .filter(|disclosure| !disclosure.is_empty())
+        .filter_map(|disclosure| {
+            let disclosure: Value =
+                serde_json::from_slice(&jose::base64url(disclosure).ok()?).ok()?;
+            let name = disclosure.get(1)?.as_str()?;
+            Some((name.to_string(), disclosure.get(2)?.clone()))
+        })
+        .collect()
+}
+
+/// Get a date from a claim that is either seconds since the Unix epoch or an
+/// RFC 3339 string.
+fn claim_date(claim: Option<&Value>) -> Option<DateTime<Utc>> {
+    match claim? {
+        Value::Number(secs) => DateTime::from_timestamp(secs.as_i64()?, 0),
+        Value::String(date) => DateTime::parse_from_rfc3339(date).ok().map(Into::into),
+        _ => None,
+    }
+}
+
+/// Build the stored form of a credential from what the issuer sent. The
+/// credential's claims are read, not verified: it is verified when it is
+/// stored. Display metadata is added separately.
+fn stored_credential(
+    credential_issuer: &str, config: &Value, issued: &str, now: DateTime<Utc>,
+) -> anyhow::Result<Credential> {
+    let claims = issued_claims(issued).ok_or_else(|| anyhow!("credential could not be decoded"))?;
+    let vc = claims.get("vc").unwrap_or(&claims);
+
+    let (subject_id, subject) = match vc.get("credentialSubject") {
+        Some(Value::Object(subject)) => {
+            let mut subject = subject.clone();
+            (subject.remove("id"), subject)
+        }
+        _ => {
+            let mut subject: Map<String, Value> = claims
+                .as_object()
+                .into_iter()
+                .flatten()
+                .filter(|(name, _)| !SD_JWT_CLAIMS.contains(&name.as_str()))
+                .map(|(name, value)| (name.clone(), value.clone()))
+                .collect();
+            subject.extend(disclosed_claims(issued));
+            (claims.get("sub").cloned(), subject)
+        }
+    };
+    let types = vc
+        .get("type")
+        .or_else(|| config.pointer("/credential_definition/type"))
+        .cloned()
+        .or_else(|| claims.get("vct").map(|vct| json!([vct])))
+        .unwrap_or_else(|| json!([]));
+    let id = vc.get("id").or_else(|| claims.get("jti")).and_then(Value::as_str).map_or_else(
+        || format!("urn:sha-256:{}", Base64UrlUnpadded::encode_string(&Sha256::digest(issued))),
+        String::from,
+    );
+    let issuance_date = claim_date(vc.get("validFrom").or_else(|| vc.get("issuanceDate")))
+        .or_else(|| claim_date(claims.get("iat")))
+        .unwrap_or(now);
+    let valid_from = claim_date(claims.get("nbf")).or_else(|| claim_date(vc.get("validFrom")));
+    let valid_until = claim_date(claims.get("exp"))
+        .or_else(|| claim_date(vc.get("validUntil").or_else(|| vc.get("expirationDate"))));
+
+    let credential = json!({
+        "id": id,
+        "issuer": credential_issuer,
+        "issuer_name": "",
+        "issued": issued,
+        "type": types,
+        "format": config.get("format").cloned().unwrap_or_default(),
+        "subject_claims": [{ "id": subject_id, "claims": subject }],
+        "issuance_date": issuance_date,
+        "valid_from": valid_from,
+        "valid_until": valid_until,
+    });
+    Ok(serde_json::from_value(credential)?)
+}
+
+impl<Ev> Provider<Ev>
+where
+    Ev: 'static,
+{
+    /// Get the metadata of an offer's issuer, from the cache if it has been
+    /// fetched before.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the metadata cannot be fetched, is not for the
+    /// issuer, or has signed metadata that cannot be verified.
+    pub async fn offer_metadata(&self, credential_issuer: &str) -> anyhow::Result<IssuerMetadata> {
+        self.issuer_metadata(credential_issuer, false).await
+    }
+
+    /// Get an access token with the offer's pre-authorized code and, if the
+    /// offer requires one, the transaction code the user entered.
+    ///
+    /// The token endpoint is taken from the metadata of the issuer's
+    /// authorization server.
+    pub async fn pre_authorized_token(
+        &self, credential_issuer: &str, metadata: &Value, pre_authorized_code: &str,
+        tx_code: Option<&str>,
+    ) -> anyhow::Result<TokenResponse> {
+        let (url, server_metadata) = self.token_endpoint(credential_issuer, metadata).await;
+        let client_id = config::client_id();
+        let mut form = vec![
+            ("grant_type", PRE_AUTHORIZED_GRANT),
+            ("pre-authorized_code", pre_authorized_code),
+            ("client_id", client_id.as_str()),
+        ];
+        if let Some(tx_code) = tx_code {
+            form.push(("tx_code", tx_code));
+        }
+        let request = || Ok(self.http.post(&url).header("Accept", JSON).body_form(&form)?);
+        let token = self.dpop_token(credential_issuer, &url, &server_metadata, request).await?;
+        Ok(serde_json::from_value(token)?)
+    }
+
+    /// Get the token endpoint of the issuer's authorization server, the first
+    /// its metadata names or else the issuer itself, with the server's
+    /// metadata.
+    ///
+    /// A server whose metadata cannot be fetched is sent token requests at its
+    /// default endpoint, without DPoP.
+    pub(super) async fn token_endpoint(
+        &self, credential_issuer: &str, metadata: &Value,
+    ) -> (String, Value) {
+        let server = metadata
+            .pointer("/authorization_servers/0")
+            .and_then(Value::as_str)
+            .unwrap_or(credential_issuer);
+        let server_metadata = format!("{server}/.well-known/oauth-authorization-server");
+        let server_metadata: Value = match self.get_cached(&server_metadata, false).await {
+            Ok(body) => serde_json::from_slice(&body).unwrap_or_default(),
+            Err(_) => Value::Null,
+        };
+        let url = endpoint(&server_metadata, "token_endpoint", server, "/token");
+        (url, server_metadata)
+    }
+
+    /// Create a proof of possession of the holder key for a credential of the
+    /// given configuration, over the issuer's nonce if it has given one.
+    ///
+    /// If the issuer accepts key attestations, the platform is asked to attest
+    /// the holder key. Returns `None` if the issuer does not require a proof.
+    pub async fn credential_proof(
+        &self, credential_issuer: &str, config: &impl Serialize, nonce: Option<String>,
+    ) -> anyhow::Result<Option<CredentialProof>> {
+        let accepts_attestation =
+            serde_json::to_value(config)?.pointer("/proof_types_supported/attestation").is_some();
+        let key_attestation =
+            if accepts_attestation { self.key_attestation(nonce.clone()).await? } else { None };
+        let claims = ProofClaims {
+            client_id: Some(config::client_id()),
+            audience: credential_issuer.into(),
+            issued_at: self.clock.now_async().await?.timestamp(),
+            nonce,
+        };
+        self.proof(config, &claims, key_attestation.as_deref()).await
+    }
+
+    /// Request a credential of the given configuration with a proof of
+    /// possession, authorized by the access token.
+    ///
+    /// # Errors
+    ///
+    /// Returns the issuer's `ServiceError` if it rejects the request, or an
+    /// error if the issuer defers issuance, which the wallet does not
+    /// support, or sends a credential that cannot be read.
+    pub async fn request_credential(
+        &self, credential_issuer: &str, metadata: &Value, access_token: &str, config_id: &str,
+        proof: Option<&CredentialProof>,
+    ) -> anyhow::Result<IssuedCredential> {
+        let config = metadata
+            .pointer(&format!("/credential_configurations_supported/{config_id}"))
+            .ok_or_else(|| anyhow!("the issuer does not describe the {config_id} credential"))?;
+        let url = endpoint(metadata, "credential_endpoint", credential_issuer, "/credential");
+        let mut body = json!({ "credential_configuration_id": config_id });
+        if let Some(proof) = proof {
+            body["proof"] = serde_json::to_value(proof)?;
+        }
+        let request = || Ok(self.http.post(&url).header("Accept", JSON).body_json(&body)?);
+        let response: Value =
+            serde_json::from_slice(&self.send_authorized(&url, access_token, request).await?)?;
+
+        let issued = response
+            .get("credential")
+            .or_else(|| response.pointer("/credentials/0/credential"))
+            .or_else(|| response.pointer("/credentials/0"));
+        let issued = match issued {
+            Some(Value::String(issued)) => issued.clone(),
+            Some(issued @ Value::Object(_)) => issued.to_string(),
+            _ if response.get("transaction_id").is_some() => {
+                bail!("deferred issuance is not supported")
+            }
+            _ => bail!("credential response has no credential"),
+        };
+        let now = self.clock.now_async().await?;
+        let mut credential = stored_credential(credential_issuer, config, &issued, now)?;
+        self.apply_display(&mut credential, metadata, config, false).await;
+
+        Ok(IssuedCredential {
+            config_id: config_id.into(),
+            credential,
+            c_nonce: response.get("c_nonce").and_then(Value::as_str).map(String::from),
+            c_nonce_expires_in: response.get("c_nonce_expires_in").and_then(Value::as_i64),
+        })
+    }
+
+    /// Verify and store a credential received under the given configuration,
+    /// together with the configuration's claim definitions so its claims are
+    /// shown in the order the issuer's metadata lists them.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the credential fails verification or cannot be
+    /// stored.
+    pub async fn save_issued(
+        &self, credential: &Credential, metadata: &Value, config_id: &str,
+    ) -> anyhow::Result<()> {
+        let claim_metadata = metadata
+            .pointer(&format!("/credential_configurations_supported/{config_id}"))
+            .and_then(claim_definitions)
+            .cloned();
+        self.store_credential(credential, claim_metadata).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crux_http::protocol::HttpResponse;
+
+    use super::*;
+    use crate::provider::testing::Shell;
+
+    // Without authorization server metadata, a token is requested from the
+    // server the issuer names, at its default endpoint, as a bearer token.
+    #[test]
+    fn token_without_server_metadata() {
+        let mut shell = Shell::new(1_700_000_000).with_http(|request| {
+            if request.url == "https://auth.example/token" {
+                let token =
+                    json!({ "access_token": "at", "token_type": "Bearer", "expires_in": 900 });
+                return HttpResponse::ok().body(token.to_string()).build();
+            }
+            HttpResponse::status(404).build()
+        });
+        let metadata = json!({
+            "credential_issuer": "https://issuer.example",
+            "authorization_servers": ["https://auth.example"],
+        });
+        let token = shell.run(|provider| async move {
+            provider.pre_authorized_token("https://issuer.example", &metadata, "code", None).await
+        });
+        assert_eq!(token.expect("should get a token").access_token, "at");
+        let request = shell.requests.last().expect("should request a token");
+        assert!(!request.headers.iter().any(|h| h.name.eq_ignore_ascii_case("DPoP")));
+    }
+
+    // An SD-JWT's subject claims are its non-registered claims together with
+    // its disclosed claims, and its dates come from its registered claims.
+    #[test]
+    fn sd_jwt_credential() {
+        let header = Base64UrlUnpadded::encode_string(br#"{"alg":"EdDSA"}"#);
+        let payload = json!({
+            "iss": "https://issuer.example",
+            "sub": "did:key:z6Mk",
+            "iat": 1_700_000_000,
+            "exp": 1_800_000_000,
+            "vct": "EmployeeID",
+            "_sd": ["digest"],
+            "email": "normal.user@example.com",
+        });
+        let payload = Base64UrlUnpadded::encode_string(payload.to_string().as_bytes());
+        let disclosure = Base64UrlUnpadded::encode_string(br#"["salt","given_name","Normal"]"#);
+        let issued = format!("{header}.{payload}.c2ln~{disclosure}~");
+        let config = json!({ "format": "vc+sd-jwt", "vct": "EmployeeID" });
+        let now = DateTime::from_timestamp(1_750_000_000, 0).expect("should be a valid time");
+
+        let credential = stored_credential("https://issuer.example", &config, &issued, now)
+            .expect("should build credential");
+        let credential = serde_json::to_value(credential).expect("should serialize");
+        assert_eq!(credential["type"], json!(["EmployeeID"]));
+        assert_eq!(credential["issuance_date"], json!("2023-11-14T22:13:20Z"));
+        assert_eq!(credential["subject_claims"][0]["id"], json!("did:key:z6Mk"));
+        assert_eq!(
+            credential["subject_claims"][0]["claims"],
+            json!({ "email": "normal.user@example.com", "given_name": "Normal" })
+        );
+    }
+}
@@ -0,0 +1,302 @@
+//! JSON Web Signatures.
+//!
+//! Decoding and verification of compact JWS, such as status list tokens.
+//! Issuer keys are resolved from the issuer's DID or, for issuers identified
+//! by URL, from the JWKS published in the issuer's `jwt-vc-issuer` metadata.
+
+use anyhow::{anyhow, bail};
+use base64ct::{Base64UrlUnpadded, Encoding};
+use k256::ecdsa::signature::Verifier as _;
+use serde::Deserialize;
+use serde_json::Value;
+use vercre_holder::provider::DidResolver;
+
+use super::Provider;
+
+/// Path, relative to the issuer, of the issuer's JWT VC metadata.
+const JWT_VC_ISSUER_PATH: &str = "/.well-known/jwt-vc-issuer";
+
+/// Protected header of a JWS.
+#[derive(Clone, Debug, Deserialize)]
+pub struct JwsHeader {
+    /// Signature algorithm.
+    pub alg: String,
+
+    /// ID of the signing key.
+    #[serde(default)]
+    pub kid: Option<String>,
+
+    /// Media type of the JWS.
+    #[serde(default)]
+    pub typ: Option<String>,
+}
+
+/// A decoded, but not yet verified, compact JWS.
+#[derive(Clone, Debug)]
+pub struct Jws {
+    /// Protected header.
+    pub header: JwsHeader,
+
+    /// Payload claims.
+    pub payload: Value,
+
+    /// Encoded header and payload the signature is over.
+    signing_input: String,
+
+    /// Signature.
+    signature: Vec<u8>,
+}
+
+/// Decode a compact JWS without verifying it.
+pub fn decode(token: &str) -> anyhow::Result<Jws> {
+    let parts: Vec<&str> = token.split('.').collect();
+    let [header, payload, signature] = parts.as_slice() else {
+        bail!("JWS is not in compact form");
+    };
+    Ok(Jws {
+        header: serde_json::from_slice(&base64url(header)?)?,
+        payload: serde_json::from_slice(&base64url(payload)?)?,
+        signing_input: format!("{header}.{payload}"),
+        signature: base64url(signature)?,
+    })
+}
+
+/// Encode and sign a compact JWS.
+pub fn encode(
+    header: &Value, payload: &Value, sign: impl FnOnce(&[u8]) -> Vec<u8>,
+) -> anyhow::Result<String> {
+    let header = Base64UrlUnpadded::encode_string(&serde_json::to_vec(header)?);
+    let payload = Base64UrlUnpadded::encode_string(&serde_json::to_vec(payload)?);
+    let signing_input = format!("{header}.{payload}");
+    let signature = Base64UrlUnpadded::encode_string(&sign(signing_input.as_bytes()));
+    Ok(format!("{signing_input}.{signature}"))
+}
+
+/// Decode unpadded base64url.
+pub fn base64url(encoded: &str) -> anyhow::Result<Vec<u8>> {
+    Base64UrlUnpadded::decode_vec(encoded).map_err(|e| anyhow!("invalid base64url: {e}"))
+}
+
+/// A public key a JWS can be verified with.
+pub enum PublicKey {
+    /// An Ed25519 key, used with `EdDSA`.
+    Ed25519(ed25519_dalek::VerifyingKey),
+
+    /// A secp256k1 key, used with `ES256K`.
+    Secp256k1(k256::ecdsa::VerifyingKey),
+}
+
+impl PublicKey {
+    /// Get a public key from a JWK.
+    pub fn from_jwk(jwk: &Value) -> anyhow::Result<Self> {
+        let field = |name: &str| {
+            jwk.get(name).and_then(Value::as_str).ok_or_else(|| anyhow!("JWK has no {name}"))
+        };
+        match (field("kty")?, field("crv")?) {
+            ("OKP", "Ed25519") => {
+                let Ok(x) = <[u8; 32]>::try_from(base64url(field("x")?)?) else {
+                    bail!("Ed25519 JWK has an invalid length");
+                };
+                Ok(Self::Ed25519(ed25519_dalek::VerifyingKey::from_bytes(&x)?))
+            }
+            ("EC", "secp256k1") => {
+                let mut point = vec![0x04];
+                point.extend(base64url(field("x")?)?);
+                point.extend(base64url(field("y")?)?);
+                Ok(Self::Secp256k1(k256::ecdsa::VerifyingKey::from_sec1_bytes(&point)?))
+            }
+            (kty, crv) => bail!("unsupported key type {kty} with curve {crv}"),
+        }
+    }
+
+    /// Get a public key from a multibase-encoded multikey, as used by
+    /// `did:key`.
+    pub fn from_multikey(multikey: &str) -> anyhow::Result<Self> {
+        let (_, bytes) = multibase::decode(multikey)?;
+        match bytes.as_slice() {
+            [0xed, 0x01, key @ ..] => {
+                let Ok(key) = <&[u8; 32]>::try_from(key) else {
+                    bail!("Ed25519 multikey has an invalid length");
+                };
+                Ok(Self::Ed25519(ed25519_dalek::VerifyingKey::from_bytes(key)?))
+            }
+            [0xe7, 0x01, key @ ..] => {
+                Ok(Self::Secp256k1(k256::ecdsa::VerifyingKey::from_sec1_bytes(key)?))
+            }
+            _ => bail!("unsupported multikey type"),
+        }
+    }
+
+    /// Verify a signature made with the given algorithm.
+    pub fn verify(&self, alg: &str, msg: &[u8], signature: &[u8]) -> anyhow::Result<()> {
+        match (self, alg) {
+            (Self::Ed25519(key), "EdDSA") => {
+                let signature = ed25519_dalek::Signature::from_slice(signature)?;
+                key.verify_strict(msg, &signature)?;
+            }
+            (Self::Secp256k1(key), "ES256K") => {
+                let signature = k256::ecdsa::Signature::from_slice(signature)?;
+                key.verify(msg, &signature)?;
+            }
+            _ => bail!("signature algorithm {alg} does not match the key"),
+        }
+        Ok(())
+    }
+}
+
+/// Determine if a signer speaks for a credential issuer: the signer is the
+/// issuer's identifier, whose keys it publishes as a JWKS, or the `did:web`
+/// DID at the issuer's URL.
+pub fn is_issuer(signer: &str, credential_issuer: &str) -> bool {
+    let credential_issuer = credential_issuer.trim_end_matches('/');
+    if signer.trim_end_matches('/') == credential_issuer {
+        return true;
+    }
+    let Some(Ok(url)) = signer.strip_prefix("did:web:").map(did_web_url) else {
+        return false;
+    };
+    let base = url.strip_suffix("/.well-known/did.json").or_else(|| url.strip_suffix("/did.json"));
+    base == Some(credential_issuer)
+}
+
+/// Get the URL of the DID document for a `did:web` method-specific ID.
+fn did_web_url(id: &str) -> anyhow::Result<String> {
+    let mut segments = id.split(':');
+    let Some(host) = segments.next().filter(|host| !host.is_empty()) else {
+        bail!("did:web has no host");
+    };
+    let host = host.replace("%3A", ":");
+    let path: Vec<&str> = segments.collect();
+    if path.is_empty() {
+        Ok(format!("https://{host}/.well-known/did.json"))
+    } else {
+        Ok(format!("https://{host}/{}/did.json", path.join("/")))
+    }
+}
+
+impl<Ev> Provider<Ev>
+where
+    Ev: 'static,
+{
+    /// Verify a compact JWS was signed by the issuer and return its claims.
+    pub(super) async fn verify_jws(&self, token: &str, issuer: &str) -> anyhow::Result<Value> {
+        let jws = decode(token)?;
+        let key = self.issuer_key(issuer, jws.header.kid.as_deref()).await?;
+        key.verify(&jws.header.alg, jws.signing_input.as_bytes(), &jws.signature)?;
+        Ok(jws.payload)
+    }
+
+    /// Resolve an issuer's signing key. Keys of DID issuers must belong to the
+    /// issuer's DID. Other issuers publish their keys as a JWKS.
+    pub(super) async fn issuer_key(
+        &self, issuer: &str, kid: Option<&str>,
+    ) -> anyhow::Result<PublicKey> {
+        if !issuer.starts_with("did:") {
+            return self.jwks_key(issuer, kid).await;
+        }
+        let Some(kid) = kid else {
+            bail!("signature has no key ID");
+        };
+        let (did, _) = kid.split_once('#').unwrap_or((kid, ""));
+        if did != issuer {
+            bail!("key {kid} does not belong to issuer {issuer}");
+        }
+        self.did_key(kid).await
+    }
+
+    /// Resolve a key from a DID URL.
+    async fn did_key(&self, kid: &str) -> anyhow::Result<PublicKey> {
+        let (did, fragment) = kid.split_once('#').unwrap_or((kid, ""));
+        if let Some(multikey) = did.strip_prefix("did:key:") {
+            return PublicKey::from_multikey(multikey);
+        }
+        if let Some(jwk) = did.strip_prefix("did:jwk:") {
+            return PublicKey::from_jwk(&serde_json::from_slice(&base64url(jwk)?)?);
+        }
+        let Some(id) = did.strip_prefix("did:web:") else {
+            bail!("unsupported DID method for {did}");
+        };
+
+        let document = serde_json::to_value(self.resolve(&did_web_url(id)?).await?)?;
+        let relative = format!("#{fragment}");
+        let method = document
+            .get("verificationMethod")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .find(|method| {
+                let id = method.get("id").and_then(Value::as_str);
+                id == Some(kid) || id == Some(&relative)
+            })
+            .ok_or_else(|| anyhow!("no verification method {kid}"))?;
+        if let Some(jwk) = method.get("publicKeyJwk") {
+            return PublicKey::from_jwk(jwk);
+        }
+        if let Some(multikey) = method.get("publicKeyMultibase").and_then(Value::as_str) {
+            return PublicKey::from_multikey(multikey);
+        }
+        bail!("verification method {kid} has no supported public key")
+    }
+
+    /// Resolve a key from the JWKS in the issuer's JWT VC metadata.
+    async fn jwks_key(&self, issuer: &str, kid: Option<&str>) -> anyhow::Result<PublicKey> {
+        let url = format!("{}{JWT_VC_ISSUER_PATH}", issuer.trim_end_matches('/'));
+        let metadata = self.get_json(&url).await?;
+        if metadata.get("issuer").and_then(Value::as_str) != Some(issuer) {
+            bail!("JWT VC metadata at {url} is for a different issuer");
+        }
+        let jwks = match (metadata.get("jwks"), metadata.get("jwks_uri").and_then(Value::as_str)) {
+            (Some(jwks), _) => jwks.clone(),
+            (None, Some(jwks_uri)) => self.get_json(jwks_uri).await?,
+            (None, None) => bail!("issuer {issuer} does not publish its keys"),
+        };
+        let keys = jwks.get("keys").and_then(Value::as_array).cloned().unwrap_or_default();
+        let jwk = match kid {
+            Some(kid) => {
+                keys.iter().find(|key| key.get("kid").and_then(Value::as_str) == Some(kid))
+            }
+            None if keys.len() == 1 => keys.first(),
+            None => None,
+        };
+        PublicKey::from_jwk(jwk.ok_or_else(|| anyhow!("issuer {issuer} has no matching key"))?)
+    }
+
+    /// Fetch a JSON document.
+    async fn get_json(&self, url: &str) -> anyhow::Result<Value> {
+        let mut response = self.http.get(url).send_async().await?;
+        if !response.status().is_success() {
+            bail!("request to {url} failed with status {}", response.status());
+        }
+        Ok(serde_json::from_slice(&response.body_bytes().await?)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ed25519_dalek::{Signer as _, SigningKey};
+
+    use super::*;
+
+    // A JWS signed with an Ed25519 key should verify against the key's
+    // `did:key` multikey and fail once tampered with.
+    #[test]
+    fn verify_did_key_signature() {
+        let signing_key = SigningKey::from_bytes(&[7; 32]);
+        let mut multikey = vec![0xed, 0x01];
+        multikey.extend(signing_key.verifying_key().as_bytes());
+        let multikey = multibase::encode(multibase::Base::Base58Btc, multikey);
+
+        let header = Base64UrlUnpadded::encode_string(br#"{"alg":"EdDSA"}"#);
+        let payload = Base64UrlUnpadded::encode_string(br#"{"sub":"status"}"#);
+        let signing_input = format!("{header}.{payload}");
+        let signature = signing_key.sign(signing_input.as_bytes());
+        let token =
+            format!("{signing_input}.{}", Base64UrlUnpadded::encode_string(&signature.to_bytes()));
+
+        let jws = decode(&token).expect("should decode");
+        let key = PublicKey::from_multikey(&multikey).expect("should decode multikey");
+        key.verify("EdDSA", jws.signing_input.as_bytes(), &jws.signature).expect("should verify");
+        assert!(key.verify("EdDSA", b"tampered", &jws.signature).is_err());
+        assert!(key.verify("ES256K", jws.signing_input.as_bytes(), &jws.signature).is_err());
+    }
+}
@@ -0,0 +1,404 @@
+//! Holder signing keys.
+//!
+//! Each issuer gets its own holder key so that presentations of credentials
+//! from different issuers cannot be correlated through a shared public key.
+//! The key a credential is bound to is recorded alongside the stored
+//! credential and selected again when the credential is presented.
+//!
+//! Keys can be rotated. A rotated key is kept while stored credentials are
+//! still bound to it so those credentials can be presented until they are
+//! re-issued, and is removed from the key store once no credential references
+//! it. Keys that no stored credential is bound to are reported as orphaned so
+//! they can be reviewed and removed.
+
+use std::collections::{HashMap, HashSet};
+
+use anyhow::anyhow;
+use base64ct::{Base64UrlUnpadded, Encoding};
+use ed25519_dalek::SigningKey;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use super::Provider;
+use crate::capabilities::key::{KeyMetadata, KeyStoreEntry};
+use crate::model::credential::CredentialRecord;
+
+/// Key ID of the single wallet key used before pairwise keys were introduced.
+/// Credentials stored without a key ID are bound to this key.
+pub const LEGACY_KEY_ID: &str = "credential";
+
+/// Key store purpose for holder signing keys.
+pub const SIGNING: &str = "signing";
+
+/// Algorithm holder signing keys are used with.
+pub const SIGNING_ALGORITHM: &str = "EdDSA";
+
+/// Key-value store key under which the key registry is persisted.
+const REGISTRY_KEY: &str = "holder_keys";
+
+/// The holder key a provider signs with.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum KeyBinding {
+    /// The current key for a key scope: the legacy wallet key or an issuer's
+    /// pairwise key. Used when a new credential is issued.
+    Scope(String),
+
+    /// A specific key. Used when presenting a credential with the key it was
+    /// issued against, even if that key has since been rotated.
+    Key(String),
+}
+
+/// Registry of holder keys.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct KeyRegistry {
+    /// Current key ID for each key scope. A scope that has never been rotated
+    /// uses the scope itself as the key ID.
+    pub current: HashMap<String, String>,
+
+    /// Superseded keys that are kept because stored credentials are still
+    /// bound to them.
+    pub retired: Vec<String>,
+}
+
+impl KeyRegistry {
+    /// Get the ID of the current key for a key scope.
+    #[must_use]
+    pub fn current_key(&self, scope: &str) -> String {
+        self.current.get(scope).cloned().unwrap_or_else(|| scope.into())
+    }
+
+    /// Determine if a key is the current key of its scope.
+    #[must_use]
+    pub fn is_current(&self, key_id: &str) -> bool {
+        self.current_key(key_scope(key_id)) == key_id
+    }
+
+    /// Determine if a key has been superseded by a rotation. Credentials bound
+    /// to a retired key need to be re-issued.
+    #[must_use]
+    pub fn is_retired(&self, key_id: &str) -> bool {
+        self.retired.iter().any(|retired| retired == key_id)
+    }
+}
+
+/// The holder key registry together with the metadata of the keys held in the
+/// key store.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct HolderKeys {
+    /// Current and retired holder keys.
+    pub registry: KeyRegistry,
+
+    /// Metadata of all keys in the key store.
+    pub keys: Vec<KeyMetadata>,
+}
+
+impl HolderKeys {
+    /// Get the holder signing keys that no stored credential is bound to.
+    #[must_use]
+    pub fn orphaned(&self, records: &[CredentialRecord]) -> Vec<&KeyMetadata> {
+        let referenced: HashSet<&str> =
+            records.iter().map(|record| record.key_id.as_str()).collect();
+        self.keys
+            .iter()
+            .filter(|key| key.purpose == SIGNING && !referenced.contains(key.id.as_str()))
+            .collect()
+    }
+}
+
+/// Get the scope of a key ID. Rotated keys have IDs of the form
+/// `<scope>#<generation>`.
+pub fn key_scope(key_id: &str) -> &str {
+    key_id.split_once('#').map_or(key_id, |(scope, _)| scope)
+}
+
+/// Get the ID of the key that replaces the given key on rotation.
+fn next_key_id(key_id: &str) -> String {
+    let (scope, generation) = match key_id.split_once('#') {
+        Some((scope, generation)) => (scope, generation.parse::<u32>().unwrap_or_default()),
+        None => (key_id, 0),
+    };
+    format!("{scope}#{}", generation + 1)
+}
+
+/// Derive the ID of the pairwise holder key for an issuer.
+///
+/// The issuer identifier is hashed so the key store does not leak which
+/// issuers the holder has dealt with.
+pub fn issuer_key_id(issuer: &str) -> String {
+    let digest = Sha256::digest(issuer.as_bytes());
+    format!("issuer:{}", Base64UrlUnpadded::encode_string(&digest))
+}
+
+impl<Ev> Provider<Ev>
+where
+    Ev: 'static,
+{
+    /// Retrieve the key registry from the key-value store.
+    pub async fn key_registry(&self) -> anyhow::Result<KeyRegistry> {
+        match self.kv.get_async(REGISTRY_KEY.into()).await? {
+            Some(data) => Ok(serde_json::from_slice(&data)?),
+            None => Ok(KeyRegistry::default()),
+        }
+    }
+
+    /// Retrieve the key registry and the metadata of all keys in the key store.
+    pub async fn holder_keys(&self) -> anyhow::Result<HolderKeys> {
+        Ok(HolderKeys {
+            registry: self.key_registry().await?,
+            keys: self.key_store.list_async().await?,
+        })
+    }
+
+    /// Save the key registry to the key-value store.
+    async fn save_key_registry(&self, registry: &KeyRegistry) -> anyhow::Result<()> {
+        let data = serde_json::to_vec(registry)?;
+        self.kv.set_async(REGISTRY_KEY.into(), data).await?;
+        Ok(())
+    }
+
+    /// Resolve the ID of the key the provider signs with.
+    pub(super) async fn signing_key_id(&self) -> anyhow::Result<String> {
+        match &self.key {
+            KeyBinding::Scope(scope) => Ok(self.key_registry().await?.current_key(scope)),
+            KeyBinding::Key(key_id) => Ok(key_id.clone()),
+        }
+    }
+
+    /// Get the signing key the provider is bound to, generating and storing a
+    /// new secret if the key does not exist yet.
+    pub(super) async fn get_or_create_signing_key(&self) -> anyhow::Result<SigningKey> {
+        let key_id = self.signing_key_id().await?;
+        self.get_or_create_key(&key_id, SIGNING).await
+    }
+
+    /// Get the platform's attestation of the signing key the provider is bound
+    /// to, over the issuer's nonce if it has given one. Returns `None` if the
+    /// platform cannot attest keys.
+    pub async fn key_attestation(&self, nonce: Option<String>) -> anyhow::Result<Option<String>> {
+        self.get_or_create_signing_key().await?;
+        let key_id = self.signing_key_id().await?;
+        Ok(self.key_store.attest_async(key_id, SIGNING, nonce).await?)
+    }
+
+    /// Get an Ed25519 key held in the key store for the given purpose,
+    /// generating and storing a new secret if the key does not exist yet.
+    pub(super) async fn get_or_create_key(
+        &self, key_id: &str, purpose: &str,
+    ) -> anyhow::Result<SigningKey> {
+        let secret = match self.key_store.get_async(key_id, purpose).await? {
+            // The key store capability serializes key data on the way in.
+            KeyStoreEntry::Data(bytes) => serde_json::from_slice::<Vec<u8>>(&bytes)?,
+            KeyStoreEntry::None => {
+                let new_secret = self.key_store.generate_secret_async(32).await?;
+                self.key_store
+                    .set_async(key_id, purpose, SIGNING_ALGORITHM, new_secret.clone())
+                    .await?;
+                new_secret
+            }
+        };
+        let Ok(secret_bytes) = <[u8; 32]>::try_from(secret) else {
+            return Err(anyhow!("Unable to convert shell secret to fixed size byte array"));
+        };
+        Ok(SigningKey::from_bytes(&secret_bytes))
+    }
+
+    /// Rotate the holder keys.
+    ///
+    /// A new key is generated for every key scope in use. Superseded keys are
+    /// retired and kept while stored credentials are bound to them. Returns
+    /// the updated keys so credentials needing re-issuance can be flagged.
+    ///
+    /// The registry is saved before the new keys are generated. If generating
+    /// a key fails, the registry still records every key in the key store,
+    /// and the missing key is generated when it is first used.
+    pub async fn rotate_keys(&self) -> anyhow::Result<HolderKeys> {
+        let mut registry = self.key_registry().await?;
+        let records = self.load_records().await?;
+
+        let mut scopes: HashSet<String> = registry.current.keys().cloned().collect();
+        scopes.extend(records.iter().map(|record| key_scope(&record.key_id).to_string()));
+
+        let mut new_key_ids = Vec::new();
+        for scope in scopes {
+            let old_key_id = registry.current_key(&scope);
+            let new_key_id = next_key_id(&old_key_id);
+            registry.current.insert(scope, new_key_id.clone());
+            registry.retired.push(old_key_id);
+            new_key_ids.push(new_key_id);
+        }
+        self.save_key_registry(&registry).await?;
+
+        for key_id in new_key_ids {
+            self.get_or_create_key(&key_id, SIGNING).await?;
+        }
+
+        self.remove_unreferenced_keys(&mut registry, &records).await?;
+        self.save_key_registry(&registry).await?;
+        Ok(HolderKeys {
+            registry,
+            keys: self.key_store.list_async().await?,
+        })
+    }
+
+    /// Remove retired keys that no stored credential is bound to any more.
+    ///
+    /// Returns the remaining keys. Current keys that no credential is bound to
+    /// are kept, since an issuance may be about to bind a credential to them,
+    /// and are reported as orphaned instead.
+    pub async fn prune_keys(&self) -> anyhow::Result<HolderKeys> {
+        let mut registry = self.key_registry().await?;
+        let records = self.load_records().await?;
+        self.remove_unreferenced_keys(&mut registry, &records).await?;
+        self.save_key_registry(&registry).await?;
+        Ok(HolderKeys {
+            registry,
+            keys: self.key_store.list_async().await?,
+        })
+    }
+
+    /// Remove the holder signing keys that no stored credential is bound to.
+    ///
+    /// Current keys are kept, as by `prune_keys`, since an issuance may be
+    /// about to bind a credential to them.
+    pub async fn remove_orphaned_keys(&self) -> anyhow::Result<HolderKeys> {
+        let mut holder_keys = self.holder_keys().await?;
+        let records = self.load_records().await?;
+        let orphaned: HashSet<String> = holder_keys
+            .orphaned(&records)
+            .into_iter()
+            .filter(|key| !holder_keys.registry.is_current(&key.id))
+            .map(|key| key.id.clone())
+            .collect();
+        for key_id in &orphaned {
+            self.key_store.delete_async(key_id, SIGNING).await?;
+        }
+        holder_keys.registry.retired.retain(|key_id| !orphaned.contains(key_id));
+        holder_keys.keys.retain(|key| key.purpose != SIGNING || !orphaned.contains(&key.id));
+        self.save_key_registry(&holder_keys.registry).await?;
+        Ok(holder_keys)
+    }
+
+    async fn remove_unreferenced_keys(
+        &self, registry: &mut KeyRegistry, records: &[CredentialRecord],
+    ) -> anyhow::Result<()> {
+        let referenced: HashSet<&str> =
+            records.iter().map(|record| record.key_id.as_str()).collect();
+        let mut retained = Vec::new();
+        for key_id in std::mem::take(&mut registry.retired) {
+            if referenced.contains(key_id.as_str()) {
+                retained.push(key_id);
+            } else {
+                self.key_store.delete_async(&key_id, SIGNING).await?;
+            }
+        }
+        registry.retired = retained;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use vercre_holder::credential::Credential;
+    use vercre_holder::provider::Signer;
+
+    use super::*;
+    use crate::capabilities::store::Catalog;
+    use crate::provider::testing::Shell;
+
+    // Rotated key IDs keep their scope and increment the generation.
+    #[test]
+    fn rotated_key_ids() {
+        let scope = issuer_key_id("http://vercre.io");
+        let first = next_key_id(&scope);
+        assert_eq!(first, format!("{scope}#1"));
+        assert_eq!(next_key_id(&first), format!("{scope}#2"));
+        assert_eq!(key_scope(&first), scope);
+        assert_eq!(key_scope(LEGACY_KEY_ID), LEGACY_KEY_ID);
+    }
+
+    // Signing keys no credential is bound to are orphaned. Keys held for
+    // other purposes are not holder keys.
+    #[test]
+    fn orphaned_keys() {
+        let key = |id: &str, purpose: &str| KeyMetadata {
+            id: id.into(),
+            purpose: purpose.into(),
+            ..KeyMetadata::default()
+        };
+        let holder_keys = HolderKeys {
+            registry: KeyRegistry::default(),
+            keys: vec![key(LEGACY_KEY_ID, SIGNING), key("data#1", "encryption")],
+        };
+        let orphaned = holder_keys.orphaned(&[]);
+        assert_eq!(orphaned, vec![&key(LEGACY_KEY_ID, SIGNING)]);
+    }
+
+    // Each issuer gets its own holder key, and so its own verification
+    // method.
+    #[test]
+    fn pairwise_keys() {
+        let mut shell = Shell::new(1_700_000_000);
+        let (a, b) = shell.run(|provider| async move {
+            let mut keys = Vec::new();
+            for issuer in ["https://a.example", "https://b.example"] {
+                let provider = provider.for_issuer(issuer);
+                let kid = provider.verification_method().await.expect("should create key");
+                keys.push((provider.signing_key_id().await.expect("should get key ID"), kid));
+            }
+            (keys[0].clone(), keys[1].clone())
+        });
+        assert_eq!(a.0, issuer_key_id("https://a.example"));
+        assert_ne!(a.0, b.0);
+        assert_ne!(a.1, b.1);
+        assert_eq!(shell.keys.len(), 2);
+    }
+
+    // A rotated key is retired and kept while a credential is bound to it,
+    // and removed once none is. The current key is never removed.
+    #[test]
+    fn key_rotation() {
+        let json = include_bytes!("../model/credentials.json");
+        let credentials: Vec<Credential> =
+            serde_json::from_slice(json).expect("should deserialize");
+        let credential = credentials[0].clone();
+
+        let mut shell = Shell::new(1_700_000_000);
+        let (rotated, pruned, removed, old_key_id) = shell.run(|provider| async move {
+            let provider = provider.for_issuer(&credential.issuer);
+            provider.verification_method().await.expect("should create key");
+            let old_key_id = provider.signing_key_id().await.expect("should get key ID");
+            let record = CredentialRecord {
+                credential: credential.clone(),
+                key_id: old_key_id.clone(),
+                claim_metadata: None,
+            };
+            provider.save_record(&record).await.expect("should save credential");
+
+            let rotated = provider.rotate_keys().await.expect("should rotate");
+            let pruned = provider.prune_keys().await.expect("should prune");
+            provider
+                .store
+                .delete_async(Catalog::Credential.to_string(), &credential.id)
+                .await
+                .expect("should delete credential");
+            let removed = provider.remove_orphaned_keys().await.expect("should remove");
+            (rotated, pruned, removed, old_key_id)
+        });
+
+        let new_key_id = next_key_id(&old_key_id);
+        assert_eq!(rotated.registry.retired, vec![old_key_id.clone()]);
+        assert_eq!(rotated.registry.current_key(&old_key_id), new_key_id);
+        assert!(pruned.keys.iter().any(|key| key.id == old_key_id));
+
+        let signing: Vec<&str> = removed
+            .keys
+            .iter()
+            .filter(|key| key.purpose == SIGNING)
+            .map(|key| key.id.as_str())
+            .collect();
+        assert_eq!(signing, vec![new_key_id.as_str()]);
+        assert!(removed.registry.retired.is_empty());
+        assert!(shell.keys.contains_key(&(new_key_id, SIGNING.into())));
+        assert!(!shell.keys.contains_key(&(old_key_id, SIGNING.into())));
+    }
+}
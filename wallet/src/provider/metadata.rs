@@ -0,0 +1,424 @@
+//! Credential issuer metadata.
+//!
+//! Metadata is only accepted from the issuer an offer names: its
+//! `credential_issuer` must be identical to the issuer identifier in the
+//! offer, so an offer cannot pass off metadata served for one issuer as
+//! another's.
+//!
+//! An issuer can also sign its metadata. The `signed_metadata` JWT must be
+//! signed by the issuer, or the `did:web` DID at its URL, so whoever serves
+//! the metadata cannot sign it with a key of their own. It is verified with
+//! the signer's key, must be about the offer's issuer and, when verified,
+//! its claims take precedence over the plain JSON values. Metadata with a
+//! `signed_metadata` value that cannot be verified is rejected, so metadata
+//! that reaches the wallet with one has been verified.
+//!
+//! Display metadata of stored credentials can be refreshed from their
+//! issuers' current metadata, so a renamed or rebranded credential is shown
+//! as the issuer now describes it without being issued again.
+
+use std::collections::BTreeMap;
+
+use anyhow::{anyhow, bail};
+use serde_json::Value;
+use vercre_holder::credential::Credential;
+
+use super::issuance::IssuerMetadata;
+use super::{jose, Provider};
+
+/// Claims of a `signed_metadata` JWT that describe the JWT rather than the
+/// issuer.
+const JWT_CLAIMS: [&str; 5] = ["iss", "sub", "iat", "exp", "nbf"];
+
+/// Check metadata is for the given credential issuer.
+fn check_identifier(metadata: &Value, credential_issuer: &str) -> anyhow::Result<()> {
+    match metadata.get("credential_issuer").and_then(Value::as_str) {
+        Some(identifier) if identifier == credential_issuer => Ok(()),
+        Some(identifier) => {
+            bail!("metadata is for issuer {identifier}, not the offer's issuer {credential_issuer}")
+        }
+        None => bail!("metadata has no credential issuer identifier"),
+    }
+}
+
+/// Check the claims of a `signed_metadata` JWT are about the given credential
+/// issuer and current at the given time.
+fn check_signed_claims(claims: &Value, credential_issuer: &str, now: i64) -> anyhow::Result<()> {
+    let claim = |name: &str| claims.get(name).and_then(Value::as_i64);
+    if claims.get("sub").and_then(Value::as_str) != Some(credential_issuer) {
+        bail!("signed metadata is not about issuer {credential_issuer}");
+    }
+    if claim("iat").is_none() {
+        bail!("signed metadata has no issue time");
+    }
+    if claim("exp").is_some_and(|exp| exp <= now) {
+        bail!("signed metadata has expired");
+    }
+    if claim("nbf").is_some_and(|nbf| nbf > now) {
+        bail!("signed metadata is not yet valid");
+    }
+    Ok(())
+}
+
+/// Overwrite metadata values with the claims of verified signed metadata.
+fn apply_signed_claims(metadata: &mut Value, claims: Value) {
+    let (Some(metadata), Value::Object(claims)) = (metadata.as_object_mut(), claims) else {
+        return;
+    };
+    for (name, value) in claims {
+        if !JWT_CLAIMS.contains(&name.as_str()) {
+            metadata.insert(name, value);
+        }
+    }
+}
+
+/// The claim definitions of a credential configuration.
+pub(super) fn claim_definitions(config: &Value) -> Option<&Value> {
+    config.pointer("/credential_definition/credentialSubject").or_else(|| config.get("claims"))
+}
+
+/// The locale of a display entry.
+fn locale(display: &Value) -> Option<&str> {
+    display.get("locale").and_then(Value::as_str)
+}
+
+/// Find the configuration in issuer metadata that a stored credential was
+/// issued under, by its format and type.
+fn issued_config<'a>(metadata: &'a Value, credential: &Credential) -> Option<&'a Value> {
+    let configs = metadata.get("credential_configurations_supported")?.as_object()?;
+    configs.values().find(|config| {
+        let types = config.pointer("/credential_definition/type").and_then(Value::as_array);
+        let vct = config.get("vct").and_then(Value::as_str);
+        config.get("format").and_then(Value::as_str) == Some(credential.format.as_str())
+            && (types.is_some_and(|types| {
+                types
+                    .iter()
+                    .filter_map(Value::as_str)
+                    .eq(credential.type_.iter().map(String::as_str))
+            }) || vct.is_some_and(|vct| credential.type_.iter().any(|t| t == vct)))
+    })
+}
+
+impl<Ev> Provider<Ev>
+where
+    Ev: 'static,
+{
+    /// Get an issuer's metadata from its well-known endpoint, or the cache,
+    /// with display metadata for the user's locale where the issuer supports
+    /// it. Cached metadata is revalidated with the issuer if `revalidate` is
+    /// set.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the metadata cannot be fetched, is not for the
+    /// issuer, or has signed metadata that cannot be verified.
+    pub(super) async fn issuer_metadata(
+        &self, credential_issuer: &str, revalidate: bool,
+    ) -> anyhow::Result<IssuerMetadata> {
+        let url = format!("{credential_issuer}/.well-known/openid-credential-issuer");
+        let mut metadata: Value =
+            serde_json::from_slice(&self.get_cached(&url, revalidate).await?)?;
+
+        check_identifier(&metadata, credential_issuer)?;
+        let signed = metadata.get("signed_metadata").and_then(Value::as_str).map(String::from);
+        if let Some(signed) = &signed {
+            let claims = self
+                .verify_signed_metadata(signed, credential_issuer)
+                .await
+                .map_err(|e| anyhow!("signed metadata could not be verified: {e}"))?;
+            apply_signed_claims(&mut metadata, claims);
+            check_identifier(&metadata, credential_issuer)?;
+        }
+        Ok(IssuerMetadata {
+            metadata,
+            signed: signed.is_some(),
+        })
+    }
+
+    /// Verify a `signed_metadata` JWT with its signer's key and return its
+    /// claims. The signer must speak for the credential issuer.
+    async fn verify_signed_metadata(
+        &self, signed: &str, credential_issuer: &str,
+    ) -> anyhow::Result<Value> {
+        let claims = jose::decode(signed)?.payload;
+        let signer = claims
+            .get("iss")
+            .and_then(Value::as_str)
+            .ok_or_else(|| anyhow!("signed metadata has no signer"))?;
+        if !jose::is_issuer(signer, credential_issuer) {
+            bail!("signed metadata is signed by {signer}, not issuer {credential_issuer}");
+        }
+        let claims = self.verify_jws(signed, signer).await?;
+        let now = self.clock.now_async().await?.timestamp();
+        check_signed_claims(&claims, credential_issuer, now)?;
+        Ok(claims)
+    }
+
+    /// Refresh the display metadata of stored credentials from their issuers'
+    /// current metadata: the credential and issuer names, colours and images,
+    /// and the claim labels and order. Cached metadata and images are
+    /// revalidated with each issuer.
+    ///
+    /// Credentials whose issuer no longer describes them are left as they
+    /// are. Refreshed credentials are saved, so the change is reported by the
+    /// store.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the stored credentials cannot be loaded, or after
+    /// refreshing the others, naming the issuers whose metadata could not be
+    /// fetched.
+    pub async fn refresh_display(&self) -> anyhow::Result<()> {
+        let mut by_issuer = BTreeMap::<String, Vec<_>>::new();
+        for record in self.load_records().await? {
+            by_issuer.entry(record.credential.issuer.clone()).or_default().push(record);
+        }
+
+        let mut failed = Vec::new();
+        for (issuer, records) in by_issuer {
+            let metadata = match self.issuer_metadata(&issuer, true).await {
+                Ok(issuer_metadata) => issuer_metadata.metadata,
+                Err(_) => {
+                    failed.push(issuer);
+                    continue;
+                }
+            };
+            for mut record in records {
+                let Some(config) = issued_config(&metadata, &record.credential) else {
+                    continue;
+                };
+                let before = record.clone();
+                self.apply_display(&mut record.credential, &metadata, config, true).await;
+                record.claim_metadata = claim_definitions(config).cloned();
+                if record != before {
+                    self.save_record(&record).await?;
+                }
+            }
+        }
+
+        if !failed.is_empty() {
+            bail!("display metadata could not be refreshed from {}", failed.join(", "));
+        }
+        Ok(())
+    }
+
+    /// Update a credential's display metadata from its configuration and its
+    /// issuer's metadata. The logo and background image are those of the
+    /// display entry best matching the user's locale, fetched again if
+    /// `revalidate` is set. Images that cannot be fetched are left as they
+    /// are.
+    pub(super) async fn apply_display(
+        &self, credential: &mut Credential, metadata: &Value, config: &Value, revalidate: bool,
+    ) {
+        let issuer_display = metadata.get("display").and_then(Value::as_array);
+        let issuer_display = issuer_display.and_then(|display| self.locale.lookup(display, locale));
+        if let Some(name) = issuer_display.and_then(|display| display.get("name")?.as_str()) {
+            credential.issuer_name = name.into();
+        }
+        let definitions = claim_definitions(config).cloned().map(serde_json::from_value);
+        if let Some(Ok(definitions)) = definitions {
+            credential.claim_definitions = definitions;
+        }
+        let Some(displays) = config.get("display").and_then(Value::as_array) else {
+            return;
+        };
+        if let Ok(display) = serde_json::from_value(Value::from(displays.clone())) {
+            credential.display = display;
+        }
+        let Some(display) = self.locale.lookup(displays, locale) else {
+            return;
+        };
+        if let Some(uri) = display.pointer("/logo/uri").and_then(Value::as_str) {
+            if let Ok(logo) = self.fetch_image(uri, revalidate).await {
+                credential.logo = Some(logo);
+            }
+        }
+        if let Some(uri) = display.pointer("/background_image/uri").and_then(Value::as_str) {
+            if let Ok(background) = self.fetch_image(uri, revalidate).await {
+                credential.background = Some(background);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use base64ct::{Base64UrlUnpadded, Encoding};
+    use crux_http::protocol::HttpResponse;
+    use ed25519_dalek::{Signer as _, SigningKey};
+    use serde_json::json;
+
+    use super::*;
+    use crate::model::locale::LocalePreference;
+    use crate::provider::testing::Shell;
+
+    const ISSUER: &str = "https://issuer.io";
+
+    // Get the issuer's metadata as the wallet would, from an issuer that
+    // serves the given metadata and publishes the key with seed 1.
+    fn fetch_metadata(metadata: Value) -> Result<IssuerMetadata, String> {
+        let key = SigningKey::from_bytes(&[1; 32]);
+        let x = Base64UrlUnpadded::encode_string(key.verifying_key().as_bytes());
+        let mut shell = Shell::new(1_700_000_000).with_http(move |request| {
+            let body = match request.url.strip_prefix(ISSUER) {
+                Some("/.well-known/openid-credential-issuer") => metadata.clone(),
+                Some("/.well-known/jwt-vc-issuer") => json!({
+                    "issuer": ISSUER,
+                    "jwks": { "keys": [{ "kty": "OKP", "crv": "Ed25519", "x": x, "kid": "k1" }] },
+                }),
+                _ => return HttpResponse::status(404).build(),
+            };
+            HttpResponse::ok().body(body.to_string()).build()
+        });
+        shell.run(|provider| async move {
+            provider.issuer_metadata(ISSUER, false).await.map_err(|e| e.to_string())
+        })
+    }
+
+    // Sign metadata claims with the key with the given seed.
+    fn sign_metadata(seed: u8, iss: &str, kid: &str) -> String {
+        let key = SigningKey::from_bytes(&[seed; 32]);
+        let header = json!({ "typ": "JWT", "alg": "EdDSA", "kid": kid });
+        let claims = json!({
+            "iss": iss,
+            "sub": ISSUER,
+            "iat": 1_700_000_000,
+            "display": [{ "name": "Signed" }],
+        });
+        jose::encode(&header, &claims, |msg| key.sign(msg).to_vec()).expect("should sign")
+    }
+
+    // Metadata must be for the offer's issuer, and verified signed claims
+    // replace the plain values they cover.
+    #[test]
+    fn signed_metadata() {
+        let issuer = "https://issuer.io";
+        let mut metadata = json!({
+            "credential_issuer": issuer,
+            "display": [{ "name": "Plain" }],
+            "signed_metadata": "eyJ...",
+        });
+        check_identifier(&metadata, issuer).expect("should match");
+        let spoofed = check_identifier(&metadata, "https://other.io").expect_err("should reject");
+        assert!(spoofed.to_string().contains("not the offer's issuer"));
+
+        let claims = json!({
+            "iss": issuer,
+            "sub": issuer,
+            "iat": 1_700_000_000,
+            "exp": 1_700_003_600,
+            "display": [{ "name": "Signed" }],
+        });
+        check_signed_claims(&claims, issuer, 1_700_000_100).expect("should be current");
+        assert!(check_signed_claims(&claims, issuer, 1_700_003_600).is_err());
+        assert!(check_signed_claims(&claims, "https://other.io", 1_700_000_100).is_err());
+
+        apply_signed_claims(&mut metadata, claims);
+        assert_eq!(metadata["display"][0]["name"], "Signed");
+        assert_eq!(metadata["credential_issuer"], issuer);
+        assert!(metadata.get("iat").is_none());
+    }
+
+    // A stored credential is matched to the configuration it was issued
+    // under by its format and type.
+    #[test]
+    fn stored_credential_config() {
+        let json = include_bytes!("../model/credentials.json");
+        let credentials: Vec<Credential> =
+            serde_json::from_slice(json).expect("should deserialize");
+        let metadata = json!({ "credential_configurations_supported": {
+            "EmployeeID_SD_JWT": { "format": "vc+sd-jwt", "vct": "EmployeeIDCredential" },
+            "EmployeeID_JWT": {
+                "format": "jwt_vc_json",
+                "credential_definition": {
+                    "type": ["VerifiableCredential", "EmployeeIDCredential"]
+                },
+                "display": [{ "name": "Staff Card" }]
+            }
+        }});
+        let config = issued_config(&metadata, &credentials[0]).expect("should match");
+        assert_eq!(config["display"][0]["name"], "Staff Card");
+    }
+
+    // The issuer's name is taken from the display entry best matching the
+    // user's locale.
+    #[test]
+    fn issuer_name_locale() {
+        let json = include_bytes!("../model/credentials.json");
+        let credentials: Vec<Credential> =
+            serde_json::from_slice(json).expect("should deserialize");
+        let mut credential = credentials[0].clone();
+        let metadata = json!({ "display": [
+            { "name": "Issuer", "locale": "en" },
+            { "name": "Émetteur", "locale": "fr" }
+        ]});
+
+        let mut shell = Shell::new(1_700_000_000);
+        let credential = shell.run(|provider| async move {
+            let locale = LocalePreference {
+                locale: "fr-CA".into(),
+                time_zone: String::new(),
+            };
+            let provider = provider.with_locale(&locale);
+            provider.apply_display(&mut credential, &metadata, &json!({}), false).await;
+            credential
+        });
+        assert_eq!(credential.issuer_name, "Émetteur");
+    }
+
+    // Signed metadata is only accepted from the issuer, and the provider
+    // reports whether the metadata it returns was signed.
+    #[test]
+    fn signed_metadata_signer() {
+        let plain = json!({ "credential_issuer": ISSUER, "display": [{ "name": "Plain" }] });
+        let unsigned = fetch_metadata(plain.clone()).expect("should accept unsigned metadata");
+        assert!(!unsigned.signed);
+        assert_eq!(unsigned.metadata["display"][0]["name"], "Plain");
+
+        let mut metadata = plain.clone();
+        metadata["signed_metadata"] = sign_metadata(1, ISSUER, "k1").into();
+        let signed = fetch_metadata(metadata).expect("should accept the issuer's signature");
+        assert!(signed.signed);
+        assert_eq!(signed.metadata["display"][0]["name"], "Signed");
+
+        // Whoever serves the metadata cannot sign it with their own key.
+        let attacker = SigningKey::from_bytes(&[2; 32]);
+        let mut multikey = vec![0xed, 0x01];
+        multikey.extend(attacker.verifying_key().as_bytes());
+        let did = format!("did:key:{}", multibase::encode(multibase::Base::Base58Btc, multikey));
+        let kid = format!("{did}#{}", did.trim_start_matches("did:key:"));
+        let mut metadata = plain;
+        metadata["signed_metadata"] = sign_metadata(2, &did, &kid).into();
+        let error = fetch_metadata(metadata).expect_err("should reject a self-signed signature");
+        assert!(error.contains(&format!("not issuer {ISSUER}")), "{error}");
+    }
+
+    // A credential's images are those of the display entry best matching the
+    // user's locale.
+    #[test]
+    fn display_locale() {
+        let json = include_bytes!("../model/credentials.json");
+        let credentials: Vec<Credential> =
+            serde_json::from_slice(json).expect("should deserialize");
+        let mut credential = credentials[0].clone();
+        let png = "data:image/png;base64,iVBORw0KGgo=";
+        let config = json!({ "display": [
+            { "name": "Card", "locale": "en", "logo": { "uri": png } },
+            { "name": "Carte", "locale": "fr", "logo": { "uri": "data:image/gif,GIF89a" } }
+        ]});
+
+        let mut shell = Shell::new(1_700_000_000);
+        let credential = shell.run(|provider| async move {
+            let locale = LocalePreference {
+                locale: "fr-CA".into(),
+                time_zone: String::new(),
+            };
+            let provider = provider.with_locale(&locale);
+            provider.apply_display(&mut credential, &json!({}), &config, false).await;
+            credential
+        });
+        let logo = credential.logo.expect("should have a logo");
+        assert_eq!(logo.media_type, "image/gif");
+        assert_eq!(credential.display.map(|display| display.len()), Some(2));
+    }
+}
@@ -0,0 +1,36 @@
+//! Nonce endpoint.
+//!
+//! Issuers that advertise a `nonce_endpoint` issue nonces for proofs of
+//! possession from it, rather than with the token response.
+
+use serde::{Deserialize, Serialize};
+
+use super::response::json_body;
+use super::Provider;
+
+/// Response from an issuer's nonce endpoint.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct NonceResponse {
+    /// Nonce to use in proofs of possession.
+    pub c_nonce: String,
+
+    /// Lifetime of the nonce in seconds, if the issuer says.
+    #[serde(default)]
+    pub c_nonce_expires_in: Option<i64>,
+}
+
+impl<Ev> Provider<Ev>
+where
+    Ev: 'static,
+{
+    /// Get a fresh nonce from an issuer's nonce endpoint.
+    pub async fn nonce(&self, nonce_endpoint: &str) -> anyhow::Result<NonceResponse> {
+        let mut response = self
+            .http
+            .post(nonce_endpoint)
+            .header("Accept", "application/json")
+            .send_async()
+            .await?;
+        json_body(&mut response).await
+    }
+}
@@ -0,0 +1,576 @@
+//! Proofs of possession for credential requests.
+//!
+//! An issuer lists the proof types it accepts for each credential
+//! configuration in `proof_types_supported`, each with the signature
+//! algorithms it can verify. The wallet signs with Ed25519 and can create:
+//!
+//! - `jwt`: a JWT signed with `EdDSA`.
+//! - `cwt`: a CWT secured with a `COSE_Sign1` structure using COSE algorithm -8
+//!   (`EdDSA`).
+//! - `ldp_vp`: a Verifiable Presentation secured with a Data Integrity proof
+//!   using the `eddsa-jcs-2022` cryptosuite.
+//! - `attestation`: a key attestation from the wallet's platform, when one is
+//!   available.
+
+use std::fmt::{self, Display};
+
+use anyhow::{anyhow, bail};
+use base64ct::{Base64UrlUnpadded, Encoding};
+use chrono::DateTime;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use vercre_holder::provider::Signer;
+
+use super::verify::canonical_json;
+use super::Provider;
+
+/// JOSE algorithm of the wallet's proofs.
+const JOSE_ALG: &str = "EdDSA";
+
+/// COSE algorithm of the wallet's proofs (`EdDSA`).
+const COSE_ALG: i64 = -8;
+
+/// Data Integrity cryptosuite of the wallet's proofs.
+const CRYPTOSUITE: &str = "eddsa-jcs-2022";
+
+/// Proof types the wallet can create, in order of preference.
+const PREFERENCE: [ProofType; 4] =
+    [ProofType::Jwt, ProofType::Cwt, ProofType::LdpVp, ProofType::Attestation];
+
+/// Type of proof of possession.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProofType {
+    /// JWT signed by the holder key.
+    Jwt,
+
+    /// CWT in a `COSE_Sign1` structure signed by the holder key.
+    Cwt,
+
+    /// Verifiable Presentation with a Data Integrity proof by the holder key.
+    LdpVp,
+
+    /// Key attestation issued by the wallet's platform.
+    Attestation,
+}
+
+impl ProofType {
+    /// Name of the proof type in issuer metadata and credential requests.
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Jwt => "jwt",
+            Self::Cwt => "cwt",
+            Self::LdpVp => "ldp_vp",
+            Self::Attestation => "attestation",
+        }
+    }
+
+    /// Determine if the wallet can create this type of proof with one of the
+    /// signature algorithms an issuer accepts. An issuer that does not list
+    /// algorithms accepts any.
+    fn accepts(self, algorithms: Option<&Vec<Value>>) -> bool {
+        let Some(algorithms) = algorithms else {
+            return true;
+        };
+        algorithms.iter().any(|alg| match self {
+            Self::Jwt | Self::Attestation => alg == JOSE_ALG,
+            Self::Cwt => alg.as_i64() == Some(COSE_ALG) || alg == JOSE_ALG,
+            Self::LdpVp => alg == CRYPTOSUITE,
+        })
+    }
+
+    /// Signature algorithm of the wallet's proofs of this type.
+    const fn algorithm(self) -> &'static str {
+        match self {
+            Self::Jwt | Self::Attestation => JOSE_ALG,
+            Self::Cwt => "-8",
+            Self::LdpVp => CRYPTOSUITE,
+        }
+    }
+}
+
+impl Display for ProofType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Choose the type of proof to send with a request for a credential of the
+/// given configuration.
+///
+/// Returns `None` if the issuer does not require a proof for the
+/// configuration.
+///
+/// # Errors
+///
+/// Returns an error naming the proof types and algorithms on both sides if the
+/// wallet cannot create any type of proof the issuer accepts.
+pub fn choose_proof_type(
+    config: &impl Serialize, key_attestation: bool,
+) -> anyhow::Result<Option<ProofType>> {
+    let config = serde_json::to_value(config)?;
+    let Some(Value::Object(supported)) = config.get("proof_types_supported") else {
+        return Ok(None);
+    };
+    if supported.is_empty() {
+        return Ok(None);
+    }
+
+    let wallet = PREFERENCE
+        .into_iter()
+        .filter(|proof_type| key_attestation || *proof_type != ProofType::Attestation);
+    for proof_type in wallet.clone() {
+        let Some(accepted) = supported.get(proof_type.as_str()) else {
+            continue;
+        };
+        let algorithms =
+            accepted.get("proof_signing_alg_values_supported").and_then(Value::as_array);
+        if proof_type.accepts(algorithms) {
+            return Ok(Some(proof_type));
+        }
+    }
+
+    let issuer: Vec<String> = supported
+        .iter()
+        .map(|(name, accepted)| {
+            let algorithms = accepted
+                .get("proof_signing_alg_values_supported")
+                .and_then(Value::as_array)
+                .map(|algs| algs.iter().map(|alg| alg.to_string().replace('"', "")).collect())
+                .unwrap_or_else(Vec::<String>::new);
+            format!("{name} ({})", algorithms.join(", "))
+        })
+        .collect();
+    let wallet: Vec<String> =
+        wallet.map(|proof_type| format!("{proof_type} ({})", proof_type.algorithm())).collect();
+    bail!(
+        "no proof type is supported by both the issuer and the wallet: the issuer accepts {} \
+         and the wallet can create {}",
+        issuer.join(", "),
+        wallet.join(", ")
+    )
+}
+
+/// Claims a proof of possession makes.
+#[derive(Clone, Debug, Default)]
+pub struct ProofClaims {
+    /// Client ID of the wallet, if the issuer knows it.
+    pub client_id: Option<String>,
+
+    /// Credential issuer the proof is for.
+    pub audience: String,
+
+    /// Time the proof was created, in seconds since the Unix epoch.
+    pub issued_at: i64,
+
+    /// Nonce from the issuer.
+    pub nonce: Option<String>,
+}
+
+/// Proof of possession of the holder key, as sent in a credential request.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "proof_type", rename_all = "snake_case")]
+pub enum CredentialProof {
+    /// JWT proof.
+    Jwt { jwt: String },
+
+    /// Base64url-encoded CWT proof.
+    Cwt { cwt: String },
+
+    /// Verifiable Presentation proof.
+    LdpVp { ldp_vp: Value },
+
+    /// Key attestation proof.
+    Attestation { attestation: String },
+}
+
+impl<Ev> Provider<Ev>
+where
+    Ev: 'static,
+{
+    /// Create a proof of possession of the holder key for a credential of the
+    /// given configuration, of a type the issuer accepts.
+    ///
+    /// Returns `None` if the issuer does not require a proof.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if no proof type is supported by both the issuer and
+    /// the wallet, or the proof cannot be signed.
+    pub async fn proof(
+        &self, config: &impl Serialize, claims: &ProofClaims, key_attestation: Option<&str>,
+    ) -> anyhow::Result<Option<CredentialProof>> {
+        let Some(proof_type) = choose_proof_type(config, key_attestation.is_some())? else {
+            return Ok(None);
+        };
+        let kid = self.verification_method().await?;
+        let proof = match proof_type {
+            ProofType::Jwt => {
+                let signing_input = jwt_signing_input(claims, &kid)?;
+                let signature = self.try_sign(signing_input.as_bytes()).await?;
+                let signature = Base64UrlUnpadded::encode_string(&signature);
+                CredentialProof::Jwt {
+                    jwt: format!("{signing_input}.{signature}"),
+                }
+            }
+            ProofType::Cwt => {
+                let (protected, payload) = cwt_parts(claims, &kid);
+                let signature = self.try_sign(&cose_signing_input(&protected, &payload)).await?;
+                let cwt = cose_sign1(protected, payload, signature);
+                CredentialProof::Cwt {
+                    cwt: Base64UrlUnpadded::encode_string(&cwt),
+                }
+            }
+            ProofType::LdpVp => {
+                let (presentation, config) = ldp_vp_parts(claims, &kid)?;
+                let signature = self.try_sign(&data_integrity_hash(&presentation, &config)).await?;
+                CredentialProof::LdpVp {
+                    ldp_vp: secure(presentation, config, &signature),
+                }
+            }
+            ProofType::Attestation => CredentialProof::Attestation {
+                attestation: key_attestation
+                    .ok_or_else(|| anyhow!("no key attestation is available"))?
+                    .into(),
+            },
+        };
+        Ok(Some(proof))
+    }
+}
+
+/// Header and claims of a JWT proof, encoded for signing.
+fn jwt_signing_input(claims: &ProofClaims, kid: &str) -> anyhow::Result<String> {
+    let header = json!({ "typ": "openid4vci-proof+jwt", "alg": JOSE_ALG, "kid": kid });
+    let mut payload = json!({ "aud": claims.audience, "iat": claims.issued_at });
+    if let Some(client_id) = &claims.client_id {
+        payload["iss"] = client_id.as_str().into();
+    }
+    if let Some(nonce) = &claims.nonce {
+        payload["nonce"] = nonce.as_str().into();
+    }
+    let header = Base64UrlUnpadded::encode_string(&serde_json::to_vec(&header)?);
+    let payload = Base64UrlUnpadded::encode_string(&serde_json::to_vec(&payload)?);
+    Ok(format!("{header}.{payload}"))
+}
+
+/// Protected header and payload of a CWT proof, CBOR-encoded.
+fn cwt_parts(claims: &ProofClaims, kid: &str) -> (Vec<u8>, Vec<u8>) {
+    let protected = Cbor::Map(vec![
+        (Cbor::Int(1), Cbor::Int(COSE_ALG)),
+        (Cbor::Int(3), Cbor::Text("openid4vci-proof+cwt".into())),
+        (Cbor::Int(4), Cbor::Bytes(kid.as_bytes().to_vec())),
+    ]);
+
+    // CWT claim keys: 1 `iss`, 3 `aud`, 6 `iat`, 10 `nonce`.
+    let mut payload = Vec::new();
+    if let Some(client_id) = &claims.client_id {
+        payload.push((Cbor::Int(1), Cbor::Text(client_id.clone())));
+    }
+    payload.push((Cbor::Int(3), Cbor::Text(claims.audience.clone())));
+    payload.push((Cbor::Int(6), Cbor::Int(claims.issued_at)));
+    if let Some(nonce) = &claims.nonce {
+        payload.push((Cbor::Int(10), Cbor::Text(nonce.clone())));
+    }
+    (protected.to_vec(), Cbor::Map(payload).to_vec())
+}
+
+/// The `Sig_structure` a `COSE_Sign1` signature is made over.
+fn cose_signing_input(protected: &[u8], payload: &[u8]) -> Vec<u8> {
+    Cbor::Array(vec![
+        Cbor::Text("Signature1".into()),
+        Cbor::Bytes(protected.to_vec()),
+        Cbor::Bytes(Vec::new()),
+        Cbor::Bytes(payload.to_vec()),
+    ])
+    .to_vec()
+}
+
+/// A tagged `COSE_Sign1` structure.
+fn cose_sign1(protected: Vec<u8>, payload: Vec<u8>, signature: Vec<u8>) -> Vec<u8> {
+    let sign1 = Cbor::Array(vec![
+        Cbor::Bytes(protected),
+        Cbor::Map(Vec::new()),
+        Cbor::Bytes(payload),
+        Cbor::Bytes(signature),
+    ]);
+    Cbor::Tag(18, Box::new(sign1)).to_vec()
+}
+
+/// Unsecured presentation and proof configuration of an `ldp_vp` proof.
+fn ldp_vp_parts(claims: &ProofClaims, kid: &str) -> anyhow::Result<(Value, Value)> {
+    let holder = kid.split('#').next().unwrap_or(kid);
+    let context = json!(["https://www.w3.org/ns/credentials/v2"]);
+    let created = DateTime::from_timestamp(claims.issued_at, 0)
+        .ok_or_else(|| anyhow!("invalid proof time {}", claims.issued_at))?;
+    let presentation = json!({
+        "@context": context,
+        "type": ["VerifiablePresentation"],
+        "holder": holder,
+    });
+    let mut config = json!({
+        "@context": context,
+        "type": "DataIntegrityProof",
+        "cryptosuite": CRYPTOSUITE,
+        "created": created.to_rfc3339(),
+        "verificationMethod": kid,
+        "proofPurpose": "authentication",
+        "domain": claims.audience,
+    });
+    if let Some(nonce) = &claims.nonce {
+        config["challenge"] = nonce.as_str().into();
+    }
+    Ok((presentation, config))
+}
+
+/// Data an `eddsa-jcs-2022` proof signs: the hashes of the canonical proof
+/// configuration and document.
+fn data_integrity_hash(document: &Value, config: &Value) -> Vec<u8> {
+    let mut hash_data = Sha256::digest(canonical_json(config)).to_vec();
+    hash_data.extend(Sha256::digest(canonical_json(document)));
+    hash_data
+}
+
+/// Add a Data Integrity proof to a document.
+fn secure(mut document: Value, config: Value, signature: &[u8]) -> Value {
+    let Value::Object(mut proof) = config else {
+        return document;
+    };
+    proof.remove("@context");
+    let proof_value = multibase::encode(multibase::Base::Base58Btc, signature);
+    proof.insert("proofValue".into(), proof_value.into());
+    if let Some(document) = document.as_object_mut() {
+        document.insert("proof".into(), Value::Object(proof));
+    }
+    document
+}
+
+/// The subset of CBOR needed to encode CWT proofs.
+enum Cbor {
+    Int(i64),
+    Bytes(Vec<u8>),
+    Text(String),
+    Array(Vec<Cbor>),
+    Map(Vec<(Cbor, Cbor)>),
+    Tag(u64, Box<Cbor>),
+}
+
+impl Cbor {
+    fn to_vec(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.encode(&mut out);
+        out
+    }
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Self::Int(n) if *n >= 0 => head(0, n.unsigned_abs(), out),
+            Self::Int(n) => head(1, (-1 - n).unsigned_abs(), out),
+            Self::Bytes(bytes) => {
+                head(2, bytes.len() as u64, out);
+                out.extend(bytes);
+            }
+            Self::Text(text) => {
+                head(3, text.len() as u64, out);
+                out.extend(text.as_bytes());
+            }
+            Self::Array(items) => {
+                head(4, items.len() as u64, out);
+                items.iter().for_each(|item| item.encode(out));
+            }
+            Self::Map(entries) => {
+                head(5, entries.len() as u64, out);
+                for (key, value) in entries {
+                    key.encode(out);
+                    value.encode(out);
+                }
+            }
+            Self::Tag(tag, item) => {
+                head(6, *tag, out);
+                item.encode(out);
+            }
+        }
+    }
+}
+
+/// Encode the head of a CBOR data item: its major type and argument.
+fn head(major: u8, argument: u64, out: &mut Vec<u8>) {
+    let major = major << 5;
+    match argument {
+        0..=23 => out.push(major | argument as u8),
+        24..=0xff => out.extend([major | 24, argument as u8]),
+        0x100..=0xffff => {
+            out.push(major | 25);
+            out.extend((argument as u16).to_be_bytes());
+        }
+        0x1_0000..=0xffff_ffff => {
+            out.push(major | 26);
+            out.extend((argument as u32).to_be_bytes());
+        }
+        _ => {
+            out.push(major | 27);
+            out.extend(argument.to_be_bytes());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ed25519_dalek::{Signature, VerifyingKey};
+
+    use super::*;
+    use crate::provider::testing::Shell;
+
+    fn claims() -> ProofClaims {
+        ProofClaims {
+            client_id: Some("wallet".into()),
+            audience: "https://issuer.io".into(),
+            issued_at: 1_700_000_000,
+            nonce: Some("n1".into()),
+        }
+    }
+
+    /// Create a proof of the given type with the shell's holder key and return
+    /// it with the key's verification method and public key.
+    fn prove(shell: &mut Shell, proof_type: &str) -> (CredentialProof, String, VerifyingKey) {
+        let config = json!({ "proof_types_supported": { proof_type: {} } });
+        let (proof, kid, public_key) = shell.run(|provider| async move {
+            let proof = provider.proof(&config, &claims(), None).await.expect("should prove");
+            let kid = provider.verification_method().await.expect("should get kid");
+            let public_key = provider.public_key().await.expect("should get public key");
+            (proof.expect("should require a proof"), kid, public_key)
+        });
+        let public_key = <[u8; 32]>::try_from(public_key).expect("should be an Ed25519 key");
+        (proof, kid, VerifyingKey::from_bytes(&public_key).expect("should be a valid key"))
+    }
+
+    fn verify(public_key: &VerifyingKey, msg: &[u8], signature: &[u8]) {
+        let signature = Signature::from_slice(signature).expect("should be a signature");
+        public_key.verify_strict(msg, &signature).expect("signature should verify");
+    }
+
+    // A JWT proof names the holder key and is signed by it.
+    #[test]
+    fn jwt_proof() {
+        let mut shell = Shell::new(1_700_000_000);
+        let (proof, kid, public_key) = prove(&mut shell, "jwt");
+        let CredentialProof::Jwt { jwt } = proof else {
+            panic!("expected a JWT proof, got {proof:?}");
+        };
+
+        let (signing_input, signature) = jwt.rsplit_once('.').expect("should be a JWT");
+        let header = signing_input.split('.').next().expect("should have a header");
+        let header: Value =
+            serde_json::from_slice(&Base64UrlUnpadded::decode_vec(header).expect("should decode"))
+                .expect("should be JSON");
+        assert_eq!(header["kid"], json!(kid));
+        assert_eq!(header["typ"], json!("openid4vci-proof+jwt"));
+        let signature = Base64UrlUnpadded::decode_vec(signature).expect("should decode");
+        verify(&public_key, signing_input.as_bytes(), &signature);
+    }
+
+    // A CWT proof is a COSE_Sign1 structure over the claims signed by the
+    // holder key.
+    #[test]
+    fn cwt_proof() {
+        let mut shell = Shell::new(1_700_000_000);
+        let (proof, kid, public_key) = prove(&mut shell, "cwt");
+        let CredentialProof::Cwt { cwt } = proof else {
+            panic!("expected a CWT proof, got {proof:?}");
+        };
+
+        let cwt = Base64UrlUnpadded::decode_vec(&cwt).expect("should decode");
+        let signature = cwt[cwt.len() - 64..].to_vec();
+        let (protected, payload) = cwt_parts(&claims(), &kid);
+        verify(&public_key, &cose_signing_input(&protected, &payload), &signature);
+        assert_eq!(cose_sign1(protected, payload, signature), cwt);
+    }
+
+    // An `ldp_vp` proof is a presentation secured by the holder key over its
+    // canonical form and the proof configuration.
+    #[test]
+    fn ldp_vp_proof() {
+        let mut shell = Shell::new(1_700_000_000);
+        let (proof, kid, public_key) = prove(&mut shell, "ldp_vp");
+        let CredentialProof::LdpVp { mut ldp_vp } = proof else {
+            panic!("expected an ldp_vp proof, got {proof:?}");
+        };
+
+        let Some(Value::Object(mut config)) =
+            ldp_vp.as_object_mut().and_then(|vp| vp.remove("proof"))
+        else {
+            panic!("presentation should have a proof");
+        };
+        assert_eq!(config["verificationMethod"], json!(kid));
+        assert_eq!(config["challenge"], json!("n1"));
+        let proof_value = config.remove("proofValue").expect("should have a proof value");
+        let (_, signature) = multibase::decode(proof_value.as_str().expect("should be a string"))
+            .expect("should decode");
+        config.insert("@context".into(), ldp_vp["@context"].clone());
+        verify(&public_key, &data_integrity_hash(&ldp_vp, &Value::Object(config)), &signature);
+    }
+
+    // The platform's key attestation is sent when the issuer accepts one.
+    #[test]
+    fn attestation_proof() {
+        let mut shell = Shell::new(1_700_000_000);
+        shell.attestation = Some("attestation.jwt".into());
+        let config = json!({ "proof_types_supported": { "attestation": {} } });
+        let proof = shell.run(|provider| async move {
+            provider.credential_proof("https://issuer.io", &config, Some("n1".into())).await
+        });
+        assert_eq!(
+            proof.expect("should prove"),
+            Some(CredentialProof::Attestation {
+                attestation: "attestation.jwt".into()
+            })
+        );
+    }
+
+    // The wallet prefers a JWT proof, falls back to another type it can sign
+    // with an algorithm the issuer accepts, and explains a mismatch.
+    #[test]
+    fn proof_type_selection() {
+        let config = json!({ "format": "jwt_vc_json" });
+        assert_eq!(choose_proof_type(&config, false).expect("should choose"), None);
+
+        let config = json!({ "proof_types_supported": {
+            "jwt": { "proof_signing_alg_values_supported": ["ES256"] },
+            "cwt": { "proof_signing_alg_values_supported": [-7, -8] },
+        }});
+        assert_eq!(choose_proof_type(&config, false).expect("should choose"), Some(ProofType::Cwt));
+
+        let config = json!({ "proof_types_supported": {
+            "attestation": { "proof_signing_alg_values_supported": ["EdDSA"] },
+            "ldp_vp": { "proof_signing_alg_values_supported": ["ecdsa-rdfc-2019"] },
+        }});
+        assert_eq!(
+            choose_proof_type(&config, true).expect("should choose"),
+            Some(ProofType::Attestation)
+        );
+        let error = choose_proof_type(&config, false).expect_err("should not choose");
+        assert!(error.to_string().contains("ldp_vp (ecdsa-rdfc-2019)"));
+    }
+
+    // A CWT proof is a tagged COSE_Sign1 structure over the CWT claims.
+    #[test]
+    fn cwt_encoding() {
+        let claims = ProofClaims {
+            audience: "https://issuer.io".into(),
+            issued_at: 1_700_000_000,
+            nonce: Some("n1".into()),
+            ..ProofClaims::default()
+        };
+        let (protected, payload) = cwt_parts(&claims, "k");
+        // {1: -8, 3: "openid4vci-proof+cwt", 4: h'6b'}
+        assert_eq!(&protected[..5], &[0xa3, 0x01, 0x27, 0x03, 0x74]);
+        assert_eq!(&protected[protected.len() - 3..], &[0x04, 0x41, b'k']);
+        // {3: "https://issuer.io", 6: 1700000000, 10: "n1"}
+        assert_eq!(&payload[..3], &[0xa3, 0x03, 0x71]);
+        assert!(payload.ends_with(&[0x06, 0x1a, 0x65, 0x53, 0xf1, 0x00, 0x0a, 0x62, b'n', b'1']));
+
+        let cwt = cose_sign1(protected, payload, vec![0; 64]);
+        assert_eq!(&cwt[..3], &[0xd2, 0x84, 0x58]);
+        assert!(cwt.ends_with(&[&[0x58, 0x40][..], &[0; 64]].concat()));
+    }
+}
@@ -0,0 +1,373 @@
+//! Responses from issuer and verifier services.
+//!
+//! Services report failures with an error status and, usually, an OAuth 2.0
+//! style error body (`error`, `error_description`) that OpenID4VCI extends
+//! with a fresh `c_nonce`. Bearer token failures may instead be reported in
+//! the `WWW-Authenticate` header. Responses are checked before their bodies
+//! are deserialized so these errors reach the app as a [`ServiceError`] rather
+//! than a confusing deserialization failure.
+
+use std::fmt;
+
+use crux_http::Response;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+/// Longest excerpt of an unrecognised error body kept for diagnosis.
+const MAX_DETAIL: usize = 200;
+
+/// Error codes defined by OAuth 2.0 (RFC 6749, RFC 6750 and RFC 9449),
+/// OpenID4VCI and OpenID4VP.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(from = "String", into = "String")]
+pub enum ErrorCode {
+    /// The request is missing a parameter or is otherwise malformed.
+    InvalidRequest,
+
+    /// Client authentication failed.
+    InvalidClient,
+
+    /// The authorization grant, such as a pre-authorized code or transaction
+    /// code, is invalid, expired or already used.
+    InvalidGrant,
+
+    /// The client is not authorized to use the grant type.
+    UnauthorizedClient,
+
+    /// The grant type is not supported by the authorization server.
+    UnsupportedGrantType,
+
+    /// The requested scope is invalid or unknown.
+    InvalidScope,
+
+    /// The access token is invalid or has expired.
+    InvalidToken,
+
+    /// The access token does not grant access to the requested resource.
+    InsufficientScope,
+
+    /// The user or service denied the request.
+    AccessDenied,
+
+    /// The credential request is malformed.
+    InvalidCredentialRequest,
+
+    /// The requested credential type is not supported.
+    UnsupportedCredentialType,
+
+    /// The requested credential format is not supported.
+    UnsupportedCredentialFormat,
+
+    /// The proof of possession is invalid, often because its nonce is stale.
+    InvalidProof,
+
+    /// The nonce in the proof is invalid or has expired.
+    InvalidNonce,
+
+    /// The credential response encryption parameters are not supported.
+    InvalidEncryptionParameters,
+
+    /// The issuer will not issue the credential.
+    CredentialRequestDenied,
+
+    /// The deferred credential is not ready yet.
+    IssuancePending,
+
+    /// The deferred transaction ID is invalid.
+    InvalidTransactionId,
+
+    /// The notification ID is invalid.
+    InvalidNotificationId,
+
+    /// The notification request is malformed.
+    InvalidNotificationRequest,
+
+    /// The verifier does not support any of the wallet's formats.
+    VpFormatsNotSupported,
+
+    /// The verifier's presentation definition could not be retrieved.
+    InvalidPresentationDefinitionUri,
+
+    /// The service encountered an unexpected condition.
+    ServerError,
+
+    /// The service is temporarily overloaded or down for maintenance.
+    TemporarilyUnavailable,
+
+    /// The server requires a nonce in the DPoP proof, given in the
+    /// `DPoP-Nonce` header.
+    UseDpopNonce,
+
+    /// The DPoP proof is invalid.
+    InvalidDpopProof,
+
+    /// An error code the wallet does not recognise, or none at all.
+    Other(String),
+}
+
+impl ErrorCode {
+    /// The error code as sent on the wire.
+    #[must_use]
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::InvalidRequest => "invalid_request",
+            Self::InvalidClient => "invalid_client",
+            Self::InvalidGrant => "invalid_grant",
+            Self::UnauthorizedClient => "unauthorized_client",
+            Self::UnsupportedGrantType => "unsupported_grant_type",
+            Self::InvalidScope => "invalid_scope",
+            Self::InvalidToken => "invalid_token",
+            Self::InsufficientScope => "insufficient_scope",
+            Self::AccessDenied => "access_denied",
+            Self::InvalidCredentialRequest => "invalid_credential_request",
+            Self::UnsupportedCredentialType => "unsupported_credential_type",
+            Self::UnsupportedCredentialFormat => "unsupported_credential_format",
+            Self::InvalidProof => "invalid_proof",
+            Self::InvalidNonce => "invalid_nonce",
+            Self::InvalidEncryptionParameters => "invalid_encryption_parameters",
+            Self::CredentialRequestDenied => "credential_request_denied",
+            Self::IssuancePending => "issuance_pending",
+            Self::InvalidTransactionId => "invalid_transaction_id",
+            Self::InvalidNotificationId => "invalid_notification_id",
+            Self::InvalidNotificationRequest => "invalid_notification_request",
+            Self::VpFormatsNotSupported => "vp_formats_not_supported",
+            Self::InvalidPresentationDefinitionUri => "invalid_presentation_definition_uri",
+            Self::ServerError => "server_error",
+            Self::TemporarilyUnavailable => "temporarily_unavailable",
+            Self::UseDpopNonce => "use_dpop_nonce",
+            Self::InvalidDpopProof => "invalid_dpop_proof",
+            Self::Other(code) => code,
+        }
+    }
+
+    /// No error code was given.
+    fn unknown() -> Self {
+        Self::Other(String::new())
+    }
+}
+
+impl Default for ErrorCode {
+    fn default() -> Self {
+        Self::unknown()
+    }
+}
+
+impl From<String> for ErrorCode {
+    fn from(code: String) -> Self {
+        match code.as_str() {
+            "invalid_request" => Self::InvalidRequest,
+            "invalid_client" => Self::InvalidClient,
+            "invalid_grant" => Self::InvalidGrant,
+            "unauthorized_client" => Self::UnauthorizedClient,
+            "unsupported_grant_type" => Self::UnsupportedGrantType,
+            "invalid_scope" => Self::InvalidScope,
+            "invalid_token" => Self::InvalidToken,
+            "insufficient_scope" => Self::InsufficientScope,
+            "access_denied" => Self::AccessDenied,
+            "invalid_credential_request" => Self::InvalidCredentialRequest,
+            "unsupported_credential_type" => Self::UnsupportedCredentialType,
+            "unsupported_credential_format" => Self::UnsupportedCredentialFormat,
+            "invalid_proof" => Self::InvalidProof,
+            "invalid_nonce" => Self::InvalidNonce,
+            "invalid_encryption_parameters" => Self::InvalidEncryptionParameters,
+            "credential_request_denied" => Self::CredentialRequestDenied,
+            "issuance_pending" => Self::IssuancePending,
+            "invalid_transaction_id" => Self::InvalidTransactionId,
+            "invalid_notification_id" => Self::InvalidNotificationId,
+            "invalid_notification_request" => Self::InvalidNotificationRequest,
+            "vp_formats_not_supported" => Self::VpFormatsNotSupported,
+            "invalid_presentation_definition_uri" => Self::InvalidPresentationDefinitionUri,
+            "server_error" => Self::ServerError,
+            "temporarily_unavailable" => Self::TemporarilyUnavailable,
+            "use_dpop_nonce" => Self::UseDpopNonce,
+            "invalid_dpop_proof" => Self::InvalidDpopProof,
+            _ => Self::Other(code),
+        }
+    }
+}
+
+impl From<ErrorCode> for String {
+    fn from(code: ErrorCode) -> Self {
+        code.as_str().to_string()
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// What the wallet should do to recover from a service error.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ServiceRecovery {
+    /// Create a new proof over a fresh nonce: the one the issuer supplied with
+    /// the error or, if none, one from the issuer's nonce endpoint.
+    RefreshNonce(Option<String>),
+
+    /// Get a new access token and repeat the request.
+    Reauthorize,
+
+    /// The error cannot be recovered from automatically.
+    None,
+}
+
+/// An error response from an issuer or verifier service.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[allow(clippy::module_name_repetitions)]
+pub struct ServiceError {
+    /// HTTP status code of the response.
+    #[serde(skip)]
+    pub status: u16,
+
+    /// Error code.
+    #[serde(default)]
+    pub error: ErrorCode,
+
+    /// Human-readable description of the error, if the service gave one.
+    #[serde(default, alias = "message")]
+    pub error_description: Option<String>,
+
+    /// Fresh nonce to use in a new proof, sent by OpenID4VCI issuers.
+    #[serde(default)]
+    pub c_nonce: Option<String>,
+
+    /// Lifetime of the fresh nonce, in seconds.
+    #[serde(default)]
+    pub c_nonce_expires_in: Option<i64>,
+}
+
+impl ServiceError {
+    /// Build an error from a response's status, headers and body.
+    fn from_response(status: u16, www_authenticate: Option<&str>, body: &[u8]) -> Self {
+        let mut error = serde_json::from_slice::<Self>(body).unwrap_or_else(|_| {
+            let text = String::from_utf8_lossy(body);
+            let text = text.trim();
+            Self {
+                error_description: (!text.is_empty())
+                    .then(|| text.chars().take(MAX_DETAIL).collect()),
+                ..Self::default()
+            }
+        });
+        error.status = status;
+
+        // Bearer token errors are reported in the `WWW-Authenticate` header,
+        // often with an empty body.
+        if error.error == ErrorCode::unknown() {
+            if let Some(challenge) = www_authenticate {
+                if let Some(code) = challenge_param(challenge, "error") {
+                    error.error = code.into();
+                }
+                if error.error_description.is_none() {
+                    error.error_description = challenge_param(challenge, "error_description");
+                }
+            }
+        }
+        error
+    }
+
+    /// Decide how the wallet can recover from the error.
+    #[must_use]
+    pub fn recovery(&self) -> ServiceRecovery {
+        match self.error {
+            ErrorCode::InvalidProof | ErrorCode::InvalidNonce => {
+                ServiceRecovery::RefreshNonce(self.c_nonce.clone())
+            }
+            ErrorCode::InvalidToken => ServiceRecovery::Reauthorize,
+            _ => ServiceRecovery::None,
+        }
+    }
+
+    /// Whether repeating the request later may succeed.
+    #[must_use]
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self.error,
+            ErrorCode::ServerError | ErrorCode::TemporarilyUnavailable | ErrorCode::IssuancePending
+        ) || self.status >= 500
+    }
+}
+
+impl fmt::Display for ServiceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "service responded with status {}", self.status)?;
+        if self.error != ErrorCode::unknown() {
+            write!(f, ": {}", self.error)?;
+        }
+        if let Some(description) = &self.error_description {
+            write!(f, " ({description})")?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ServiceError {}
+
+/// Get a parameter from an authentication challenge such as
+/// `Bearer error="invalid_token", error_description="expired"`.
+fn challenge_param(challenge: &str, name: &str) -> Option<String> {
+    let (_, params) = challenge.split_once(' ')?;
+    params.split(',').find_map(|param| {
+        let (key, value) = param.trim().split_once('=')?;
+        (key.trim() == name).then(|| value.trim().trim_matches('"').to_string())
+    })
+}
+
+/// Check a response was successful and deserialize its JSON body. An error
+/// status is returned as a [`ServiceError`].
+pub(super) async fn json_body<T: DeserializeOwned>(
+    response: &mut Response<Vec<u8>>,
+) -> anyhow::Result<T> {
+    let body = success_body(response).await?;
+    Ok(serde_json::from_slice(&body)?)
+}
+
+/// Check a response was successful and get its body. An error status is
+/// returned as a [`ServiceError`].
+pub(super) async fn success_body(response: &mut Response<Vec<u8>>) -> anyhow::Result<Vec<u8>> {
+    let body = response.body_bytes().await?;
+    let status = response.status();
+    if status.is_success() {
+        return Ok(body);
+    }
+    let www_authenticate =
+        response.header("WWW-Authenticate").map(|values| values.last().as_str().to_string());
+    Err(ServiceError::from_response(status.into(), www_authenticate.as_deref(), &body).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // OAuth and OpenID4VCI error bodies, bearer challenges and unrecognised
+    // bodies are all turned into service errors.
+    #[test]
+    fn error_responses() {
+        let body = br#"{"error":"invalid_proof","error_description":"stale","c_nonce":"n2"}"#;
+        let error = ServiceError::from_response(400, None, body);
+        assert_eq!(error.error, ErrorCode::InvalidProof);
+        assert_eq!(error.error_description.as_deref(), Some("stale"));
+        assert_eq!(error.recovery(), ServiceRecovery::RefreshNonce(Some("n2".into())));
+
+        let challenge =
+            r#"Bearer realm="issuer", error="invalid_token", error_description="expired""#;
+        let error = ServiceError::from_response(401, Some(challenge), b"");
+        assert_eq!(error.error, ErrorCode::InvalidToken);
+        assert_eq!(error.error_description.as_deref(), Some("expired"));
+        assert_eq!(error.recovery(), ServiceRecovery::Reauthorize);
+
+        let error = ServiceError::from_response(400, None, br#"{"message":"bad offer"}"#);
+        assert_eq!(error.error, ErrorCode::unknown());
+        assert_eq!(error.error_description.as_deref(), Some("bad offer"));
+        assert_eq!(error.to_string(), "service responded with status 400 (bad offer)");
+
+        let error = ServiceError::from_response(503, None, b"<html>down</html>");
+        assert!(error.is_transient());
+        assert_eq!(error.recovery(), ServiceRecovery::None);
+
+        let code: ErrorCode = "use_dpop_nonce".to_string().into();
+        assert_eq!(code, ErrorCode::UseDpopNonce);
+        let code: ErrorCode = "slow_down".to_string().into();
+        assert_eq!(code, ErrorCode::Other("slow_down".into()));
+    }
+}
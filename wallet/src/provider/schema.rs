@@ -0,0 +1,142 @@
+//! Schema versioning for persisted wallet data.
+//!
+//! Records are stored inside a versioned envelope so that changes to the shape
+//! of stored data, including upstream changes to `vercre-holder` types, do not
+//! break installed wallets. Records written with an older schema version are
+//! upgraded by the registered migrations when they are loaded.
+
+use anyhow::{anyhow, bail};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::provider::keys::LEGACY_KEY_ID;
+
+/// Current schema version of stored records.
+pub const SCHEMA_VERSION: u32 = 2;
+
+/// Schema version of records stored before the versioned envelope was
+/// introduced.
+const UNVERSIONED: u32 = 1;
+
+/// A migration upgrades a record from one schema version to the next.
+type Migration = fn(Value) -> anyhow::Result<Value>;
+
+/// Migrations keyed by the schema version they upgrade from.
+const MIGRATIONS: &[(u32, Migration)] = &[(1, bind_legacy_key)];
+
+/// Versioned envelope around a stored record.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct Envelope {
+    /// Schema version the record was written with.
+    schema_version: u32,
+
+    /// The record.
+    data: Value,
+}
+
+/// Encode a record in an envelope with the current schema version.
+pub fn encode<T: Serialize>(record: &T) -> anyhow::Result<Vec<u8>> {
+    let envelope = Envelope {
+        schema_version: SCHEMA_VERSION,
+        data: serde_json::to_value(record)?,
+    };
+    Ok(serde_json::to_vec(&envelope)?)
+}
+
+/// Decode a record, upgrading it to the current schema version. Returns the
+/// record and whether it was migrated and so should be saved again.
+///
+/// Only a record without a schema version is read as unversioned. An envelope
+/// that cannot be read is an error.
+pub fn decode<T: DeserializeOwned>(bytes: &[u8]) -> anyhow::Result<(T, bool)> {
+    let value: Value = serde_json::from_slice(bytes)?;
+    let (mut version, mut data) = if value.get("schema_version").is_some() {
+        let envelope = serde_json::from_value::<Envelope>(value)
+            .map_err(|e| anyhow!("invalid schema envelope: {e}"))?;
+        (envelope.schema_version, envelope.data)
+    } else {
+        (UNVERSIONED, value)
+    };
+    if version > SCHEMA_VERSION {
+        bail!("schema version {version} is newer than supported version {SCHEMA_VERSION}");
+    }
+
+    let migrated = version < SCHEMA_VERSION;
+    while version < SCHEMA_VERSION {
+        let Some((_, migrate)) = MIGRATIONS.iter().find(|(from, _)| *from == version) else {
+            bail!("no migration from schema version {version}");
+        };
+        data = migrate(data)?;
+        version += 1;
+    }
+
+    Ok((serde_json::from_value(data)?, migrated))
+}
+
+/// Version 1 to 2: credentials stored before pairwise keys were introduced
+/// are bound to the original wallet key.
+fn bind_legacy_key(mut data: Value) -> anyhow::Result<Value> {
+    let Some(record) = data.as_object_mut() else {
+        bail!("credential record is not an object");
+    };
+    record.entry("key_id").or_insert_with(|| LEGACY_KEY_ID.into());
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::credential::CredentialRecord;
+
+    // Unversioned credentials should be migrated and bound to the original
+    // wallet key.
+    #[test]
+    fn migrate_unversioned_credentials() {
+        let json = include_bytes!("../model/credentials.json");
+        let credentials: Vec<Value> = serde_json::from_slice(json).expect("should deserialize");
+        assert_eq!(credentials.len(), 2);
+
+        for credential in credentials {
+            let bytes = serde_json::to_vec(&credential).expect("should serialize");
+            let (record, migrated) =
+                decode::<CredentialRecord>(&bytes).expect("should decode unversioned record");
+            assert!(migrated);
+            assert_eq!(record.key_id, LEGACY_KEY_ID);
+
+            let bytes = encode(&record).expect("should encode");
+            let (decoded, migrated) =
+                decode::<CredentialRecord>(&bytes).expect("should decode current record");
+            assert!(!migrated);
+            assert_eq!(decoded, record);
+        }
+    }
+
+    // An envelope that cannot be read should be rejected rather than read as
+    // an unversioned record.
+    #[test]
+    fn reject_invalid_envelope() {
+        let json = include_bytes!("../model/credentials.json");
+        let credentials: Vec<Value> = serde_json::from_slice(json).expect("should deserialize");
+        let record = bind_legacy_key(credentials[0].clone()).expect("should bind key");
+        for envelope in [
+            serde_json::json!({ "schema_version": "2", "data": record }),
+            serde_json::json!({ "schema_version": 2, "data": record, "extra": true }),
+        ] {
+            let bytes = serde_json::to_vec(&envelope).expect("should serialize");
+            assert!(decode::<CredentialRecord>(&bytes).is_err());
+        }
+    }
+
+    // Records written by a newer version of the wallet should be rejected.
+    #[test]
+    fn reject_newer_schema() {
+        let bytes = serde_json::to_vec(&serde_json::json!({
+            "schema_version": SCHEMA_VERSION + 1,
+            "data": {}
+        }))
+        .expect("should serialize");
+        assert!(decode::<CredentialRecord>(&bytes).is_err());
+    }
+}
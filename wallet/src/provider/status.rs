@@ -0,0 +1,520 @@
+//! Credential status checking.
+//!
+//! Credentials refer to an entry in a status list published by their issuer,
+//! using either an IETF Token Status List (`status.status_list`) or a W3C
+//! Bitstring Status List (`credentialStatus`). Status lists are fetched as
+//! signed JWTs, verified against the credential issuer's key and cached until
+//! their time-to-live expires.
+//!
+//! Issuers can also push status updates over an event stream. The wallet
+//! subscribes with a token signed by its key for the issuer, so the issuer
+//! only sends it updates about credentials it holds.
+
+use std::collections::HashMap;
+use std::io::Read;
+
+use anyhow::{anyhow, bail};
+use chrono::{DateTime, Duration, Utc};
+use ed25519_dalek::Signer as _;
+use flate2::read::{GzDecoder, ZlibDecoder};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use vercre_holder::credential::Credential;
+use vercre_holder::provider::Signer;
+
+use super::{jose, Provider};
+use crate::model::status::{CredentialStatus, STATUS_EVENTS_PATH};
+
+/// Prefix of key-value store keys under which status lists are cached.
+const CACHE_PREFIX: &str = "status_list:";
+
+/// How long a status list is cached, in minutes, when its issuer does not say.
+const DEFAULT_TTL_MINUTES: i64 = 15;
+
+/// Largest decompressed status list accepted, in bytes.
+const MAX_LIST_SIZE: u64 = 16 * 1024 * 1024;
+
+/// A reference from a credential to its entry in a status list.
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum StatusReference {
+    /// Entry in an IETF Token Status List.
+    Token { uri: String, index: usize },
+
+    /// Entry in a W3C Bitstring Status List.
+    Bitstring { uri: String, index: usize, purpose: String, size: usize },
+}
+
+/// A decompressed status list.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+struct StatusList {
+    /// Number of bits per status entry.
+    bits: usize,
+
+    /// Entries are packed from the least significant bit of each byte (Token
+    /// Status List) rather than the most significant (Bitstring Status List).
+    lsb_first: bool,
+
+    /// The list of status entries.
+    #[serde(with = "serde_bytes")]
+    list: Vec<u8>,
+
+    /// When the cached list should be fetched again.
+    expires: DateTime<Utc>,
+}
+
+impl StatusList {
+    /// Get the status value at the given index.
+    fn value(&self, index: usize) -> Option<u8> {
+        if !matches!(self.bits, 1 | 2 | 4 | 8) {
+            return None;
+        }
+        let bit = index.checked_mul(self.bits)?;
+        let byte = *self.list.get(bit / 8)?;
+        let shift = if self.lsb_first { bit % 8 } else { 8 - self.bits - bit % 8 };
+        let mask = u8::MAX >> (8 - self.bits);
+        Some((byte >> shift) & mask)
+    }
+}
+
+impl CredentialStatus {
+    /// Combine the statuses of two status list entries for the same
+    /// credential. Revocation outweighs suspension, which outweighs a status
+    /// that could not be determined.
+    fn and(self, other: Self) -> Self {
+        let weight = |status: &Self| match status {
+            Self::Valid => 0,
+            Self::Unknown => 1,
+            Self::Suspended => 2,
+            Self::Revoked => 3,
+        };
+        if weight(&other) > weight(&self) {
+            other
+        } else {
+            self
+        }
+    }
+}
+
+/// Get the claims of an issued credential without verifying them.
+pub(super) fn issued_claims(issued: &str) -> Option<Value> {
+    if let Ok(claims @ Value::Object(_)) = serde_json::from_str(issued) {
+        return Some(claims);
+    }
+    // The issuer-signed JWT of an SD-JWT precedes the first disclosure.
+    let jwt = issued.split('~').next()?;
+    jose::decode(jwt).ok().map(|jws| jws.payload)
+}
+
+/// Find the status list entries an issued credential refers to.
+fn status_references(issued: &str) -> Vec<StatusReference> {
+    let Some(claims) = issued_claims(issued) else {
+        return vec![];
+    };
+    let mut references = Vec::new();
+
+    if let Some(entry) = claims.pointer("/status/status_list") {
+        let uri = entry.get("uri").and_then(Value::as_str);
+        let index = entry.get("idx").and_then(Value::as_u64);
+        if let (Some(uri), Some(Ok(index))) = (uri, index.map(usize::try_from)) {
+            references.push(StatusReference::Token {
+                uri: uri.into(),
+                index,
+            });
+        }
+    }
+
+    let vc = claims.get("vc").unwrap_or(&claims);
+    let entries = match vc.get("credentialStatus") {
+        Some(Value::Array(entries)) => entries.clone(),
+        Some(entry) => vec![entry.clone()],
+        None => vec![],
+    };
+    for entry in entries {
+        let field = |name: &str| entry.get(name).and_then(Value::as_str);
+        if !matches!(field("type"), Some("BitstringStatusListEntry" | "StatusList2021Entry")) {
+            continue;
+        }
+        let (Some(uri), Some(purpose), Some(Ok(index))) = (
+            field("statusListCredential"),
+            field("statusPurpose"),
+            field("statusListIndex").map(str::parse),
+        ) else {
+            continue;
+        };
+        let size = entry.get("statusSize").and_then(Value::as_u64).unwrap_or(1);
+        references.push(StatusReference::Bitstring {
+            uri: uri.into(),
+            index,
+            purpose: purpose.into(),
+            size: usize::try_from(size).unwrap_or_default(),
+        });
+    }
+    references
+}
+
+/// Decompress a status list, refusing lists that expand beyond the size
+/// limit.
+fn decompress(mut reader: impl Read) -> anyhow::Result<Vec<u8>> {
+    let mut list = Vec::new();
+    (&mut reader).take(MAX_LIST_SIZE + 1).read_to_end(&mut list)?;
+    if list.len() as u64 > MAX_LIST_SIZE {
+        bail!("status list is too large");
+    }
+    Ok(list)
+}
+
+/// Get when a status list should be fetched again from its time-to-live and
+/// expiry, whichever is sooner.
+fn expiry(
+    now: DateTime<Utc>, ttl: Option<Duration>, expires: Option<DateTime<Utc>>,
+) -> anyhow::Result<DateTime<Utc>> {
+    if let Some(expires) = expires {
+        if expires <= now {
+            bail!("status list has expired");
+        }
+    }
+    let refresh = now + ttl.unwrap_or_else(|| Duration::minutes(DEFAULT_TTL_MINUTES));
+    Ok(expires.map_or(refresh, |expires| expires.min(refresh)))
+}
+
+impl<Ev> Provider<Ev>
+where
+    Ev: 'static,
+{
+    /// Check the status of stored credentials. Credentials whose status
+    /// cannot be determined are reported as unknown.
+    pub async fn check_status(
+        &self, credentials: Vec<Credential>,
+    ) -> HashMap<String, CredentialStatus> {
+        let mut statuses = HashMap::new();
+        for credential in credentials {
+            let status = self.credential_status(&credential).await.unwrap_or_default();
+            statuses.insert(credential.id, status);
+        }
+        statuses
+    }
+
+    /// Check the status of a credential against the status lists it refers
+    /// to.
+    pub async fn credential_status(
+        &self, credential: &Credential,
+    ) -> anyhow::Result<CredentialStatus> {
+        let references = status_references(&credential.issued);
+        if references.is_empty() {
+            return Ok(CredentialStatus::Unknown);
+        }
+
+        let mut status = CredentialStatus::Valid;
+        for reference in references {
+            let entry_status = match &reference {
+                StatusReference::Token { uri, index } => {
+                    let list = self.status_list(&reference, &credential.issuer).await?;
+                    match list.value(*index) {
+                        Some(0) => CredentialStatus::Valid,
+                        Some(1) => CredentialStatus::Revoked,
+                        Some(2) => CredentialStatus::Suspended,
+                        Some(_) => CredentialStatus::Unknown,
+                        None => bail!("status list {uri} has no entry {index}"),
+                    }
+                }
+                StatusReference::Bitstring {
+                    uri, index, purpose, ..
+                } => {
+                    let list = self.status_list(&reference, &credential.issuer).await?;
+                    let Some(value) = list.value(*index) else {
+                        bail!("status list {uri} has no entry {index}");
+                    };
+                    match (purpose.as_str(), value) {
+                        (_, 0) => CredentialStatus::Valid,
+                        ("revocation", _) => CredentialStatus::Revoked,
+                        ("suspension", _) => CredentialStatus::Suspended,
+                        // Other purposes, such as messages, do not affect
+                        // validity.
+                        _ => CredentialStatus::Valid,
+                    }
+                }
+            };
+            status = status.and(entry_status);
+        }
+        Ok(status)
+    }
+
+    /// Get the URL of an issuer's status event stream, authenticated with a
+    /// token signed by the wallet's key for the issuer.
+    ///
+    /// The stream is at the identifier in the issuer's metadata rather than
+    /// the issuer a credential names, which need not be a URL.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the issuer's metadata cannot be fetched or the
+    /// token cannot be signed.
+    pub async fn status_events(&self, credential_issuer: &str) -> anyhow::Result<String> {
+        let metadata = self.issuer_metadata(credential_issuer, false).await?.metadata;
+        let events_issuer = metadata
+            .get("credential_issuer")
+            .and_then(Value::as_str)
+            .ok_or_else(|| anyhow!("metadata has no credential issuer identifier"))?
+            .trim_end_matches('/');
+
+        let provider = self.for_issuer(credential_issuer);
+        let kid = provider.verification_method().await?;
+        let holder = kid.split('#').next().unwrap_or(&kid);
+        let header = json!({ "typ": "JWT", "alg": "EdDSA", "kid": kid });
+        let payload = json!({
+            "iss": holder,
+            "aud": events_issuer,
+            "iat": self.clock.now_async().await?.timestamp(),
+        });
+        let key = provider.get_or_create_signing_key().await?;
+        let token = jose::encode(&header, &payload, |msg| key.sign(msg).to_vec())?;
+        Ok(format!("{events_issuer}{STATUS_EVENTS_PATH}?token={token}"))
+    }
+
+    /// Get a status list from the cache, or fetch and verify it if it is not
+    /// cached or the cached list has expired.
+    async fn status_list(
+        &self, reference: &StatusReference, issuer: &str,
+    ) -> anyhow::Result<StatusList> {
+        let (StatusReference::Token { uri, .. } | StatusReference::Bitstring { uri, .. }) =
+            reference;
+        let key = format!("{CACHE_PREFIX}{uri}");
+        let now = self.clock.now_async().await?;
+        if let Some(data) = self.kv.get_async(key.clone()).await? {
+            if let Ok(list) = serde_json::from_slice::<StatusList>(&data) {
+                if list.expires > now {
+                    return Ok(list);
+                }
+            }
+        }
+
+        let list = match reference {
+            StatusReference::Token { uri, .. } => self.fetch_token_list(uri, issuer, now).await?,
+            StatusReference::Bitstring {
+                uri, purpose, size, ..
+            } => self.fetch_bitstring_list(uri, purpose, *size, issuer, now).await?,
+        };
+        self.kv.set_async(key, serde_json::to_vec(&list)?).await?;
+        Ok(list)
+    }
+
+    /// Fetch and verify an IETF Token Status List.
+    async fn fetch_token_list(
+        &self, uri: &str, issuer: &str, now: DateTime<Utc>,
+    ) -> anyhow::Result<StatusList> {
+        let token = self.fetch_status_token(uri, "application/statuslist+jwt").await?;
+        let claims = self.verify_jws(&token, issuer).await?;
+        if claims.get("sub").and_then(Value::as_str) != Some(uri) {
+            bail!("status list token is not for {uri}");
+        }
+
+        let bits = claims.pointer("/status_list/bits").and_then(Value::as_u64);
+        let lst = claims.pointer("/status_list/lst").and_then(Value::as_str);
+        let (Some(bits), Some(lst)) = (bits, lst) else {
+            bail!("status list token has no status list");
+        };
+        let list = decompress(ZlibDecoder::new(jose::base64url(lst)?.as_slice()))?;
+
+        let ttl = claims.get("ttl").and_then(Value::as_i64).map(Duration::seconds);
+        let expires = claims
+            .get("exp")
+            .and_then(Value::as_i64)
+            .and_then(|exp| DateTime::from_timestamp(exp, 0));
+        Ok(StatusList {
+            bits: usize::try_from(bits)?,
+            lsb_first: true,
+            list,
+            expires: expiry(now, ttl, expires)?,
+        })
+    }
+
+    /// Fetch and verify a W3C Bitstring Status List credential.
+    async fn fetch_bitstring_list(
+        &self, uri: &str, purpose: &str, size: usize, issuer: &str, now: DateTime<Utc>,
+    ) -> anyhow::Result<StatusList> {
+        let token = self.fetch_status_token(uri, "application/vc+jwt").await?;
+        let claims = self.verify_jws(&token, issuer).await?;
+        let vc = claims.get("vc").unwrap_or(&claims);
+        let subject = vc.get("credentialSubject").ok_or_else(|| anyhow!("no status list"))?;
+
+        let purposes = match subject.get("statusPurpose") {
+            Some(Value::Array(purposes)) => purposes.clone(),
+            Some(purpose) => vec![purpose.clone()],
+            None => vec![],
+        };
+        if !purposes.iter().any(|p| p.as_str() == Some(purpose)) {
+            bail!("status list {uri} is not for {purpose}");
+        }
+        let Some(encoded) = subject.get("encodedList").and_then(Value::as_str) else {
+            bail!("status list {uri} has no encoded list");
+        };
+        // The list is multibase base64url encoded, but some issuers omit the
+        // multibase prefix.
+        let compressed = match multibase::decode(encoded) {
+            Ok((_, compressed)) => compressed,
+            Err(_) => jose::base64url(encoded)?,
+        };
+        let list = decompress(GzDecoder::new(compressed.as_slice()))?;
+
+        let ttl = subject.get("ttl").and_then(Value::as_i64).map(Duration::milliseconds);
+        let valid_until = vc
+            .get("validUntil")
+            .and_then(Value::as_str)
+            .and_then(|date| DateTime::parse_from_rfc3339(date).ok())
+            .map(|date| date.with_timezone(&Utc));
+        Ok(StatusList {
+            bits: size,
+            lsb_first: false,
+            list,
+            expires: expiry(now, ttl, valid_until)?,
+        })
+    }
+
+    /// Fetch a signed status list.
+    async fn fetch_status_token(&self, uri: &str, media_type: &str) -> anyhow::Result<String> {
+        let mut response = self.http.get(uri).header("Accept", media_type).send_async().await?;
+        if !response.status().is_success() {
+            bail!("status list request to {uri} failed with status {}", response.status());
+        }
+        Ok(response.body_string().await?.trim().to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crux_http::protocol::HttpResponse;
+    use vercre_holder::provider::CredentialStorer;
+
+    use super::*;
+    use crate::model::credential::CredentialRecord;
+    use crate::provider::testing::Shell;
+
+    // Token Status Lists pack entries from the least significant bit;
+    // Bitstring Status Lists from the most significant.
+    #[test]
+    fn status_list_values() {
+        let token = StatusList {
+            bits: 2,
+            lsb_first: true,
+            list: vec![0b1001_0100],
+            expires: DateTime::default(),
+        };
+        assert_eq!(token.value(0), Some(0));
+        assert_eq!(token.value(1), Some(1));
+        assert_eq!(token.value(2), Some(1));
+        assert_eq!(token.value(3), Some(2));
+        assert_eq!(token.value(4), None);
+
+        let bitstring = StatusList {
+            bits: 1,
+            lsb_first: false,
+            list: vec![0b1000_0001],
+            expires: DateTime::default(),
+        };
+        assert_eq!(bitstring.value(0), Some(1));
+        assert_eq!(bitstring.value(1), Some(0));
+        assert_eq!(bitstring.value(7), Some(1));
+    }
+
+    // Status list entries are read from the `status` claim of JWT credentials
+    // and the `credentialStatus` of VCDM credentials.
+    #[test]
+    fn read_status_references() {
+        let vc = serde_json::json!({
+            "credentialStatus": [{
+                "type": "BitstringStatusListEntry",
+                "statusPurpose": "revocation",
+                "statusListIndex": "94567",
+                "statusListCredential": "https://example.com/credentials/status/3"
+            }],
+            "status": {
+                "status_list": { "idx": 3, "uri": "https://example.com/statuslists/1" }
+            }
+        });
+        let references = status_references(&vc.to_string());
+        assert_eq!(
+            references,
+            vec![
+                StatusReference::Token {
+                    uri: "https://example.com/statuslists/1".into(),
+                    index: 3,
+                },
+                StatusReference::Bitstring {
+                    uri: "https://example.com/credentials/status/3".into(),
+                    index: 94567,
+                    purpose: "revocation".into(),
+                    size: 1,
+                },
+            ]
+        );
+    }
+
+    // Revoked credentials are left out of those found for presentation.
+    #[test]
+    fn find_leaves_out_revoked() {
+        const LIST_URI: &str = "https://issuer.example/statuslists/1";
+
+        let json = include_bytes!("../model/credentials.json");
+        let mut credentials: Vec<Credential> =
+            serde_json::from_slice(json).expect("should deserialize");
+        for (index, credential) in credentials.iter_mut().enumerate() {
+            let status = json!({ "status": { "status_list": { "idx": index, "uri": LIST_URI } } });
+            credential.issued = status.to_string();
+        }
+        let valid_id = credentials[1].id.clone();
+
+        // The first entry is revoked and the second valid.
+        let list = StatusList {
+            bits: 2,
+            lsb_first: true,
+            list: vec![0b0000_0001],
+            expires: DateTime::from_timestamp(1_800_000_000, 0).expect("should be a time"),
+        };
+        let mut shell = Shell::new(1_700_000_000);
+        shell.kv.insert(
+            format!("{CACHE_PREFIX}{LIST_URI}"),
+            serde_json::to_vec(&list).expect("should serialize"),
+        );
+        let found = shell.run(|provider| async move {
+            for credential in credentials {
+                let record = CredentialRecord {
+                    key_id: provider.signing_key_id().await.expect("should get key ID"),
+                    credential,
+                    claim_metadata: None,
+                };
+                provider.save_record(&record).await.expect("should save credential");
+            }
+            provider.find(None).await.expect("should find credentials")
+        });
+        assert_eq!(found.iter().map(|c| &c.id).collect::<Vec<_>>(), vec![&valid_id]);
+    }
+
+    // The status event stream is at the issuer's metadata identifier and is
+    // subscribed to with a token signed by the wallet's key for the issuer.
+    #[test]
+    fn status_events_token() {
+        const ISSUER: &str = "https://issuer.example";
+
+        let mut shell = Shell::new(1_700_000_000).with_http(|request| {
+            if request.url == format!("{ISSUER}/.well-known/openid-credential-issuer") {
+                let metadata = json!({ "credential_issuer": ISSUER });
+                return HttpResponse::ok().body(metadata.to_string()).build();
+            }
+            HttpResponse::status(404).build()
+        });
+        let (url, kid) = shell.run(|provider| async move {
+            let url = provider.status_events(ISSUER).await.expect("should get stream URL");
+            let issuer = provider.for_issuer(ISSUER);
+            let kid = issuer.verification_method().await.expect("should get issuer key");
+            (url, kid)
+        });
+
+        let token = url.strip_prefix(&format!("{ISSUER}/events?token=")).expect("should be stream");
+        let token = jose::decode(token).expect("should decode");
+        assert!(kid.starts_with("did:key:z6Mk"));
+        assert_eq!(token.header.kid.as_deref(), Some(kid.as_str()));
+        assert_eq!(token.payload["iss"], kid.split('#').next().unwrap_or_default());
+        assert_eq!(token.payload["aud"], ISSUER);
+        assert_eq!(token.payload["iat"], 1_700_000_000);
+    }
+}
@@ -0,0 +1,355 @@
+//! Test harness for the provider.
+//!
+//! Runs provider tasks in a minimal Crux app. The harness stands in for the
+//! shell with in-memory key, key-value and credential stores, a fixed clock
+//! and an HTTP handler supplied by the test.
+
+use std::any::Any;
+use std::collections::{BTreeMap, VecDeque};
+use std::future::Future;
+
+use chrono::DateTime;
+use crux_core::compose::Compose;
+use crux_core::testing::AppTester;
+use crux_http::protocol::{HttpRequest, HttpResponse, HttpResult};
+use crux_http::Http;
+use crux_kv::value::Value as KvValue;
+use crux_kv::{KeyValue, KeyValueOperation, KeyValueResponse, KeyValueResult};
+use futures::future::BoxFuture;
+use futures::FutureExt;
+
+use super::Provider;
+use crate::capabilities::clock::{Clock, ClockResponse};
+use crate::capabilities::key::{
+    KeyMetadata, KeyMetadataEntry, KeyStore, KeyStoreOperation, KeyStoreResponse, KeyStoreResult,
+};
+use crate::capabilities::store::{
+    Store, StoreChange, StoreEntry, StoreOperation, StoreResponse, StoreResult,
+};
+
+/// Output of a provider task.
+type Output = Box<dyn Any + Send>;
+
+/// A provider task.
+type Task = Box<dyn FnOnce(Provider<Event>) -> BoxFuture<'static, Output> + Send>;
+
+/// Handler for the HTTP requests the provider makes.
+type HttpHandler = Box<dyn FnMut(&HttpRequest) -> HttpResponse>;
+
+pub enum Event {
+    /// Run a task with a provider.
+    Run(Task),
+
+    /// The task has finished.
+    Done(Output),
+}
+
+#[derive(Default)]
+pub struct Model {
+    output: Option<Output>,
+}
+
+#[derive(crux_core::macros::Effect)]
+pub struct Capabilities {
+    #[effect(skip)]
+    pub compose: Compose<Event>,
+    pub clock: Clock<Event>,
+    pub http: Http<Event>,
+    pub key_store: KeyStore<Event>,
+    pub kv: KeyValue<Event>,
+    pub store: Store<Event>,
+}
+
+#[derive(Default)]
+pub struct App;
+
+impl crux_core::App for App {
+    type Capabilities = Capabilities;
+    type Event = Event;
+    type Model = Model;
+    type ViewModel = ();
+
+    fn update(&self, event: Event, model: &mut Model, caps: &Capabilities) {
+        match event {
+            Event::Run(task) => {
+                let provider = Provider::new(
+                    caps.clock.clone(),
+                    caps.http.clone(),
+                    caps.key_store.clone(),
+                    caps.kv.clone(),
+                    caps.store.clone(),
+                );
+                caps.compose.spawn(|ctx| async move {
+                    ctx.update_app(Event::Done(task(provider).await));
+                });
+            }
+            Event::Done(output) => model.output = Some(output),
+        }
+    }
+
+    fn view(&self, _model: &Model) {}
+}
+
+/// The shell's side of the provider's capabilities.
+pub struct Shell {
+    /// Current time, in seconds since the Unix epoch.
+    pub now: i64,
+
+    /// Keys, by ID and purpose.
+    pub keys: BTreeMap<(String, String), (KeyMetadata, Vec<u8>)>,
+
+    /// Key attestation the platform gives, if it can attest keys.
+    pub attestation: Option<String>,
+
+    /// Key-value store.
+    pub kv: BTreeMap<String, Vec<u8>>,
+
+    /// Credential store, by catalog and ID.
+    pub store: BTreeMap<(String, String), Vec<u8>>,
+
+    /// HTTP requests made, in order.
+    pub requests: Vec<HttpRequest>,
+
+    http: HttpHandler,
+}
+
+impl Shell {
+    /// Create a shell whose clock reads the given time. HTTP requests fail
+    /// with a 404 response until a handler is given.
+    pub fn new(now: i64) -> Self {
+        Self {
+            now,
+            keys: BTreeMap::new(),
+            attestation: None,
+            kv: BTreeMap::new(),
+            store: BTreeMap::new(),
+            requests: Vec::new(),
+            http: Box::new(|_| HttpResponse::status(404).build()),
+        }
+    }
+
+    /// Handle HTTP requests with the given handler.
+    #[must_use]
+    pub fn with_http(
+        mut self, handler: impl FnMut(&HttpRequest) -> HttpResponse + 'static,
+    ) -> Self {
+        self.http = Box::new(handler);
+        self
+    }
+
+    /// Run a task with a provider, handling its requests until it finishes,
+    /// and return its output.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the task waits on a request the shell does not answer.
+    pub fn run<T, F>(&mut self, task: impl FnOnce(Provider<Event>) -> F + Send + 'static) -> T
+    where
+        T: Send + 'static,
+        F: Future<Output = T> + Send + 'static,
+    {
+        let task: Task = Box::new(move |provider| {
+            task(provider).map(|output| Box::new(output) as Output).boxed()
+        });
+        let app = AppTester::<App, Effect>::default();
+        let mut model = Model::default();
+        let mut effects = VecDeque::new();
+        let update = app.update(Event::Run(task), &mut model);
+        absorb(&app, update, &mut model, &mut effects);
+
+        while let Some(effect) = effects.pop_front() {
+            let update = match effect {
+                Effect::Clock(mut request) => {
+                    let now = ClockResponse::Now {
+                        seconds: self.now,
+                        nanos: 0,
+                    };
+                    app.resolve(&mut request, now)
+                }
+                Effect::Http(mut request) => {
+                    self.requests.push(request.operation.clone());
+                    let response = (self.http)(&request.operation);
+                    app.resolve(&mut request, HttpResult::Ok(response))
+                }
+                Effect::KeyStore(mut request) => {
+                    let result = self.key_store(&request.operation);
+                    app.resolve(&mut request, result)
+                }
+                Effect::KeyValue(mut request) => {
+                    let result = self.key_value(&request.operation);
+                    app.resolve(&mut request, result)
+                }
+                Effect::Store(mut request) => {
+                    let Some(result) = self.store(&request.operation) else {
+                        continue;
+                    };
+                    app.resolve(&mut request, result)
+                }
+            }
+            .expect("should resolve request");
+            absorb(&app, update, &mut model, &mut effects);
+        }
+
+        let output = model.output.take().expect("task should finish");
+        *output.downcast::<T>().expect("task output should have the expected type")
+    }
+
+    fn key_store(&mut self, operation: &KeyStoreOperation) -> KeyStoreResult {
+        let response = match operation {
+            KeyStoreOperation::Get { id, purpose } => KeyStoreResponse::Retrieved {
+                key: self.keys.get(&(id.clone(), purpose.clone())).map(|(_, d)| d.clone()).into(),
+            },
+            KeyStoreOperation::Set {
+                id,
+                purpose,
+                algorithm,
+                data,
+            } => {
+                let created = DateTime::from_timestamp(self.now, 0).unwrap_or_default();
+                self.keys
+                    .entry((id.clone(), purpose.clone()))
+                    .and_modify(|(_, key)| key.clone_from(data))
+                    .or_insert_with(|| {
+                        let metadata = KeyMetadata {
+                            id: id.clone(),
+                            purpose: purpose.clone(),
+                            algorithm: algorithm.clone(),
+                            created: created.to_rfc3339(),
+                        };
+                        (metadata, data.clone())
+                    });
+                KeyStoreResponse::Set
+            }
+            KeyStoreOperation::Delete { id, purpose } => {
+                self.keys.remove(&(id.clone(), purpose.clone()));
+                KeyStoreResponse::Deleted
+            }
+            KeyStoreOperation::GenerateSecret { length } => KeyStoreResponse::GeneratedSecret {
+                secret: (0..*length).map(|_| rand::random()).collect(),
+            },
+            KeyStoreOperation::List => KeyStoreResponse::Listed {
+                keys: self.keys.values().map(|(metadata, _)| metadata.clone()).collect(),
+            },
+            KeyStoreOperation::Metadata { id, purpose } => KeyStoreResponse::Described {
+                metadata: match self.keys.get(&(id.clone(), purpose.clone())) {
+                    Some((metadata, _)) => KeyMetadataEntry::Metadata(metadata.clone()),
+                    None => KeyMetadataEntry::None,
+                },
+            },
+            KeyStoreOperation::Attest { .. } => KeyStoreResponse::Attested {
+                attestation: self.attestation.clone().into(),
+            },
+        };
+        KeyStoreResult::Ok { response }
+    }
+
+    fn key_value(&mut self, operation: &KeyValueOperation) -> KeyValueResult {
+        let value = |value: Option<Vec<u8>>| value.map_or(KvValue::None, KvValue::Bytes);
+        let response = match operation {
+            KeyValueOperation::Get { key } => KeyValueResponse::Get {
+                value: value(self.kv.get(key).cloned()),
+            },
+            KeyValueOperation::Set { key, value: data } => KeyValueResponse::Set {
+                previous: value(self.kv.insert(key.clone(), data.clone())),
+            },
+            KeyValueOperation::Delete { key } => KeyValueResponse::Delete {
+                previous: value(self.kv.remove(key)),
+            },
+            KeyValueOperation::Exists { key } => KeyValueResponse::Exists {
+                is_present: self.kv.contains_key(key),
+            },
+            KeyValueOperation::ListKeys { prefix, .. } => KeyValueResponse::ListKeys {
+                keys: self
+                    .kv
+                    .keys()
+                    .filter(|key| key.starts_with(prefix.as_str()))
+                    .cloned()
+                    .collect(),
+                next_cursor: 0,
+            },
+        };
+        KeyValueResult::Ok { response }
+    }
+
+    /// Answer a store request. Queries return the whole catalog, and watches
+    /// are never answered: the harness does not report changes.
+    fn store(&mut self, operation: &StoreOperation) -> Option<StoreResult> {
+        let response = match operation {
+            StoreOperation::Save {
+                catalog, id, data, ..
+            } => {
+                self.store.insert((catalog.clone(), id.clone()), data.clone());
+                StoreResponse::Saved
+            }
+            StoreOperation::Get { catalog, id } => StoreResponse::Retrieved {
+                entry: self.store.get(&(catalog.clone(), id.clone())).cloned().into(),
+            },
+            StoreOperation::List { catalog } | StoreOperation::Query { catalog, .. } => {
+                StoreResponse::List {
+                    entries: self
+                        .store
+                        .iter()
+                        .filter(|((entry_catalog, _), _)| entry_catalog == catalog)
+                        .map(|(_, data)| StoreEntry::Data(data.clone()))
+                        .collect(),
+                }
+            }
+            StoreOperation::Delete { catalog, id } => {
+                self.store.remove(&(catalog.clone(), id.clone()));
+                StoreResponse::Deleted
+            }
+            StoreOperation::Batch { changes } => {
+                for change in changes {
+                    match change {
+                        StoreChange::Save {
+                            catalog, id, data, ..
+                        } => {
+                            self.store.insert((catalog.clone(), id.clone()), data.clone());
+                        }
+                        StoreChange::Delete { catalog, id } => {
+                            self.store.remove(&(catalog.clone(), id.clone()));
+                        }
+                    }
+                }
+                StoreResponse::Committed
+            }
+            StoreOperation::Watch { .. } => return None,
+        };
+        Some(StoreResult::Ok { response })
+    }
+}
+
+/// Queue an update's effects and process its events, queueing theirs.
+fn absorb(
+    app: &AppTester<App, Effect>, update: crux_core::testing::Update<Effect, Event>,
+    model: &mut Model, effects: &mut VecDeque<Effect>,
+) {
+    effects.extend(update.effects);
+    for event in update.events {
+        let update = app.update(event, model);
+        absorb(app, update, model, effects);
+    }
+}
+
+/// Get the URL of the status event stream the wallet subscribes to for a
+/// credential issuer, as `Provider::status_events` makes it, with the issuer
+/// serving the given metadata.
+///
+/// # Errors
+///
+/// Returns an error if the provider cannot make the URL.
+#[cfg(feature = "testing")]
+pub fn status_events(
+    credential_issuer: &str, metadata: &serde_json::Value, now: i64,
+) -> anyhow::Result<String> {
+    let metadata_url = format!("{credential_issuer}/.well-known/openid-credential-issuer");
+    let metadata = metadata.to_string();
+    let mut shell = Shell::new(now).with_http(move |request| {
+        if request.url == metadata_url {
+            return HttpResponse::ok().body(metadata.clone()).build();
+        }
+        HttpResponse::status(404).build()
+    });
+    let credential_issuer = credential_issuer.to_string();
+    shell.run(|provider| async move { provider.status_events(&credential_issuer).await })
+}
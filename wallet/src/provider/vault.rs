@@ -0,0 +1,482 @@
+//! Encryption at rest for store entries.
+//!
+//! Entries are sealed with AES-256-GCM under a data key held in the shell's
+//! key store, so the shell's database only ever sees ciphertext. Data keys are
+//! versioned: each sealed entry records the version of the key it was sealed
+//! with, and new entries are always sealed with the current version.
+//!
+//! Entries sealed with an older data key are re-sealed with the current key
+//! when they are loaded. Rotating the data key re-seals every entry at once,
+//! so the superseded keys can be deleted. Entries written before encryption was
+//! introduced are sealed the first time the credentials are loaded; once that
+//! has been done, an unsealed entry is rejected as unreadable rather than
+//! trusted as plaintext.
+
+use std::collections::{HashMap, HashSet};
+
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use anyhow::anyhow;
+use async_std::sync::Mutex;
+use base64ct::{Base64UrlUnpadded, Encoding};
+use hmac::{Hmac, Mac};
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::Sha256;
+use thiserror::Error;
+
+use super::{Provider, QuarantinedEntry};
+use crate::capabilities::key::KeyStoreEntry;
+use crate::capabilities::store::{Catalog, StoreEntry};
+
+/// Key store purpose for data encryption keys.
+const ENCRYPTION: &str = "encryption";
+
+/// Algorithm data encryption keys are used with.
+const ENCRYPTION_ALGORITHM: &str = "A256GCM";
+
+/// Key-value store key under which the current data key version is persisted.
+const DATA_KEY_VERSION: &str = "data_key_version";
+
+/// Key-value store key set once every entry written before encryption was
+/// introduced has been sealed.
+const PLAINTEXT_MIGRATED: &str = "plaintext_migrated";
+
+/// Label the store index key is derived from a data key with, so the data key
+/// itself is only used for encryption.
+const INDEX_KEY_LABEL: &[u8] = b"store index";
+
+/// Length of an AES-GCM nonce in bytes.
+const NONCE_LENGTH: usize = 12;
+
+lazy_static! {
+    /// Serializes the creation of data keys, so concurrent operations cannot
+    /// create different keys for the same version.
+    static ref DATA_KEY_LOCK: Mutex<()> = Mutex::new(());
+}
+
+/// A store entry sealed with a data key.
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct SealedEntry {
+    /// ID of the entry in its catalog. Bound to the ciphertext as associated
+    /// data so sealed entries cannot be swapped.
+    pub id: String,
+
+    /// Version of the data key the entry is sealed with.
+    pub key_version: u32,
+
+    /// Nonce used to seal the entry.
+    #[serde(with = "serde_bytes")]
+    pub nonce: Vec<u8>,
+
+    /// The sealed entry.
+    #[serde(with = "serde_bytes")]
+    pub ciphertext: Vec<u8>,
+}
+
+/// A store entry cannot be read because it is corrupt or its data key no
+/// longer exists. Unlike capability errors, retrying will not help.
+#[derive(Debug, Error)]
+#[error("unreadable store entry: {0}")]
+pub struct UnreadableEntry(pub String);
+
+/// A store entry that has been opened.
+pub struct OpenedEntry {
+    /// The plaintext entry.
+    pub plaintext: Vec<u8>,
+
+    /// The entry is sealed with the current data key. If not, the entry should
+    /// be re-sealed.
+    pub current: bool,
+}
+
+/// Data keys by version, cached for the duration of an operation.
+pub struct DataKeys {
+    current: u32,
+    accept_plaintext: bool,
+    keys: HashMap<u32, Key<Aes256Gcm>>,
+}
+
+/// Keyed digests of the values a store entry is indexed by, so the shell can
+/// match entries by value without learning the value.
+pub struct IndexKey(Hmac<Sha256>);
+
+impl IndexKey {
+    /// Digest an index value. Equal values give equal digests under the same
+    /// data key.
+    pub fn digest(&self, value: &str) -> String {
+        let mut mac = self.0.clone();
+        mac.update(value.as_bytes());
+        Base64UrlUnpadded::encode_string(&mac.finalize().into_bytes())
+    }
+}
+
+/// Get the key store ID of a data key version.
+fn data_key_id(version: u32) -> String {
+    format!("data#{version}")
+}
+
+/// Get the ID of a stored entry, sealed or not, if it can be determined.
+pub fn entry_id(data: &[u8]) -> Option<String> {
+    if let Ok(sealed) = serde_json::from_slice::<SealedEntry>(data) {
+        return Some(sealed.id);
+    }
+    let value = serde_json::from_slice::<Value>(data).ok()?;
+    value.get("id")?.as_str().map(ToString::to_string)
+}
+
+/// Associated data binding a sealed entry to its catalog and ID.
+fn associated_data(catalog: &str, id: &str) -> Vec<u8> {
+    format!("{catalog}/{id}").into_bytes()
+}
+
+impl<Ev> Provider<Ev>
+where
+    Ev: 'static,
+{
+    /// Seal plaintext data with the current data key.
+    pub(super) async fn seal(
+        &self, catalog: &str, id: &str, plaintext: &[u8],
+    ) -> anyhow::Result<SealedEntry> {
+        let (key_version, key) = self.current_data_key().await?;
+        let nonce = self.key_store.generate_secret_async(NONCE_LENGTH).await?;
+        if nonce.len() != NONCE_LENGTH {
+            return Err(anyhow!("shell returned a nonce of unexpected length"));
+        }
+        let cipher = Aes256Gcm::new(&key);
+        let payload = Payload {
+            msg: plaintext,
+            aad: &associated_data(catalog, id),
+        };
+        let ciphertext = cipher
+            .encrypt(Nonce::from_slice(&nonce), payload)
+            .map_err(|e| anyhow!("failed to seal store entry: {e}"))?;
+        Ok(SealedEntry {
+            id: id.into(),
+            key_version,
+            nonce,
+            ciphertext,
+        })
+    }
+
+    /// Get the key for the index digests of entries sealed with the current
+    /// data key. Entries are indexed again when they are re-sealed.
+    pub(super) async fn index_key(&self) -> anyhow::Result<IndexKey> {
+        let (_, key) = self.current_data_key().await?;
+        let mac = |key: &[u8]| {
+            <Hmac<Sha256> as Mac>::new_from_slice(key)
+                .map_err(|e| anyhow!("invalid index key: {e}"))
+        };
+        let mut derive = mac(&key)?;
+        derive.update(INDEX_KEY_LABEL);
+        Ok(IndexKey(mac(&derive.finalize().into_bytes())?))
+    }
+
+    /// Open a store entry. Until the plaintext migration has completed,
+    /// entries that are not sealed are returned as-is and flagged for
+    /// re-sealing. After that they are unreadable.
+    pub(super) async fn open(
+        &self, catalog: &str, data: &[u8], keys: &mut DataKeys,
+    ) -> anyhow::Result<OpenedEntry> {
+        let Ok(sealed) = serde_json::from_slice::<SealedEntry>(data) else {
+            if !keys.accept_plaintext {
+                return Err(UnreadableEntry("entry is not sealed".into()).into());
+            }
+            return Ok(OpenedEntry {
+                plaintext: data.to_vec(),
+                current: false,
+            });
+        };
+
+        if !keys.keys.contains_key(&sealed.key_version) {
+            let key = self.data_key(sealed.key_version).await?;
+            keys.keys.insert(sealed.key_version, key);
+        }
+        if sealed.nonce.len() != NONCE_LENGTH {
+            return Err(UnreadableEntry(format!("entry {} has an invalid nonce", sealed.id)).into());
+        }
+        let cipher = Aes256Gcm::new(&keys.keys[&sealed.key_version]);
+        let payload = Payload {
+            msg: &sealed.ciphertext,
+            aad: &associated_data(catalog, &sealed.id),
+        };
+        let plaintext = cipher
+            .decrypt(Nonce::from_slice(&sealed.nonce), payload)
+            .map_err(|e| UnreadableEntry(format!("failed to open entry {}: {e}", sealed.id)))?;
+
+        Ok(OpenedEntry {
+            plaintext,
+            current: sealed.key_version == keys.current,
+        })
+    }
+
+    /// Create an empty data key cache for opening a batch of entries.
+    pub(super) async fn data_keys(&self) -> anyhow::Result<DataKeys> {
+        Ok(DataKeys {
+            current: self.data_key_version().await?,
+            accept_plaintext: self.kv.get_async(PLAINTEXT_MIGRATED.into()).await?.is_none(),
+            keys: HashMap::new(),
+        })
+    }
+
+    /// Record that every entry opened with the given keys has been sealed or
+    /// quarantined, completing the plaintext migration. Unsealed entries are
+    /// rejected from then on.
+    pub(super) async fn plaintext_migrated(&self, keys: &DataKeys) -> anyhow::Result<()> {
+        if keys.accept_plaintext {
+            self.kv.set_async(PLAINTEXT_MIGRATED.into(), serde_json::to_vec(&true)?).await?;
+        }
+        Ok(())
+    }
+
+    /// Seal every stored entry with a fresh data key, then delete the keys it
+    /// supersedes.
+    ///
+    /// A superseded key is kept while a quarantined entry's original data is
+    /// sealed with it, so the entry can still be recovered.
+    pub async fn rotate_data_key(&self) -> anyhow::Result<()> {
+        self.next_data_key().await?;
+
+        // Loading re-seals each readable entry with the new key, and moves any
+        // other to quarantine, before returning.
+        self.load_credentials().await?;
+
+        let current = self.data_key_version().await?;
+        let quarantined = self.quarantined_key_versions().await?;
+        for version in (1..current).filter(|version| !quarantined.contains(version)) {
+            let key_id = data_key_id(version);
+            if let KeyStoreEntry::Data(_) = self.key_store.get_async(&key_id, ENCRYPTION).await? {
+                self.key_store.delete_async(key_id, ENCRYPTION).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Start sealing new entries with a fresh data key. Entries sealed with
+    /// the previous key are re-sealed as they are loaded.
+    async fn next_data_key(&self) -> anyhow::Result<()> {
+        let _lock = DATA_KEY_LOCK.lock().await;
+        let version = self.data_key_version().await? + 1;
+        self.create_data_key(version).await?;
+        Ok(())
+    }
+
+    /// Get the data key versions the original data of quarantined entries is
+    /// sealed with.
+    async fn quarantined_key_versions(&self) -> anyhow::Result<HashSet<u32>> {
+        let catalog = Catalog::Quarantine.to_string();
+        let mut keys = self.data_keys().await?;
+        let mut versions = HashSet::new();
+        for entry in self.store.list_async(&catalog).await? {
+            let StoreEntry::Data(data) = entry else {
+                continue;
+            };
+            let opened = match self.open(&catalog, &data, &mut keys).await {
+                Ok(opened) => opened,
+                // An entry that cannot be opened holds no recoverable data.
+                Err(e) if e.is::<UnreadableEntry>() => continue,
+                Err(e) => return Err(e),
+            };
+            let entry: QuarantinedEntry = serde_json::from_slice(&opened.plaintext)?;
+            if let Ok(original) = serde_json::from_slice::<SealedEntry>(&entry.data) {
+                versions.insert(original.key_version);
+            }
+        }
+        Ok(versions)
+    }
+
+    /// Get the current data key version. Zero if no data key has been created.
+    async fn data_key_version(&self) -> anyhow::Result<u32> {
+        match self.kv.get_async(DATA_KEY_VERSION.into()).await? {
+            Some(data) => Ok(serde_json::from_slice(&data)?),
+            None => Ok(0),
+        }
+    }
+
+    /// Get the current data key, creating the first version if none exists.
+    async fn current_data_key(&self) -> anyhow::Result<(u32, Key<Aes256Gcm>)> {
+        let version = self.data_key_version().await?;
+        if version > 0 {
+            return Ok((version, self.data_key(version).await?));
+        }
+
+        // Another operation may have created the first key while this one
+        // waited for the lock.
+        let _lock = DATA_KEY_LOCK.lock().await;
+        let version = self.data_key_version().await?;
+        if version > 0 {
+            return Ok((version, self.data_key(version).await?));
+        }
+        Ok((1, self.create_data_key(1).await?))
+    }
+
+    /// Retrieve the data key with the given version from the key store.
+    async fn data_key(&self, version: u32) -> anyhow::Result<Key<Aes256Gcm>> {
+        match self.key_store.get_async(data_key_id(version), ENCRYPTION).await? {
+            // The key store capability serializes key data on the way in.
+            KeyStoreEntry::Data(bytes) => {
+                let secret = serde_json::from_slice::<Vec<u8>>(&bytes)?;
+                if secret.len() != 32 {
+                    return Err(anyhow!("data key version {version} has an invalid length"));
+                }
+                Ok(*Key::<Aes256Gcm>::from_slice(&secret))
+            }
+            KeyStoreEntry::None => {
+                Err(UnreadableEntry(format!("data key version {version} is missing")).into())
+            }
+        }
+    }
+
+    /// Generate and store a data key, making it the current version. Must be
+    /// called with the data key lock held.
+    ///
+    /// A key the key store already holds for the version is used rather than
+    /// overwritten, since entries may have been sealed with it.
+    async fn create_data_key(&self, version: u32) -> anyhow::Result<Key<Aes256Gcm>> {
+        let key_id = data_key_id(version);
+        let key = match self.key_store.get_async(&key_id, ENCRYPTION).await? {
+            KeyStoreEntry::Data(_) => self.data_key(version).await?,
+            KeyStoreEntry::None => {
+                let secret = self.key_store.generate_secret_async(32).await?;
+                if secret.len() != 32 {
+                    return Err(anyhow!("shell returned a data key of unexpected length"));
+                }
+                self.key_store
+                    .set_async(key_id, ENCRYPTION, ENCRYPTION_ALGORITHM, secret.clone())
+                    .await?;
+                *Key::<Aes256Gcm>::from_slice(&secret)
+            }
+        };
+        self.kv.set_async(DATA_KEY_VERSION.into(), serde_json::to_vec(&version)?).await?;
+        Ok(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use vercre_holder::credential::Credential;
+
+    use super::*;
+    use crate::model::credential::CredentialRecord;
+    use crate::provider::testing::Shell;
+
+    const CATALOG: &str = "credential";
+
+    // An entry opens with the key it was sealed with, and is flagged for
+    // re-sealing once the data key has been rotated.
+    #[test]
+    fn seal_and_rotate() {
+        let mut shell = Shell::new(1_700_000_000);
+        let (first, second, opened, reopened) = shell.run(|provider| async move {
+            let first = provider.seal(CATALOG, "a", b"secret").await.expect("should seal");
+            let data = serde_json::to_vec(&first).expect("should serialize");
+            let mut keys = provider.data_keys().await.expect("should get keys");
+            let opened = provider.open(CATALOG, &data, &mut keys).await.expect("should open");
+
+            provider.next_data_key().await.expect("should rotate");
+            let second = provider.seal(CATALOG, "b", b"secret").await.expect("should seal");
+            let mut keys = provider.data_keys().await.expect("should get keys");
+            let reopened = provider.open(CATALOG, &data, &mut keys).await.expect("should open");
+            (first, second, opened, reopened)
+        });
+        assert_eq!((first.key_version, second.key_version), (1, 2));
+        assert_ne!(first.ciphertext, b"secret");
+        assert_eq!((opened.plaintext.as_slice(), opened.current), (&b"secret"[..], true));
+        assert_eq!((reopened.plaintext.as_slice(), reopened.current), (&b"secret"[..], false));
+    }
+
+    // A sealed entry cannot be opened as another entry or in another
+    // catalog.
+    #[test]
+    fn swapped_entry() {
+        let mut shell = Shell::new(1_700_000_000);
+        let (as_other_id, in_other_catalog) = shell.run(|provider| async move {
+            let sealed = provider.seal(CATALOG, "a", b"secret").await.expect("should seal");
+            let swapped = SealedEntry {
+                id: "b".into(),
+                ..sealed.clone()
+            };
+            let swapped = serde_json::to_vec(&swapped).expect("should serialize");
+            let sealed = serde_json::to_vec(&sealed).expect("should serialize");
+            let mut keys = provider.data_keys().await.expect("should get keys");
+            let as_other_id = provider.open(CATALOG, &swapped, &mut keys).await.err();
+            let in_other_catalog = provider.open("quarantine", &sealed, &mut keys).await.err();
+            (as_other_id, in_other_catalog)
+        });
+        for error in [as_other_id, in_other_catalog] {
+            assert!(error.expect("should not open").is::<UnreadableEntry>());
+        }
+    }
+
+    // Unsealed entries are read until the plaintext migration has completed,
+    // and are unreadable after.
+    #[test]
+    fn plaintext_migration() {
+        let mut shell = Shell::new(1_700_000_000);
+        let (before, after) = shell.run(|provider| async move {
+            let data = br#"{"id":"a"}"#;
+            let mut keys = provider.data_keys().await.expect("should get keys");
+            let before = provider.open(CATALOG, data, &mut keys).await.map(|opened| opened.current);
+            provider.plaintext_migrated(&keys).await.expect("should record migration");
+            let mut keys = provider.data_keys().await.expect("should get keys");
+            let after = provider.open(CATALOG, data, &mut keys).await.err();
+            (before.expect("should open"), after)
+        });
+        assert!(!before);
+        assert!(after.expect("should not open").is::<UnreadableEntry>());
+    }
+
+    // Rotating the data key re-seals stored entries with the new key and
+    // deletes the key they were sealed with.
+    #[test]
+    fn rotate_data_key() {
+        let json = include_bytes!("../model/credentials.json");
+        let credentials: Vec<Credential> =
+            serde_json::from_slice(json).expect("should deserialize");
+        let credential = credentials[0].clone();
+        let id = credential.id.clone();
+
+        let mut shell = Shell::new(1_700_000_000);
+        let loaded = shell.run(|provider| async move {
+            let record = CredentialRecord {
+                key_id: provider.signing_key_id().await.expect("should get key ID"),
+                credential,
+                claim_metadata: None,
+            };
+            provider.save_record(&record).await.expect("should save credential");
+            provider.rotate_data_key().await.expect("should rotate");
+            provider.load_credentials().await.expect("should load")
+        });
+        assert_eq!(loaded.records.len(), 1);
+        let stored = shell.store.get(&(CATALOG.to_string(), id)).expect("should be stored");
+        let sealed: SealedEntry = serde_json::from_slice(stored).expect("should be sealed");
+        assert_eq!(sealed.key_version, 2);
+        let data_keys = shell.keys.keys().filter(|(_, purpose)| purpose == ENCRYPTION);
+        assert_eq!(data_keys.map(|(id, _)| id.as_str()).collect::<Vec<_>>(), vec!["data#2"]);
+    }
+
+    // Operations that seal the first entries at the same time share one data
+    // key.
+    #[test]
+    fn concurrent_first_key() {
+        let mut shell = Shell::new(1_700_000_000);
+        let opened = shell.run(|provider| async move {
+            let (a, b) = futures::join!(
+                provider.seal(CATALOG, "a", b"a"),
+                provider.seal(CATALOG, "b", b"b")
+            );
+            let mut keys = provider.data_keys().await.expect("should get keys");
+            let mut opened = Vec::new();
+            for sealed in [a, b] {
+                let data =
+                    serde_json::to_vec(&sealed.expect("should seal")).expect("should serialize");
+                opened.push(provider.open(CATALOG, &data, &mut keys).await.is_ok());
+            }
+            opened
+        });
+        assert_eq!(opened, vec![true, true]);
+        assert_eq!(shell.keys.keys().filter(|(_, purpose)| purpose == ENCRYPTION).count(), 1);
+    }
+}
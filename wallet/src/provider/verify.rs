@@ -0,0 +1,402 @@
+//! Credential verification.
+//!
+//! Issued credentials are verified before they are stored. The credential must
+//! be signed by the issuer it was issued by, or the `did:web` DID at its URL,
+//! with a key resolved from the signer's DID or JWKS, and must be bound to the
+//! holder key the wallet used in its proof of possession.
+//!
+//! Supported proofs are JWT-VC, SD-JWT and Data Integrity using the
+//! `eddsa-jcs-2022` cryptosuite.
+
+use anyhow::{anyhow, bail};
+use base64ct::{Base64UrlUnpadded, Encoding};
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+use vercre_holder::credential::Credential;
+use vercre_holder::provider::Signer;
+
+use super::{jose, Provider};
+
+/// Data Integrity cryptosuite the wallet can verify.
+const EDDSA_JCS_2022: &str = "eddsa-jcs-2022";
+
+/// Serialize a JSON value using the JSON Canonicalization Scheme (RFC 8785):
+/// no whitespace, object members sorted by the UTF-16 code units of their
+/// names, and numbers serialized as ECMAScript does.
+pub(super) fn canonical_json(value: &Value) -> String {
+    match value {
+        Value::Object(object) => {
+            let mut members: Vec<(&String, &Value)> = object.iter().collect();
+            members.sort_by(|(a, _), (b, _)| a.encode_utf16().cmp(b.encode_utf16()));
+            let members: Vec<String> = members
+                .into_iter()
+                .map(|(name, value)| {
+                    format!("{}:{}", Value::from(name.as_str()), canonical_json(value))
+                })
+                .collect();
+            format!("{{{}}}", members.join(","))
+        }
+        Value::Array(items) => {
+            let items: Vec<String> = items.iter().map(canonical_json).collect();
+            format!("[{}]", items.join(","))
+        }
+        Value::Number(number) => number.as_f64().map_or_else(|| number.to_string(), jcs_number),
+        other => other.to_string(),
+    }
+}
+
+/// Serialize a number as ECMAScript's `Number.prototype.toString` does, from
+/// the shortest decimal digits that round-trip to the same double.
+fn jcs_number(number: f64) -> String {
+    if number == 0.0 {
+        return "0".into();
+    }
+    if number < 0.0 {
+        return format!("-{}", jcs_number(-number));
+    }
+
+    // Digits and decimal exponent such that the number is 0.digits × 10^n.
+    let scientific = format!("{number:e}");
+    let (mantissa, exponent) = scientific.split_once('e').unwrap_or((&scientific, "0"));
+    let digits = mantissa.replace('.', "");
+    let k = i32::try_from(digits.len()).unwrap_or(i32::MAX);
+    let n = exponent.parse::<i32>().unwrap_or_default() + 1;
+    let zeros = |count: i32| "0".repeat(usize::try_from(count).unwrap_or_default());
+
+    if k <= n && n <= 21 {
+        format!("{digits}{}", zeros(n - k))
+    } else if 0 < n && n <= 21 {
+        let (integer, fraction) = digits.split_at(usize::try_from(n).unwrap_or_default());
+        format!("{integer}.{fraction}")
+    } else if -6 < n && n <= 0 {
+        format!("0.{}{digits}", zeros(-n))
+    } else {
+        let sign = if n > 0 { '+' } else { '-' };
+        let (first, rest) = digits.split_at(1);
+        let rest = if rest.is_empty() { String::new() } else { format!(".{rest}") };
+        format!("{first}{rest}e{sign}{}", (n - 1).abs())
+    }
+}
+
+/// Collect the disclosure digests an SD-JWT payload commits to, from `_sd`
+/// arrays and array element placeholders.
+fn sd_digests(value: &Value, digests: &mut Vec<String>) {
+    match value {
+        Value::Object(object) => {
+            for (name, value) in object {
+                match (name.as_str(), value) {
+                    ("_sd", Value::Array(items)) => {
+                        digests.extend(items.iter().filter_map(Value::as_str).map(String::from));
+                    }
+                    ("...", Value::String(digest)) => digests.push(digest.clone()),
+                    _ => sd_digests(value, digests),
+                }
+            }
+        }
+        Value::Array(items) => items.iter().for_each(|item| sd_digests(item, digests)),
+        _ => {}
+    }
+}
+
+/// Check every disclosure of an SD-JWT is committed to by the issuer-signed
+/// payload.
+fn check_disclosures(payload: &Value, disclosures: &[&str]) -> anyhow::Result<()> {
+    if let Some(alg) = payload.get("_sd_alg").and_then(Value::as_str) {
+        if alg != "sha-256" {
+            bail!("unsupported disclosure digest algorithm {alg}");
+        }
+    }
+    let mut digests = Vec::new();
+    sd_digests(payload, &mut digests);
+    for disclosure in disclosures.iter().filter(|d| !d.is_empty()) {
+        let digest = Base64UrlUnpadded::encode_string(&Sha256::digest(disclosure.as_bytes()));
+        if !digests.contains(&digest) {
+            bail!("disclosure is not part of the issuer-signed credential");
+        }
+    }
+    Ok(())
+}
+
+/// Get the ID of a credential's issuer, which may be a string or an object.
+fn issuer_id(issuer: Option<&Value>) -> Option<&str> {
+    issuer.and_then(|issuer| issuer.as_str().or_else(|| issuer.get("id")?.as_str()))
+}
+
+impl<Ev> Provider<Ev>
+where
+    Ev: 'static,
+{
+    /// Verify an issued credential's proof and holder binding. The credential
+    /// must be signed for the issuer it was issued by.
+    pub(super) async fn verify_credential(&self, credential: &Credential) -> anyhow::Result<()> {
+        let issued = credential.issued.trim();
+        let issuer = credential.issuer.as_str();
+        let claims = if let Ok(document @ Value::Object(_)) = serde_json::from_str(issued) {
+            self.verify_data_integrity(&document, issuer).await?;
+            document
+        } else if issued.contains('~') {
+            let mut parts = issued.split('~');
+            let jwt = parts.next().unwrap_or_default();
+            let payload = self.verify_jwt(jwt, issuer).await?;
+            let disclosures: Vec<&str> = parts.collect();
+            check_disclosures(&payload, &disclosures)?;
+            payload
+        } else {
+            self.verify_jwt(issued, issuer).await?
+        };
+        self.check_holder_binding(&claims).await
+    }
+
+    /// Verify a JWT-encoded credential signed for the credential issuer and
+    /// return its claims.
+    async fn verify_jwt(&self, token: &str, credential_issuer: &str) -> anyhow::Result<Value> {
+        let jws = jose::decode(token)?;
+        let signer = jws
+            .payload
+            .get("iss")
+            .and_then(Value::as_str)
+            .or_else(|| issuer_id(jws.payload.pointer("/vc/issuer")))
+            .ok_or_else(|| anyhow!("credential has no issuer"))?;
+        if !jose::is_issuer(signer, credential_issuer) {
+            bail!("credential is signed by {signer}, not issuer {credential_issuer}");
+        }
+        self.verify_jws(token, signer).await
+    }
+
+    /// Verify a credential secured with a Data Integrity proof signed for the
+    /// credential issuer.
+    async fn verify_data_integrity(
+        &self, document: &Value, credential_issuer: &str,
+    ) -> anyhow::Result<()> {
+        let Some(Value::Object(proof)) = document.get("proof") else {
+            bail!("credential has no Data Integrity proof");
+        };
+        let field = |name: &str| {
+            proof.get(name).and_then(Value::as_str).ok_or_else(|| anyhow!("proof has no {name}"))
+        };
+        let cryptosuite = field("cryptosuite")?;
+        if field("type")? != "DataIntegrityProof" || cryptosuite != EDDSA_JCS_2022 {
+            bail!("unsupported Data Integrity cryptosuite {cryptosuite}");
+        }
+        let signer =
+            issuer_id(document.get("issuer")).ok_or_else(|| anyhow!("credential has no issuer"))?;
+        if !jose::is_issuer(signer, credential_issuer) {
+            bail!("credential is signed by {signer}, not issuer {credential_issuer}");
+        }
+        let key = self.issuer_key(signer, Some(field("verificationMethod")?)).await?;
+        let (_, signature) = multibase::decode(field("proofValue")?)?;
+
+        let mut unsecured = document.clone();
+        if let Some(unsecured) = unsecured.as_object_mut() {
+            unsecured.remove("proof");
+        }
+        let mut config: Map<String, Value> = proof.clone();
+        config.remove("proofValue");
+        if let Some(context) = document.get("@context") {
+            config.insert("@context".into(), context.clone());
+        }
+        let mut hash_data = Sha256::digest(canonical_json(&Value::Object(config))).to_vec();
+        hash_data.extend(Sha256::digest(canonical_json(&unsecured)));
+
+        key.verify("EdDSA", &hash_data, &signature)
+    }
+
+    /// Check a credential is bound to the holder key used in the proof of
+    /// possession, either by its subject DID or its confirmation claim.
+    async fn check_holder_binding(&self, claims: &Value) -> anyhow::Result<()> {
+        let method = self.verification_method().await?;
+        let (did, _) = method.split_once('#').unwrap_or((&method, ""));
+
+        if let Some(cnf) = claims.get("cnf") {
+            if let Some(kid) = cnf.get("kid").and_then(Value::as_str) {
+                if kid == method || kid == did {
+                    return Ok(());
+                }
+            }
+            if let Some(x) = cnf.pointer("/jwk/x").and_then(Value::as_str) {
+                if x == Base64UrlUnpadded::encode_string(&self.public_key().await?) {
+                    return Ok(());
+                }
+            }
+            bail!("credential is bound to a different holder key");
+        }
+
+        let vc = claims.get("vc").unwrap_or(claims);
+        let subject = claims
+            .get("sub")
+            .or_else(|| vc.pointer("/credentialSubject/id"))
+            .and_then(Value::as_str)
+            .ok_or_else(|| anyhow!("credential is not bound to a holder key"))?;
+        if subject != did {
+            bail!("credential subject {subject} is not the wallet's holder DID");
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crux_http::protocol::HttpResponse;
+    use ed25519_dalek::{Signer as _, SigningKey};
+    use serde_json::json;
+
+    use super::*;
+    use crate::provider::testing::Shell;
+
+    const ISSUER: &str = "https://issuer.example";
+
+    // Issue a JWT credential to the wallet's holder DID, signed by the given
+    // key for the given signer.
+    fn issue(key: &SigningKey, signer: &str, kid: &str, holder: &str) -> Credential {
+        let header = json!({ "typ": "JWT", "alg": "EdDSA", "kid": kid });
+        let payload = json!({
+            "iss": signer,
+            "sub": holder,
+            "iat": 1_700_000_000,
+            "vc": { "type": ["VerifiableCredential"], "credentialSubject": { "id": holder } },
+        });
+        let issued = jose::encode(&header, &payload, |msg| key.sign(msg).to_vec())
+            .expect("should sign credential");
+        serde_json::from_value(json!({
+            "id": "urn:uuid:1",
+            "issuer": ISSUER,
+            "issuer_name": "",
+            "issued": issued,
+            "type": ["VerifiableCredential"],
+            "format": "jwt_vc_json",
+            "subject_claims": [],
+            "issuance_date": "2023-11-14T22:13:20Z",
+        }))
+        .expect("should build credential")
+    }
+
+    // Disclosures of an SD-JWT must be committed to by the issuer-signed
+    // payload, including those nested in objects and arrays.
+    #[test]
+    fn sd_jwt_disclosures() {
+        let given_name = Base64UrlUnpadded::encode_string(br#"["salt","given_name","Alice"]"#);
+        let nationality = Base64UrlUnpadded::encode_string(br#"["salt","NZ"]"#);
+        let digest = |d: &str| Base64UrlUnpadded::encode_string(&Sha256::digest(d.as_bytes()));
+
+        let payload = json!({
+            "_sd_alg": "sha-256",
+            "_sd": [digest(&given_name)],
+            "nationalities": [{"...": digest(&nationality)}],
+        });
+        check_disclosures(&payload, &[&given_name, &nationality, ""]).expect("should match");
+
+        let forged = Base64UrlUnpadded::encode_string(br#"["salt","given_name","Mallory"]"#);
+        assert!(check_disclosures(&payload, &[&forged]).is_err());
+    }
+
+    // Canonical JSON sorts object members and has no whitespace.
+    #[test]
+    fn canonical_json_order() {
+        let value = json!({"b": [1, {"d": true, "c": null}], "a": "x"});
+        assert_eq!(canonical_json(&value), r#"{"a":"x","b":[1,{"c":null,"d":true}]}"#);
+    }
+
+    // Canonical JSON sorts object members by UTF-16 code units and formats
+    // numbers as ECMAScript does (RFC 8785 sections 3.2.2 and 3.2.3).
+    #[test]
+    fn canonical_json_rfc8785() {
+        let value = json!({
+            "numbers": [333_333_333.333_333_29, 1e30, 4.50, 2e-3, 1e-27],
+            "string": "\u{20ac}$\u{f}\nA'B\"\\\\\"/",
+            "literals": [null, true, false],
+        });
+        assert_eq!(
+            canonical_json(&value),
+            r#"{"literals":[null,true,false],"numbers":[333333333.3333333,1e+30,4.5,0.002,1e-27],"string":"€$\u000f\nA'B\"\\\\\"/"}"#
+        );
+
+        let value = json!({
+            "\u{20ac}": "Euro Sign",
+            "\r": "Carriage Return",
+            "\u{fb33}": "Hebrew Letter Dalet With Dagesh",
+            "1": "One",
+            "\u{1f600}": "Emoji: Grinning Face",
+            "\u{80}": "Control",
+            "\u{f6}": "Latin Small Letter O With Diaeresis",
+        });
+        assert_eq!(
+            canonical_json(&value),
+            "{\"\\r\":\"Carriage Return\",\"1\":\"One\",\"\u{80}\":\"Control\",\
+             \"\u{f6}\":\"Latin Small Letter O With Diaeresis\",\"\u{20ac}\":\"Euro Sign\",\
+             \"\u{1f600}\":\"Emoji: Grinning Face\",\
+             \"\u{fb33}\":\"Hebrew Letter Dalet With Dagesh\"}"
+        );
+    }
+
+    // Numbers are serialized from the nearest double (RFC 8785 appendix B).
+    #[test]
+    fn canonical_json_numbers() {
+        for (number, expected) in [
+            (0.0, "0"),
+            (-0.0, "0"),
+            (5e-324, "5e-324"),
+            (1.797_693_134_862_315_7e308, "1.7976931348623157e+308"),
+            (9_007_199_254_740_992.0, "9007199254740992"),
+            (9_007_199_254_740_994.0, "9007199254740994"),
+            (999_999_999_999_999_700_000.0, "999999999999999700000"),
+            (1e21, "1e+21"),
+            (295_147_905_179_352_830_000.0, "295147905179352830000"),
+            (0.000_001, "0.000001"),
+            (1e-7, "1e-7"),
+            (-1.5, "-1.5"),
+        ] {
+            assert_eq!(jcs_number(number), expected);
+        }
+    }
+
+    // A credential must be signed for the issuer it was issued by: a
+    // credential signed by another party with a key it resolves itself is
+    // rejected.
+    #[test]
+    fn credential_issuer_binding() {
+        let issuer_key = SigningKey::from_bytes(&[1; 32]);
+        let x = Base64UrlUnpadded::encode_string(issuer_key.verifying_key().as_bytes());
+        let mut shell = Shell::new(1_700_000_000).with_http(move |request| {
+            if request.url == format!("{ISSUER}/.well-known/jwt-vc-issuer") {
+                let metadata = json!({
+                    "issuer": ISSUER,
+                    "jwks": { "keys": [{ "kty": "OKP", "crv": "Ed25519", "x": x, "kid": "k1" }] },
+                });
+                return HttpResponse::ok().body(metadata.to_string()).build();
+            }
+            HttpResponse::status(404).build()
+        });
+
+        let (accepted, rejected) = shell.run(|provider| async move {
+            let method = provider.verification_method().await.expect("should get holder key");
+            let holder = method.split('#').next().unwrap_or_default().to_string();
+
+            let credential = issue(&issuer_key, ISSUER, "k1", &holder);
+            let accepted = provider.verify_credential(&credential).await;
+
+            let forger = SigningKey::from_bytes(&[2; 32]);
+            let mut multikey = vec![0xed, 0x01];
+            multikey.extend(forger.verifying_key().as_bytes());
+            let did =
+                format!("did:key:{}", multibase::encode(multibase::Base::Base58Btc, multikey));
+            let kid = format!("{did}#{}", did.trim_start_matches("did:key:"));
+            let credential = issue(&forger, &did, &kid, &holder);
+            let rejected = provider.verify_credential(&credential).await.map_err(|e| e.to_string());
+            (accepted.map_err(|e| e.to_string()), rejected)
+        });
+
+        accepted.expect("should accept the issuer's credential");
+        let error = rejected.expect_err("should reject a credential signed by another party");
+        assert!(error.contains("not issuer https://issuer.example"), "{error}");
+    }
+
+    // A credential issuer speaks for itself and the `did:web` DID at its URL.
+    #[test]
+    fn issuer_signers() {
+        assert!(jose::is_issuer("https://issuer.example/", ISSUER));
+        assert!(jose::is_issuer("did:web:issuer.example", ISSUER));
+        assert!(jose::is_issuer("did:web:issuer.example:tenant", "https://issuer.example/tenant"));
+        assert!(!jose::is_issuer("did:web:other.example", ISSUER));
+        assert!(!jose::is_issuer("did:key:z6Mk", ISSUER));
+    }
+}
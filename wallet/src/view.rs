@@ -1,13 +1,17 @@
 //! # View Model
 //! 
+pub mod claim;
 pub mod credential;
 pub mod issuance;
+pub mod key;
 
 use credential::CredentialView;
 use issuance::IssuanceView;
+use key::KeyView;
 use serde::{Deserialize, Serialize};
 
 use super::Aspect;
+pub use crate::model::error::{ErrorCategory, RecoveryAction, WalletError};
 
 /// View model for the wallet application.
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
@@ -21,6 +25,10 @@ pub struct ViewModel {
     /// Issuance view model.
     pub issuance_view: IssuanceView,
 
-    /// Error message.
-    pub error: String,
+    /// Key management view model.
+    pub key_view: KeyView,
+
+    /// The current error, with a message for the user and the suggested
+    /// recovery action. Empty if there is no error.
+    pub error: WalletError,
 }
@@ -0,0 +1,287 @@
+//! Claim view models
+//!
+//! Claims are presented as a tree so nested objects and arrays keep their
+//! structure. Labels and value types are taken from the issuer's claim
+//! metadata where it is available.
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::model::locale::LocalePreference;
+
+/// Names of the fields of a claim definition, as opposed to the names of
+/// nested claims.
+const DEFINITION_FIELDS: [&str; 3] = ["mandatory", "value_type", "display"];
+
+/// A claim and, for objects and arrays, its nested claims.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct ClaimNode {
+    /// Display name of the claim from the issuer's metadata, or the claim
+    /// name if the metadata has none.
+    pub label: String,
+
+    /// Path to the claim from the credential subject: claim names and, for
+    /// array elements, indices.
+    pub path: Vec<String>,
+
+    /// The claim value.
+    pub value: ClaimValue,
+
+    /// Position of the claim amongst its siblings. Claims defined in the
+    /// issuer's metadata come first.
+    pub order: usize,
+}
+
+/// Typed value of a claim.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub enum ClaimValue {
+    /// No value, as for claims in a credential offer.
+    #[default]
+    Unset,
+
+    /// A text value.
+    String(String),
+
+    /// A numeric value, as a decimal string so the issued precision is kept.
+    Number(String),
+
+    /// A boolean value.
+    Boolean(bool),
+
+    /// An ISO 8601 date or date-time, to be formatted by the shell.
+    Date(String),
+
+    /// An image, as a URL or `data:` URI.
+    Image(String),
+
+    /// An object with nested claims.
+    Object(Vec<ClaimNode>),
+
+    /// An array of claims.
+    Array(Vec<ClaimNode>),
+}
+
+impl ClaimNode {
+    /// Whether the label or value of the claim, or of any nested claim,
+    /// satisfies the predicate.
+    pub fn any(&self, predicate: &impl Fn(&str) -> bool) -> bool {
+        if predicate(&self.label) {
+            return true;
+        }
+        match &self.value {
+            ClaimValue::Unset | ClaimValue::Boolean(_) => false,
+            ClaimValue::String(value)
+            | ClaimValue::Number(value)
+            | ClaimValue::Date(value)
+            | ClaimValue::Image(value) => predicate(value),
+            ClaimValue::Object(nodes) | ClaimValue::Array(nodes) => {
+                nodes.iter().any(|node| node.any(predicate))
+            }
+        }
+    }
+}
+
+/// Build the claim tree for a credential subject's claims, described by the
+/// issuer's claim definitions. Labels are taken from the display entries
+/// best matching the locale.
+pub fn claim_nodes(
+    claims: &Map<String, Value>, definitions: Option<&Value>, locale: &LocalePreference,
+) -> Vec<ClaimNode> {
+    nodes(claims, definitions, &[], locale)
+}
+
+/// Build the claim tree for the claim definitions of an offered credential,
+/// which has no values.
+pub fn definition_nodes(definitions: Option<&Value>, locale: &LocalePreference) -> Vec<ClaimNode> {
+    definition_tree(definitions, &[], locale)
+}
+
+/// Claim definitions with the claims of each object sorted by name, for
+/// definitions whose metadata order is not known.
+pub fn sorted(definitions: Value) -> Value {
+    match definitions {
+        Value::Object(map) => {
+            let mut entries: Vec<(String, Value)> = map.into_iter().collect();
+            entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+            entries.into_iter().map(|(name, value)| (name, sorted(value))).collect()
+        }
+        value => value,
+    }
+}
+
+fn nodes(
+    claims: &Map<String, Value>, definitions: Option<&Value>, path: &[String],
+    locale: &LocalePreference,
+) -> Vec<ClaimNode> {
+    let defined = nested_names(definitions);
+    let mut nodes: Vec<ClaimNode> = claims
+        .iter()
+        .enumerate()
+        .map(|(index, (name, value))| {
+            let definition = definitions.and_then(|d| d.get(name));
+            let order = defined.iter().position(|n| n == name).unwrap_or(defined.len() + index);
+            node(name, value, definition, child_path(path, name), order, locale)
+        })
+        .collect();
+    nodes.sort_by(|a, b| a.order.cmp(&b.order));
+    nodes
+}
+
+fn node(
+    name: &str, value: &Value, definition: Option<&Value>, path: Vec<String>, order: usize,
+    locale: &LocalePreference,
+) -> ClaimNode {
+    let label = label(name, definition, locale);
+    let value = match value {
+        Value::Object(claims) => ClaimValue::Object(nodes(claims, definition, &path, locale)),
+        Value::Array(items) => ClaimValue::Array(
+            items
+                .iter()
+                .enumerate()
+                .map(|(i, item)| {
+                    node(name, item, definition, child_path(&path, &i.to_string()), i, locale)
+                })
+                .collect(),
+        ),
+        Value::Bool(value) => ClaimValue::Boolean(*value),
+        Value::Number(value) => ClaimValue::Number(value.to_string()),
+        Value::String(value) => typed_string(value, definition),
+        Value::Null => ClaimValue::Unset,
+    };
+    ClaimNode {
+        label,
+        path,
+        value,
+        order,
+    }
+}
+
+fn definition_tree(
+    definitions: Option<&Value>, path: &[String], locale: &LocalePreference,
+) -> Vec<ClaimNode> {
+    nested_names(definitions)
+        .into_iter()
+        .enumerate()
+        .map(|(order, name)| {
+            let definition = definitions.and_then(|d| d.get(&name));
+            let path = child_path(path, &name);
+            let value = if nested_names(definition).is_empty() {
+                ClaimValue::Unset
+            } else {
+                ClaimValue::Object(definition_tree(definition, &path, locale))
+            };
+            ClaimNode {
+                label: label(&name, definition, locale),
+                path,
+                value,
+                order,
+            }
+        })
+        .collect()
+}
+
+/// Type a string claim value using the definition's `value_type`.
+fn typed_string(value: &str, definition: Option<&Value>) -> ClaimValue {
+    let value_type = definition.and_then(|d| d.get("value_type")).and_then(Value::as_str);
+    match value_type {
+        Some("number" | "integer") if value.parse::<f64>().is_ok() => {
+            ClaimValue::Number(value.into())
+        }
+        Some("boolean") if matches!(value, "true" | "false") => {
+            ClaimValue::Boolean(value == "true")
+        }
+        Some("date" | "datetime" | "date-time" | "full-date") => ClaimValue::Date(value.into()),
+        Some(media_type) if media_type.starts_with("image/") => ClaimValue::Image(value.into()),
+        _ => ClaimValue::String(value.into()),
+    }
+}
+
+/// Names of the claims a definition describes, in the order the issuer's
+/// metadata lists them.
+fn nested_names(definition: Option<&Value>) -> Vec<String> {
+    definition
+        .and_then(Value::as_object)
+        .map(|object| {
+            object
+                .iter()
+                .filter(|(name, value)| {
+                    !DEFINITION_FIELDS.contains(&name.as_str()) && value.is_object()
+                })
+                .map(|(name, _)| name.clone())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Get a claim's display name for the locale from its definition, falling
+/// back to the claim name.
+fn label(name: &str, definition: Option<&Value>, locale: &LocalePreference) -> String {
+    let displays = definition.and_then(|d| d.get("display")).and_then(Value::as_array);
+    let display = displays
+        .and_then(|displays| locale.lookup(displays, |d| d.get("locale").and_then(Value::as_str)));
+    if let Some(label) = display.and_then(|d| d.get("name")).and_then(Value::as_str) {
+        return label.into();
+    }
+    let mut chars = name.chars();
+    chars.next().map_or_else(String::new, |first| first.to_uppercase().chain(chars).collect())
+}
+
+fn child_path(path: &[String], name: &str) -> Vec<String> {
+    let mut path = path.to_vec();
+    path.push(name.into());
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    // Nested objects and arrays keep their structure, values are typed by the
+    // claim definitions and defined claims are in metadata order.
+    #[test]
+    fn claim_tree() {
+        let definitions = json!({
+            "birthdate": {"value_type": "date", "display": [{"name": "Date of birth"}]},
+            "address": {
+                "locality": {"value_type": "string", "display": [{"name": "Locality"}]},
+            },
+            "nationalities": {"value_type": "string"},
+        });
+        let claims = json!({
+            "nationalities": ["NZ", "AU"],
+            "birthdate": "1990-01-01",
+            "address": {"locality": "Wellington"},
+            "member": true,
+        });
+        let Value::Object(claims) = claims else {
+            panic!("claims should be an object");
+        };
+
+        let nodes = claim_nodes(&claims, Some(&definitions), &LocalePreference::default());
+        let labels: Vec<&str> = nodes.iter().map(|n| n.label.as_str()).collect();
+        assert_eq!(labels, vec!["Date of birth", "Address", "Nationalities", "Member"]);
+        assert_eq!(nodes[0].value, ClaimValue::Date("1990-01-01".into()));
+        assert_eq!(nodes[3].value, ClaimValue::Boolean(true));
+
+        let ClaimValue::Object(address) = &nodes[1].value else {
+            panic!("should be an object");
+        };
+        assert_eq!(address[0].label, "Locality");
+        assert_eq!(address[0].path, vec!["address", "locality"]);
+        assert_eq!(address[0].value, ClaimValue::String("Wellington".into()));
+
+        let ClaimValue::Array(nationalities) = &nodes[2].value else {
+            panic!("should be an array");
+        };
+        assert_eq!(nationalities[1].path, vec!["nationalities", "1"]);
+        assert_eq!(nationalities[1].value, ClaimValue::String("AU".into()));
+
+        // Without the metadata order, defined claims are sorted by name.
+        let definitions = sorted(definitions);
+        let nodes = claim_nodes(&claims, Some(&definitions), &LocalePreference::default());
+        let labels: Vec<&str> = nodes.iter().map(|n| n.label.as_str()).collect();
+        assert_eq!(labels, vec!["Address", "Date of birth", "Nationalities", "Member"]);
+    }
+}
@@ -1,28 +1,20 @@
 //! Credential view models
 
+use std::cmp::Ordering;
 use std::collections::HashMap;
 
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use vercre_holder::credential::{Credential as CredentialModel, ImageData};
 
+use super::claim::{self, ClaimNode};
+use crate::model::credential::QuarantinedRecord;
+pub use crate::model::credential::Validity;
+use crate::model::locale::LocalePreference;
+pub use crate::model::query::{CredentialFilter, CredentialQuery, CredentialSort};
+pub use crate::model::status::CredentialStatus;
 use crate::model::{CredentialState, OfferedCredential};
 
-/// View model for nested claims
-#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
-pub struct ClaimView {
-    /// The claim name.
-    pub name: String,
-
-    /// The claim value.
-    pub value: String,
-}
-
-impl From<(String, String)> for ClaimView {
-    fn from((name, value): (String, String)) -> Self {
-        Self { name, value }
-    }
-}
-
 /// View model for a verifiable credential.
 ///
 /// Matches the `Credential` model from the `vercre-holder` crate as closely as
@@ -57,19 +49,20 @@ pub struct Credential {
     /// formatted.
     pub format: String,
 
-    /// Claims display. The key is the subject ID and the value is the claims
-    /// displayed as labelled strings
-    pub claims: HashMap<String, Vec<ClaimView>>,
+    /// Claims display. The key is the subject ID and the value is the tree of
+    /// the subject's claims, in display order.
+    pub claims: HashMap<String, Vec<ClaimNode>>,
 
-    /// The date the credential was issued as an RFC2822 string.
+    /// The date the credential was issued, formatted for the user's locale
+    /// and time zone.
     pub issuance_date: String,
 
-    /// The date the credential is valid from as an RFC2822 string. Empty string
-    /// if not applicable.
+    /// The date the credential is valid from, formatted for the user's locale
+    /// and time zone. Empty string if not applicable.
     pub valid_from: String,
 
-    /// The date the credential is valid until (expiry) as an RFC2822 string.
-    /// Empty string if not applicable.
+    /// The date the credential is valid until (expiry), formatted for the
+    /// user's locale and time zone. Empty string if not applicable.
     pub valid_until: String,
 
     /// Name from issuer's metadata.
@@ -97,34 +90,62 @@ pub struct Credential {
     /// The elements of `ImageData` will be empty strings if the background has
     /// not been set.
     pub background: ImageData,
+
+    /// The holder key the credential is bound to has been rotated, so the
+    /// credential should be re-issued.
+    pub needs_reissue: bool,
+
+    /// Status of the credential as last reported by the issuer.
+    pub status: CredentialStatus,
+
+    /// Whether the credential is within its validity period.
+    pub validity: Validity,
 }
 
 impl From<CredentialModel> for Credential {
     fn from(credential: CredentialModel) -> Self {
+        Self::new(credential, None, &LocalePreference::default())
+    }
+}
+
+impl Credential {
+    /// Convert a credential into a credential view, choosing display metadata
+    /// and formatting dates for the locale.
+    ///
+    /// Claims are ordered by the claim definitions of the issuer's metadata,
+    /// if known, or else by the credential's definitions sorted by name.
+    pub fn new(
+        credential: CredentialModel, claim_metadata: Option<&Value>, locale: &LocalePreference,
+    ) -> Self {
+        let definitions = claim_metadata.cloned().or_else(|| {
+            serde_json::to_value(&credential.claim_definitions).ok().map(claim::sorted)
+        });
         let mut claims = HashMap::new();
         for sub_claims in &credential.subject_claims {
-            let claims_display = credential.claims_display(sub_claims.id.as_deref(), None);
-            let mut claims_view = Vec::new();
-            for (name, value) in claims_display {
-                claims_view.push((name, value).into());
-            }
-            claims.insert(sub_claims.id.clone().unwrap_or_default(), claims_view);
+            let claim_nodes = match serde_json::to_value(&sub_claims.claims) {
+                Ok(Value::Object(values)) => {
+                    claim::claim_nodes(&values, definitions.as_ref(), locale)
+                }
+                _ => vec![],
+            };
+            claims.insert(sub_claims.id.clone().unwrap_or_default(), claim_nodes);
         }
-        let (name, description, background_color, text_color) = match credential.display {
-            Some(display_list) => {
-                // TODO: Locale support.
-                let display = display_list
-                    .clone()
-                    .into_iter()
-                    .find(|display| display.locale.is_none())
-                    .unwrap_or_else(|| display_list[0].clone());
-                (
-                    display.name,
-                    display.description.unwrap_or_default(),
-                    display.background_color.unwrap_or_default(),
-                    display.text_color.unwrap_or_default(),
-                )
-            }
+        // Lookup falls back to the first display, and finds none only in an
+        // empty list.
+        let display = credential
+            .display
+            .as_deref()
+            .and_then(|display_list| {
+                locale.lookup(display_list, |display| display.locale.as_deref())
+            })
+            .cloned();
+        let (name, description, background_color, text_color) = match display {
+            Some(display) => (
+                display.name,
+                display.description.unwrap_or_default(),
+                display.background_color.unwrap_or_default(),
+                display.text_color.unwrap_or_default(),
+            ),
             None => (String::new(), String::new(), String::new(), String::new()),
         };
         Self {
@@ -135,50 +156,73 @@ impl From<CredentialModel> for Credential {
             type_: credential.type_,
             format: credential.format,
             claims,
-            issuance_date: credential.issuance_date.format("%a, %d %b %Y").to_string(),
+            issuance_date: locale.format_date(&credential.issuance_date),
             valid_from: credential
                 .valid_from
-                .map_or_else(String::new, |date| date.format("%a, %d %b %Y").to_string()),
+                .map_or_else(String::new, |date| locale.format_date(&date)),
             valid_until: credential
                 .valid_until
-                .map_or_else(String::new, |date| date.format("%a, %d %b %Y").to_string()),
+                .map_or_else(String::new, |date| locale.format_date(&date)),
             name,
             description,
             background_color,
             text_color,
             logo: credential.logo.unwrap_or_default(),
             background: credential.background.unwrap_or_default(),
+            needs_reissue: false,
+            status: CredentialStatus::Unknown,
+            validity: Validity::Valid,
         }
     }
-}
 
-impl Credential {
-    /// Convert an offer into a credential view.
+    /// Whether the credential's name, issuer or any of its claims contain the
+    /// search text, ignoring case.
+    fn matches(&self, search: &str) -> bool {
+        let search = search.trim().to_lowercase();
+        let contains = |text: &str| text.to_lowercase().contains(&search);
+        search.is_empty()
+            || contains(&self.name)
+            || contains(&self.issuer_name)
+            || contains(&self.issuer)
+            || self.claims.values().flatten().any(|node| node.any(&contains))
+    }
+
+    /// Convert an offer into a credential view, choosing display metadata for
+    /// the locale.
+    ///
+    /// Claims are ordered as the offered configuration of the issuer's
+    /// metadata, if given, lists them, or else sorted by name.
     ///
     /// Some data is empty. We just use the credential data shape as a template.
-    pub fn from_offer(issuer: &str, issuer_name: &str, offered: OfferedCredential) -> Self {
+    pub fn from_offer(
+        issuer: &str, issuer_name: &str, offered: OfferedCredential, config: Option<&Value>,
+        locale: &LocalePreference,
+    ) -> Self {
+        let config = config.cloned().unwrap_or_else(|| {
+            claim::sorted(serde_json::to_value(&offered.config).unwrap_or_default())
+        });
+        let definitions = config
+            .pointer("/credential_definition/credentialSubject")
+            .or_else(|| config.get("claims"));
         let mut claims = HashMap::new();
-        let claims_display = offered.config.claims_display(None);
-        let mut claims_view = Vec::new();
-        for name in claims_display {
-            claims_view.push((name, String::new()).into());
-        }
-        claims.insert(String::new(), claims_view);
-        let (name, description, background_color, text_color) = match offered.config.display {
-            Some(display_list) => {
-                // TODO: Locale support.
-                let display = display_list
-                    .clone()
-                    .into_iter()
-                    .find(|display| display.locale.is_none())
-                    .unwrap_or_else(|| display_list[0].clone());
-                (
-                    display.name,
-                    display.description.unwrap_or_default(),
-                    display.background_color.unwrap_or_default(),
-                    display.text_color.unwrap_or_default(),
-                )
-            }
+        claims.insert(String::new(), claim::definition_nodes(definitions, locale));
+        // Lookup falls back to the first display, and finds none only in an
+        // empty list.
+        let display = offered
+            .config
+            .display
+            .as_deref()
+            .and_then(|display_list| {
+                locale.lookup(display_list, |display| display.locale.as_deref())
+            })
+            .cloned();
+        let (name, description, background_color, text_color) = match display {
+            Some(display) => (
+                display.name,
+                display.description.unwrap_or_default(),
+                display.background_color.unwrap_or_default(),
+                display.text_color.unwrap_or_default(),
+            ),
             None => (String::new(), String::new(), String::new(), String::new()),
         };
         Self {
@@ -196,21 +240,96 @@ impl Credential {
     }
 }
 
+/// View model for a stored entry that could not be read and has been
+/// quarantined.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct QuarantinedCredential {
+    /// ID of the quarantined entry, by which it is discarded.
+    pub id: String,
+
+    /// Why the entry could not be read.
+    pub reason: String,
+}
+
+impl From<QuarantinedRecord> for QuarantinedCredential {
+    fn from(record: QuarantinedRecord) -> Self {
+        Self {
+            id: record.id,
+            reason: record.reason,
+        }
+    }
+}
+
 /// View for the verifiable credential sub-app
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct CredentialView {
     /// Currently active credential ID
     pub id: Option<String>,
 
-    /// List of stored credentials
+    /// Stored credentials matching the active query. Credentials that are
+    /// expired or not yet valid are listed after valid credentials, each group
+    /// in the order of the query.
     pub credentials: Vec<Credential>,
+
+    /// The active search, filter and sort of the credential list.
+    pub query: CredentialQuery,
+
+    /// Stored entries that could not be read.
+    pub quarantined: Vec<QuarantinedCredential>,
+
+    /// Transaction IDs of deferred issuances whose credentials are ready to be
+    /// retrieved.
+    pub deferred_ready: Vec<String>,
 }
 
-impl From<CredentialState> for CredentialView {
-    fn from(state: CredentialState) -> Self {
+impl CredentialView {
+    /// Create the credential view, with display metadata and dates for the
+    /// locale.
+    #[must_use]
+    pub fn new(state: CredentialState, locale: &LocalePreference) -> Self {
+        let query = &state.query;
+        let mut listed: Vec<(&CredentialModel, Credential)> = state
+            .credentials
+            .iter()
+            .map(|record| {
+                let view = Credential {
+                    needs_reissue: state.keys.registry.is_retired(&record.key_id),
+                    status: state.status.get(&record.credential.id).cloned().unwrap_or_default(),
+                    validity: state.validity(&record.credential),
+                    ..Credential::new(
+                        record.credential.clone(),
+                        record.claim_metadata.as_ref(),
+                        locale,
+                    )
+                };
+                (&record.credential, view)
+            })
+            .filter(|(_, view)| {
+                let filter = &query.filter;
+                filter.accepts(&view.type_, &view.issuer, &view.format, &view.validity)
+                    && view.matches(&query.search)
+            })
+            .collect();
+        listed.sort_by(|(a, a_view), (b, b_view)| {
+            let ordering = match query.sort {
+                CredentialSort::Stored => Ordering::Equal,
+                CredentialSort::IssuanceDate => a.issuance_date.cmp(&b.issuance_date),
+                CredentialSort::Expiry => match (a.valid_until, b.valid_until) {
+                    (Some(a), Some(b)) => a.cmp(&b),
+                    (a, b) => a.is_none().cmp(&b.is_none()),
+                },
+                CredentialSort::Name => a_view.name.to_lowercase().cmp(&b_view.name.to_lowercase()),
+            };
+            let ordering = if query.descending { ordering.reverse() } else { ordering };
+            a_view.validity.cmp(&b_view.validity).then(ordering)
+        });
+
         Self {
-            id: state.id,
-            credentials: state.credentials.into_iter().map(Credential::from).collect(),
+            id: state.id.clone(),
+            credentials: listed.into_iter().map(|(_, view)| view).collect(),
+            query: state.query.clone(),
+            quarantined: state.quarantined.into_iter().map(Into::into).collect(),
+            deferred_ready: state.deferred_ready,
         }
     }
 }
@@ -221,6 +340,8 @@ mod tests {
     use vercre_holder::CredentialConfiguration;
 
     use super::*;
+    use crate::model::compatibility::Compatibility;
+    use crate::model::credential::CredentialRecord;
 
     // Test conversion of a `Credential` model to a `Credential` view.
     #[test]
@@ -231,14 +352,25 @@ mod tests {
         assert_eq!(credentials.len(), 2);
 
         let credential = Credential::from(credentials[0].clone());
-        assert_yaml_snapshot!("employee", credential, {
-            ".claims.**" => insta::sorted_redaction()
-        });
+        assert_yaml_snapshot!("employee", credential);
 
         let credential = Credential::from(credentials[1].clone());
-        assert_yaml_snapshot!("developer", credential,{
-            ".claims.**" => insta::sorted_redaction()
-        });
+        assert_yaml_snapshot!("developer", credential);
+    }
+
+    // A credential with an empty display list is shown without display
+    // metadata.
+    #[test]
+    fn empty_display_list() {
+        let json = include_bytes!("../model/credentials.json");
+        let mut credentials: Vec<CredentialModel> =
+            serde_json::from_slice(json).expect("should deserialize");
+        let mut credential = credentials.remove(0);
+        credential.display = Some(vec![]);
+
+        let credential = Credential::from(credential);
+        assert_eq!(credential.name, "");
+        assert_eq!(credential.background_color, "");
     }
 
     // Test conversion of an offer to a `Credential` view.
@@ -365,10 +497,49 @@ mod tests {
             background: None,
             received: true,
             stored: false,
+            compatibility: Compatibility::Supported,
         };
-        let credential = Credential::from_offer("issuer", "Issuer", offer);
-        assert_yaml_snapshot!("offer", credential, {
-            ".claims.**" => insta::sorted_redaction()
-        });
+        let locale = LocalePreference::default();
+        let credential = Credential::from_offer("issuer", "Issuer", offer, Some(&json), &locale);
+        assert_yaml_snapshot!("offer", credential);
+    }
+
+    // The credential list is searched, filtered and sorted by the active
+    // query.
+    #[test]
+    fn test_credential_query() {
+        let json = include_bytes!("../model/credentials.json");
+        let credentials: Vec<CredentialModel> =
+            serde_json::from_slice(json).expect("should deserialize");
+        let mut state = CredentialState::init();
+        for credential in credentials {
+            state.credentials.push(CredentialRecord {
+                credential,
+                key_id: "key".into(),
+                claim_metadata: None,
+            });
+        }
+        let names = |state: &CredentialState| -> Vec<String> {
+            let view = CredentialView::new(state.clone(), &LocalePreference::default());
+            view.credentials.into_iter().map(|c| c.name).collect()
+        };
+        assert_eq!(names(&state), vec!["Employee ID", "Developer"]);
+
+        state.query.sort = CredentialSort::IssuanceDate;
+        assert_eq!(names(&state), vec!["Developer", "Employee ID"]);
+
+        state.query.sort = CredentialSort::Expiry;
+        assert_eq!(names(&state), vec!["Developer", "Employee ID"]);
+
+        state.query.sort = CredentialSort::Name;
+        state.query.descending = true;
+        assert_eq!(names(&state), vec!["Employee ID", "Developer"]);
+
+        state.query.search = "wellington".into();
+        assert_eq!(names(&state), vec!["Employee ID"]);
+
+        state.query.search = String::new();
+        state.query.filter.types = vec!["DeveloperCredential".into()];
+        assert_eq!(names(&state), vec!["Developer"]);
     }
 }
@@ -1,9 +1,14 @@
 //! Issuance flow view models.
 
+use std::collections::HashMap;
+
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 
 use super::credential::Credential;
-use crate::model::IssuanceState;
+use crate::model::compatibility::Compatibility;
+use crate::model::locale::LocalePreference;
+use crate::model::{IssuanceState, OfferedCredential};
 
 /// View-friendly representation of a transaction code specification.
 #[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq)]
@@ -55,15 +60,111 @@ pub struct IssuanceView {
 
     /// PIN requirements.
     pub tx_code: TxCode,
+
+    /// The offer requires a PIN the user has not entered yet.
+    pub pin_required: bool,
+
+    /// Whether the wallet can handle each offered credential, ordered by
+    /// credential configuration ID. Empty until the issuer's metadata has been
+    /// received.
+    pub support: Vec<CredentialSupport>,
+
+    /// The issuer's metadata is not signed, so its integrity rests on the
+    /// connection to the issuer alone. False until the metadata has been
+    /// received.
+    pub unsigned_metadata: bool,
 }
 
-impl From<Option<IssuanceState>> for IssuanceView {
-    fn from(state: Option<IssuanceState>) -> Self {
-        match state {
-            Some(_state) => {
-                todo!()
-            }
-            None => Self::default(),
+impl IssuanceView {
+    /// Create the view of an issuance, choosing display metadata for the
+    /// locale. The credential shown is the next one to be issued, or the
+    /// first offered if the wallet can handle none of them.
+    pub fn new(state: Option<&IssuanceState>, locale: &LocalePreference) -> Self {
+        let Some(state) = state else {
+            return Self::default();
+        };
+        let Some(issuance) = state.issuance() else {
+            return match state {
+                IssuanceState::Offered { offer, grant } => Self {
+                    issuer: offer.credential_issuer.clone(),
+                    tx_code: grant.tx_code.clone().into(),
+                    pin_required: grant.tx_code.is_some(),
+                    ..Self::default()
+                },
+                _ => Self::default(),
+            };
+        };
+
+        let issuer = issuance.credential_issuer();
+        let issuer_name = match issuance.metadata.get("display") {
+            Some(Value::Array(display)) => locale
+                .lookup(display, |display| display.get("locale").and_then(Value::as_str))
+                .and_then(|display| display.get("name")?.as_str())
+                .unwrap_or_default(),
+            _ => "",
+        };
+        let offered = issuance
+            .next_credential()
+            .or_else(|| issuance.offerred.keys().min().cloned())
+            .and_then(|config_id| {
+                let offered = issuance.offerred.get(&config_id)?.clone();
+                let config = issuance
+                    .metadata
+                    .pointer(&format!("/credential_configurations_supported/{config_id}"));
+                Some(Credential::from_offer(issuer, issuer_name, offered, config, locale))
+            })
+            .unwrap_or_default();
+        Self {
+            issuer: issuer.into(),
+            issuer_name: issuer_name.into(),
+            offered,
+            tx_code: issuance.grant.tx_code.clone().into(),
+            pin_required: issuance.needs_pin(),
+            support: CredentialSupport::list(&issuance.offerred),
+            unsigned_metadata: !issuance.signed_metadata,
         }
     }
 }
+
+/// Whether the wallet can handle an offered credential.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct CredentialSupport {
+    /// Credential configuration ID.
+    pub config_id: String,
+
+    /// Credential name from the issuer's display metadata, if any.
+    pub name: String,
+
+    /// The wallet can request, verify and store the credential.
+    pub supported: bool,
+
+    /// Why the wallet cannot handle the credential. Empty if supported.
+    pub reasons: Vec<String>,
+}
+
+impl CredentialSupport {
+    /// Support status of each offered credential, ordered by configuration
+    /// ID.
+    fn list(offerred: &HashMap<String, OfferedCredential>) -> Vec<Self> {
+        let mut support: Vec<Self> = offerred
+            .iter()
+            .map(|(config_id, offered)| Self {
+                config_id: config_id.clone(),
+                name: offered
+                    .config
+                    .display
+                    .as_ref()
+                    .and_then(|display| display.first())
+                    .map(|display| display.name.clone())
+                    .unwrap_or_default(),
+                supported: offered.compatibility.is_supported(),
+                reasons: match &offered.compatibility {
+                    Compatibility::Supported => Vec::new(),
+                    Compatibility::Unsupported(reasons) => reasons.clone(),
+                },
+            })
+            .collect();
+        support.sort_by(|a, b| a.config_id.cmp(&b.config_id));
+        support
+    }
+}
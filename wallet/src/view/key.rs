@@ -0,0 +1,77 @@
+//! Key management view models
+
+use serde::{Deserialize, Serialize};
+
+use crate::model::CredentialState;
+use crate::provider::keys::SIGNING;
+
+/// Status of a holder key.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub enum KeyStatus {
+    /// The key is used for new credentials.
+    #[default]
+    Current,
+
+    /// The key has been superseded by a rotation but credentials are still
+    /// bound to it.
+    Retired,
+
+    /// No stored credential is bound to the key.
+    Orphaned,
+}
+
+/// View model for a holder key.
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+pub struct HolderKey {
+    /// Key ID.
+    pub id: String,
+
+    /// Algorithm the key is used with.
+    pub algorithm: String,
+
+    /// When the key was created as an RFC 3339 string.
+    pub created: String,
+
+    /// Status of the key.
+    pub status: KeyStatus,
+
+    /// Number of stored credentials bound to the key.
+    pub credentials: usize,
+}
+
+/// View for key management
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct KeyView {
+    /// Holder keys held in the key store.
+    pub keys: Vec<HolderKey>,
+}
+
+impl From<&CredentialState> for KeyView {
+    fn from(state: &CredentialState) -> Self {
+        let keys = state
+            .keys
+            .keys
+            .iter()
+            .filter(|key| key.purpose == SIGNING)
+            .map(|key| {
+                let credentials =
+                    state.credentials.iter().filter(|record| record.key_id == key.id).count();
+                let status = if credentials == 0 {
+                    KeyStatus::Orphaned
+                } else if state.keys.registry.is_retired(&key.id) {
+                    KeyStatus::Retired
+                } else {
+                    KeyStatus::Current
+                };
+                HolderKey {
+                    id: key.id.clone(),
+                    algorithm: key.algorithm.clone(),
+                    created: key.created.clone(),
+                    status,
+                    credentials,
+                }
+            })
+            .collect();
+        Self { keys }
+    }
+}